@@ -0,0 +1,363 @@
+//! Static archives and the loading of input files.
+//!
+//! An archive is a collection of object files, of which only the members defining a symbol the
+//! link needs are linked. Members are extracted until no undefined symbol is defined by a member
+//! not yet extracted, regardless of where on the command line the archive is, and are appended to
+//! the inputs in the order they are extracted. The timestamps, owners and modes in the member
+//! headers are ignored so that they do not affect the output. Shared libraries are loaded along
+//! with the object files; their definitions satisfy references, so that members defining the same
+//! symbols are not extracted.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+};
+
+use crate::{
+    elf::SymbolBinding,
+    inputs::{InputFile, ObjectFile},
+    library::InputPath,
+    symbols::wrapped_name,
+    util::parallel_map,
+};
+
+const MAGIC: &[u8] = b"!<arch>\n";
+const THIN_MAGIC: &[u8] = b"!<thin>\n";
+const HEADER_SIZE: usize = 60;
+
+/// The range of the symbol table of an archive, and whether its numbers are 8 bytes wide.
+type SymbolTableRange = (Range<usize>, bool);
+
+/// Why an archive member is part of the link.
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pub archive: String,
+    pub member: String,
+
+    /// The symbol the member defines which was undefined, and the path of the input referring to
+    /// it or the option naming it, such as `--undefined`.
+    pub symbol: String,
+    pub referenced_by: String,
+}
+
+/// A member of an archive.
+#[derive(Debug)]
+pub struct Member {
+    pub name: String,
+
+    /// The range of the contents of the member in the archive.
+    pub data: Range<usize>,
+}
+
+#[derive(Debug)]
+pub struct Archive {
+    pub path: String,
+    pub data: Vec<u8>,
+    pub members: Vec<Member>,
+
+    /// The member defining each symbol, by the symbol table of the archive or of the members if
+    /// the archive has none. The first definition wins.
+    pub index: HashMap<String, usize>,
+}
+
+impl Archive {
+    /// Whether `data` is the contents of an archive.
+    pub fn is_archive(data: &[u8]) -> bool {
+        data.starts_with(MAGIC) || data.starts_with(THIN_MAGIC)
+    }
+
+    pub fn new(path: String, data: Vec<u8>) -> Result<Self, String> {
+        if data.starts_with(THIN_MAGIC) {
+            return Err(format!("{}: thin archives are not supported", path));
+        }
+        let mut archive = Self {
+            path,
+            data,
+            members: vec![],
+            index: HashMap::new(),
+        };
+        let (symtab, offsets) = archive
+            .read_members()
+            .map_err(|e| format!("{}: {}", archive.path, e))?;
+
+        match symtab {
+            Some((data, wide)) => {
+                let symbols = parse_symtab(&archive.data[data], wide)
+                    .map_err(|e| format!("{}: {}", archive.path, e))?;
+                for (name, offset) in symbols {
+                    let member = offsets.get(&offset).copied().ok_or_else(|| {
+                        format!(
+                            "{}: symbol table refers to no member at 0x{:x}",
+                            archive.path, offset
+                        )
+                    })?;
+                    archive.index.entry(name).or_insert(member);
+                }
+            }
+            None => {
+                for member in 0..archive.members.len() {
+                    let Ok(input) = archive.member(member) else {
+                        continue;
+                    };
+                    for name in defined_symbols(&input) {
+                        archive.index.entry(name.into()).or_insert(member);
+                    }
+                }
+            }
+        }
+        Ok(archive)
+    }
+
+    /// Splits the archive into members. Returns the range and width of the symbol table, if the
+    /// archive has one, and the member at each header offset.
+    fn read_members(&mut self) -> Result<(Option<SymbolTableRange>, HashMap<u64, usize>), String> {
+        let mut symtab = None;
+        let mut long_names: Range<usize> = 0..0;
+        let mut offsets = HashMap::new();
+
+        let mut pos = MAGIC.len();
+        while pos < self.data.len() {
+            let header = self
+                .data
+                .get(pos..pos + HEADER_SIZE)
+                .ok_or_else(|| format!("truncated member header at 0x{:x}", pos))?;
+            if &header[58..60] != b"`\n" {
+                return Err(format!("invalid member header at 0x{:x}", pos));
+            }
+            let field = |range: Range<usize>| {
+                std::str::from_utf8(&header[range])
+                    .map(|s| s.trim_end().to_string())
+                    .map_err(|_| format!("invalid member header at 0x{:x}", pos))
+            };
+            let raw_name = field(0..16)?;
+            let size: usize = field(48..58)?
+                .parse()
+                .map_err(|_| format!("invalid member size at 0x{:x}", pos))?;
+            let mut start = pos + HEADER_SIZE;
+            let end = start + size;
+            if end > self.data.len() {
+                return Err(format!("truncated member at 0x{:x}", pos));
+            }
+
+            let name = match raw_name.as_str() {
+                "/" => {
+                    symtab = Some((start..end, false));
+                    None
+                }
+                "/SYM64/" => {
+                    symtab = Some((start..end, true));
+                    None
+                }
+                "//" => {
+                    long_names = start..end;
+                    None
+                }
+                name => Some(if let Some(offset) = name.strip_prefix('/') {
+                    // A GNU long name: an offset into the long name table.
+                    let offset: usize = offset
+                        .parse()
+                        .map_err(|_| format!("invalid member name {}", name))?;
+                    let table = &self.data[long_names.clone()];
+                    let rest = table
+                        .get(offset..)
+                        .ok_or_else(|| format!("invalid member name {}", name))?;
+                    let len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                    String::from_utf8_lossy(&rest[..len])
+                        .trim_end_matches('/')
+                        .to_string()
+                } else if let Some(len) = name.strip_prefix("#1/") {
+                    // A BSD long name, stored at the start of the contents.
+                    let len: usize = len
+                        .parse()
+                        .map_err(|_| format!("invalid member name {}", name))?;
+                    let bytes = self
+                        .data
+                        .get(start..start + len)
+                        .ok_or_else(|| format!("invalid member name {}", name))?;
+                    start += len;
+                    String::from_utf8_lossy(bytes)
+                        .trim_end_matches('\0')
+                        .to_string()
+                } else {
+                    name.trim_end_matches('/').to_string()
+                }),
+            };
+
+            if let Some(name) = name {
+                offsets.insert(pos as u64, self.members.len());
+                self.members.push(Member {
+                    name,
+                    data: start..end,
+                });
+            }
+            // Members are aligned to 2 bytes.
+            pos = end + end % 2;
+        }
+        Ok((symtab, offsets))
+    }
+
+    /// The path of a member as shown to users, `archive(member)`.
+    pub fn member_path(&self, member: usize) -> String {
+        format!("{}({})", self.path, self.members[member].name)
+    }
+
+    /// Parses a member as an object file.
+    pub fn member(&self, member: usize) -> Result<InputFile, String> {
+        let path = self.member_path(member);
+        let data = &self.data[self.members[member].data.clone()];
+        let object = ObjectFile::from_reader(data).map_err(|e| format!("{}: {}", path, e))?;
+        InputFile::new(path.clone(), object).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+/// Parses the symbol table of an archive: the number of symbols, the header offset of the member
+/// defining each, and their names, with big-endian numbers of 4 bytes, or 8 if `wide`.
+fn parse_symtab(data: &[u8], wide: bool) -> Result<Vec<(String, u64)>, String> {
+    let width = if wide { 8 } else { 4 };
+    let number = |pos: usize| -> Result<u64, String> {
+        let bytes = data
+            .get(pos..pos + width)
+            .ok_or("truncated archive symbol table")?;
+        Ok(bytes.iter().fold(0, |n, &b| (n << 8) | b as u64))
+    };
+
+    let count = number(0)? as usize;
+    let mut names = data
+        .get(width * (count + 1)..)
+        .ok_or("truncated archive symbol table")?
+        .split(|&b| b == 0);
+    let mut symbols = vec![];
+    for i in 0..count {
+        let offset = number(width * (i + 1))?;
+        let name = names.next().ok_or("truncated archive symbol table")?;
+        symbols.push((String::from_utf8_lossy(name).into_owned(), offset));
+    }
+    Ok(symbols)
+}
+
+/// The global symbols an object file defines.
+fn defined_symbols(input: &InputFile) -> impl Iterator<Item = &str> {
+    input
+        .symbols
+        .iter()
+        .zip(&input.symbol_names)
+        .filter(|(sym, _)| sym.binding != SymbolBinding::Local && !sym.is_undefined())
+        .map(|(_, name)| name.as_str())
+}
+
+/// Opens the input files found for the command line, and extracts the archive members the link
+/// needs. `roots` are symbols options such as `--undefined` ask for, with the options, and `wrap`
+/// the symbols given by `--wrap`, which redirect references. Files are parsed on up to `threads`
+/// threads; the result does not depend on how many.
+pub fn load(
+    paths: &[InputPath],
+    roots: &[(String, &str)],
+    wrap: &[String],
+    threads: usize,
+) -> Result<Vec<InputFile>, String> {
+    enum Loaded {
+        Object(Box<InputFile>),
+        Archive(Archive),
+    }
+
+    let loaded = parallel_map(paths, threads, |found| -> Result<Loaded, String> {
+        let path = &found.path;
+        let data = fs::read(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+        if Archive::is_archive(&data) {
+            return Ok(Loaded::Archive(Archive::new(path.clone(), data)?));
+        }
+        let object =
+            ObjectFile::from_reader(data.as_slice()).map_err(|e| format!("{}: {}", path, e))?;
+        let input = if InputFile::is_shared(&object) {
+            InputFile::new_shared(path.clone(), object)
+        } else {
+            InputFile::new(path.clone(), object)
+        };
+        let mut input = input.map_err(|e| format!("{}: {}", path, e))?;
+        if let Some(library) = &mut input.shared {
+            library.as_needed = found.as_needed;
+        }
+        Ok(Loaded::Object(Box::new(input)))
+    });
+
+    let mut inputs = vec![];
+    let mut archives = vec![];
+    for loaded in loaded {
+        match loaded? {
+            Loaded::Object(input) => inputs.push(*input),
+            Loaded::Archive(archive) => archives.push(archive),
+        }
+    }
+    if archives.is_empty() {
+        return Ok(inputs);
+    }
+
+    // Each round extracts the members defining the symbols undefined so far, in the order the
+    // references are seen.
+    let mut extracted: Vec<Vec<bool>> = archives
+        .iter()
+        .map(|archive| vec![false; archive.members.len()])
+        .collect();
+    let mut defined = HashSet::new();
+    let mut scanned = 0;
+    loop {
+        for input in &inputs[scanned..] {
+            defined.extend(defined_symbols(input).map(String::from));
+        }
+        scanned = inputs.len();
+        let mut wanted: Vec<(usize, usize, String, String)> = vec![];
+        let mut want = |name: &str, referenced_by: &str| {
+            if name.is_empty() || defined.contains(name) {
+                return;
+            }
+            let found = archives
+                .iter()
+                .enumerate()
+                .find_map(|(a, archive)| archive.index.get(name).map(|&m| (a, m)));
+            if let Some((a, m)) = found {
+                if !extracted[a][m] {
+                    extracted[a][m] = true;
+                    wanted.push((a, m, name.into(), referenced_by.into()));
+                }
+            }
+        };
+        for (name, option) in roots {
+            want(name, option);
+        }
+        // References from shared libraries are resolved when they are loaded, so they extract
+        // nothing.
+        for input in &inputs {
+            if input.shared.is_some() {
+                continue;
+            }
+            let undefined = input
+                .symbols
+                .iter()
+                .zip(&input.symbol_names)
+                .filter(|(sym, _)| sym.is_undefined() && sym.binding == SymbolBinding::Global)
+                .map(|(_, name)| wrapped_name(name, wrap));
+            for name in undefined {
+                want(&name, &input.path);
+            }
+        }
+        if wanted.is_empty() {
+            break;
+        }
+
+        let members = parallel_map(&wanted, threads, |(a, m, symbol, referenced_by)| {
+            let mut input = archives[*a].member(*m)?;
+            input.extraction = Some(Extraction {
+                archive: archives[*a].path.clone(),
+                member: archives[*a].members[*m].name.clone(),
+                symbol: symbol.clone(),
+                referenced_by: referenced_by.clone(),
+            });
+            Ok::<_, String>(input)
+        });
+        for member in members {
+            inputs.push(member?);
+        }
+    }
+    Ok(inputs)
+}
@@ -0,0 +1,229 @@
+//! Build IDs, which identify the output in `.note.gnu.build-id`.
+//!
+//! The ID is a hash of the whole output file computed with the descriptor of the note zeroed, so
+//! that the same inputs and options give the same ID. The hashes are implemented here: MD5 and
+//! SHA-1 as GNU ld computes them, and xxHash64 for `fast`.
+
+use std::{fs::File, io::Read};
+
+use crate::{elf::NOTE_GNU_BUILD_ID, options::BuildId, util::align_up};
+
+/// The size of the descriptor of the note.
+pub fn size(kind: &BuildId) -> u64 {
+    match kind {
+        BuildId::Fast => 8,
+        BuildId::Md5 | BuildId::Uuid => 16,
+        BuildId::Sha1 => 20,
+        BuildId::Hex(bytes) => bytes.len() as u64,
+    }
+}
+
+/// Builds `.note.gnu.build-id` with the descriptor zeroed.
+pub fn note(kind: &BuildId) -> Vec<u8> {
+    let size = size(kind);
+    let mut data = vec![];
+    data.extend_from_slice(&4u32.to_le_bytes());
+    data.extend_from_slice(&(size as u32).to_le_bytes());
+    data.extend_from_slice(&NOTE_GNU_BUILD_ID.to_le_bytes());
+    data.extend_from_slice(b"GNU\0");
+    data.resize(data.len() + align_up(size, 4) as usize, 0);
+    data
+}
+
+/// Computes the ID of the output file `image`.
+pub fn compute(kind: &BuildId, image: &[u8]) -> Result<Vec<u8>, String> {
+    Ok(match kind {
+        BuildId::Fast => xxh64(image, 0).to_le_bytes().to_vec(),
+        BuildId::Md5 => md5(image).to_vec(),
+        BuildId::Sha1 => sha1(image).to_vec(),
+        BuildId::Uuid => {
+            // A random UUID of version 4.
+            let mut uuid = [0; 16];
+            File::open("/dev/urandom")
+                .and_then(|mut f| f.read_exact(&mut uuid))
+                .map_err(|e| format!("cannot generate a UUID: {}", e))?;
+            uuid[6] = (uuid[6] & 0x0F) | 0x40;
+            uuid[8] = (uuid[8] & 0x3F) | 0x80;
+            uuid.to_vec()
+        }
+        BuildId::Hex(bytes) => bytes.clone(),
+    })
+}
+
+/// Pads a message as MD5 and SHA-1 do: a 1 bit, zeros up to 8 bytes short of a multiple of 64,
+/// then the length in bits.
+fn pad(data: &[u8], length: impl Fn(u64) -> [u8; 8]) -> Vec<u8> {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&length(data.len() as u64 * 8));
+    message
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let constants: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+        .collect();
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+    for block in pad(data, u64::to_le_bytes).chunks(64) {
+        let words: Vec<u32> = block
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (out, s) in digest.chunks_mut(4).zip(state) {
+        out.copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    for block in pad(data, u64::to_be_bytes).chunks(64) {
+        let mut words = [0u32; 80];
+        for (w, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *w = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &w) in words.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5A82_7999),
+                1 => (b ^ c ^ d, 0x6ED9_EBA1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (out, s) in digest.chunks_mut(4).zip(state) {
+        out.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn xxh64(data: &[u8], seed: u64) -> u64 {
+    let round = |acc: u64, input: u64| {
+        acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME64_1)
+    };
+    let read64 = |bytes: &[u8]| u64::from_le_bytes(bytes[..8].try_into().unwrap());
+
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut acc = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            for (i, v) in acc.iter_mut().enumerate() {
+                *v = round(*v, read64(&rest[8 * i..]));
+            }
+            rest = &rest[32..];
+        }
+        let mut hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        for v in acc {
+            hash = (hash ^ round(0, v))
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+        }
+        hash
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, read64(rest)))
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let word = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+        hash = (hash ^ word.wrapping_mul(PRIME64_1))
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = (hash ^ (byte as u64).wrapping_mul(PRIME64_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME64_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
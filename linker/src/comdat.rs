@@ -0,0 +1,42 @@
+//! Selection of COMDAT section groups.
+//!
+//! Compilers emit inline functions and template instantiations in every object file using them,
+//! each copy in a group marked `GRP_COMDAT` whose signature names the entity. Only the first group
+//! of each signature in input order is linked; the members of the others are discarded before
+//! symbols are resolved, so that their definitions do not clash with those of the group kept.
+
+use std::collections::HashSet;
+
+use crate::{
+    elf::{SectionType, GROUP_COMDAT},
+    inputs::InputFile,
+};
+
+/// Discards the members of COMDAT groups whose signature an earlier group has.
+pub fn select(inputs: &mut [InputFile]) -> Result<(), String> {
+    let mut kept = HashSet::new();
+    for input in inputs.iter_mut() {
+        for index in 0..input.sections.len() {
+            if input.sections[index].ty != SectionType::Group {
+                continue;
+            }
+            let (flags, members) = input
+                .group(index)
+                .map_err(|e| format!("{}: {}", input.path, e))?;
+            if flags & GROUP_COMDAT == 0 {
+                continue;
+            }
+
+            let signature = input
+                .group_signature(index)
+                .map_err(|e| format!("{}: {}", input.path, e))?;
+            if kept.insert(signature.to_string()) {
+                continue;
+            }
+            for member in members {
+                input.live[member] = false;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,616 @@
+//! Demangling of C++ symbol names mangled as the Itanium C++ ABI specifies, for the
+//! `extern "C++"` patterns of version scripts.
+//!
+//! Names are rendered the way the GNU linker renders them for matching, such as
+//! `ns::f(int, char const*)`, which is how `c++filt` renders them except that abbreviations such
+//! as `std::string` are kept unless they name the class of a constructor. Only the
+//! common part of the grammar is supported: functions and variables, possibly in namespaces,
+//! classes and local scopes, templates, operators, vtables and other special names, and types
+//! built from pointers, references and qualifiers. Names using the rest, such as function
+//! pointer types or expressions in template arguments, are not demangled.
+//!
+//! See [the mangling section] of the ABI.
+//!
+//! [the mangling section]: https://itanium-cxx-abi.github.io/cxx-abi/abi.html#mangling
+
+#[cfg(test)]
+mod tests;
+
+/// Operators by their two-letter code, without the `operator` keyword.
+const OPERATORS: &[(&[u8; 2], &str)] = &[
+    (b"nw", " new"),
+    (b"na", " new[]"),
+    (b"dl", " delete"),
+    (b"da", " delete[]"),
+    (b"ps", "+"),
+    (b"ng", "-"),
+    (b"ad", "&"),
+    (b"de", "*"),
+    (b"co", "~"),
+    (b"pl", "+"),
+    (b"mi", "-"),
+    (b"ml", "*"),
+    (b"dv", "/"),
+    (b"rm", "%"),
+    (b"an", "&"),
+    (b"or", "|"),
+    (b"eo", "^"),
+    (b"aS", "="),
+    (b"pL", "+="),
+    (b"mI", "-="),
+    (b"mL", "*="),
+    (b"dV", "/="),
+    (b"rM", "%="),
+    (b"aN", "&="),
+    (b"oR", "|="),
+    (b"eO", "^="),
+    (b"ls", "<<"),
+    (b"rs", ">>"),
+    (b"lS", "<<="),
+    (b"rS", ">>="),
+    (b"eq", "=="),
+    (b"ne", "!="),
+    (b"lt", "<"),
+    (b"gt", ">"),
+    (b"le", "<="),
+    (b"ge", ">="),
+    (b"ss", "<=>"),
+    (b"nt", "!"),
+    (b"aa", "&&"),
+    (b"oo", "||"),
+    (b"pp", "++"),
+    (b"mm", "--"),
+    (b"cm", ","),
+    (b"pm", "->*"),
+    (b"pt", "->"),
+    (b"cl", "()"),
+    (b"ix", "[]"),
+    (b"qu", "?"),
+];
+
+/// Builtin types by their one-letter code.
+const BUILTIN_TYPES: &[(u8, &str)] = &[
+    (b'v', "void"),
+    (b'w', "wchar_t"),
+    (b'b', "bool"),
+    (b'c', "char"),
+    (b'a', "signed char"),
+    (b'h', "unsigned char"),
+    (b's', "short"),
+    (b't', "unsigned short"),
+    (b'i', "int"),
+    (b'j', "unsigned int"),
+    (b'l', "long"),
+    (b'm', "unsigned long"),
+    (b'x', "long long"),
+    (b'y', "unsigned long long"),
+    (b'n', "__int128"),
+    (b'o', "unsigned __int128"),
+    (b'f', "float"),
+    (b'd', "double"),
+    (b'e', "long double"),
+    (b'g', "__float128"),
+    (b'z', "..."),
+];
+
+/// Abbreviations of names in `std`, by the letter following `S`, with what they stand for in
+/// general and as the scope of a constructor or destructor.
+const STD_ABBREVIATIONS: &[(u8, &str, &str)] = &[
+    (b'a', "std::allocator", "std::allocator"),
+    (b'b', "std::basic_string", "std::basic_string"),
+    (
+        b's',
+        "std::string",
+        "std::basic_string<char, std::char_traits<char>, std::allocator<char> >",
+    ),
+    (
+        b'i',
+        "std::istream",
+        "std::basic_istream<char, std::char_traits<char> >",
+    ),
+    (
+        b'o',
+        "std::ostream",
+        "std::basic_ostream<char, std::char_traits<char> >",
+    ),
+    (
+        b'd',
+        "std::iostream",
+        "std::basic_iostream<char, std::char_traits<char> >",
+    ),
+];
+
+/// Demangles a symbol name, or returns `None` if it is not a mangled C++ name or uses parts of
+/// the grammar which are not supported.
+pub fn demangle(name: &str) -> Option<String> {
+    let mangled = name.strip_prefix("_Z")?;
+    let mut demangler = Demangler {
+        input: mangled.as_bytes(),
+        pos: 0,
+        substitutions: vec![],
+        template_args: vec![],
+    };
+    let demangled = demangler.encoding(true)?;
+    // Copies of functions made by optimizations have suffixes such as `.constprop.0`.
+    match &mangled[demangler.pos..] {
+        "" => Some(demangled),
+        suffix if suffix.starts_with('.') => Some(format!("{} [clone {}]", demangled, suffix)),
+        _ => None,
+    }
+}
+
+/// A demangled name, with what its rendering as a function needs.
+struct Name {
+    text: String,
+
+    /// The qualifiers of a member function, such as ` const`.
+    qualifiers: String,
+
+    /// Whether the name ends with template arguments, in which case a function has its return
+    /// type mangled.
+    template: bool,
+
+    /// Whether the name is of a constructor, destructor or conversion operator, which have no
+    /// return type even if they are templates.
+    no_return_type: bool,
+}
+
+struct Demangler<'a> {
+    input: &'a [u8],
+    pos: usize,
+
+    /// The components which may be referred to by `S_`, `S0_`, ... in the order they appear.
+    substitutions: Vec<String>,
+
+    /// The template arguments of the function or variable, which `T_`, `T0_`, ... refer to.
+    template_args: Vec<String>,
+}
+
+impl Demangler<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.input.get(self.pos + offset).copied()
+    }
+
+    fn consume(&mut self, prefix: &[u8]) -> bool {
+        if self.input[self.pos..].starts_with(prefix) {
+            self.pos += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, prefix: &[u8]) -> Option<()> {
+        self.consume(prefix).then_some(())
+    }
+
+    /// Whether a function's parameters end at the current position.
+    fn at_end(&self) -> bool {
+        matches!(self.peek(), None | Some(b'E') | Some(b'.'))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// Parses the index of a substitution or a template parameter: empty for 0, otherwise a
+    /// base-36 number plus 1, followed by `_`.
+    fn seq_id(&mut self) -> Option<usize> {
+        let mut id = 0;
+        let mut digits = false;
+        while let Some(c) = self.peek().filter(|&c| c != b'_') {
+            let digit = match c {
+                b'0'..=b'9' => c - b'0',
+                b'A'..=b'Z' => c - b'A' + 10,
+                _ => return None,
+            };
+            id = id * 36 + digit as usize;
+            digits = true;
+            self.pos += 1;
+        }
+        self.expect(b"_")?;
+        Some(if digits { id + 1 } else { 0 })
+    }
+
+    /// Parses the name of a function or variable. Template functions have their return type
+    /// mangled, which is rendered if `return_type` is set.
+    fn encoding(&mut self, return_type: bool) -> Option<String> {
+        if let Some(special) = self.special_name()? {
+            return Some(special);
+        }
+
+        let name = self.name()?;
+        if self.at_end() {
+            return Some(name.text);
+        }
+        let mut rendered_return_type = String::new();
+        if name.template && !name.no_return_type {
+            let ty = self.ty()?;
+            if return_type {
+                rendered_return_type = format!("{} ", ty);
+            }
+        }
+        let params = self.params()?;
+        Some(format!(
+            "{}{}({}){}",
+            rendered_return_type, name.text, params, name.qualifiers
+        ))
+    }
+
+    /// Parses the names of vtables, typeinfo objects and guard variables, returning `None` if the
+    /// name is not one of them.
+    fn special_name(&mut self) -> Option<Option<String>> {
+        let special = if self.consume(b"TV") {
+            format!("vtable for {}", self.ty()?)
+        } else if self.consume(b"TT") {
+            format!("VTT for {}", self.ty()?)
+        } else if self.consume(b"TI") {
+            format!("typeinfo for {}", self.ty()?)
+        } else if self.consume(b"TS") {
+            format!("typeinfo name for {}", self.ty()?)
+        } else if self.consume(b"Th") {
+            self.consume(b"n");
+            self.number()?;
+            self.expect(b"_")?;
+            format!("non-virtual thunk to {}", self.encoding(true)?)
+        } else if self.consume(b"GV") {
+            format!("guard variable for {}", self.name()?.text)
+        } else {
+            return Some(None);
+        };
+        Some(Some(special))
+    }
+
+    /// Parses the parameter types of a function, rendered without the parentheses.
+    fn params(&mut self) -> Option<String> {
+        let mut params = vec![];
+        while !self.at_end() {
+            params.push(self.ty()?);
+        }
+        if params == ["void"] {
+            params.clear();
+        }
+        Some(params.join(", "))
+    }
+
+    fn name(&mut self) -> Option<Name> {
+        match self.peek()? {
+            b'N' => self.nested_name(),
+            b'Z' => self.local_name(),
+            _ => {
+                let substituted =
+                    self.peek() == Some(b'S') && !self.input[self.pos..].starts_with(b"St");
+                let mut text = if self.consume(b"St") {
+                    format!("std::{}", self.unqualified_name()?.0)
+                } else if substituted {
+                    self.substitution(false)?
+                } else {
+                    self.unqualified_name()?.0
+                };
+                let template = self.peek() == Some(b'I');
+                if template {
+                    if !substituted {
+                        self.substitutions.push(text.clone());
+                    }
+                    let (args, values) = self.template_args()?;
+                    // `operator<` and `operator<<` are kept apart from their arguments.
+                    if text.ends_with('<') {
+                        text.push(' ');
+                    }
+                    text.push_str(&args);
+                    self.template_args = values;
+                }
+                Some(Name {
+                    no_return_type: text.contains("operator "),
+                    text,
+                    qualifiers: String::new(),
+                    template,
+                })
+            }
+        }
+    }
+
+    fn nested_name(&mut self) -> Option<Name> {
+        self.expect(b"N")?;
+        let mut qualifiers = String::new();
+        for (code, qualifier) in [(b"r", " restrict"), (b"V", " volatile"), (b"K", " const")] {
+            if self.consume(code) {
+                qualifiers.insert_str(0, qualifier);
+            }
+        }
+        if self.consume(b"R") {
+            qualifiers.push_str(" &");
+        } else if self.consume(b"O") {
+            qualifiers.push_str(" &&");
+        }
+
+        let mut prefix = String::new();
+        // The name of the innermost class, which constructors and destructors are named after.
+        let mut class = String::new();
+        let mut template = false;
+        let mut no_return_type = false;
+        while !self.consume(b"E") {
+            // Template arguments belong to the component before them.
+            if self.peek() != Some(b'I') {
+                template = false;
+                no_return_type = false;
+            }
+            let component = match self.peek()? {
+                b'I' => {
+                    let (text, args) = self.template_args()?;
+                    // `operator<` and `operator<<` are kept apart from their arguments.
+                    if prefix.ends_with('<') {
+                        prefix.push(' ');
+                    }
+                    prefix.push_str(&text);
+                    self.template_args = args;
+                    template = true;
+                    if self.peek() != Some(b'E') {
+                        self.substitutions.push(prefix.clone());
+                    }
+                    continue;
+                }
+                b'S' if self.peek_at(1) == Some(b't') => {
+                    self.pos += 2;
+                    prefix = "std".into();
+                    continue;
+                }
+                b'S' => {
+                    prefix = self.substitution(true)?;
+                    // The class is the last component, outside template arguments.
+                    let base = &prefix[..prefix.find('<').unwrap_or(prefix.len())];
+                    class = base.rsplit("::").next()?.to_string();
+                    continue;
+                }
+                b'T' => {
+                    prefix = self.template_param()?;
+                    self.substitutions.push(prefix.clone());
+                    continue;
+                }
+                b'C' => {
+                    self.pos += 1;
+                    if !matches!(self.peek()?, b'1'..=b'5') {
+                        return None;
+                    }
+                    self.pos += 1;
+                    no_return_type = true;
+                    class.clone()
+                }
+                b'D' if matches!(self.peek_at(1)?, b'0'..=b'5') => {
+                    self.pos += 2;
+                    no_return_type = true;
+                    format!("~{}", class)
+                }
+                _ => {
+                    let (name, source) = self.unqualified_name()?;
+                    if let Some(source) = source {
+                        class = source;
+                    }
+                    no_return_type = name.starts_with("operator ");
+                    name
+                }
+            };
+            if prefix.is_empty() {
+                prefix = component;
+            } else {
+                prefix = format!("{}::{}", prefix, component);
+            }
+            if self.peek() != Some(b'E') {
+                self.substitutions.push(prefix.clone());
+            }
+        }
+        Some(Name {
+            text: prefix,
+            qualifiers,
+            template,
+            no_return_type,
+        })
+    }
+
+    /// Parses the name of an entity local to a function, such as a static variable.
+    fn local_name(&mut self) -> Option<Name> {
+        self.expect(b"Z")?;
+        let function = self.encoding(false)?;
+        self.expect(b"E")?;
+        let entity = if self.consume(b"s") {
+            "string literal".to_string()
+        } else {
+            self.name()?.text
+        };
+        // Entities of the same name in a function are told apart by a discriminator.
+        if self.consume(b"__") {
+            self.number()?;
+            self.expect(b"_")?;
+        } else if self.consume(b"_") {
+            self.number()?;
+        }
+        Some(Name {
+            text: format!("{}::{}", function, entity),
+            qualifiers: String::new(),
+            template: false,
+            no_return_type: false,
+        })
+    }
+
+    /// Parses a name which is not qualified by a scope. Returns it along with the identifier it is
+    /// made of, if it is not an operator.
+    fn unqualified_name(&mut self) -> Option<(String, Option<String>)> {
+        // Names with internal linkage are prefixed with `L`.
+        self.consume(b"L");
+        let c = self.peek()?;
+        let (mut name, source) = if c.is_ascii_digit() {
+            let source = self.source_name()?;
+            (source.clone(), Some(source))
+        } else if self.consume(b"cv") {
+            (format!("operator {}", self.ty()?), None)
+        } else {
+            let code = self.input.get(self.pos..self.pos + 2)?;
+            let &(_, operator) = OPERATORS.iter().find(|(c, _)| c.as_slice() == code)?;
+            self.pos += 2;
+            (format!("operator{}", operator), None)
+        };
+        while self.consume(b"B") {
+            let tag = self.source_name()?;
+            name = format!("{}[abi:{}]", name, tag);
+        }
+        Some((name, source))
+    }
+
+    fn source_name(&mut self) -> Option<String> {
+        let len = self.number()?;
+        let name = self.input.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        let name = std::str::from_utf8(name).ok()?;
+        // Anonymous namespaces are named `_GLOBAL__N_1`.
+        if name.starts_with("_GLOBAL__N") {
+            return Some("(anonymous namespace)".into());
+        }
+        Some(name.into())
+    }
+
+    /// Parses a substitution. `scope` is whether it is the scope of a name.
+    fn substitution(&mut self, scope: bool) -> Option<String> {
+        self.expect(b"S")?;
+        if let Some(&(_, short, full)) = STD_ABBREVIATIONS
+            .iter()
+            .find(|&&(c, _, _)| Some(c) == self.peek())
+        {
+            self.pos += 1;
+            let structor = scope && matches!(self.peek(), Some(b'C' | b'D'));
+            return Some(if structor { full } else { short }.into());
+        }
+        let id = self.seq_id()?;
+        self.substitutions.get(id).cloned()
+    }
+
+    fn template_param(&mut self) -> Option<String> {
+        self.expect(b"T")?;
+        let id = self.seq_id()?;
+        self.template_args.get(id).cloned()
+    }
+
+    /// Parses template arguments. Returns them rendered with the angle brackets, and each of
+    /// them.
+    fn template_args(&mut self) -> Option<(String, Vec<String>)> {
+        self.expect(b"I")?;
+        let mut args = vec![];
+        while !self.consume(b"E") {
+            let arg = if self.consume(b"L") {
+                let arg = self.literal()?;
+                self.expect(b"E")?;
+                arg
+            } else {
+                self.ty()?
+            };
+            args.push(arg);
+        }
+        let mut text = format!("<{}", args.join(", "));
+        // `>>` would be read as a shift operator.
+        if text.ends_with('>') {
+            text.push(' ');
+        }
+        text.push('>');
+        Some((text, args))
+    }
+
+    /// Parses an integer literal in template arguments, after the `L`.
+    fn literal(&mut self) -> Option<String> {
+        let ty = self.ty()?;
+        let negative = self.consume(b"n");
+        let value = self.number()?;
+        let value = if negative {
+            format!("-{}", value)
+        } else {
+            value.to_string()
+        };
+        Some(match ty.as_str() {
+            "bool" if value == "0" => "false".into(),
+            "bool" if value == "1" => "true".into(),
+            "int" => value,
+            "unsigned int" => format!("{}u", value),
+            "long" => format!("{}l", value),
+            "unsigned long" => format!("{}ul", value),
+            _ => format!("({}){}", ty, value),
+        })
+    }
+
+    fn ty(&mut self) -> Option<String> {
+        let c = self.peek()?;
+        if let Some(&(_, builtin)) = BUILTIN_TYPES.iter().find(|&&(b, _)| b == c) {
+            self.pos += 1;
+            return Some(builtin.into());
+        }
+
+        let ty = match c {
+            b'D' => {
+                let builtin = match self.peek_at(1)? {
+                    b'n' => "decltype(nullptr)",
+                    b's' => "char16_t",
+                    b'i' => "char32_t",
+                    b'u' => "char8_t",
+                    _ => return None,
+                };
+                self.pos += 2;
+                return Some(builtin.into());
+            }
+            b'P' | b'R' | b'O' => {
+                self.pos += 1;
+                let suffix = match c {
+                    b'P' => "*",
+                    b'R' => "&",
+                    _ => "&&",
+                };
+                format!("{}{}", self.ty()?, suffix)
+            }
+            b'r' | b'V' | b'K' => {
+                let mut qualifiers = String::new();
+                for (code, qualifier) in
+                    [(b"r", " restrict"), (b"V", " volatile"), (b"K", " const")]
+                {
+                    if self.consume(code) {
+                        qualifiers.insert_str(0, qualifier);
+                    }
+                }
+                format!("{}{}", self.ty()?, qualifiers)
+            }
+            b'S' if self.peek_at(1) != Some(b't') => {
+                let name = self.substitution(false)?;
+                if self.peek() != Some(b'I') {
+                    // Substitutions are not substitution candidates themselves.
+                    return Some(name);
+                }
+                format!("{}{}", name, self.template_args()?.0)
+            }
+            b'T' => {
+                let param = self.template_param()?;
+                if self.peek() == Some(b'I') {
+                    self.substitutions.push(param.clone());
+                    format!("{}{}", param, self.template_args()?.0)
+                } else {
+                    param
+                }
+            }
+            b'N' | b'S' | b'Z' | b'0'..=b'9' => {
+                // The template arguments of a class do not replace those of the function.
+                let args = self.template_args.clone();
+                let name = self.name()?.text;
+                self.template_args = args;
+                name
+            }
+            _ => return None,
+        };
+        self.substitutions.push(ty.clone());
+        Some(ty)
+    }
+}
@@ -0,0 +1,30 @@
+use super::demangle;
+
+#[test]
+fn demangle_test() {
+    for (mangled, expected) in [
+        ("_Z3fooi", "foo(int)"),
+        ("_ZN2ns1A3getEv", "ns::A::get()"),
+        ("_ZNK2ns1A4sizeEv", "ns::A::size() const"),
+        ("_ZN2ns1AC2ERKS0_", "ns::A::A(ns::A const&)"),
+        ("_ZN2ns1AD1Ev", "ns::A::~A()"),
+        ("_Z3maxIiET_S0_S0_", "int max<int>(int, int)"),
+        ("_ZNKSs4findEPKcm", "std::string::find(char const*, unsigned long) const"),
+        (
+            "_ZNSsC1Ev",
+            "std::basic_string<char, std::char_traits<char>, std::allocator<char> >::basic_string()",
+        ),
+        (
+            "_ZStlsISt11char_traitsIcEERSt13basic_ostreamIcT_ES5_PKc",
+            "std::basic_ostream<char, std::char_traits<char> >& std::operator<< <std::char_traits<char> >(std::basic_ostream<char, std::char_traits<char> >&, char const*)",
+        ),
+        ("_ZTVN2ns1AE", "vtable for ns::A"),
+        ("_ZZ4mainE5count", "main::count"),
+        ("_ZN12_GLOBAL__N_13fooEv", "(anonymous namespace)::foo()"),
+        ("_Z3barv.cold", "bar() [clone .cold]"),
+    ] {
+        assert_eq!(demangle(mangled).as_deref(), Some(expected), "{}", mangled);
+    }
+    assert_eq!(demangle("foo"), None);
+    assert_eq!(demangle("_Z"), None);
+}
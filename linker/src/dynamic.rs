@@ -0,0 +1,1141 @@
+//! Dynamic linking: what the output tells the loader.
+//!
+//! An output is dynamic if it is a shared library or links against one. It then has a dynamic
+//! symbol table with the symbols other modules may bind to or which bind to other modules,
+//! relocations applied by the loader, a procedure linkage table for calls to functions which may
+//! be defined elsewhere, and `.dynamic`, which lists all these along with the libraries needed.
+//! The contents are decided before layout so that their sizes are known; addresses are filled in
+//! when the output is written.
+//!
+//! Executables are not position-independent, so they refer to data in shared libraries through
+//! copies in their own `.bss`, and to functions whose address they take through PLT entries which
+//! become the functions' addresses everywhere.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    elf::{
+        Elf64Rela, Elf64Symbol, RelocationType, SectionFlag64, SectionType, SymbolBinding,
+        SymbolType, SymbolVisibility, SECTION_HEADER_NUMBER_UNDEF,
+    },
+    hash,
+    inputs::{InputFile, SectionId},
+    layout::{candidates, Content, Layout},
+    options::Options,
+    script::{SymbolPattern, VersionNode},
+    symbols::{Definition, Symbol, SymbolRef, SymbolTable},
+    util::{align_up, FromBytes as _, StringTable},
+    version::{self, VersionDefinition, VersionNeed, VERSYM_HIDDEN, VER_FLG_BASE, VER_NDX_GLOBAL},
+};
+
+pub const DT_NULL: i64 = 0;
+pub const DT_NEEDED: i64 = 1;
+pub const DT_PLTRELSZ: i64 = 2;
+pub const DT_PLTGOT: i64 = 3;
+pub const DT_HASH: i64 = 4;
+pub const DT_STRTAB: i64 = 5;
+pub const DT_SYMTAB: i64 = 6;
+pub const DT_RELA: i64 = 7;
+pub const DT_RELASZ: i64 = 8;
+pub const DT_RELAENT: i64 = 9;
+pub const DT_STRSZ: i64 = 10;
+pub const DT_SYMENT: i64 = 11;
+pub const DT_INIT: i64 = 12;
+pub const DT_FINI: i64 = 13;
+pub const DT_SONAME: i64 = 14;
+pub const DT_RPATH: i64 = 15;
+pub const DT_SYMBOLIC: i64 = 16;
+pub const DT_DEBUG: i64 = 21;
+pub const DT_TEXTREL: i64 = 22;
+pub const DT_JMPREL: i64 = 23;
+pub const DT_BIND_NOW: i64 = 24;
+pub const DT_PLTREL: i64 = 20;
+pub const DT_INIT_ARRAY: i64 = 25;
+pub const DT_FINI_ARRAY: i64 = 26;
+pub const DT_INIT_ARRAYSZ: i64 = 27;
+pub const DT_FINI_ARRAYSZ: i64 = 28;
+pub const DT_RUNPATH: i64 = 29;
+pub const DT_FLAGS: i64 = 30;
+pub const DT_PREINIT_ARRAY: i64 = 32;
+pub const DT_PREINIT_ARRAYSZ: i64 = 33;
+pub const DT_GNU_HASH: i64 = 0x6FFF_FEF5;
+pub const DT_VERSYM: i64 = 0x6FFF_FFF0;
+pub const DT_RELACOUNT: i64 = 0x6FFF_FFF9;
+pub const DT_FLAGS_1: i64 = 0x6FFF_FFFB;
+pub const DT_VERDEF: i64 = 0x6FFF_FFFC;
+pub const DT_VERDEFNUM: i64 = 0x6FFF_FFFD;
+pub const DT_VERNEED: i64 = 0x6FFF_FFFE;
+pub const DT_VERNEEDNUM: i64 = 0x6FFF_FFFF;
+
+/// The `DT_FLAGS` bit asking the loader to bind all symbols at load time.
+pub const DF_BIND_NOW: u64 = 0x8;
+
+/// The `DT_FLAGS_1` bit asking the loader to bind all symbols at load time.
+pub const DF_1_NOW: u64 = 0x1;
+
+/// The loader named in `PT_INTERP` unless `--dynamic-linker` says otherwise.
+pub const DEFAULT_DYNAMIC_LINKER: &str = "/lib64/ld-linux-x86-64.so.2";
+
+pub const DYNAMIC_ENTRY_SIZE: u64 = 16;
+pub const RELA_SIZE: u64 = 24;
+pub const PLT_ENTRY_SIZE: u64 = 16;
+pub const SYMBOL_SIZE: u64 = 24;
+
+/// The entries of `.got.plt` reserved for the loader: the address of `.dynamic`, then two words
+/// the loader fills for lazy binding.
+pub const GOT_PLT_RESERVED: u64 = 3;
+
+/// Where a dynamic relocation applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynamicPlace {
+    /// An offset in an input section.
+    Input { id: SectionId, offset: u64 },
+
+    /// An entry of the global offset table.
+    Got(usize),
+
+    /// The space a symbol is copied to, by index in `Dynamic::copies`.
+    Copy(usize),
+
+    /// The slot an IPLT entry jumps through, by index in `Layout::iplt`.
+    IpltSlot(usize),
+}
+
+/// Space in an executable a data symbol of a shared library is copied to at load time by an
+/// `R_X86_64_COPY` relocation, so that the executable can refer to it by a fixed address. The
+/// library then binds its own references to the copy.
+#[derive(Debug, Clone)]
+pub struct CopyReloc {
+    /// The symbol, by id.
+    pub symbol: usize,
+
+    /// Whether the symbol is read-only in the library, so that the copy is in `.bss.rel.ro`
+    /// rather than `.bss`.
+    pub relro: bool,
+
+    /// The offset of the copy among those in the same section.
+    pub offset: u64,
+    pub size: u64,
+    pub align: u64,
+}
+
+/// A relocation in `.rela.dyn`.
+#[derive(Debug, Clone)]
+pub struct DynamicReloc {
+    pub ty: RelocationType,
+    pub place: DynamicPlace,
+
+    /// The symbol referred to. `R_X86_64_RELATIVE` relocations hold its address plus the addend,
+    /// and the others refer to its entry in `.dynsym`.
+    pub symbol: SymbolRef,
+    pub addend: i64,
+}
+
+/// A decoded entry of `.dynamic`. Entries naming things hold the names, looked up in the table
+/// `DT_STRTAB` points to; the others hold their raw values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynamicEntry {
+    Needed(String),
+    Soname(String),
+    Rpath(String),
+    Runpath(String),
+    Hash(u64),
+    GnuHash(u64),
+    StrTab(u64),
+    SymTab(u64),
+    StrSz(u64),
+    SymEnt(u64),
+    Rela(u64),
+    RelaSz(u64),
+    RelaEnt(u64),
+    RelaCount(u64),
+    JmpRel(u64),
+    PltRelSz(u64),
+    PltRel(u64),
+    PltGot(u64),
+    Init(u64),
+    Fini(u64),
+    InitArray(u64),
+    InitArraySz(u64),
+    FiniArray(u64),
+    FiniArraySz(u64),
+    PreinitArray(u64),
+    PreinitArraySz(u64),
+    Symbolic,
+    TextRel,
+    BindNow,
+    Debug(u64),
+    Flags(u64),
+    Flags1(u64),
+    VerSym(u64),
+    VerDef(u64),
+    VerDefNum(u64),
+    VerNeed(u64),
+    VerNeedNum(u64),
+
+    /// An entry of a type not decoded.
+    Other {
+        tag: i64,
+        value: u64,
+    },
+}
+
+impl DynamicEntry {
+    /// Decodes an entry, looking names up with `string`.
+    pub fn new(
+        tag: i64,
+        value: u64,
+        string: impl FnOnce(u64) -> Result<String, String>,
+    ) -> Result<Self, String> {
+        Ok(match tag {
+            DT_NEEDED => Self::Needed(string(value)?),
+            DT_SONAME => Self::Soname(string(value)?),
+            DT_RPATH => Self::Rpath(string(value)?),
+            DT_RUNPATH => Self::Runpath(string(value)?),
+            DT_HASH => Self::Hash(value),
+            DT_GNU_HASH => Self::GnuHash(value),
+            DT_STRTAB => Self::StrTab(value),
+            DT_SYMTAB => Self::SymTab(value),
+            DT_STRSZ => Self::StrSz(value),
+            DT_SYMENT => Self::SymEnt(value),
+            DT_RELA => Self::Rela(value),
+            DT_RELASZ => Self::RelaSz(value),
+            DT_RELAENT => Self::RelaEnt(value),
+            DT_RELACOUNT => Self::RelaCount(value),
+            DT_JMPREL => Self::JmpRel(value),
+            DT_PLTRELSZ => Self::PltRelSz(value),
+            DT_PLTREL => Self::PltRel(value),
+            DT_PLTGOT => Self::PltGot(value),
+            DT_INIT => Self::Init(value),
+            DT_FINI => Self::Fini(value),
+            DT_INIT_ARRAY => Self::InitArray(value),
+            DT_INIT_ARRAYSZ => Self::InitArraySz(value),
+            DT_FINI_ARRAY => Self::FiniArray(value),
+            DT_FINI_ARRAYSZ => Self::FiniArraySz(value),
+            DT_PREINIT_ARRAY => Self::PreinitArray(value),
+            DT_PREINIT_ARRAYSZ => Self::PreinitArraySz(value),
+            DT_SYMBOLIC => Self::Symbolic,
+            DT_TEXTREL => Self::TextRel,
+            DT_BIND_NOW => Self::BindNow,
+            DT_DEBUG => Self::Debug(value),
+            DT_FLAGS => Self::Flags(value),
+            DT_FLAGS_1 => Self::Flags1(value),
+            DT_VERSYM => Self::VerSym(value),
+            DT_VERDEF => Self::VerDef(value),
+            DT_VERDEFNUM => Self::VerDefNum(value),
+            DT_VERNEED => Self::VerNeed(value),
+            DT_VERNEEDNUM => Self::VerNeedNum(value),
+            tag => Self::Other { tag, value },
+        })
+    }
+}
+
+/// The entries of a `.dynamic` section or a `PT_DYNAMIC` segment, in order, up to `DT_NULL`.
+pub struct DynamicIter<'a> {
+    head: &'a [u8],
+    pos: usize,
+
+    /// The string table `DT_STRTAB` points to, if the object has one.
+    strtab: Option<&'a [u8]>,
+}
+
+impl<'a> DynamicIter<'a> {
+    /// Iterates over the entries in `data`, looking names up in `strtab`.
+    pub fn new(data: &'a [u8], strtab: Option<&'a [u8]>) -> Self {
+        Self {
+            head: data,
+            pos: 0,
+            strtab,
+        }
+    }
+
+    /// Returns the value of the first entry with the given tag, which may follow `DT_NULL` only
+    /// if the table is malformed.
+    pub fn value(data: &[u8], tag: i64) -> Option<u64> {
+        data.chunks_exact(DYNAMIC_ENTRY_SIZE as usize)
+            .map(|mut entry| {
+                (
+                    i64::read_le_bytes(&mut entry),
+                    u64::read_le_bytes(&mut entry),
+                )
+            })
+            .take_while(|&(t, _)| t != DT_NULL)
+            .find(|&(t, _)| t == tag)
+            .map(|(_, value)| value)
+    }
+}
+
+impl Iterator for DynamicIter<'_> {
+    type Item = Result<DynamicEntry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = DYNAMIC_ENTRY_SIZE as usize;
+        let mut entry = self.head.get(self.pos..self.pos + size)?;
+        let tag = i64::read_le_bytes(&mut entry);
+        let value = u64::read_le_bytes(&mut entry);
+        if tag == DT_NULL {
+            return None;
+        }
+        self.pos += size;
+
+        let strtab = self.strtab;
+        Some(DynamicEntry::new(tag, value, |offset| {
+            let bytes = strtab
+                .ok_or("the dynamic section has no string table")?
+                .get(offset as usize..)
+                .ok_or_else(|| format!("string offset 0x{:x} is out of the table", offset))?;
+            let len = bytes
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or("a string is not null-terminated")?;
+            String::from_utf8(bytes[..len].to_vec()).map_err(|e| e.to_string())
+        }))
+    }
+}
+
+/// Whether references to a symbol may bind to a definition in another module at load time.
+/// Executables are not position-independent, so only definitions in shared libraries are;
+/// shared libraries must also allow their own default-visibility symbols to be preempted, unless
+/// a version script makes them local or a dynamic list leaves them out.
+pub fn is_preemptible(symbol: &Symbol, shared: bool) -> bool {
+    match symbol.definition {
+        Definition::Shared { .. } => true,
+        Definition::Undefined => shared,
+        Definition::Section { .. } | Definition::Absolute(_) | Definition::Common { .. } => {
+            shared
+                && symbol.visibility == SymbolVisibility::Default
+                && symbol.binding != SymbolBinding::Local
+                && !symbol.bind_local
+        }
+        Definition::Linker(_) => false,
+    }
+}
+
+/// Returns the global symbols the output exports or imports, by id. Shared libraries export their
+/// symbols which are neither hidden nor made local by a version script, and executables those
+/// which shared libraries refer to or a dynamic list names.
+pub fn dynamic_symbols(inputs: &[InputFile], symbols: &SymbolTable, shared: bool) -> Vec<usize> {
+    let mut needed_by_shared = HashSet::new();
+    for (file, input) in inputs.iter().enumerate() {
+        if input.shared.is_none() {
+            continue;
+        }
+        for (index, sym) in input.symbols.iter().enumerate() {
+            if sym.is_undefined() {
+                needed_by_shared.extend(symbols.file_symbols[file][index]);
+            }
+        }
+    }
+
+    (0..symbols.symbols.len())
+        .filter(|id| {
+            let symbol = &symbols.symbols[*id];
+            let visible = matches!(
+                symbol.visibility,
+                SymbolVisibility::Default | SymbolVisibility::Protected
+            ) && symbol.binding != SymbolBinding::Local;
+            match symbol.definition {
+                Definition::Shared { .. } => symbol.referenced,
+                Definition::Undefined => shared && symbol.referenced,
+                Definition::Section { .. }
+                | Definition::Absolute(_)
+                | Definition::Common { .. } => {
+                    visible && (shared || symbol.export || needed_by_shared.contains(id))
+                }
+                Definition::Linker(_) => false,
+            }
+        })
+        .collect()
+}
+
+/// Applies dynamic lists to the global symbols object files define: executables export the
+/// symbols they list, and shared libraries bind references to the symbols they leave out to their
+/// own definitions. Warns about patterns which match no symbol.
+pub fn apply_dynamic_list(symbols: &mut SymbolTable, patterns: &[SymbolPattern], shared: bool) {
+    let defined = version::defined_symbols(symbols, patterns.iter().any(|p| p.cxx));
+    let mut used = vec![false; patterns.len()];
+    for (id, demangled) in &defined {
+        let symbol = &mut symbols.symbols[*id];
+        let mut listed = false;
+        for (pattern, used) in patterns.iter().zip(&mut used) {
+            if pattern.matches(&symbol.name, demangled.as_deref()) {
+                listed = true;
+                *used = true;
+            }
+        }
+        if shared {
+            symbol.bind_local = !listed;
+        } else {
+            symbol.export = listed;
+        }
+    }
+
+    for (pattern, used) in patterns.iter().zip(used) {
+        if !used && pattern.pattern != "*" {
+            eprintln!(
+                "warning: pattern `{}` of the dynamic list matches no symbol",
+                pattern.pattern
+            );
+        }
+    }
+}
+
+/// What the output tells the loader.
+#[derive(Debug, Default)]
+pub struct Dynamic {
+    /// Whether the output is a shared library rather than an executable.
+    pub shared: bool,
+
+    /// Whether the loader should bind all symbols at load time, set by `-z now`.
+    pub bind_now: bool,
+
+    /// The loader named in `PT_INTERP`, for executables.
+    pub interp: Option<String>,
+
+    /// The global symbols in `.dynsym` after the null entry, by id.
+    pub symbols: Vec<usize>,
+    symbol_index: HashMap<usize, u32>,
+
+    /// `.dynstr`.
+    pub strtab: StringTable,
+
+    /// The contents of `.hash` and `.gnu.hash`, empty for the tables `--hash-style` leaves out.
+    pub hash_data: Vec<u8>,
+    pub gnu_hash_data: Vec<u8>,
+
+    /// The names of the libraries needed, as recorded in `DT_NEEDED`.
+    pub needed: Vec<String>,
+    pub soname: Option<String>,
+
+    /// The directories given by `-rpath`, joined by `:`, and whether they are recorded in
+    /// `DT_RUNPATH` rather than `DT_RPATH`.
+    pub rpath: Option<String>,
+    pub runpath: bool,
+
+    /// The version indexes of the entries of `.dynsym`, including the null entry, if the output
+    /// uses versions.
+    pub versyms: Vec<u16>,
+    pub verdefs: Vec<VersionDefinition>,
+    pub verneeds: Vec<VersionNeed>,
+
+    /// The contents of `.gnu.version_d` and `.gnu.version_r`.
+    pub verdef_data: Vec<u8>,
+    pub verneed_data: Vec<u8>,
+
+    /// The relocations in `.rela.dyn`.
+    pub relocs: Vec<DynamicReloc>,
+
+    /// The global symbols called through the procedure linkage table, in the order of the
+    /// entries.
+    pub plt: Vec<usize>,
+    plt_index: HashMap<usize, usize>,
+
+    /// The symbols of shared libraries copied into an executable, and the copy each of them and
+    /// the other symbols at the same address in the library, its aliases, are defined by.
+    pub copies: Vec<CopyReloc>,
+    copy_index: HashMap<usize, usize>,
+
+    /// The functions of shared libraries whose address an executable takes, whose PLT entries
+    /// become their addresses in every module.
+    pub canonical_plt: HashSet<usize>,
+
+    /// The types of the init and fini arrays the inputs have, for their `.dynamic` entries.
+    pub arrays: Vec<SectionType>,
+
+    /// Whether `_init` and `_fini` are defined, for `DT_INIT` and `DT_FINI`.
+    pub init: bool,
+    pub fini: bool,
+}
+
+impl Dynamic {
+    /// Decides what the output tells the loader, or returns `None` for a static executable.
+    /// `versions` are the nodes of the version scripts, and `plt`, `relocs` and `addressed` the
+    /// PLT entries, the dynamic relocations and the symbols of shared libraries referred to by
+    /// address the relocations of the inputs need.
+    pub fn new(
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        options: &Options,
+        versions: &[VersionNode],
+        mut plt: Vec<usize>,
+        mut relocs: Vec<DynamicReloc>,
+        addressed: &[usize],
+    ) -> Option<Self> {
+        let shared = options.shared;
+        if !shared && inputs.iter().all(|i| i.shared.is_none()) {
+            return None;
+        }
+
+        let (copies, copy_index, canonical_plt) = copy_relocs(inputs, symbols, addressed);
+        for &id in addressed {
+            if canonical_plt.contains(&id) && !plt.contains(&id) {
+                plt.push(id);
+            }
+        }
+        for (i, copy) in copies.iter().enumerate() {
+            relocs.push(DynamicReloc {
+                ty: RelocationType::Copy,
+                place: DynamicPlace::Copy(i),
+                symbol: SymbolRef::Global(copy.symbol),
+                addend: 0,
+            });
+        }
+
+        let mut ids = dynamic_symbols(inputs, symbols, shared);
+        // Aliases of copied symbols are defined here too, so the library binds to the copy.
+        ids.extend(copy_index.keys());
+        // Symbols the relocations refer to are in `.dynsym` whatever they are.
+        for reloc in &relocs {
+            if let (SymbolRef::Global(id), false) = (
+                reloc.symbol,
+                matches!(
+                    reloc.ty,
+                    RelocationType::Relative | RelocationType::IRelative
+                ),
+            ) {
+                ids.push(id);
+            }
+        }
+        ids.extend(&plt);
+        ids.sort_unstable();
+        ids.dedup();
+        // `.gnu.hash` covers the symbols defined here, which come last, sorted by bucket. Copied
+        // symbols and canonical PLT entries are looked up here too.
+        let (imported, mut defined): (Vec<usize>, Vec<usize>) = ids.iter().partition(|&&id| {
+            matches!(
+                symbols.symbols[id].definition,
+                Definition::Undefined | Definition::Shared { .. }
+            ) && !copy_index.contains_key(&id)
+                && !canonical_plt.contains(&id)
+        });
+        if options.hash_style.gnu() {
+            let buckets = hash::gnu_bucket_count(defined.len());
+            defined.sort_by_key(|&id| hash::gnu_hash(dynamic_name(&symbols.symbols[id])) % buckets);
+        }
+        let hashed = imported.len() + 1;
+        ids = imported;
+        ids.extend(defined);
+
+        let mut dynamic = Self {
+            shared,
+            bind_now: options.now,
+            interp: (!shared).then(|| {
+                options
+                    .dynamic_linker
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_DYNAMIC_LINKER.into())
+            }),
+            soname: options.soname.clone(),
+            rpath: (!options.rpath.is_empty()).then(|| {
+                let mut dirs: Vec<&str> = vec![];
+                for dir in options.rpath.iter().flat_map(|p| p.split(':')) {
+                    if !dirs.contains(&dir) {
+                        dirs.push(dir);
+                    }
+                }
+                dirs.join(":")
+            }),
+            runpath: options.new_dtags,
+            init: is_defined_in_object(symbols, "_init"),
+            fini: is_defined_in_object(symbols, "_fini"),
+            ..Default::default()
+        };
+
+        // Libraries given with `--as-needed` are needed only if they define a symbol referred to.
+        let used: HashSet<usize> = symbols
+            .symbols
+            .iter()
+            .filter(|s| s.referenced)
+            .filter_map(|s| match s.definition {
+                Definition::Shared { file, .. } => Some(file),
+                _ => None,
+            })
+            .collect();
+        for (file, input) in inputs.iter().enumerate() {
+            if let Some(library) = &input.shared {
+                if library.as_needed && !used.contains(&file) {
+                    continue;
+                }
+                if !dynamic.needed.contains(&library.soname) {
+                    dynamic.needed.push(library.soname.clone());
+                }
+            }
+        }
+        for ty in [
+            SectionType::PreinitArray,
+            SectionType::InitArray,
+            SectionType::FiniArray,
+        ] {
+            // Only executables may have preinit arrays.
+            if (ty != SectionType::PreinitArray || !shared)
+                && candidates(inputs).any(|id| inputs[id.file].sections[id.index].ty == ty)
+            {
+                dynamic.arrays.push(ty);
+            }
+        }
+
+        for name in &dynamic.needed {
+            dynamic.strtab.add(name);
+        }
+        if let Some(soname) = &dynamic.soname {
+            dynamic.strtab.add(soname);
+        }
+        if let Some(rpath) = &dynamic.rpath {
+            dynamic.strtab.add(rpath);
+        }
+        for (i, &id) in ids.iter().enumerate() {
+            dynamic.strtab.add(dynamic_name(&symbols.symbols[id]));
+            dynamic.symbol_index.insert(id, i as u32 + 1);
+        }
+        let names: Vec<&str> = std::iter::once("")
+            .chain(ids.iter().map(|&id| dynamic_name(&symbols.symbols[id])))
+            .collect();
+        if options.hash_style.sysv() {
+            dynamic.hash_data = hash::build_sysv(&names);
+        }
+        if options.hash_style.gnu() {
+            dynamic.gnu_hash_data = hash::build_gnu(&names[hashed..], hashed);
+        }
+        dynamic.symbols = ids;
+
+        dynamic.assign_versions(inputs, symbols, options, versions);
+
+        dynamic.plt_index = plt.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        dynamic.plt = plt;
+        dynamic.relocs = relocs;
+        dynamic.copies = copies;
+        dynamic.copy_index = copy_index;
+        dynamic.canonical_plt = canonical_plt;
+        Some(dynamic)
+    }
+
+    /// Returns the copy a symbol of a shared library is defined by in an executable, if any.
+    pub fn copy(&self, id: usize) -> Option<&CopyReloc> {
+        self.copy_index.get(&id).map(|&i| &self.copies[i])
+    }
+
+    /// Returns the size and alignment of the copies in `.bss.rel.ro`, if `relro`, or `.bss`.
+    pub fn copies_size(&self, relro: bool) -> (u64, u64) {
+        self.copies
+            .iter()
+            .filter(|c| c.relro == relro)
+            .fold((0, 1), |(size, align), c| {
+                (size.max(c.offset + c.size), align.max(c.align))
+            })
+    }
+
+    /// Fills the version sections: the versions the output defines, named by version scripts and
+    /// in object files by `foo@@VER` and `foo@VER`, and the versions of the symbols it binds to
+    /// in shared libraries.
+    fn assign_versions(
+        &mut self,
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        options: &Options,
+        versions: &[VersionNode],
+    ) {
+        if self.shared {
+            let named = versions
+                .iter()
+                .filter_map(|node| Some((node.name.as_deref()?, node.parents.as_slice())));
+            let defined = self
+                .symbols
+                .iter()
+                .map(|&id| &symbols.symbols[id])
+                .filter(|s| !matches!(s.definition, Definition::Shared { .. }))
+                .filter_map(|s| Some((s.version.as_deref()?, &[][..])));
+            for (name, parents) in named.chain(defined) {
+                if self.verdefs.iter().all(|d| d.name != name) {
+                    if self.verdefs.is_empty() {
+                        // The first definition names the file itself.
+                        let base = self.soname.clone().unwrap_or_else(|| {
+                            Path::new(&options.output).file_name().map_or_else(
+                                || options.output.clone(),
+                                |n| n.to_string_lossy().into(),
+                            )
+                        });
+                        self.verdefs.push(VersionDefinition {
+                            index: VER_NDX_GLOBAL,
+                            flags: VER_FLG_BASE,
+                            name: base,
+                            parents: vec![],
+                        });
+                    }
+                    self.verdefs.push(VersionDefinition {
+                        index: self.verdefs.len() as u16 + 1,
+                        flags: 0,
+                        name: name.into(),
+                        parents: parents.to_vec(),
+                    });
+                }
+            }
+        }
+
+        let mut next_index = self.verdefs.len().max(1) as u16 + 1;
+        for &id in &self.symbols {
+            let symbol = &symbols.symbols[id];
+            let (Definition::Shared { file, .. }, Some(name)) =
+                (symbol.definition, &symbol.version)
+            else {
+                continue;
+            };
+            let soname = &inputs[file].shared.as_ref().unwrap().soname;
+            let need = match self.verneeds.iter().position(|n| n.file == *soname) {
+                Some(need) => need,
+                None => {
+                    self.verneeds.push(VersionNeed {
+                        file: soname.clone(),
+                        versions: vec![],
+                    });
+                    self.verneeds.len() - 1
+                }
+            };
+            let need = &mut self.verneeds[need];
+            if need.versions.iter().all(|(n, _)| n != name) {
+                need.versions.push((name.clone(), next_index));
+                next_index += 1;
+            }
+        }
+
+        if self.verdefs.is_empty() && self.verneeds.is_empty() {
+            return;
+        }
+        for definition in &self.verdefs {
+            self.strtab.add(&definition.name);
+        }
+        for need in &self.verneeds {
+            self.strtab.add(&need.file);
+            for (name, _) in &need.versions {
+                self.strtab.add(name);
+            }
+        }
+
+        self.versyms = vec![0];
+        for &id in &self.symbols {
+            let symbol = &symbols.symbols[id];
+            let versym = match (&symbol.definition, &symbol.version) {
+                (_, None) => VER_NDX_GLOBAL,
+                (Definition::Shared { file, .. }, Some(name)) => {
+                    let soname = &inputs[*file].shared.as_ref().unwrap().soname;
+                    self.verneeds
+                        .iter()
+                        .filter(|n| n.file == *soname)
+                        .flat_map(|n| &n.versions)
+                        .find(|(n, _)| n == name)
+                        .map_or(VER_NDX_GLOBAL, |&(_, index)| index)
+                }
+                (_, Some(name)) => {
+                    let index = self
+                        .verdefs
+                        .iter()
+                        .find(|d| d.name == *name)
+                        .map_or(VER_NDX_GLOBAL, |d| d.index);
+                    // Symbols named `foo@VER` are not the default version of `foo`.
+                    if symbol.name.contains('@') {
+                        index | VERSYM_HIDDEN
+                    } else {
+                        index
+                    }
+                }
+            };
+            self.versyms.push(versym);
+        }
+
+        let strtab = &mut self.strtab;
+        self.verdef_data = version::build_definitions(&self.verdefs, |name| strtab.add(name));
+        self.verneed_data = version::build_needs(&self.verneeds, |name| strtab.add(name));
+    }
+
+    /// Returns the index of the entry of a global symbol in `.dynsym`.
+    pub fn symbol_index(&self, id: usize) -> Option<u32> {
+        self.symbol_index.get(&id).copied()
+    }
+
+    /// Returns the index of the PLT entry of a global symbol, counting from the first entry after
+    /// the header.
+    pub fn plt_index(&self, id: usize) -> Option<usize> {
+        self.plt_index.get(&id).copied()
+    }
+
+    /// Returns the tags of the entries of `.dynamic`, in order. Their values are known after
+    /// layout; the number of entries is known before.
+    pub fn tags(&self) -> Vec<i64> {
+        let mut tags = vec![DT_NEEDED; self.needed.len()];
+        if self.soname.is_some() {
+            tags.push(DT_SONAME);
+        }
+        if self.rpath.is_some() {
+            tags.push(if self.runpath { DT_RUNPATH } else { DT_RPATH });
+        }
+        if !self.hash_data.is_empty() {
+            tags.push(DT_HASH);
+        }
+        if !self.gnu_hash_data.is_empty() {
+            tags.push(DT_GNU_HASH);
+        }
+        tags.extend([DT_STRTAB, DT_SYMTAB, DT_STRSZ, DT_SYMENT]);
+        if !self.relocs.is_empty() {
+            tags.extend([DT_RELA, DT_RELASZ, DT_RELAENT]);
+        }
+        if !self.plt.is_empty() {
+            tags.extend([DT_JMPREL, DT_PLTRELSZ, DT_PLTREL, DT_PLTGOT]);
+        }
+        if !self.versyms.is_empty() {
+            tags.push(DT_VERSYM);
+        }
+        if !self.verdefs.is_empty() {
+            tags.extend([DT_VERDEF, DT_VERDEFNUM]);
+        }
+        if !self.verneeds.is_empty() {
+            tags.extend([DT_VERNEED, DT_VERNEEDNUM]);
+        }
+        if self.init {
+            tags.push(DT_INIT);
+        }
+        if self.fini {
+            tags.push(DT_FINI);
+        }
+        for ty in &self.arrays {
+            tags.extend(match ty {
+                SectionType::PreinitArray => [DT_PREINIT_ARRAY, DT_PREINIT_ARRAYSZ],
+                SectionType::InitArray => [DT_INIT_ARRAY, DT_INIT_ARRAYSZ],
+                _ => [DT_FINI_ARRAY, DT_FINI_ARRAYSZ],
+            });
+        }
+        if !self.shared {
+            tags.push(DT_DEBUG);
+        }
+        if self.bind_now {
+            tags.extend([DT_FLAGS, DT_FLAGS_1]);
+        }
+        tags.push(DT_NULL);
+        tags
+    }
+
+    /// Builds `.dynamic`.
+    pub fn section(&self, inputs: &[InputFile], symbols: &SymbolTable, layout: &Layout) -> Vec<u8> {
+        let address = |f: fn(&Content) -> bool| layout.content_address(f).unwrap_or(0);
+        let symbol = |name: &str| {
+            symbols
+                .get(name)
+                .and_then(|id| layout.symbol_address(inputs, symbols, SymbolRef::Global(id)))
+                .unwrap_or(0)
+        };
+        let array = |ty: SectionType| {
+            layout
+                .sections
+                .iter()
+                .find(|s| s.is_alloc() && s.ty == ty)
+                .map_or((0, 0), |s| (s.addr, s.size))
+        };
+
+        let mut needed = self.needed.iter();
+        let mut data = vec![];
+        for tag in self.tags() {
+            let value = match tag {
+                DT_NEEDED => self.strtab.offset(needed.next().unwrap()) as u64,
+                DT_SONAME => self.strtab.offset(self.soname.as_deref().unwrap()) as u64,
+                DT_RPATH | DT_RUNPATH => self.strtab.offset(self.rpath.as_deref().unwrap()) as u64,
+                DT_HASH => address(|c| matches!(c, Content::Hash)),
+                DT_GNU_HASH => address(|c| matches!(c, Content::GnuHash)),
+                DT_STRTAB => address(|c| matches!(c, Content::DynStr)),
+                DT_SYMTAB => address(|c| matches!(c, Content::DynSym)),
+                DT_STRSZ => self.strtab.data.len() as u64,
+                DT_SYMENT => SYMBOL_SIZE,
+                DT_RELA => address(|c| matches!(c, Content::RelaDyn)),
+                DT_RELASZ => RELA_SIZE * self.relocs.len() as u64,
+                DT_RELAENT => RELA_SIZE,
+                DT_JMPREL => address(|c| matches!(c, Content::RelaPlt)),
+                DT_PLTRELSZ => RELA_SIZE * self.plt.len() as u64,
+                DT_PLTREL => DT_RELA as u64,
+                DT_PLTGOT => address(|c| matches!(c, Content::GotPlt)),
+                DT_VERSYM => address(|c| matches!(c, Content::VerSym)),
+                DT_VERDEF => address(|c| matches!(c, Content::VerDef)),
+                DT_VERDEFNUM => self.verdefs.len() as u64,
+                DT_VERNEED => address(|c| matches!(c, Content::VerNeed)),
+                DT_VERNEEDNUM => self.verneeds.len() as u64,
+                DT_INIT => symbol("_init"),
+                DT_FINI => symbol("_fini"),
+                DT_PREINIT_ARRAY => array(SectionType::PreinitArray).0,
+                DT_PREINIT_ARRAYSZ => array(SectionType::PreinitArray).1,
+                DT_INIT_ARRAY => array(SectionType::InitArray).0,
+                DT_INIT_ARRAYSZ => array(SectionType::InitArray).1,
+                DT_FINI_ARRAY => array(SectionType::FiniArray).0,
+                DT_FINI_ARRAYSZ => array(SectionType::FiniArray).1,
+                DT_FLAGS => DF_BIND_NOW,
+                DT_FLAGS_1 => DF_1_NOW,
+                _ => 0,
+            };
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    /// Builds `.dynsym`.
+    pub fn symtab(&self, symbols: &SymbolTable, layout: &Layout) -> Vec<u8> {
+        let mut data = vec![0; SYMBOL_SIZE as usize];
+        for &id in &self.symbols {
+            let symbol = &symbols.symbols[id];
+            let (shndx, value) = layout
+                .symbol_entry(symbols, id)
+                .unwrap_or((SECTION_HEADER_NUMBER_UNDEF, 0));
+            Elf64Symbol {
+                name: self.strtab.offset(dynamic_name(symbol)),
+                binding: symbol.binding,
+                ty: symbol.ty,
+                visibility: symbol.visibility,
+                shndx,
+                value,
+                size: symbol.size,
+            }
+            .write(&mut data);
+        }
+        data
+    }
+
+    /// Builds `.gnu.version`.
+    pub fn versym_data(&self) -> Vec<u8> {
+        self.versyms.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// Builds `.rela.dyn`.
+    pub fn rela_dyn(
+        &self,
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        layout: &Layout,
+    ) -> Vec<u8> {
+        let mut data = vec![];
+        for reloc in &self.relocs {
+            let offset = match reloc.place {
+                DynamicPlace::Input { id, offset } => layout.input_address(id, offset),
+                DynamicPlace::Got(index) => layout.got_address().map(|got| got + 8 * index as u64),
+                DynamicPlace::Copy(index) => layout.copy_address(&self.copies[index]),
+                DynamicPlace::IpltSlot(index) => layout.iplt_slot_address(index),
+            };
+            let (sym, addend) = if reloc.ty == RelocationType::IRelative {
+                // The loader calls the resolver at the addend, relocated like `Relative`.
+                let resolver = layout.definition_address(inputs, symbols, reloc.symbol);
+                (0, resolver.unwrap_or(0) as i64)
+            } else if reloc.ty == RelocationType::Relative {
+                // The loader adds the load address to the address the symbol has here. Section
+                // symbols of merged sections refer to the piece at the addend.
+                let target = match reloc.symbol {
+                    SymbolRef::Local { file, index }
+                        if inputs[file].symbols[index].ty == SymbolType::Section =>
+                    {
+                        let sym = &inputs[file].symbols[index];
+                        let id = SectionId {
+                            file,
+                            index: sym.shndx as usize,
+                        };
+                        layout.input_address(id, sym.value.wrapping_add(reloc.addend as u64))
+                    }
+                    symbol => layout
+                        .symbol_address(inputs, symbols, symbol)
+                        .map(|s| s.wrapping_add(reloc.addend as u64)),
+                };
+                (0, target.unwrap_or(0) as i64)
+            } else {
+                let index = match reloc.symbol {
+                    SymbolRef::Global(id) => self.symbol_index(id).unwrap_or(0),
+                    SymbolRef::Local { .. } => 0,
+                };
+                (index, reloc.addend)
+            };
+            Elf64Rela {
+                offset: offset.unwrap_or(0),
+                sym,
+                ty: reloc.ty as u32,
+                addend,
+            }
+            .write(&mut data);
+        }
+        data
+    }
+
+    /// Builds `.rela.plt`, which has a relocation filling the `.got.plt` slot of each PLT entry.
+    pub fn rela_plt(&self, layout: &Layout) -> Vec<u8> {
+        let mut data = vec![];
+        for (i, &id) in self.plt.iter().enumerate() {
+            Elf64Rela {
+                offset: layout.got_plt_slot_address(i).unwrap_or(0),
+                sym: self.symbol_index(id).unwrap_or(0),
+                ty: RelocationType::JumpSlot as u32,
+                addend: 0,
+            }
+            .write(&mut data);
+        }
+        data
+    }
+
+    /// Builds `.plt`. The header pushes the second reserved word of `.got.plt` and jumps to the
+    /// resolver in the third. Each entry jumps through its slot, which initially points back to
+    /// the entry's `push` of its index, followed by a jump to the header.
+    pub fn plt_data(&self, layout: &Layout) -> Vec<u8> {
+        let plt = layout
+            .content_address(|c| matches!(c, Content::Plt))
+            .unwrap_or(0);
+        let got_plt = layout
+            .content_address(|c| matches!(c, Content::GotPlt))
+            .unwrap_or(0);
+        let rel32 = |target: u64, next: u64| (target.wrapping_sub(next) as u32).to_le_bytes();
+
+        let mut data = vec![];
+        data.extend_from_slice(&[0xff, 0x35]);
+        data.extend_from_slice(&rel32(got_plt + 8, plt + 6));
+        data.extend_from_slice(&[0xff, 0x25]);
+        data.extend_from_slice(&rel32(got_plt + 16, plt + 12));
+        data.extend_from_slice(&[0x0f, 0x1f, 0x40, 0x00]);
+        for i in 0..self.plt.len() {
+            let entry = plt + PLT_ENTRY_SIZE * (i as u64 + 1);
+            let slot = layout.got_plt_slot_address(i).unwrap_or(0);
+            data.extend_from_slice(&[0xff, 0x25]);
+            data.extend_from_slice(&rel32(slot, entry + 6));
+            data.push(0x68);
+            data.extend_from_slice(&(i as u32).to_le_bytes());
+            data.push(0xe9);
+            data.extend_from_slice(&rel32(plt, entry + 16));
+        }
+        data
+    }
+
+    /// Builds `.got.plt`: the address of `.dynamic`, two words for the loader, then the slots of
+    /// the PLT entries.
+    pub fn got_plt_data(&self, layout: &Layout) -> Vec<u8> {
+        let dynamic = layout
+            .content_address(|c| matches!(c, Content::Dynamic))
+            .unwrap_or(0);
+        let plt = layout
+            .content_address(|c| matches!(c, Content::Plt))
+            .unwrap_or(0);
+        let mut data = vec![];
+        data.extend_from_slice(&dynamic.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        for i in 0..self.plt.len() {
+            let push = plt + PLT_ENTRY_SIZE * (i as u64 + 1) + 6;
+            data.extend_from_slice(&push.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// Builds `.iplt`. Each entry jumps through its slot, which holds the address the resolver of
+/// its indirect function returned.
+pub fn iplt_data(layout: &Layout) -> Vec<u8> {
+    let iplt = layout
+        .content_address(|c| matches!(c, Content::Iplt))
+        .unwrap_or(0);
+    let mut data = vec![];
+    for i in 0..layout.iplt.len() {
+        let entry = iplt + PLT_ENTRY_SIZE * i as u64;
+        let slot = layout.iplt_slot_address(i).unwrap_or(0);
+        data.extend_from_slice(&[0xff, 0x25]);
+        data.extend_from_slice(&(slot.wrapping_sub(entry + 6) as u32).to_le_bytes());
+        data.extend_from_slice(&[0xcc; PLT_ENTRY_SIZE as usize - 6]);
+    }
+    data
+}
+
+/// Builds `.rela.iplt`, the `R_X86_64_IRELATIVE` relocations filling the slots of the IPLT
+/// entries of a static executable. The C library applies them at startup, finding them between
+/// `__rela_iplt_start` and `__rela_iplt_end`.
+pub fn rela_iplt(inputs: &[InputFile], symbols: &SymbolTable, layout: &Layout) -> Vec<u8> {
+    let mut data = vec![];
+    for (i, &symbol) in layout.iplt.iter().enumerate() {
+        let resolver = layout.definition_address(inputs, symbols, symbol);
+        Elf64Rela {
+            offset: layout.iplt_slot_address(i).unwrap_or(0),
+            sym: 0,
+            ty: RelocationType::IRelative as u32,
+            addend: resolver.unwrap_or(0) as i64,
+        }
+        .write(&mut data);
+    }
+    data
+}
+
+/// The name of a symbol in `.dynsym`, without the version.
+pub fn dynamic_name(symbol: &Symbol) -> &str {
+    version::split_name(&symbol.name).0
+}
+
+fn is_defined_in_object(symbols: &SymbolTable, name: &str) -> bool {
+    symbols.symbol(name).is_some_and(|s| {
+        matches!(
+            s.definition,
+            Definition::Section { .. } | Definition::Common { .. }
+        ) && s.binding != SymbolBinding::Local
+    })
+}
+
+/// Decides the copies of the symbols of shared libraries an executable refers to by address, and
+/// which of them are functions getting a canonical PLT entry instead. Copies are aligned as the
+/// symbols are in the libraries, and are defined by the aliases of the symbols too, by id.
+fn copy_relocs(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    addressed: &[usize],
+) -> (Vec<CopyReloc>, HashMap<usize, usize>, HashSet<usize>) {
+    let mut copies: Vec<CopyReloc> = vec![];
+    let mut copy_index = HashMap::new();
+    let mut canonical_plt = HashSet::new();
+    for &id in addressed {
+        let symbol = &symbols.symbols[id];
+        let Definition::Shared { file, index } = symbol.definition else {
+            continue;
+        };
+        if copy_index.contains_key(&id) {
+            continue;
+        }
+        if matches!(symbol.ty, SymbolType::Func | SymbolType::GnuIfunc) {
+            canonical_plt.insert(id);
+            continue;
+        }
+
+        let input = &inputs[file];
+        let sym = &input.symbols[index];
+        let section = input.sections.get(sym.shndx as usize);
+        let relro = section.is_some_and(|header| {
+            !header.flags.contains(SectionFlag64::WRITE)
+                || input.section_names[sym.shndx as usize].starts_with(".data.rel.ro")
+        });
+        // A symbol is aligned as its section, unless its address shows it is less so.
+        let mut align = section.map_or(1, |header| header.addralign.max(1));
+        if sym.value != 0 {
+            align = align.min(1 << sym.value.trailing_zeros());
+        }
+        let end = copies
+            .iter()
+            .filter(|c| c.relro == relro)
+            .map(|c| c.offset + c.size)
+            .max()
+            .unwrap_or(0);
+        copies.push(CopyReloc {
+            symbol: id,
+            relro,
+            offset: align_up(end, align),
+            size: sym.size,
+            align,
+        });
+
+        let copy = copies.len() - 1;
+        for (alias, other) in input.symbols.iter().enumerate() {
+            if other.is_undefined() || other.shndx != sym.shndx || other.value != sym.value {
+                continue;
+            }
+            let Some(alias_id) = symbols.file_symbols[file][alias] else {
+                continue;
+            };
+            if symbols.symbols[alias_id].definition == (Definition::Shared { file, index: alias }) {
+                copy_index.insert(alias_id, copy);
+            }
+        }
+        copy_index.insert(id, copy);
+    }
+    (copies, copy_index, canonical_plt)
+}
@@ -0,0 +1,299 @@
+//! Parsing and combining of `.eh_frame` sections, and the `.eh_frame_hdr` search table.
+//!
+//! An `.eh_frame` section is a sequence of records: CIEs holding what the frames of many functions
+//! share, and FDEs describing the frame of one function each and pointing back to their CIE. The
+//! records of all inputs are combined into one output section, dropping the FDEs of functions which
+//! are not part of the output and sharing identical CIEs, and ended by a zero terminator. Records
+//! move independently, so offsets in the input sections are translated record by record.
+
+use std::collections::HashMap;
+
+use crate::{
+    elf::SECTION_HEADER_NUMBER_LORESERVE,
+    inputs::{InputFile, SectionId},
+    layout::{candidates, Layout},
+    symbols::{SymbolRef, SymbolTable},
+};
+
+/// DW_EH_PE_pcrel | DW_EH_PE_sdata4
+const ENCODING_PCREL_SDATA4: u8 = 0x1B;
+
+/// DW_EH_PE_udata4
+const ENCODING_UDATA4: u8 = 0x03;
+
+/// DW_EH_PE_datarel | DW_EH_PE_sdata4
+const ENCODING_DATAREL_SDATA4: u8 = 0x3B;
+
+/// A CIE or FDE of an `.eh_frame` section.
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    /// The offset of the record in the section.
+    pub offset: u64,
+
+    /// The size of the record including its length field.
+    pub size: u64,
+
+    /// For an FDE, the offset of its CIE in the section.
+    pub cie: Option<u64>,
+}
+
+/// Splits the contents of an `.eh_frame` section into records. A record of length 0 ends the
+/// section.
+pub fn parse(data: &[u8]) -> Result<Vec<Record>, String> {
+    let word = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .ok_or_else(|| format!("truncated .eh_frame record at 0x{:x}", offset))
+    };
+
+    let mut records = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let length = word(offset)?;
+        if length == 0 {
+            break;
+        }
+        if length == 0xFFFF_FFFF {
+            return Err(format!(
+                "64-bit .eh_frame record at 0x{:x} is not supported",
+                offset
+            ));
+        }
+        let size = 4 + length as usize;
+        if offset + size > data.len() || length < 4 {
+            return Err(format!("truncated .eh_frame record at 0x{:x}", offset));
+        }
+
+        // The CIE pointer of an FDE is the distance back to its CIE from the pointer itself.
+        let id = word(offset + 4)?;
+        let cie = if id == 0 {
+            None
+        } else {
+            let cie = (offset + 4)
+                .checked_sub(id as usize)
+                .ok_or_else(|| format!(".eh_frame record at 0x{:x} has no CIE", offset))?;
+            Some(cie as u64)
+        };
+        records.push(Record {
+            offset: offset as u64,
+            size: size as u64,
+            cie,
+        });
+        offset += size;
+    }
+    Ok(records)
+}
+
+/// An FDE of the output, for the search table.
+#[derive(Debug)]
+struct Fde {
+    /// The offset of the FDE in the output section.
+    offset: u64,
+
+    /// The start of the function, as the symbol and addend of the `pc_begin` relocation.
+    function: SymbolRef,
+    addend: i64,
+}
+
+/// The output `.eh_frame`, built from the input sections named `.eh_frame`.
+#[derive(Debug, Default)]
+pub struct EhFrame {
+    /// The records kept, with their relocations not yet applied.
+    pub data: Vec<u8>,
+    pub align: u64,
+
+    /// The input sections combined, in input order.
+    pub members: Vec<SectionId>,
+
+    /// The offset in the member, the size and the offset in `data` of each record of each member.
+    /// Records which are dropped have no offset in `data`.
+    records: HashMap<SectionId, Vec<(u64, u64, Option<u64>)>>,
+
+    fdes: Vec<Fde>,
+}
+
+/// What makes CIEs identical: their contents and the relocation types, addends and targets at each
+/// offset.
+type CieKey<'a> = (&'a [u8], Vec<(u64, u32, i64, SymbolRef)>);
+
+impl EhFrame {
+    pub fn new(inputs: &[InputFile], symbols: &SymbolTable) -> Result<Self, String> {
+        let mut eh_frame = Self {
+            align: 1,
+            ..Default::default()
+        };
+
+        // For each CIE of an input, the first identical CIE. The first is emitted before the first
+        // FDE using it, as the record at the given index of its member.
+        let mut cies: HashMap<CieKey, (SectionId, usize)> = HashMap::new();
+        let mut canonical: HashMap<(SectionId, u64), (SectionId, usize)> = HashMap::new();
+
+        for id in candidates(inputs) {
+            let input = &inputs[id.file];
+            if input.section_names[id.index] != ".eh_frame" {
+                continue;
+            }
+            let header = &input.sections[id.index];
+            let data = input.object.section_data(header)?;
+            let records = parse(data).map_err(|e| format!("{}: {}", input.path, e))?;
+            let mut relocations = input.relocations(id.index)?;
+            relocations.sort_by_key(|r| r.offset);
+
+            eh_frame.members.push(id);
+            eh_frame.align = eh_frame.align.max(header.addralign);
+            eh_frame.records.insert(
+                id,
+                records.iter().map(|r| (r.offset, r.size, None)).collect(),
+            );
+
+            for (index, record) in records.iter().enumerate() {
+                let start = record.offset as usize;
+                let bytes = &data[start..start + record.size as usize];
+                let record_relocations: Vec<_> = relocations
+                    .iter()
+                    .filter(|r| record.offset <= r.offset && r.offset < record.offset + record.size)
+                    .collect();
+
+                let Some(cie_offset) = record.cie else {
+                    let key = (
+                        bytes,
+                        record_relocations
+                            .iter()
+                            .map(|r| {
+                                let symbol = symbols.resolve(id.file, r.sym as usize);
+                                (r.offset - record.offset, r.ty, r.addend, symbol)
+                            })
+                            .collect(),
+                    );
+                    let first = *cies.entry(key).or_insert((id, index));
+                    canonical.insert((id, record.offset), first);
+                    continue;
+                };
+
+                // The first relocation after the CIE pointer is `pc_begin`, which refers to the
+                // function. FDEs of functions which are not part of the output are dropped.
+                let pc_begin = record_relocations
+                    .iter()
+                    .find(|r| r.offset == record.offset + 8);
+                if let Some(rela) = pc_begin {
+                    let sym = &input.symbols[rela.sym as usize];
+                    let regular = sym.shndx != 0 && sym.shndx < SECTION_HEADER_NUMBER_LORESERVE;
+                    if regular && !input.live[sym.shndx as usize] {
+                        continue;
+                    }
+                }
+
+                let &(cie_id, cie_index) = canonical.get(&(id, cie_offset)).ok_or_else(|| {
+                    format!(
+                        "{}: .eh_frame FDE at 0x{:x} refers to no CIE",
+                        input.path, record.offset
+                    )
+                })?;
+                let cie = eh_frame.records[&cie_id][cie_index];
+                let cie_out = match cie.2 {
+                    Some(out) => out,
+                    None => {
+                        let cie_input = &inputs[cie_id.file];
+                        let cie_data = cie_input
+                            .object
+                            .section_data(&cie_input.sections[cie_id.index])?;
+                        let out =
+                            eh_frame.append(&cie_data[cie.0 as usize..(cie.0 + cie.1) as usize]);
+                        eh_frame.records.get_mut(&cie_id).unwrap()[cie_index].2 = Some(out);
+                        out
+                    }
+                };
+
+                let out = eh_frame.append(bytes);
+                let pointer = (out + 4 - cie_out) as u32;
+                eh_frame.data[out as usize + 4..out as usize + 8]
+                    .copy_from_slice(&pointer.to_le_bytes());
+                eh_frame.records.get_mut(&id).unwrap()[index].2 = Some(out);
+                if let Some(rela) = pc_begin {
+                    eh_frame.fdes.push(Fde {
+                        offset: out,
+                        function: symbols.resolve(id.file, rela.sym as usize),
+                        addend: rela.addend,
+                    });
+                }
+            }
+        }
+        if !eh_frame.data.is_empty() {
+            eh_frame.data.extend_from_slice(&[0; 4]);
+        }
+        Ok(eh_frame)
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> u64 {
+        let offset = self.data.len() as u64;
+        self.data.extend_from_slice(bytes);
+        offset
+    }
+
+    /// Whether the output has FDEs, which the search table indexes.
+    pub fn has_fdes(&self) -> bool {
+        !self.fdes.is_empty()
+    }
+
+    /// Whether an input section is a member.
+    pub fn contains(&self, id: SectionId) -> bool {
+        self.records.contains_key(&id)
+    }
+
+    /// Translates an offset in a member to an offset in the output section, or returns `None` if
+    /// the record at the offset is dropped.
+    pub fn offset(&self, id: SectionId, offset: u64) -> Option<u64> {
+        self.records
+            .get(&id)?
+            .iter()
+            .find(|&&(start, size, _)| start <= offset && offset < start + size)
+            .and_then(|&(start, _, out)| Some(out? + (offset - start)))
+    }
+
+    /// The size of `.eh_frame_hdr`.
+    pub fn hdr_size(&self) -> u64 {
+        12 + 8 * self.fdes.len() as u64
+    }
+
+    /// Builds `.eh_frame_hdr` at address `addr`: a pointer to `.eh_frame` and a table of the
+    /// starts of the functions and their FDEs sorted by the former, for the unwinder to search.
+    pub fn hdr(
+        &self,
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        layout: &Layout,
+        addr: u64,
+    ) -> Vec<u8> {
+        let mut data = vec![
+            1,
+            ENCODING_PCREL_SDATA4,
+            ENCODING_UDATA4,
+            ENCODING_DATAREL_SDATA4,
+        ];
+        let Some(eh_frame) = layout.eh_frame_address() else {
+            data.resize(self.hdr_size() as usize, 0);
+            return data;
+        };
+        let relative = |to: u64, from: u64| (to.wrapping_sub(from) as i32).to_le_bytes();
+        data.extend_from_slice(&relative(eh_frame, addr + 4));
+        data.extend_from_slice(&(self.fdes.len() as u32).to_le_bytes());
+
+        let mut table: Vec<_> = self
+            .fdes
+            .iter()
+            .map(|fde| {
+                let function = layout
+                    .symbol_address(inputs, symbols, fde.function)
+                    .unwrap_or(0)
+                    .wrapping_add(fde.addend as u64);
+                (function, eh_frame + fde.offset)
+            })
+            .collect();
+        table.sort();
+        for (function, fde) in table {
+            data.extend_from_slice(&relative(function, addr));
+            data.extend_from_slice(&relative(fde, addr));
+        }
+        data
+    }
+}
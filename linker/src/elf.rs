@@ -9,13 +9,77 @@ use std::mem;
 use bitflags::bitflags;
 use enum_try_from::impl_enum_try_from;
 
-use crate::util::FromBytes as _;
+use crate::util::{FromBytes as _, ToBytes as _};
 
 /// SHN_UNDEF
 ///
 /// Represents undefined section.
 pub const SECTION_HEADER_NUMBER_UNDEF: u16 = 0;
 
+/// SHN_LORESERVE
+///
+/// The lower bound of the range of reserved indexes.
+pub const SECTION_HEADER_NUMBER_LORESERVE: u16 = 0xFF00;
+
+/// SHN_ABS
+///
+/// Specifies absolute values for the corresponding reference. Symbols defined relative to this
+/// section number have absolute values and are not affected by relocation.
+pub const SECTION_HEADER_NUMBER_ABS: u16 = 0xFFF1;
+
+/// SHN_COMMON
+///
+/// Symbols defined relative to this section are common symbols.
+pub const SECTION_HEADER_NUMBER_COMMON: u16 = 0xFFF2;
+
+/// SHN_XINDEX
+///
+/// The actual section header index is too large to fit in the containing field and is to be found
+/// in another location.
+pub const SECTION_HEADER_NUMBER_XINDEX: u16 = 0xFFFF;
+
+/// GRP_COMDAT
+///
+/// The section group is a COMDAT group: of the groups with the same signature, only one is linked.
+pub const GROUP_COMDAT: u32 = 1;
+
+/// NT_GNU_ABI_TAG
+///
+/// The type of the note in `.note.ABI-tag`, giving the OS and the oldest kernel version supported.
+pub const NOTE_GNU_ABI_TAG: u32 = 1;
+
+/// NT_GNU_BUILD_ID
+///
+/// The type of the note in `.note.gnu.build-id`, whose descriptor identifies the build.
+pub const NOTE_GNU_BUILD_ID: u32 = 3;
+
+/// NT_FDO_PACKAGING_METADATA
+///
+/// The type of the note in `.note.package`, whose descriptor is a JSON object describing the
+/// package the file belongs to.
+pub const NOTE_FDO_PACKAGING_METADATA: u32 = 0xCAFE_1A7E;
+
+/// NT_GNU_PROPERTY_TYPE_0
+///
+/// The type of the note in `.note.gnu.property`, whose descriptor is an array of properties.
+pub const NOTE_GNU_PROPERTY_TYPE_0: u32 = 5;
+
+/// GNU_PROPERTY_X86_FEATURE_1_AND
+///
+/// The x86 features an object is compatible with. The features of the output are those of all
+/// inputs.
+pub const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xC000_0002;
+
+/// GNU_PROPERTY_X86_FEATURE_1_IBT
+///
+/// Compatible with indirect branch tracking.
+pub const GNU_PROPERTY_X86_FEATURE_1_IBT: u32 = 1 << 0;
+
+/// GNU_PROPERTY_X86_FEATURE_1_SHSTK
+///
+/// Compatible with shadow stacks.
+pub const GNU_PROPERTY_X86_FEATURE_1_SHSTK: u32 = 1 << 1;
+
 #[derive(Debug, Default, Clone)]
 pub struct Elf64Header {
     /// Marks the file as an object file and provides machine-independent data with which to decode
@@ -103,6 +167,23 @@ impl Elf64Header {
             shstrndx: u16::read_le_bytes(&mut left),
         })
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        self.ident.write(output);
+        (self.ty as u16).write_le_bytes(output);
+        (self.machine as u16).write_le_bytes(output);
+        self.version.write_le_bytes(output);
+        self.entry.write_le_bytes(output);
+        self.phoff.write_le_bytes(output);
+        self.shoff.write_le_bytes(output);
+        self.flags.write_le_bytes(output);
+        self.ehsize.write_le_bytes(output);
+        self.phentsize.write_le_bytes(output);
+        self.phnum.write_le_bytes(output);
+        self.shentsize.write_le_bytes(output);
+        self.shnum.write_le_bytes(output);
+        self.shstrndx.write_le_bytes(output);
+    }
 }
 
 /// The initial bytes of the ELF file.
@@ -174,6 +255,16 @@ impl ElfIdent {
 
         Ok(unsafe { mem::transmute::<[u8; mem::size_of::<Self>()], Self>(bytes) })
     }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.magic);
+        output.push(self.class as u8);
+        output.push(self.data as u8);
+        output.push(self.version as u8);
+        output.push(self.osabi as u8);
+        output.push(self.abi_version);
+        output.extend_from_slice(&self.pad);
+    }
 }
 
 impl Default for ElfIdent {
@@ -265,6 +356,11 @@ impl_enum_try_from! {
         /// HP-UX operating system.
         HpUx = 1,
 
+        /// ELFOSABI_GNU
+        ///
+        /// GNU extensions, such as `SHF_GNU_RETAIN` and `STB_GNU_UNIQUE`.
+        Gnu = 3,
+
         /// ELFOSABI_STANDALONE
         ///
         /// Standalone (embedded) application.
@@ -416,6 +512,21 @@ pub struct Elf64SectionHeader {
     pub entsize: u64,
 }
 
+impl Elf64SectionHeader {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        self.name.write_le_bytes(output);
+        (self.ty as u32).write_le_bytes(output);
+        self.flags.bits().write_le_bytes(output);
+        self.addr.write_le_bytes(output);
+        self.offset.write_le_bytes(output);
+        self.size.write_le_bytes(output);
+        self.link.write_le_bytes(output);
+        self.info.write_le_bytes(output);
+        self.addralign.write_le_bytes(output);
+        self.entsize.write_le_bytes(output);
+    }
+}
+
 impl_enum_try_from! {
     #[repr(u32)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -619,6 +730,11 @@ bitflags! {
         /// Section with compressed data.
         const COMPRESSED = 1 << 11;
 
+        /// SHF_GNU_RETAIN
+        ///
+        /// Section is not to be garbage collected by the linker.
+        const GNU_RETAIN = 1 << 21;
+
         // Makes these bits known. They are reserved for processor-specific semantics.
         const _ = 0xF000_0000;
 
@@ -639,6 +755,19 @@ pub struct Elf64ProgramHeader {
     pub align: u64,
 }
 
+impl Elf64ProgramHeader {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        (self.ty as u32).write_le_bytes(output);
+        self.flags.bits().write_le_bytes(output);
+        self.offset.write_le_bytes(output);
+        self.vaddr.write_le_bytes(output);
+        self.paddr.write_le_bytes(output);
+        self.filesz.write_le_bytes(output);
+        self.memsz.write_le_bytes(output);
+        self.align.write_le_bytes(output);
+    }
+}
+
 impl_enum_try_from! {
     #[repr(u32)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -672,3 +801,330 @@ bitflags! {
         const _ = 0xF000_0000;
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct Elf64Symbol {
+    /// Holds an index into the object file's symbol string table, which holds the character
+    /// representations of the symbol names. If the value is zero, the symbol has no name.
+    pub name: u32,
+
+    /// Specifies the symbol's binding attributes, taken from the high four bits of `st_info`.
+    pub binding: SymbolBinding,
+
+    /// Specifies the symbol's type, taken from the low four bits of `st_info`.
+    pub ty: SymbolType,
+
+    /// Specifies the symbol's visibility, taken from the low two bits of `st_other`.
+    pub visibility: SymbolVisibility,
+
+    /// Holds the section header table index of the section in relation to which the symbol is
+    /// defined, or one of the reserved indexes such as [SECTION_HEADER_NUMBER_ABS].
+    pub shndx: u16,
+
+    /// Gives the value of the associated symbol. In relocatable files this is an offset from the
+    /// beginning of the section identified by `shndx`; in executable and shared object files this
+    /// is a virtual address.
+    pub value: u64,
+
+    /// Holds the size of the data object or function, or zero if the symbol has no size or an
+    /// unknown size.
+    pub size: u64,
+}
+
+impl Elf64Symbol {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        self.name.write_le_bytes(output);
+        output.push(((self.binding as u8) << 4) | self.ty as u8);
+        output.push(self.visibility as u8);
+        self.shndx.write_le_bytes(output);
+        self.value.write_le_bytes(output);
+        self.size.write_le_bytes(output);
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.shndx == SECTION_HEADER_NUMBER_UNDEF
+    }
+}
+
+impl_enum_try_from! {
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum SymbolBinding {
+        /// STB_LOCAL
+        ///
+        /// Local symbols are not visible outside the object file containing their definition.
+        Local = 0,
+
+        /// STB_GLOBAL
+        ///
+        /// Global symbols are visible to all object files being combined.
+        Global = 1,
+
+        /// STB_WEAK
+        ///
+        /// Weak symbols resemble global symbols, but their definitions have lower precedence.
+        Weak = 2,
+
+        /// STB_GNU_UNIQUE
+        ///
+        /// Unique symbol.
+        GnuUnique = 10,
+    },
+    u8,
+    String,
+    "invalid symbol binding".into()
+}
+
+impl_enum_try_from! {
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum SymbolType {
+        /// STT_NOTYPE
+        ///
+        /// The symbol's type is not specified.
+        NoType = 0,
+
+        /// STT_OBJECT
+        ///
+        /// The symbol is associated with a data object, such as a variable, an array, etc.
+        Object = 1,
+
+        /// STT_FUNC
+        ///
+        /// The symbol is associated with a function or other executable code.
+        Func = 2,
+
+        /// STT_SECTION
+        ///
+        /// The symbol is associated with a section.
+        Section = 3,
+
+        /// STT_FILE
+        ///
+        /// Gives the name of the source file associated with the object file.
+        File = 4,
+
+        /// STT_COMMON
+        ///
+        /// The symbol labels an uninitialized common block.
+        Common = 5,
+
+        /// STT_TLS
+        ///
+        /// The symbol specifies a thread-local storage entity.
+        Tls = 6,
+
+        /// STT_GNU_IFUNC
+        ///
+        /// The symbol is an indirect code object.
+        GnuIfunc = 10,
+    },
+    u8,
+    String,
+    "invalid symbol type".into()
+}
+
+impl_enum_try_from! {
+    #[repr(u8)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum SymbolVisibility {
+        /// STV_DEFAULT
+        ///
+        /// The visibility is as specified by the symbol binding type.
+        Default = 0,
+
+        /// STV_INTERNAL
+        ///
+        /// Processor-specific hidden class.
+        Internal = 1,
+
+        /// STV_HIDDEN
+        ///
+        /// The symbol is not visible to other components.
+        Hidden = 2,
+
+        /// STV_PROTECTED
+        ///
+        /// The symbol is visible to other components but not preemptable.
+        Protected = 3,
+    },
+    u8,
+    String,
+    "invalid symbol visibility".into()
+}
+
+#[derive(Debug, Clone)]
+pub struct Elf64Rela {
+    /// Gives the location at which to apply the relocation action. For a relocatable file, the
+    /// value is the byte offset from the beginning of the section to the storage unit affected by
+    /// the relocation.
+    pub offset: u64,
+
+    /// The symbol table index with respect to which the relocation must be made, taken from the
+    /// high 32 bits of `r_info`.
+    pub sym: u32,
+
+    /// The type of relocation to apply, taken from the low 32 bits of `r_info`.
+    pub ty: u32,
+
+    /// Specifies a constant addend used to compute the value to be stored into the relocatable
+    /// field.
+    pub addend: i64,
+}
+
+impl Elf64Rela {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        self.offset.write_le_bytes(output);
+        (((self.sym as u64) << 32) | self.ty as u64).write_le_bytes(output);
+        self.addend.write_le_bytes(output);
+    }
+}
+
+impl_enum_try_from! {
+    #[repr(u32)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum RelocationType {
+        /// R_X86_64_NONE
+        None = 0,
+
+        /// R_X86_64_64
+        ///
+        /// `S + A`
+        Abs64 = 1,
+
+        /// R_X86_64_PC32
+        ///
+        /// `S + A - P`
+        Pc32 = 2,
+
+        /// R_X86_64_GOT32
+        ///
+        /// `G + A`
+        Got32 = 3,
+
+        /// R_X86_64_PLT32
+        ///
+        /// `L + A - P`
+        Plt32 = 4,
+
+        /// R_X86_64_COPY
+        Copy = 5,
+
+        /// R_X86_64_GLOB_DAT
+        ///
+        /// `S`
+        GlobDat = 6,
+
+        /// R_X86_64_JUMP_SLOT
+        ///
+        /// `S`
+        JumpSlot = 7,
+
+        /// R_X86_64_RELATIVE
+        ///
+        /// `B + A`
+        Relative = 8,
+
+        /// R_X86_64_GOTPCREL
+        ///
+        /// `G + GOT + A - P`
+        GotPcRel = 9,
+
+        /// R_X86_64_32
+        ///
+        /// `S + A`, zero-extended.
+        Abs32 = 10,
+
+        /// R_X86_64_32S
+        ///
+        /// `S + A`, sign-extended.
+        Abs32S = 11,
+
+        /// R_X86_64_16
+        ///
+        /// `S + A`
+        Abs16 = 12,
+
+        /// R_X86_64_PC16
+        ///
+        /// `S + A - P`
+        Pc16 = 13,
+
+        /// R_X86_64_8
+        ///
+        /// `S + A`
+        Abs8 = 14,
+
+        /// R_X86_64_PC8
+        ///
+        /// `S + A - P`
+        Pc8 = 15,
+
+        /// R_X86_64_DTPMOD64
+        DtpMod64 = 16,
+
+        /// R_X86_64_DTPOFF64
+        DtpOff64 = 17,
+
+        /// R_X86_64_TPOFF64
+        TpOff64 = 18,
+
+        /// R_X86_64_TLSGD
+        TlsGd = 19,
+
+        /// R_X86_64_TLSLD
+        TlsLd = 20,
+
+        /// R_X86_64_DTPOFF32
+        DtpOff32 = 21,
+
+        /// R_X86_64_GOTTPOFF
+        GotTpOff = 22,
+
+        /// R_X86_64_TPOFF32
+        TpOff32 = 23,
+
+        /// R_X86_64_PC64
+        ///
+        /// `S + A - P`
+        Pc64 = 24,
+
+        /// R_X86_64_GOTOFF64
+        ///
+        /// `S + A - GOT`
+        GotOff64 = 25,
+
+        /// R_X86_64_GOTPC32
+        ///
+        /// `GOT + A - P`
+        GotPc32 = 26,
+
+        /// R_X86_64_SIZE32
+        ///
+        /// `Z + A`
+        Size32 = 32,
+
+        /// R_X86_64_SIZE64
+        ///
+        /// `Z + A`
+        Size64 = 33,
+
+        /// R_X86_64_IRELATIVE
+        ///
+        /// The result of calling the resolver at `B + A`.
+        IRelative = 37,
+
+        /// R_X86_64_GOTPCRELX
+        ///
+        /// `G + GOT + A - P`, relaxable.
+        GotPcRelX = 41,
+
+        /// R_X86_64_REX_GOTPCRELX
+        ///
+        /// `G + GOT + A - P`, relaxable with a REX prefix.
+        RexGotPcRelX = 42,
+    },
+    u32,
+    String,
+    "unsupported relocation type".into()
+}
@@ -0,0 +1,309 @@
+//! Garbage collection of unused sections, enabled by `--gc-sections`.
+//!
+//! A section is live if it is a root or a live section refers to it by a relocation. Roots are the
+//! sections defining the root symbols, sections kept by the linker script or marked
+//! `SHF_GNU_RETAIN`, sections the runtime reaches without relocations such as `.init_array`, and
+//! the personality routines the unwinder reaches through the CIEs of `.eh_frame`. The LSDA of an
+//! FDE is live if the function the FDE describes is.
+//! Non-allocated sections are always kept, but do not keep the sections they refer to alive.
+
+use std::collections::HashMap;
+
+use crate::{
+    eh_frame,
+    elf::{SectionFlag64, SectionType, SECTION_HEADER_NUMBER_LORESERVE},
+    inputs::{InputFile, SectionId},
+    layout::candidates,
+    script::{Command, OutputSectionCommand, Script, SectionsCommand},
+    symbols::{Definition, SymbolRef, SymbolTable},
+};
+
+/// Prefixes of sections the runtime reaches without relocations.
+const RETAINED_PREFIXES: &[&str] = &[
+    ".init",
+    ".fini",
+    ".preinit_array",
+    ".init_array",
+    ".fini_array",
+    ".ctors",
+    ".dtors",
+    ".jcr",
+];
+
+/// Marks the sections unreachable from the roots as dead. `roots` are the names of symbols whose
+/// definitions are roots. Returns the removed sections in input order.
+pub fn collect(
+    inputs: &mut [InputFile],
+    symbols: &SymbolTable,
+    script: Option<&Script>,
+    roots: &[&str],
+) -> Result<Vec<SectionId>, String> {
+    let mut gc = Collector {
+        inputs,
+        symbols,
+        live: inputs
+            .iter()
+            .map(|i| vec![false; i.sections.len()])
+            .collect(),
+        queue: vec![],
+        dependents: HashMap::new(),
+        by_name: HashMap::new(),
+        frame_roots: vec![],
+        frame_dependents: HashMap::new(),
+    };
+    gc.find_dependents()?;
+
+    for id in candidates(inputs) {
+        let input = &inputs[id.file];
+        let header = &input.sections[id.index];
+        if !header.flags.contains(SectionFlag64::ALLOC) || is_eh_frame(input, id.index) {
+            gc.live[id.file][id.index] = true;
+        } else if is_retained(input, id.index) || is_kept(script, input, id.index) {
+            gc.mark(id);
+        }
+    }
+    for name in roots {
+        if let Some(id) = symbols.get(name) {
+            gc.mark_symbol(SymbolRef::Global(id));
+        }
+    }
+    for symbol in std::mem::take(&mut gc.frame_roots) {
+        gc.mark_symbol(symbol);
+    }
+    gc.propagate()?;
+
+    let live = gc.live;
+    let mut removed = vec![];
+    for id in candidates(inputs) {
+        if !live[id.file][id.index] {
+            removed.push(id);
+        }
+    }
+    for id in &removed {
+        inputs[id.file].live[id.index] = false;
+    }
+    Ok(removed)
+}
+
+struct Collector<'a> {
+    inputs: &'a [InputFile],
+    symbols: &'a SymbolTable,
+    live: Vec<Vec<bool>>,
+    queue: Vec<SectionId>,
+
+    /// Sections which are live if the section they are keyed by is: `SHF_LINK_ORDER` sections
+    /// and the other members of a section group.
+    dependents: HashMap<SectionId, Vec<SectionId>>,
+
+    /// Sections by name, for `__start_` and `__stop_` symbols.
+    by_name: HashMap<&'a str, Vec<SectionId>>,
+
+    /// What the CIEs of `.eh_frame` refer to.
+    frame_roots: Vec<SymbolRef>,
+
+    /// What the FDEs of `.eh_frame` refer to besides their functions, keyed by the section of the
+    /// function.
+    frame_dependents: HashMap<SectionId, Vec<SymbolRef>>,
+}
+
+impl Collector<'_> {
+    fn mark(&mut self, id: SectionId) {
+        if !self.live[id.file][id.index] {
+            self.live[id.file][id.index] = true;
+            self.queue.push(id);
+        }
+    }
+
+    fn mark_symbol(&mut self, symbol: SymbolRef) {
+        if let Some(id) = self.section_of(symbol) {
+            self.mark(id);
+            return;
+        }
+
+        // Undefined `__start_SEC` and `__stop_SEC` refer to the output section SEC, so they keep
+        // every input section of that name.
+        if let SymbolRef::Global(id) = symbol {
+            let symbol = &self.symbols.symbols[id];
+            if symbol.is_defined() {
+                return;
+            }
+            let name = &symbol.name;
+            let section = name
+                .strip_prefix("__start_")
+                .or_else(|| name.strip_prefix("__stop_"));
+            if let Some(sections) = section.and_then(|s| self.by_name.get(s)) {
+                for id in sections.clone() {
+                    self.mark(id);
+                }
+            }
+        }
+    }
+
+    /// Returns the input section a symbol is defined in.
+    fn section_of(&self, symbol: SymbolRef) -> Option<SectionId> {
+        match symbol {
+            SymbolRef::Global(id) => match self.symbols.symbols[id].definition {
+                Definition::Section { file, section, .. } => Some(SectionId {
+                    file,
+                    index: section,
+                }),
+                _ => None,
+            },
+            SymbolRef::Local { file, index } => {
+                let shndx = self.inputs[file].symbols[index].shndx;
+                let regular = shndx != 0 && shndx < SECTION_HEADER_NUMBER_LORESERVE;
+                regular.then_some(SectionId {
+                    file,
+                    index: shndx as usize,
+                })
+            }
+        }
+    }
+
+    fn propagate(&mut self) -> Result<(), String> {
+        while let Some(id) = self.queue.pop() {
+            for rela in self.inputs[id.file].relocations(id.index)? {
+                let symbol = self.symbols.resolve(id.file, rela.sym as usize);
+                self.mark_symbol(symbol);
+            }
+            if let Some(dependents) = self.dependents.get(&id) {
+                for dependent in dependents.clone() {
+                    self.mark(dependent);
+                }
+            }
+            if let Some(symbols) = self.frame_dependents.get(&id) {
+                for symbol in symbols.clone() {
+                    self.mark_symbol(symbol);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills `dependents` and `by_name`.
+    fn find_dependents(&mut self) -> Result<(), String> {
+        // The members of a section group are live together: each keeps the next alive.
+        for (file, input) in self.inputs.iter().enumerate() {
+            for index in 0..input.sections.len() {
+                if input.sections[index].ty != SectionType::Group {
+                    continue;
+                }
+                let (_, members) = input
+                    .group(index)
+                    .map_err(|e| format!("{}: {}", input.path, e))?;
+                for (i, &member) in members.iter().enumerate() {
+                    let next = members[(i + 1) % members.len()];
+                    self.dependents
+                        .entry(SectionId {
+                            file,
+                            index: member,
+                        })
+                        .or_default()
+                        .push(SectionId { file, index: next });
+                }
+            }
+        }
+
+        for id in candidates(self.inputs) {
+            let input = &self.inputs[id.file];
+            let header = &input.sections[id.index];
+            self.by_name
+                .entry(input.section_names[id.index].as_str())
+                .or_default()
+                .push(id);
+
+            if header.flags.contains(SectionFlag64::LINK_ORDER) && header.link != 0 {
+                let parent = SectionId {
+                    file: id.file,
+                    index: header.link as usize,
+                };
+                self.dependents.entry(parent).or_default().push(id);
+            }
+
+            if is_eh_frame(input, id.index) {
+                self.find_frame_roots(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds what the CIEs of an `.eh_frame` section refer to to `frame_roots`, and what its FDEs
+    /// refer to other than their functions to `frame_dependents`.
+    fn find_frame_roots(&mut self, id: SectionId) -> Result<(), String> {
+        let input = &self.inputs[id.file];
+        let data = input.object.section_data(&input.sections[id.index])?;
+        let mut relocations = input.relocations(id.index)?;
+        relocations.sort_by_key(|r| r.offset);
+
+        let records = eh_frame::parse(data).map_err(|e| format!("{}: {}", input.path, e))?;
+        for record in &records {
+            let end = record.offset + record.size;
+            let mut targets = relocations
+                .iter()
+                .filter(|r| record.offset + 8 <= r.offset && r.offset < end)
+                .map(|r| self.symbols.resolve(id.file, r.sym as usize));
+            if record.cie.is_none() {
+                self.frame_roots.extend(targets);
+                continue;
+            }
+
+            // The first relocation of an FDE is its `pc_begin`, which refers to the function.
+            let function = targets.next().and_then(|symbol| self.section_of(symbol));
+            match function {
+                Some(function) => self
+                    .frame_dependents
+                    .entry(function)
+                    .or_default()
+                    .extend(targets),
+                None => self.frame_roots.extend(targets),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_eh_frame(input: &InputFile, index: usize) -> bool {
+    input.section_names[index] == ".eh_frame"
+}
+
+fn is_retained(input: &InputFile, index: usize) -> bool {
+    let header = &input.sections[index];
+    let name = &input.section_names[index];
+    header.flags.contains(SectionFlag64::GNU_RETAIN)
+        || header.ty == SectionType::Note
+        || header.ty == SectionType::InitArray
+        || header.ty == SectionType::FiniArray
+        || header.ty == SectionType::PreinitArray
+        || RETAINED_PREFIXES.iter().any(|prefix| {
+            name == prefix || name.starts_with(prefix) && name[prefix.len()..].starts_with('.')
+        })
+}
+
+/// Whether an input section description wrapped in `KEEP` matches the section.
+fn is_kept(script: Option<&Script>, input: &InputFile, index: usize) -> bool {
+    let Some(script) = script else {
+        return false;
+    };
+    script
+        .commands
+        .iter()
+        .filter_map(|c| match c {
+            Command::Sections(commands) => Some(commands),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|c| match c {
+            SectionsCommand::OutputSection(desc) => Some(desc),
+            _ => None,
+        })
+        .flat_map(|desc| &desc.commands)
+        .any(|c| match c {
+            OutputSectionCommand::Input(desc) => {
+                desc.keep
+                    && desc
+                        .matches(&input.path, &input.section_names[index])
+                        .is_some()
+            }
+            _ => false,
+        })
+}
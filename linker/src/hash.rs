@@ -0,0 +1,168 @@
+//! Hash tables of dynamic symbols, which let the loader look symbols up by name without scanning
+//! `.dynsym`.
+//!
+//! `.hash` is the SysV table: buckets of symbol indexes, chained through an array parallel to
+//! `.dynsym`. `.gnu.hash` is the GNU table, which requires the symbols it covers to be at the end
+//! of `.dynsym` sorted by bucket, so that each bucket is a run of symbols. Its chain holds the
+//! hashes themselves, and a bloom filter in front rejects most names the file does not define
+//! without touching the buckets.
+
+use crate::{
+    util::{FromBytes as _, ToBytes as _},
+    version::elf_hash,
+};
+
+/// The shift deriving the second bloom filter bit of a symbol from its hash.
+const BLOOM_SHIFT: u32 = 26;
+
+/// The hash of a name used by GNU hash tables.
+pub fn gnu_hash(name: &str) -> u32 {
+    name.bytes()
+        .fold(5381u32, |h, b| h.wrapping_mul(33).wrapping_add(b as u32))
+}
+
+/// The number of buckets of a GNU hash table covering `count` symbols.
+pub fn gnu_bucket_count(count: usize) -> u32 {
+    (count / 4).max(1) as u32
+}
+
+/// Builds the contents of `.hash` for a symbol table with the given names, including the null
+/// entry.
+pub fn build_sysv(names: &[&str]) -> Vec<u8> {
+    let bucket_count = names.len().max(1);
+    let mut buckets = vec![0u32; bucket_count];
+    let mut chains = vec![0u32; names.len()];
+    // Symbols are pushed onto the front of their bucket's chain; the null entry is in none.
+    for (index, name) in names.iter().enumerate().skip(1).rev() {
+        let bucket = elf_hash(name) as usize % bucket_count;
+        chains[index] = buckets[bucket];
+        buckets[bucket] = index as u32;
+    }
+
+    let mut data = vec![];
+    (bucket_count as u32).write_le_bytes(&mut data);
+    (names.len() as u32).write_le_bytes(&mut data);
+    for word in buckets.iter().chain(&chains) {
+        word.write_le_bytes(&mut data);
+    }
+    data
+}
+
+/// Builds the contents of `.gnu.hash` for the symbols from index `offset` of a symbol table,
+/// whose names are `names` and which are sorted by bucket as [`gnu_bucket_count`] gives the
+/// number of buckets.
+pub fn build_gnu(names: &[&str], offset: usize) -> Vec<u8> {
+    let bucket_count = gnu_bucket_count(names.len());
+    let bloom_size = (names.len() / 32 + 1).next_power_of_two();
+    let hashes: Vec<u32> = names.iter().map(|n| gnu_hash(n)).collect();
+
+    let mut bloom = vec![0u64; bloom_size];
+    let mut buckets = vec![0u32; bucket_count as usize];
+    let mut chain = vec![0u32; names.len()];
+    for (i, &hash) in hashes.iter().enumerate() {
+        let word = (hash / 64) as usize % bloom_size;
+        bloom[word] |= 1 << (hash % 64) | 1 << ((hash >> BLOOM_SHIFT) % 64);
+
+        let bucket = (hash % bucket_count) as usize;
+        if buckets[bucket] == 0 {
+            buckets[bucket] = (offset + i) as u32;
+        }
+        // The low bit marks the last symbol of a bucket.
+        let last = hashes
+            .get(i + 1)
+            .is_none_or(|next| next % bucket_count != hash % bucket_count);
+        chain[i] = (hash & !1) | last as u32;
+    }
+
+    let mut data = vec![];
+    bucket_count.write_le_bytes(&mut data);
+    (offset as u32).write_le_bytes(&mut data);
+    (bloom_size as u32).write_le_bytes(&mut data);
+    BLOOM_SHIFT.write_le_bytes(&mut data);
+    for word in &bloom {
+        word.write_le_bytes(&mut data);
+    }
+    for word in buckets.iter().chain(&chain) {
+        word.write_le_bytes(&mut data);
+    }
+    data
+}
+
+/// Looks `name` up in the contents of a `.hash` section, with `symbol_name` giving the name of a
+/// symbol by index. Returns the index of the symbol.
+pub fn lookup_sysv<'a>(
+    data: &[u8],
+    name: &str,
+    symbol_name: impl Fn(usize) -> Result<&'a str, String>,
+) -> Result<Option<usize>, String> {
+    let bucket_count = word(data, 0)? as usize;
+    let chain_count = word(data, 1)? as usize;
+    if bucket_count == 0 {
+        return Err("a hash table has no buckets".into());
+    }
+
+    let mut index = word(data, 2 + elf_hash(name) as usize % bucket_count)? as usize;
+    // Chains are bounded by the number of symbols, which keeps malformed tables from looping.
+    for _ in 0..chain_count {
+        if index == 0 {
+            break;
+        }
+        if symbol_name(index)? == name {
+            return Ok(Some(index));
+        }
+        index = word(data, 2 + bucket_count + index)? as usize;
+    }
+    Ok(None)
+}
+
+/// Looks `name` up in the contents of a `.gnu.hash` section, with `symbol_name` giving the name
+/// of a symbol by index. Returns the index of the symbol.
+pub fn lookup_gnu<'a>(
+    data: &[u8],
+    name: &str,
+    symbol_name: impl Fn(usize) -> Result<&'a str, String>,
+) -> Result<Option<usize>, String> {
+    let bucket_count = word(data, 0)? as usize;
+    let offset = word(data, 1)? as usize;
+    let bloom_size = word(data, 2)? as usize;
+    let shift = word(data, 3)?;
+    if bucket_count == 0 || bloom_size == 0 {
+        return Err("a GNU hash table has no buckets".into());
+    }
+
+    let hash = gnu_hash(name);
+    let bloom = 4 + 2 * ((hash / 64) as usize % bloom_size);
+    let bloom = (word(data, bloom + 1)? as u64) << 32 | word(data, bloom)? as u64;
+    let mask = 1 << (hash % 64) | 1 << ((hash >> shift) % 64);
+    if bloom & mask != mask {
+        return Ok(None);
+    }
+
+    let buckets = 4 + 2 * bloom_size;
+    let chain = buckets + bucket_count;
+    let mut index = word(data, buckets + hash as usize % bucket_count)? as usize;
+    if index == 0 {
+        return Ok(None);
+    }
+    if index < offset {
+        return Err("a GNU hash bucket is out of the table".into());
+    }
+    loop {
+        let entry = word(data, chain + index - offset)?;
+        if entry | 1 == hash | 1 && symbol_name(index)? == name {
+            return Ok(Some(index));
+        }
+        if entry & 1 != 0 {
+            return Ok(None);
+        }
+        index += 1;
+    }
+}
+
+/// Returns the 32-bit word at `index` of a hash table.
+fn word(data: &[u8], index: usize) -> Result<u32, String> {
+    let mut bytes = data
+        .get(4 * index..4 * index + 4)
+        .ok_or("truncated hash table")?;
+    Ok(u32::read_le_bytes(&mut bytes))
+}
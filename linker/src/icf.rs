@@ -0,0 +1,271 @@
+//! Identical code folding, enabled by `--icf`.
+//!
+//! Sections are equal if they have the same flags, alignment and contents and their relocations are
+//! equal. Relocations are equal if they have the same offset, type and addend and refer either to
+//! the same symbol or to the same offset in equal sections. Since the equality of sections depends
+//! on the equality of the sections they refer to, sections are first grouped by their own
+//! attributes alone, and the groups are split by the groups their relocations refer to until no
+//! group splits anymore.
+//!
+//! Functions whose FDE has an LSDA are never folded, since their exception tables may differ even
+//! when their code does not.
+
+use std::collections::HashMap;
+
+use crate::{
+    eh_frame,
+    elf::{Elf64Rela, RelocationType, SectionFlag64, SectionType, SECTION_HEADER_NUMBER_LORESERVE},
+    inputs::{InputFile, SectionId},
+    layout::candidates,
+    options::IcfMode,
+    symbols::{Definition, SymbolRef, SymbolTable},
+    util::is_c_identifier,
+};
+
+/// What a relocation refers to, for comparing sections.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    /// An offset in a section which may be folded, by the class of the section.
+    Class(usize, u64),
+
+    /// An offset in a section which is not folded.
+    Section(SectionId, u64),
+
+    /// A symbol not defined in a section.
+    Symbol(SymbolRef),
+}
+
+/// Everything compared about a section in a round, with the class of the previous round, the
+/// flags and the alignment.
+type Key<'a> = (usize, u64, u64, &'a [u8], Vec<(u64, u32, i64, Target)>);
+
+/// Folds identical sections into the first of them in input order. Returns each section kept with
+/// the sections folded into it.
+pub fn fold(
+    inputs: &mut [InputFile],
+    symbols: &SymbolTable,
+    mode: IcfMode,
+) -> Result<Vec<(SectionId, Vec<SectionId>)>, String> {
+    if mode == IcfMode::None {
+        return Ok(vec![]);
+    }
+
+    let address_taken = match mode {
+        IcfMode::Safe => find_address_taken(inputs, symbols)?,
+        _ => vec![],
+    };
+    let with_lsda = find_with_lsda(inputs, symbols)?;
+    let mut sections = vec![];
+    for id in candidates(inputs) {
+        if is_foldable(&inputs[id.file], id.index)
+            && address_taken.binary_search(&id).is_err()
+            && with_lsda.binary_search(&id).is_err()
+        {
+            let input = &inputs[id.file];
+            let data = input.object.section_data(&input.sections[id.index])?;
+            let mut relocations = input.relocations(id.index)?;
+            relocations.sort_by_key(|r| r.offset);
+            sections.push((id, data, relocations));
+        }
+    }
+
+    let index: HashMap<SectionId, usize> = sections
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _, _))| (*id, i))
+        .collect();
+    let mut classes = vec![0; sections.len()];
+    let mut count = 1;
+    loop {
+        let mut keys: HashMap<Key, usize> = HashMap::new();
+        let mut next = Vec::with_capacity(sections.len());
+        for (i, (id, data, relocations)) in sections.iter().enumerate() {
+            let targets = relocations
+                .iter()
+                .map(|r| {
+                    let target = target(inputs, symbols, id.file, r, &index, &classes);
+                    (r.offset, r.ty, r.addend, target)
+                })
+                .collect();
+            let header = &inputs[id.file].sections[id.index];
+            let key = (
+                classes[i],
+                header.flags.bits(),
+                header.addralign,
+                *data,
+                targets,
+            );
+            let len = keys.len();
+            next.push(*keys.entry(key).or_insert(len));
+        }
+
+        classes = next;
+        if keys.len() == count {
+            break;
+        }
+        count = keys.len();
+    }
+
+    let mut groups: Vec<(SectionId, Vec<SectionId>)> = vec![];
+    let mut group_of: HashMap<usize, usize> = HashMap::new();
+    for (i, (id, _, _)) in sections.iter().enumerate() {
+        match group_of.get(&classes[i]) {
+            Some(&group) => groups[group].1.push(*id),
+            None => {
+                group_of.insert(classes[i], groups.len());
+                groups.push((*id, vec![]));
+            }
+        }
+    }
+    groups.retain(|(_, folded)| !folded.is_empty());
+
+    for (kept, folded) in &groups {
+        for id in folded {
+            let input = &mut inputs[id.file];
+            input.live[id.index] = false;
+            input.folded_into[id.index] = Some(*kept);
+        }
+    }
+    Ok(groups)
+}
+
+/// Whether a section may be folded: read-only allocated data or code which the runtime does not
+/// look up by name.
+fn is_foldable(input: &InputFile, index: usize) -> bool {
+    let header = &input.sections[index];
+    let name = &input.section_names[index];
+    header.flags.contains(SectionFlag64::ALLOC)
+        && !header.flags.intersects(
+            SectionFlag64::WRITE
+                | SectionFlag64::TLS
+                | SectionFlag64::LINK_ORDER
+                | SectionFlag64::MERGE,
+        )
+        && header.ty == SectionType::Progbits
+        && header.size > 0
+        && name != ".eh_frame"
+        && name != ".init"
+        && name != ".fini"
+        && !is_c_identifier(name)
+}
+
+fn target(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    file: usize,
+    rela: &Elf64Rela,
+    index: &HashMap<SectionId, usize>,
+    classes: &[usize],
+) -> Target {
+    let symbol = symbols.resolve(file, rela.sym as usize);
+    let (id, value) = match section_of(inputs, symbols, symbol) {
+        Some(target) => target,
+        None => return Target::Symbol(symbol),
+    };
+    match index.get(&id) {
+        Some(&i) => Target::Class(classes[i], value),
+        None => Target::Section(id, value),
+    }
+}
+
+/// Returns the section a symbol is defined in and its offset in the section.
+fn section_of(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    symbol: SymbolRef,
+) -> Option<(SectionId, u64)> {
+    match symbol {
+        SymbolRef::Global(id) => match symbols.symbols[id].definition {
+            Definition::Section {
+                file,
+                section,
+                value,
+            } => Some((
+                SectionId {
+                    file,
+                    index: section,
+                },
+                value,
+            )),
+            _ => None,
+        },
+        SymbolRef::Local { file, index } => {
+            let sym = &inputs[file].symbols[index];
+            let regular = sym.shndx != 0 && sym.shndx < SECTION_HEADER_NUMBER_LORESERVE;
+            regular.then_some((
+                SectionId {
+                    file,
+                    index: sym.shndx as usize,
+                },
+                sym.value,
+            ))
+        }
+    }
+}
+
+/// Finds the sections whose address is taken, that is, which are referred to by anything but a
+/// call or a jump. Folding them could make distinct functions compare equal.
+fn find_address_taken(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+) -> Result<Vec<SectionId>, String> {
+    let mut taken = vec![];
+    for id in candidates(inputs) {
+        let input = &inputs[id.file];
+        let header = &input.sections[id.index];
+        if !header.flags.contains(SectionFlag64::ALLOC)
+            || input.section_names[id.index] == ".eh_frame"
+        {
+            continue;
+        }
+        for rela in input.relocations(id.index)? {
+            if rela.ty == RelocationType::Plt32 as u32 {
+                continue;
+            }
+            let symbol = symbols.resolve(id.file, rela.sym as usize);
+            if let Some((target, _)) = section_of(inputs, symbols, symbol) {
+                taken.push(target);
+            }
+        }
+    }
+    taken.sort();
+    taken.dedup();
+    Ok(taken)
+}
+
+/// Finds the sections whose FDE in `.eh_frame` has an LSDA, which holds what the function catches.
+fn find_with_lsda(inputs: &[InputFile], symbols: &SymbolTable) -> Result<Vec<SectionId>, String> {
+    let mut found = vec![];
+    for id in candidates(inputs) {
+        let input = &inputs[id.file];
+        if input.section_names[id.index] != ".eh_frame" {
+            continue;
+        }
+        let data = input.object.section_data(&input.sections[id.index])?;
+        let mut relocations = input.relocations(id.index)?;
+        relocations.sort_by_key(|r| r.offset);
+
+        let records = eh_frame::parse(data).map_err(|e| format!("{}: {}", input.path, e))?;
+        for record in records.iter().filter(|r| r.cie.is_some()) {
+            let end = record.offset + record.size;
+            let mut targets = relocations
+                .iter()
+                .filter(|r| record.offset + 8 <= r.offset && r.offset < end);
+            // The first relocation is `pc_begin`, which refers to the function; the only other
+            // one an FDE may have is its LSDA pointer.
+            let Some(pc_begin) = targets.next() else {
+                continue;
+            };
+            if targets.next().is_none() {
+                continue;
+            }
+            let symbol = symbols.resolve(id.file, pc_begin.sym as usize);
+            if let Some((function, _)) = section_of(inputs, symbols, symbol) {
+                found.push(function);
+            }
+        }
+    }
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
@@ -1,16 +1,269 @@
-use std::{fmt::Debug, io::Read, mem};
+use std::{fmt::Debug, fs::File, io::Read, mem};
 
 use crate::{
+    archive::Extraction,
+    dynamic::{DynamicEntry, DynamicIter, DT_STRSZ, DT_STRTAB},
     elf::{
-        Elf64Header, Elf64ProgramHeader, Elf64SectionHeader, ElfClass, ElfIdent, Encoding, OsAbi,
-        SectionFlag64, SectionType, SegmentFlag, SegmentType,
+        Elf64Header, Elf64ProgramHeader, Elf64Rela, Elf64SectionHeader, Elf64Symbol, ElfClass,
+        ElfIdent, Encoding, ObjectFileType, OsAbi, SectionFlag64, SectionType, SegmentFlag,
+        SegmentType, SymbolBinding, SymbolType, SymbolVisibility,
     },
+    hash,
+    note::NoteIter,
     util::FromBytes as _,
+    version::{self, VersionDefinition, VersionNeed, VERSYM_HIDDEN, VER_NDX_GLOBAL},
 };
 
+const SYMBOL_SIZE: usize = 24;
+
 #[cfg(test)]
 mod tests;
 
+/// Identifies a section of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SectionId {
+    pub file: usize,
+    pub index: usize,
+}
+
+/// An object file given to the linker, with the tables the linker looks at decoded up front.
+pub struct InputFile {
+    pub path: String,
+    pub object: ObjectFile,
+    pub sections: Vec<Elf64SectionHeader>,
+    pub section_names: Vec<String>,
+    pub symbols: Vec<Elf64Symbol>,
+    pub symbol_names: Vec<String>,
+
+    /// Whether each section may be part of the output. Sections are dropped by garbage
+    /// collection.
+    pub live: Vec<bool>,
+
+    /// The section each section was folded into by identical code folding.
+    pub folded_into: Vec<Option<SectionId>>,
+
+    /// For an archive member, why it was extracted.
+    pub extraction: Option<Extraction>,
+
+    /// For a shared library, what the output refers to it by.
+    pub shared: Option<SharedLibrary>,
+}
+
+/// What the linker reads from a shared library given as an input.
+#[derive(Debug)]
+pub struct SharedLibrary {
+    /// The name the output records the library as needed by: its `DT_SONAME`, or its path.
+    pub soname: String,
+
+    /// The version each symbol is defined with, by index in `InputFile::symbols`.
+    pub versions: Vec<Option<String>>,
+
+    /// Whether the library was given with `--as-needed`, so that the output only needs it if it
+    /// defines a symbol an object file refers to.
+    pub as_needed: bool,
+}
+
+impl InputFile {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+        let object = ObjectFile::from_reader(file).map_err(|e| format!("{}: {}", path, e))?;
+        Self::new(path.into(), object).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn new(path: String, object: ObjectFile) -> Result<Self, String> {
+        Self::read(path, object, SectionType::Symtab)
+    }
+
+    /// Reads a shared library. Its symbols are those of the dynamic symbol table, with the names of
+    /// symbols which are not the default version suffixed by `@VERSION`, and none of its sections
+    /// are part of the output.
+    pub fn new_shared(path: String, object: ObjectFile) -> Result<Self, String> {
+        let mut input = Self::read(path, object, SectionType::Dynsym)?;
+        input.live.fill(false);
+
+        let versions = input.symbol_versions()?;
+        for (index, version) in versions.iter().enumerate() {
+            if let Some((name, true)) = version {
+                input.symbol_names[index] = format!("{}@{}", input.symbol_names[index], name);
+            }
+        }
+        let soname = input
+            .object
+            .dynamic_entries()?
+            .find_map(|entry| match entry {
+                Ok(DynamicEntry::Soname(name)) => Some(Ok(name)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .transpose()?;
+        let soname = soname.unwrap_or_else(|| {
+            std::path::Path::new(&input.path)
+                .file_name()
+                .map_or_else(|| input.path.clone(), |n| n.to_string_lossy().into())
+        });
+        input.shared = Some(SharedLibrary {
+            soname,
+            versions: versions
+                .into_iter()
+                .map(|v| v.map(|(name, _)| name))
+                .collect(),
+            as_needed: false,
+        });
+        Ok(input)
+    }
+
+    /// Whether the file is a shared library, judging by its header.
+    pub fn is_shared(object: &ObjectFile) -> bool {
+        object.header.ty == ObjectFileType::Dyn
+    }
+
+    fn read(path: String, object: ObjectFile, symtab_type: SectionType) -> Result<Self, String> {
+        let sections = object.section_headers().collect::<Result<Vec<_>, _>>()?;
+
+        let section_names = match sections.get(object.header.shstrndx as usize) {
+            Some(shstrtab) if object.header.shstrndx != 0 => sections
+                .iter()
+                .map(|header| object.string(shstrtab, header.name).map(String::from))
+                .collect::<Result<_, _>>()?,
+            _ => vec![String::new(); sections.len()],
+        };
+
+        let mut symbols = vec![];
+        let mut symbol_names = vec![];
+        if let Some(symtab) = sections.iter().find(|h| h.ty == symtab_type) {
+            let strtab = sections
+                .get(symtab.link as usize)
+                .ok_or("the symbol table has no string table")?;
+            for symbol in object.symbols(symtab)? {
+                let symbol = symbol?;
+                symbol_names.push(object.string(strtab, symbol.name)?.to_string());
+                symbols.push(symbol);
+            }
+        }
+
+        Ok(Self {
+            path,
+            object,
+            live: vec![true; sections.len()],
+            folded_into: vec![None; sections.len()],
+            extraction: None,
+            shared: None,
+            sections,
+            section_names,
+            symbols,
+            symbol_names,
+        })
+    }
+
+    /// Returns the version each dynamic symbol of a shared library is defined with, and whether it
+    /// is not the default version. Undefined symbols have no version.
+    fn symbol_versions(&self) -> Result<Vec<Option<(String, bool)>>, String> {
+        let mut versions = vec![None; self.symbols.len()];
+        let find = |ty| self.sections.iter().find(|h| h.ty == ty);
+        let (Some(versym), Some(verdef)) =
+            (find(SectionType::GnuVersym), find(SectionType::GnuVerdef))
+        else {
+            return Ok(versions);
+        };
+        let definitions = self.object.version_definitions(verdef, &self.sections)?;
+        let versyms = self.object.versyms(versym)?;
+        for (index, &versym) in versyms.iter().enumerate().take(versions.len()) {
+            let ndx = versym & !VERSYM_HIDDEN;
+            if ndx <= VER_NDX_GLOBAL || self.symbols[index].is_undefined() {
+                continue;
+            }
+            if let Some(definition) = definitions.iter().find(|d| d.index == ndx) {
+                versions[index] = Some((definition.name.clone(), versym & VERSYM_HIDDEN != 0));
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Returns why the file requires an executable stack, if it does. Files say whether they do by
+    /// the flags of an empty `.note.GNU-stack` section; files without one are assumed to. Shared
+    /// libraries have their own stack segment, which the loader checks.
+    pub fn exec_stack_reason(&self) -> Option<&'static str> {
+        if self.shared.is_some() {
+            return None;
+        }
+        match self
+            .section_names
+            .iter()
+            .position(|n| n == ".note.GNU-stack")
+        {
+            None => Some("missing .note.GNU-stack section implies executable stack"),
+            Some(index)
+                if self.sections[index]
+                    .flags
+                    .contains(SectionFlag64::EXECINSTR) =>
+            {
+                Some(
+                    "requires executable stack (because the .note.GNU-stack section is executable)",
+                )
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the flags of the section group at `index` and the indexes of its members.
+    pub fn group(&self, index: usize) -> Result<(u32, Vec<usize>), String> {
+        let data = self.object.section_data(&self.sections[index])?;
+        let mut words = data
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()));
+        let flags = words
+            .next()
+            .ok_or_else(|| format!("section group {} is empty", self.section_names[index]))?;
+        let members: Vec<usize> = words.map(|w| w as usize).collect();
+        if let Some(member) = members
+            .iter()
+            .find(|&&m| m == 0 || m >= self.sections.len())
+        {
+            return Err(format!(
+                "section group {} has an invalid member {}",
+                self.section_names[index], member
+            ));
+        }
+        Ok((flags, members))
+    }
+
+    /// Returns the signature of the section group at `index`, the name of the symbol it refers
+    /// to.
+    pub fn group_signature(&self, index: usize) -> Result<&str, String> {
+        let symbol = self.sections[index].info as usize;
+        let sym = self.symbols.get(symbol).ok_or_else(|| {
+            format!(
+                "section group {} has an invalid signature symbol {}",
+                self.section_names[index], symbol
+            )
+        })?;
+        // Assemblers may refer to a section symbol, whose name is that of the section.
+        if sym.ty == SymbolType::Section {
+            let shndx = sym.shndx as usize;
+            return Ok(self.section_names.get(shndx).map_or("", String::as_str));
+        }
+        Ok(&self.symbol_names[symbol])
+    }
+
+    /// Returns the relocation sections applying to the section at `index`.
+    pub fn relocation_sections(&self, index: usize) -> impl Iterator<Item = &Elf64SectionHeader> {
+        self.sections
+            .iter()
+            .filter(move |h| h.ty == SectionType::Rela && h.info as usize == index)
+    }
+
+    /// Collects the relocations applying to the section at `index`.
+    pub fn relocations(&self, index: usize) -> Result<Vec<Elf64Rela>, String> {
+        let mut relocations = vec![];
+        for header in self.relocation_sections(index) {
+            for rela in self.object.relocations(header)? {
+                relocations.push(rela?);
+            }
+        }
+        Ok(relocations)
+    }
+}
+
 pub struct ObjectFile {
     pub header: Elf64Header,
     pub data: Vec<u8>,
@@ -55,7 +308,7 @@ impl ObjectFile {
 
         if ident.class != ElfClass::Class64
             || ident.data != Encoding::LSB2
-            || ident.osabi != OsAbi::SysV
+            || !matches!(ident.osabi, OsAbi::SysV | OsAbi::Gnu)
         {
             return Err("unsupported format".into());
         }
@@ -79,7 +332,7 @@ impl ObjectFile {
         Ok(ObjectFile { header, data })
     }
 
-    pub fn section_headers(&self) -> SectionHeaderIter {
+    pub fn section_headers(&self) -> SectionHeaderIter<'_> {
         if self.header.shoff != 0 {
             SectionHeaderIter {
                 head: &self.data[self.header.shoff as usize - mem::size_of_val(&self.header)..],
@@ -95,7 +348,7 @@ impl ObjectFile {
         }
     }
 
-    pub fn program_headers(&self) -> ProgramHeaderIter {
+    pub fn program_headers(&self) -> ProgramHeaderIter<'_> {
         if self.header.phoff != 0 {
             ProgramHeaderIter {
                 head: &self.data[self.header.phoff as usize - mem::size_of_val(&self.header)..],
@@ -110,6 +363,173 @@ impl ObjectFile {
             }
         }
     }
+
+    /// Returns `size` bytes starting at the file offset `offset`.
+    pub fn bytes(&self, offset: u64, size: u64) -> Result<&[u8], String> {
+        let start = (offset as usize)
+            .checked_sub(mem::size_of_val(&self.header))
+            .ok_or_else(|| format!("offset 0x{:x} overlaps the ELF header", offset))?;
+        self.data
+            .get(start..start + size as usize)
+            .ok_or_else(|| format!("range 0x{:x}+0x{:x} is out of the file", offset, size))
+    }
+
+    /// Returns the contents of the section. Sections occupying no file space have empty contents.
+    pub fn section_data(&self, header: &Elf64SectionHeader) -> Result<&[u8], String> {
+        if header.ty == SectionType::Nobits || header.size == 0 {
+            return Ok(&[]);
+        }
+        self.bytes(header.offset, header.size)
+    }
+
+    /// Returns the null-terminated string at `offset` of the string table `strtab`.
+    pub fn string(&self, strtab: &Elf64SectionHeader, offset: u32) -> Result<&str, String> {
+        let table = self.section_data(strtab)?;
+        let bytes = table
+            .get(offset as usize..)
+            .ok_or_else(|| format!("string offset 0x{:x} is out of the table", offset))?;
+        let len = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("a string is not null-terminated")?;
+        std::str::from_utf8(&bytes[..len]).map_err(|e| e.to_string())
+    }
+
+    pub fn symbols(&self, symtab: &Elf64SectionHeader) -> Result<SymbolIter<'_>, String> {
+        Ok(SymbolIter {
+            head: self.section_data(symtab)?,
+            pos: 0,
+        })
+    }
+
+    pub fn relocations(&self, rela: &Elf64SectionHeader) -> Result<RelaIter<'_>, String> {
+        Ok(RelaIter {
+            head: self.section_data(rela)?,
+            pos: 0,
+        })
+    }
+
+    /// Returns the version index of each dynamic symbol, from a `.gnu.version` section.
+    pub fn versyms(&self, header: &Elf64SectionHeader) -> Result<Vec<u16>, String> {
+        Ok(version::parse_versyms(self.section_data(header)?))
+    }
+
+    /// Returns the entries of a `.gnu.version_d` section. `sections` are the section headers of
+    /// the file, among which the string table is looked up.
+    pub fn version_definitions(
+        &self,
+        header: &Elf64SectionHeader,
+        sections: &[Elf64SectionHeader],
+    ) -> Result<Vec<VersionDefinition>, String> {
+        let strtab = sections
+            .get(header.link as usize)
+            .ok_or("the version definitions have no string table")?;
+        version::parse_definitions(self.section_data(header)?, |offset| {
+            self.string(strtab, offset)
+        })
+    }
+
+    /// Returns the entries of a `.gnu.version_r` section. `sections` are the section headers of
+    /// the file, among which the string table is looked up.
+    pub fn version_needs(
+        &self,
+        header: &Elf64SectionHeader,
+        sections: &[Elf64SectionHeader],
+    ) -> Result<Vec<VersionNeed>, String> {
+        let strtab = sections
+            .get(header.link as usize)
+            .ok_or("the version needs have no string table")?;
+        version::parse_needs(self.section_data(header)?, |offset| {
+            self.string(strtab, offset)
+        })
+    }
+
+    /// Looks a dynamic symbol up by name through the hash table `header`, a `.gnu.hash` or `.hash`
+    /// section, without scanning the symbol table. `sections` are the section headers of the
+    /// file, among which the symbol table and its string table are looked up. Returns the index of
+    /// the symbol and the symbol.
+    pub fn lookup_symbol(
+        &self,
+        header: &Elf64SectionHeader,
+        sections: &[Elf64SectionHeader],
+        name: &str,
+    ) -> Result<Option<(usize, Elf64Symbol)>, String> {
+        let symtab = sections
+            .get(header.link as usize)
+            .ok_or("the hash table has no symbol table")?;
+        let strtab = sections
+            .get(symtab.link as usize)
+            .ok_or("the symbol table has no string table")?;
+        let symbol = |index: usize| {
+            self.symbols(symtab)?
+                .nth(index)
+                .ok_or_else(|| format!("symbol index {} is out of the table", index))?
+        };
+        let symbol_name = |index| self.string(strtab, symbol(index)?.name);
+
+        let data = self.section_data(header)?;
+        let index = match header.ty {
+            SectionType::GnuHash => hash::lookup_gnu(data, name, symbol_name)?,
+            SectionType::Hash => hash::lookup_sysv(data, name, symbol_name)?,
+            ty => return Err(format!("a section of type {:?} is not a hash table", ty)),
+        };
+        index.map(|i| Ok((i, symbol(i)?))).transpose()
+    }
+
+    /// Returns the entries of the dynamic table, from the `PT_DYNAMIC` segment or else from the
+    /// `.dynamic` section, with names looked up in the string table `DT_STRTAB` points to. Files
+    /// without a dynamic table have no entries.
+    pub fn dynamic_entries(&self) -> Result<DynamicIter<'_>, String> {
+        let segment = self
+            .program_headers()
+            .find(|h| matches!(h, Ok(h) if h.ty == SegmentType::Dynamic))
+            .transpose()?;
+        let section = self
+            .section_headers()
+            .find(|h| matches!(h, Ok(h) if h.ty == SectionType::Dynamic))
+            .transpose()?;
+        let data = match (segment, section) {
+            (Some(segment), _) => self.bytes(segment.offset, segment.filesz)?,
+            (None, Some(section)) => self.section_data(&section)?,
+            (None, None) => &[],
+        };
+
+        let strtab = match (
+            DynamicIter::value(data, DT_STRTAB),
+            DynamicIter::value(data, DT_STRSZ),
+        ) {
+            (Some(addr), Some(size)) => Some(self.bytes(self.address_offset(addr)?, size)?),
+            _ => None,
+        };
+        Ok(DynamicIter::new(data, strtab))
+    }
+
+    /// Returns the file offset of the address `addr`, which must be in the file part of a
+    /// loadable segment.
+    pub fn address_offset(&self, addr: u64) -> Result<u64, String> {
+        for header in self.program_headers() {
+            let header = header?;
+            if header.ty == SegmentType::Load
+                && (header.vaddr..header.vaddr + header.filesz).contains(&addr)
+            {
+                return Ok(addr - header.vaddr + header.offset);
+            }
+        }
+        Err(format!("address 0x{:x} is not in a loadable segment", addr))
+    }
+
+    /// Returns the notes of an `SHT_NOTE` section.
+    pub fn notes(&self, header: &Elf64SectionHeader) -> Result<NoteIter<'_>, String> {
+        Ok(NoteIter::new(self.section_data(header)?, header.addralign))
+    }
+
+    /// Returns the notes of a `PT_NOTE` segment.
+    pub fn segment_notes(&self, header: &Elf64ProgramHeader) -> Result<NoteIter<'_>, String> {
+        Ok(NoteIter::new(
+            self.bytes(header.offset, header.filesz)?,
+            header.align,
+        ))
+    }
 }
 
 pub struct SectionHeaderIter<'a> {
@@ -208,3 +628,93 @@ impl<'a> Iterator for ProgramHeaderIter<'a> {
         }))
     }
 }
+
+pub struct SymbolIter<'a> {
+    head: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for SymbolIter<'a> {
+    type Item = Result<Elf64Symbol, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.head.len() {
+            return None;
+        }
+
+        if self.head[self.pos..].len() < SYMBOL_SIZE {
+            return Some(Err("the size of a symbol table entry is invalid".into()));
+        }
+
+        let mut head = &self.head[self.pos..self.pos + SYMBOL_SIZE];
+        self.pos += SYMBOL_SIZE;
+
+        let name = u32::read_le_bytes(&mut head);
+        let info = u8::read_le_bytes(&mut head);
+        let other = u8::read_le_bytes(&mut head);
+
+        let binding = match SymbolBinding::try_from(info >> 4) {
+            Ok(b) => b,
+            Err(e) => return Some(Err(e)),
+        };
+        let ty = match SymbolType::try_from(info & 0xF) {
+            Ok(t) => t,
+            Err(e) => return Some(Err(e)),
+        };
+        let visibility = match SymbolVisibility::try_from(other & 0x3) {
+            Ok(v) => v,
+            Err(e) => return Some(Err(e)),
+        };
+
+        Some(Ok(Elf64Symbol {
+            name,
+            binding,
+            ty,
+            visibility,
+            shndx: u16::read_le_bytes(&mut head),
+            value: u64::read_le_bytes(&mut head),
+            size: u64::read_le_bytes(&mut head),
+        }))
+    }
+
+    /// Skips to the `n`th entry directly, since entries have a fixed size.
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.pos = n
+            .checked_mul(SYMBOL_SIZE)
+            .and_then(|skip| self.pos.checked_add(skip))
+            .unwrap_or(usize::MAX);
+        self.next()
+    }
+}
+
+pub struct RelaIter<'a> {
+    head: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for RelaIter<'a> {
+    type Item = Result<Elf64Rela, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const SIZE: usize = 24;
+        if self.pos >= self.head.len() {
+            return None;
+        }
+
+        if self.head[self.pos..].len() < SIZE {
+            return Some(Err("the size of a relocation entry is invalid".into()));
+        }
+
+        let mut head = &self.head[self.pos..self.pos + SIZE];
+        self.pos += SIZE;
+
+        let offset = u64::read_le_bytes(&mut head);
+        let info = u64::read_le_bytes(&mut head);
+        Some(Ok(Elf64Rela {
+            offset,
+            sym: (info >> 32) as u32,
+            ty: info as u32,
+            addend: i64::read_le_bytes(&mut head),
+        }))
+    }
+}
@@ -1,6 +1,12 @@
 use std::fs::File;
 
 use super::ObjectFile;
+use crate::{
+    dynamic::DynamicEntry,
+    elf::{SectionType, SegmentType, NOTE_FDO_PACKAGING_METADATA},
+    note::{Note, NoteContent},
+    version::{VERSYM_HIDDEN, VER_FLG_BASE},
+};
 
 #[test]
 fn input_test() {
@@ -9,3 +15,125 @@ fn input_test() {
 
     eprintln!("{:#x?}", obj_file);
 }
+
+#[test]
+fn notes_test() {
+    let file = File::open("/usr/bin/cat").unwrap();
+    let obj_file = ObjectFile::from_reader(file).unwrap();
+
+    let mut contents = vec![];
+    for header in obj_file.program_headers() {
+        let header = header.unwrap();
+        if header.ty != SegmentType::Note {
+            continue;
+        }
+        for note in obj_file.segment_notes(&header).unwrap() {
+            contents.push(note.unwrap().content().unwrap());
+        }
+    }
+    assert!(contents
+        .iter()
+        .any(|c| matches!(c, NoteContent::BuildId(id) if !id.is_empty())));
+    assert!(contents
+        .iter()
+        .any(|c| matches!(c, NoteContent::AbiTag { os: 0, .. })));
+
+    let package = br#"{"type":"deb","name":"krc","version":"1.0!"}"#;
+    let note = Note {
+        name: b"FDO",
+        ty: NOTE_FDO_PACKAGING_METADATA,
+        desc: package,
+    };
+    assert_eq!(
+        note.content().unwrap(),
+        NoteContent::Package(vec![
+            ("type".into(), "deb".into()),
+            ("name".into(), "krc".into()),
+            ("version".into(), "1.0!".into()),
+        ])
+    );
+}
+
+#[test]
+fn versions_test() {
+    let file = File::open("/lib/x86_64-linux-gnu/libc.so.6").unwrap();
+    let obj_file = ObjectFile::from_reader(file).unwrap();
+    let sections: Vec<_> = obj_file.section_headers().map(Result::unwrap).collect();
+    let header = |ty| sections.iter().find(|h| h.ty == ty).unwrap();
+
+    let definitions = obj_file
+        .version_definitions(header(SectionType::GnuVerdef), &sections)
+        .unwrap();
+    assert_eq!(definitions[0].flags, VER_FLG_BASE);
+    assert_eq!(definitions[0].name, "libc.so.6");
+    let glibc_2_3 = definitions.iter().find(|d| d.name == "GLIBC_2.3").unwrap();
+    assert_eq!(glibc_2_3.parents, ["GLIBC_2.2.6"]);
+
+    let needs = obj_file
+        .version_needs(header(SectionType::GnuVerneed), &sections)
+        .unwrap();
+    assert!(needs.iter().any(|n| n.file == "ld-linux-x86-64.so.2"));
+
+    // Every symbol has a version defined or needed by the file.
+    let versyms = obj_file.versyms(header(SectionType::GnuVersym)).unwrap();
+    let dynsym = header(SectionType::Dynsym);
+    assert_eq!(versyms.len() as u64, dynsym.size / dynsym.entsize);
+    assert!(versyms.iter().skip(1).all(|v| {
+        let index = v & !VERSYM_HIDDEN;
+        definitions.iter().any(|d| d.index == index)
+            || needs
+                .iter()
+                .flat_map(|n| &n.versions)
+                .any(|&(_, i)| i == index)
+    }));
+}
+
+#[test]
+fn lookup_test() {
+    let file = File::open("/lib/x86_64-linux-gnu/libc.so.6").unwrap();
+    let obj_file = ObjectFile::from_reader(file).unwrap();
+    let sections: Vec<_> = obj_file.section_headers().map(Result::unwrap).collect();
+    let gnu_hash = sections
+        .iter()
+        .find(|h| h.ty == SectionType::GnuHash)
+        .unwrap();
+    let dynsym = &sections[gnu_hash.link as usize];
+
+    let (index, symbol) = obj_file
+        .lookup_symbol(gnu_hash, &sections, "printf")
+        .unwrap()
+        .unwrap();
+    let name = obj_file
+        .string(&sections[dynsym.link as usize], symbol.name)
+        .unwrap();
+    assert_eq!(name, "printf");
+    assert!(index > 0 && !symbol.is_undefined());
+    assert!(obj_file
+        .lookup_symbol(gnu_hash, &sections, "no_such_symbol")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn dynamic_entries_test() {
+    let file = File::open("/lib/x86_64-linux-gnu/libc.so.6").unwrap();
+    let obj_file = ObjectFile::from_reader(file).unwrap();
+    let entries: Vec<_> = obj_file
+        .dynamic_entries()
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert!(entries.contains(&DynamicEntry::Soname("libc.so.6".into())));
+    assert!(entries.contains(&DynamicEntry::Needed("ld-linux-x86-64.so.2".into())));
+    assert!(entries
+        .iter()
+        .any(|e| matches!(e, DynamicEntry::GnuHash(_))));
+
+    // Executables name the libraries they need.
+    let file = File::open("/usr/bin/cat").unwrap();
+    let obj_file = ObjectFile::from_reader(file).unwrap();
+    assert!(obj_file
+        .dynamic_entries()
+        .unwrap()
+        .any(|e| e == Ok(DynamicEntry::Needed("libc.so.6".into()))));
+}
@@ -0,0 +1,2517 @@
+//! Assigns input sections to output sections, and output sections to addresses, segments and file
+//! offsets. The assignment follows a linker script if one is given, and a built-in layout
+//! otherwise.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    build_id,
+    dynamic::{
+        CopyReloc, Dynamic, DYNAMIC_ENTRY_SIZE, GOT_PLT_RESERVED, PLT_ENTRY_SIZE, RELA_SIZE,
+        SYMBOL_SIZE,
+    },
+    eh_frame::EhFrame,
+    elf::{
+        Elf64ProgramHeader, SectionFlag64, SectionType, SegmentFlag, SegmentType,
+        SECTION_HEADER_NUMBER_ABS, SECTION_HEADER_NUMBER_UNDEF,
+    },
+    inputs::{InputFile, SectionId},
+    merge::MergedSections,
+    options::{BuildId, Discard, Options, Strip},
+    property,
+    script::{
+        AssignOp, Assignment, Command, EvalContext, Expr, InputSectionDesc, Location,
+        OutputSectionCommand, OutputSectionDesc, Script, SectionInfo, SectionsCommand, SortKind,
+    },
+    symbols::{Definition, SymbolRef, SymbolTable},
+    util::align_up,
+};
+
+pub const DEFAULT_BASE_ADDRESS: u64 = 0x40_0000;
+pub const PAGE_SIZE: u64 = 0x1000;
+pub const ELF_HEADER_SIZE: u64 = 64;
+pub const PROGRAM_HEADER_SIZE: u64 = 56;
+
+/// Sections which are only written while the program is loaded, and which the loader may make
+/// read-only afterwards.
+const RELRO_SECTIONS: &[&str] = &[
+    ".tdata",
+    ".tbss",
+    ".preinit_array",
+    ".init_array",
+    ".fini_array",
+    ".data.rel.ro",
+    ".dynamic",
+    ".got",
+    ".bss.rel.ro",
+];
+
+/// Bounds the number of times a linker script is evaluated while waiting for addresses referring
+/// to each other to settle.
+const MAX_PASSES: usize = 10;
+
+#[derive(Debug, Clone)]
+pub enum Content {
+    /// A section of an input file.
+    Input(SectionId),
+
+    /// Bytes stored by data commands of a linker script.
+    Data(Vec<u8>),
+
+    /// The global offset table, filled once addresses are known.
+    Got,
+
+    /// A merged section, by its index in `Layout::merged`.
+    Merged(usize),
+
+    /// The records of the input `.eh_frame` sections which are kept.
+    EhFrame,
+
+    /// The search table of the FDEs in `.eh_frame`, filled once addresses are known.
+    EhFrameHdr,
+
+    /// The property note recording the x86 features of the output.
+    GnuProperty,
+
+    /// The note holding the build ID, computed once the rest of the output is written.
+    BuildId,
+
+    /// The path of the loader, in `.interp`.
+    Interp,
+
+    /// The dynamic symbol table and its string table.
+    DynSym,
+    DynStr,
+
+    /// The version sections `.gnu.version`, `.gnu.version_d` and `.gnu.version_r`.
+    VerSym,
+    VerDef,
+    Hash,
+    GnuHash,
+    VerNeed,
+
+    /// The relocations the loader applies, in `.rela.dyn`, and those of the PLT, in `.rela.plt`.
+    RelaDyn,
+    RelaPlt,
+
+    /// The procedure linkage table and the GOT entries its entries jump through.
+    Plt,
+    GotPlt,
+
+    /// The `.dynamic` section.
+    Dynamic,
+
+    /// The space common symbols are allocated in, in `.bss`.
+    Commons,
+
+    /// The space symbols of shared libraries are copied to, in `.bss`, and in `.bss.rel.ro` for
+    /// those read-only in the libraries.
+    Copies,
+    CopiesRelRo,
+
+    /// The entries through which references to indirect functions go, their slots, and, in a
+    /// static executable, the relocations filling the slots.
+    Iplt,
+    IgotPlt,
+    RelaIplt,
+}
+
+#[derive(Debug, Clone)]
+pub struct Piece {
+    /// The offset from the start of the output section.
+    pub offset: u64,
+    pub size: u64,
+    pub content: Content,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputSection {
+    pub name: String,
+    pub ty: SectionType,
+    pub flags: SectionFlag64,
+    pub addr: u64,
+
+    /// The load address, which differs from `addr` for sections copied at startup.
+    pub lma: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub align: u64,
+    pub entsize: u64,
+
+    /// The 4-byte big-endian pattern the gaps between pieces are filled with.
+    pub fill: Option<u32>,
+    pub pieces: Vec<Piece>,
+}
+
+impl OutputSection {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            ty: SectionType::Null,
+            flags: SectionFlag64::empty(),
+            addr: 0,
+            lma: 0,
+            offset: 0,
+            size: 0,
+            align: 1,
+            entsize: 0,
+            fill: None,
+            pieces: vec![],
+        }
+    }
+
+    /// Folds the attributes of an input section into the output section.
+    fn add_attributes(&mut self, ty: SectionType, flags: SectionFlag64, align: u64, entsize: u64) {
+        self.ty = match self.ty {
+            SectionType::Null => ty,
+            t if t == ty => t,
+            _ => SectionType::Progbits,
+        };
+        self.flags |= flags
+            & (SectionFlag64::WRITE
+                | SectionFlag64::ALLOC
+                | SectionFlag64::EXECINSTR
+                | SectionFlag64::TLS);
+        self.align = self.align.max(align);
+        self.entsize = if self.pieces.is_empty() || self.entsize == entsize {
+            entsize
+        } else {
+            0
+        };
+    }
+
+    pub fn is_alloc(&self) -> bool {
+        self.flags.contains(SectionFlag64::ALLOC)
+    }
+
+    pub fn is_nobits(&self) -> bool {
+        self.ty == SectionType::Nobits
+    }
+
+    /// Whether the section is only written while the program is loaded. With `now`, the loader
+    /// binds the PLT entries at load time, so `.got.plt` is too.
+    fn is_relro(&self, now: bool) -> bool {
+        self.is_alloc()
+            && self.flags.contains(SectionFlag64::WRITE)
+            && (RELRO_SECTIONS.contains(&self.name.as_str()) || (now && self.name == ".got.plt"))
+    }
+
+    pub fn segment_flags(&self) -> SegmentFlag {
+        let mut flags = SegmentFlag::R;
+        if self.flags.contains(SectionFlag64::WRITE) {
+            flags |= SegmentFlag::W;
+        }
+        if self.flags.contains(SectionFlag64::EXECINSTR) {
+            flags |= SegmentFlag::X;
+        }
+        flags
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub header: Elf64ProgramHeader,
+
+    /// Indexes of the output sections in the segment.
+    pub sections: Vec<usize>,
+}
+
+/// A segment before file offsets are known.
+#[derive(Debug)]
+struct SegmentPlan {
+    ty: SegmentType,
+    flags: SegmentFlag,
+    sections: Vec<usize>,
+
+    /// Whether the segment should include the ELF header and the program headers.
+    headers: bool,
+    paddr: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Layout {
+    pub sections: Vec<OutputSection>,
+    pub segments: Vec<Segment>,
+
+    /// The output section and the offset in it of each input section, indexed by file and
+    /// section. Sections which are not part of the output map to `None`.
+    pub placements: Vec<Vec<Option<(usize, u64)>>>,
+
+    /// Symbols with an entry in the global offset table, in the order of the entries.
+    pub got: Vec<SymbolRef>,
+    got_index: HashMap<SymbolRef, usize>,
+
+    /// Indirect functions with an IPLT entry, in the order of the entries.
+    pub iplt: Vec<SymbolRef>,
+    iplt_index: HashMap<SymbolRef, usize>,
+
+    /// Whether the output has `.got.plt` even without PLT entries.
+    got_plt: bool,
+
+    /// Sections folded by identical code folding and the sections they were folded into.
+    folds: Vec<(SectionId, SectionId)>,
+
+    /// The merged sections, placed where their first member would be.
+    pub merged: MergedSections,
+
+    /// The output `.eh_frame`, placed where the first input `.eh_frame` would be.
+    pub eh_frame: EhFrame,
+
+    /// The x86 features the output is compatible with, or 0 for no `.note.gnu.property`.
+    pub x86_features: u32,
+
+    /// How to compute the ID in `.note.gnu.build-id`, if the output has one.
+    pub build_id: Option<BuildId>,
+
+    /// What the output tells the loader, if it is dynamic.
+    pub dynamic: Option<Dynamic>,
+
+    /// The space allocated to common symbols.
+    commons: Commons,
+
+    pub headers_size: u64,
+
+    /// Whether to leave out `.symtab` and `.strtab`, set by `--strip-all`.
+    pub strip_symbols: bool,
+
+    /// Which local symbols to leave out of `.symtab`.
+    pub discard: Discard,
+
+    /// Whether to emit `PT_GNU_RELRO` for the sections in `RELRO_SECTIONS`, set by `-z relro`.
+    relro: bool,
+
+    /// Whether symbols are bound at load time, set by `-z now`.
+    bind_now: bool,
+
+    /// Whether the stack is executable, as set by `-z execstack` or required by an input.
+    exec_stack: bool,
+
+    /// The end of the data laid out, where the linker may append non-allocated sections.
+    pub file_size: u64,
+
+    /// Symbols assigned by the linker script, with their values and whether they are hidden.
+    pub script_symbols: Vec<(String, u64, bool)>,
+}
+
+/// What the linker creates itself, which the layout places along with the input sections.
+#[derive(Debug, Default)]
+pub struct Synthetic {
+    /// Symbols which need an entry in the global offset table, in the order of the entries.
+    pub got: Vec<SymbolRef>,
+
+    /// Indirect functions which need an IPLT entry, in the order of the entries.
+    pub iplt: Vec<SymbolRef>,
+
+    /// Whether code addresses data relative to `_GLOBAL_OFFSET_TABLE_`, the start of `.got.plt`.
+    pub got_plt: bool,
+    pub merged: MergedSections,
+    pub eh_frame: EhFrame,
+
+    /// The x86 features the output is compatible with, recorded in `.note.gnu.property`.
+    pub x86_features: u32,
+
+    /// What the output tells the loader, if it is dynamic.
+    pub dynamic: Option<Dynamic>,
+
+    /// The space allocated to common symbols in `.bss`.
+    pub commons: Commons,
+}
+
+/// The space common symbols are allocated in, in `.bss`.
+#[derive(Debug, Default)]
+pub struct Commons {
+    /// The offset of each common symbol, by id.
+    offsets: HashMap<usize, u64>,
+    size: u64,
+    align: u64,
+}
+
+impl Commons {
+    /// Allocates the common symbols in the order they were first seen, each aligned as required.
+    pub fn new(symbols: &SymbolTable) -> Self {
+        let mut commons = Self {
+            align: 1,
+            ..Default::default()
+        };
+        for (id, symbol) in symbols.symbols.iter().enumerate() {
+            if let Definition::Common { align } = symbol.definition {
+                let offset = align_up(commons.size, align);
+                commons.offsets.insert(id, offset);
+                commons.size = offset + symbol.size;
+                commons.align = commons.align.max(align);
+            }
+        }
+        commons
+    }
+}
+
+impl Layout {
+    /// Lays out the output with the built-in layout.
+    pub fn new(
+        inputs: &[InputFile],
+        synthetic: Synthetic,
+        options: &Options,
+    ) -> Result<Self, String> {
+        let mut layout = Self::empty(inputs, synthetic, options);
+
+        let mut by_name = HashMap::new();
+        for id in candidates(inputs) {
+            let input = &inputs[id.file];
+            let name = default_output_name(&input.section_names[id.index]);
+            let out = *by_name.entry(name).or_insert_with(|| {
+                layout.sections.push(OutputSection::new(name));
+                layout.sections.len() - 1
+            });
+            layout.append_input(out, inputs, id, None);
+        }
+        for (name, ty, flags, entsize, content) in layout.synthetic_sections() {
+            let out = *by_name.entry(name).or_insert_with(|| {
+                layout.sections.push(OutputSection::new(name));
+                layout.sections.len() - 1
+            });
+            layout.append_synthetic(out, ty, flags, entsize, content);
+        }
+
+        // Sorting is stable, so sections of the same rank keep the order they were seen in.
+        let now = layout.bind_now;
+        layout.sections.sort_by_key(|s| rank(s, now));
+
+        let mut headers_size = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE;
+        for _ in 0..MAX_PASSES {
+            layout.assign_default_addresses(headers_size);
+            let plans = layout.plan_segments(true);
+            let size = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * plans.len() as u64;
+            if size == headers_size {
+                layout.finish(plans, headers_size)?;
+                return Ok(layout);
+            }
+            headers_size = size;
+        }
+        Err("failed to lay out the output".into())
+    }
+
+    /// Lays out the output as directed by a linker script.
+    pub fn with_script(
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        synthetic: Synthetic,
+        options: &Options,
+        script: &Script,
+    ) -> Result<Self, String> {
+        let layout = Self::empty(inputs, synthetic, options);
+        let mut state = ScriptLayout::new(inputs, symbols, script, layout)?;
+        state.assign_sections()?;
+        state.run()?;
+        Ok(state.layout)
+    }
+
+    fn empty(inputs: &[InputFile], synthetic: Synthetic, options: &Options) -> Self {
+        let Synthetic {
+            got,
+            iplt,
+            got_plt,
+            merged,
+            eh_frame,
+            x86_features,
+            dynamic,
+            commons,
+        } = synthetic;
+        let got_index = got.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+        let iplt_index = iplt.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+        Self {
+            placements: inputs
+                .iter()
+                .map(|i| vec![None; i.sections.len()])
+                .collect(),
+            got,
+            got_index,
+            iplt,
+            iplt_index,
+            got_plt,
+            folds: inputs
+                .iter()
+                .enumerate()
+                .flat_map(|(file, input)| {
+                    input
+                        .folded_into
+                        .iter()
+                        .enumerate()
+                        .filter_map(move |(index, to)| to.map(|to| (SectionId { file, index }, to)))
+                })
+                .collect(),
+            merged,
+            eh_frame,
+            x86_features,
+            build_id: options.build_id.clone(),
+            dynamic,
+            commons,
+            bind_now: options.now,
+            strip_symbols: options.strip == Strip::All,
+            discard: options.discard,
+            relro: options.relro,
+            exec_stack: options
+                .exec_stack
+                .unwrap_or_else(|| inputs.iter().any(|i| i.exec_stack_reason().is_some())),
+            ..Default::default()
+        }
+    }
+
+    /// Returns what an input section contributes to its output section: its content, size and
+    /// alignment. Members of a merged section or of `.eh_frame` other than the first contribute
+    /// nothing.
+    fn input_piece(&self, inputs: &[InputFile], id: SectionId) -> Option<(Content, u64, u64)> {
+        let header = &inputs[id.file].sections[id.index];
+        if self.eh_frame.contains(id) {
+            return (self.eh_frame.members[0] == id).then_some((
+                Content::EhFrame,
+                self.eh_frame.data.len() as u64,
+                self.eh_frame.align,
+            ));
+        }
+        match self.merged.group(id) {
+            None => Some((Content::Input(id), header.size, header.addralign.max(1))),
+            Some(group) => {
+                let merged = &self.merged.sections[group];
+                (merged.members[0] == id).then_some({
+                    (
+                        Content::Merged(group),
+                        merged.data.len() as u64,
+                        merged.align,
+                    )
+                })
+            }
+        }
+    }
+
+    /// Appends an input section to the output section `out`, aligning it to `align` or to its
+    /// own alignment.
+    fn append_input(
+        &mut self,
+        out: usize,
+        inputs: &[InputFile],
+        id: SectionId,
+        align: Option<u64>,
+    ) {
+        let header = &inputs[id.file].sections[id.index];
+        let piece = self.input_piece(inputs, id);
+        let section = &mut self.sections[out];
+        let Some((content, size, own_align)) = piece else {
+            section.add_attributes(header.ty, header.flags, 1, header.entsize);
+            return;
+        };
+        let align = align.unwrap_or(own_align);
+        section.add_attributes(header.ty, header.flags, align, header.entsize);
+
+        let offset = align_up(section.size, align);
+        section.pieces.push(Piece {
+            offset,
+            size,
+            content,
+        });
+        section.size = offset + size;
+    }
+
+    /// Lists the sections the linker creates which the output needs, with their names, types,
+    /// flags and entry sizes.
+    fn synthetic_sections(&self) -> Vec<(&'static str, SectionType, SectionFlag64, u64, Content)> {
+        let mut sections = vec![];
+        if self.build_id.is_some() {
+            sections.push((
+                ".note.gnu.build-id",
+                SectionType::Note,
+                SectionFlag64::ALLOC,
+                0,
+                Content::BuildId,
+            ));
+        }
+        if self.x86_features != 0 {
+            sections.push((
+                ".note.gnu.property",
+                SectionType::Note,
+                SectionFlag64::ALLOC,
+                0,
+                Content::GnuProperty,
+            ));
+        }
+        let alloc = SectionFlag64::ALLOC;
+        let write = SectionFlag64::ALLOC | SectionFlag64::WRITE;
+        if let Some(dynamic) = &self.dynamic {
+            if dynamic.interp.is_some() {
+                sections.push((".interp", SectionType::Progbits, alloc, 0, Content::Interp));
+            }
+            if !dynamic.hash_data.is_empty() {
+                sections.push((".hash", SectionType::Hash, alloc, 4, Content::Hash));
+            }
+            if !dynamic.gnu_hash_data.is_empty() {
+                sections.push((
+                    ".gnu.hash",
+                    SectionType::GnuHash,
+                    alloc,
+                    0,
+                    Content::GnuHash,
+                ));
+            }
+            sections.push((
+                ".dynsym",
+                SectionType::Dynsym,
+                alloc,
+                SYMBOL_SIZE,
+                Content::DynSym,
+            ));
+            sections.push((".dynstr", SectionType::Strtab, alloc, 0, Content::DynStr));
+            if !dynamic.versyms.is_empty() {
+                sections.push((
+                    ".gnu.version",
+                    SectionType::GnuVersym,
+                    alloc,
+                    2,
+                    Content::VerSym,
+                ));
+            }
+            if !dynamic.verdefs.is_empty() {
+                sections.push((
+                    ".gnu.version_d",
+                    SectionType::GnuVerdef,
+                    alloc,
+                    0,
+                    Content::VerDef,
+                ));
+            }
+            if !dynamic.verneeds.is_empty() {
+                sections.push((
+                    ".gnu.version_r",
+                    SectionType::GnuVerneed,
+                    alloc,
+                    0,
+                    Content::VerNeed,
+                ));
+            }
+            if !dynamic.relocs.is_empty() {
+                sections.push((
+                    ".rela.dyn",
+                    SectionType::Rela,
+                    alloc,
+                    RELA_SIZE,
+                    Content::RelaDyn,
+                ));
+            }
+            if !dynamic.plt.is_empty() {
+                sections.push((
+                    ".rela.plt",
+                    SectionType::Rela,
+                    alloc,
+                    RELA_SIZE,
+                    Content::RelaPlt,
+                ));
+                sections.push((
+                    ".plt",
+                    SectionType::Progbits,
+                    alloc | SectionFlag64::EXECINSTR,
+                    PLT_ENTRY_SIZE,
+                    Content::Plt,
+                ));
+            }
+            sections.push((
+                ".dynamic",
+                SectionType::Dynamic,
+                write,
+                DYNAMIC_ENTRY_SIZE,
+                Content::Dynamic,
+            ));
+        }
+        if !self.iplt.is_empty() {
+            // Dynamic outputs have the relocations of the slots in `.rela.dyn`.
+            if self.dynamic.is_none() {
+                sections.push((
+                    ".rela.iplt",
+                    SectionType::Rela,
+                    alloc,
+                    RELA_SIZE,
+                    Content::RelaIplt,
+                ));
+            }
+            sections.push((
+                ".iplt",
+                SectionType::Progbits,
+                alloc | SectionFlag64::EXECINSTR,
+                PLT_ENTRY_SIZE,
+                Content::Iplt,
+            ));
+        }
+        if !self.got.is_empty() {
+            sections.push((".got", SectionType::Progbits, write, 8, Content::Got));
+        }
+        if self.got_plt || self.dynamic.as_ref().is_some_and(|d| !d.plt.is_empty()) {
+            sections.push((".got.plt", SectionType::Progbits, write, 8, Content::GotPlt));
+        }
+        if !self.iplt.is_empty() {
+            sections.push((
+                ".got.plt",
+                SectionType::Progbits,
+                write,
+                8,
+                Content::IgotPlt,
+            ));
+        }
+        if self.eh_frame.has_fdes() {
+            sections.push((
+                ".eh_frame_hdr",
+                SectionType::Progbits,
+                SectionFlag64::ALLOC,
+                0,
+                Content::EhFrameHdr,
+            ));
+        }
+        if !self.commons.offsets.is_empty() {
+            sections.push((".bss", SectionType::Nobits, write, 0, Content::Commons));
+        }
+        if let Some(dynamic) = &self.dynamic {
+            // `.bss.rel.ro` is the last of the RELRO sections, since it takes no space in the file.
+            for (relro, name, content) in [
+                (false, ".bss", Content::Copies),
+                (true, ".bss.rel.ro", Content::CopiesRelRo),
+            ] {
+                if dynamic.copies.iter().any(|c| c.relro == relro) {
+                    sections.push((name, SectionType::Nobits, write, 0, content));
+                }
+            }
+        }
+        sections
+    }
+
+    /// Returns the size and alignment of a section the linker creates.
+    pub fn synthetic_size(&self, content: &Content) -> (u64, u64) {
+        match content {
+            Content::Got => (8 * self.got.len() as u64, 8),
+            Content::Iplt => (PLT_ENTRY_SIZE * self.iplt.len() as u64, 16),
+            Content::IgotPlt => (8 * self.iplt.len() as u64, 8),
+            Content::RelaIplt => (RELA_SIZE * self.iplt.len() as u64, 8),
+            Content::GotPlt => {
+                let plt = self.dynamic.as_ref().map_or(0, |d| d.plt.len() as u64);
+                (8 * (GOT_PLT_RESERVED + plt), 8)
+            }
+            Content::EhFrameHdr => (self.eh_frame.hdr_size(), 4),
+            Content::Commons => (self.commons.size, self.commons.align),
+            Content::GnuProperty => (property::note(self.x86_features).len() as u64, 8),
+            Content::BuildId => match &self.build_id {
+                Some(kind) => (build_id::note(kind).len() as u64, 4),
+                None => (0, 1),
+            },
+            content => match &self.dynamic {
+                Some(dynamic) => dynamic_size(dynamic, content),
+                None => (0, 1),
+            },
+        }
+    }
+
+    fn append_synthetic(
+        &mut self,
+        out: usize,
+        ty: SectionType,
+        flags: SectionFlag64,
+        entsize: u64,
+        content: Content,
+    ) {
+        let (size, align) = self.synthetic_size(&content);
+        let section = &mut self.sections[out];
+        section.add_attributes(ty, flags, align, entsize);
+
+        let offset = align_up(section.size, align);
+        section.pieces.push(Piece {
+            offset,
+            size,
+            content,
+        });
+        section.size = offset + size;
+    }
+
+    fn assign_default_addresses(&mut self, headers_size: u64) {
+        // Shared libraries are loaded anywhere, so they are linked at address 0.
+        let base = if self.is_shared() {
+            0
+        } else {
+            DEFAULT_BASE_ADDRESS
+        };
+        let mut addr = base + headers_size;
+        let mut prev_flags = None;
+        let mut prev_relro = false;
+        for section in self.sections.iter_mut().filter(|s| s.is_alloc()) {
+            // Segments with different permissions must not share a page, and neither may the
+            // RELRO region and the data after it, since the loader protects whole pages.
+            let flags = section.segment_flags();
+            let relro = self.relro && section.is_relro(self.bind_now);
+            if prev_flags.is_some_and(|f| f != flags) || (prev_relro && !relro) {
+                addr = align_up(addr, PAGE_SIZE);
+            }
+            prev_flags = Some(flags);
+            prev_relro = relro;
+
+            addr = align_up(addr, section.align);
+            section.addr = addr;
+            section.lma = addr;
+            addr += section.size;
+        }
+    }
+
+    /// Groups allocated sections into loadable segments by their permissions and addresses. A
+    /// section starting in the last page of the previous segment joins it, widening its
+    /// permissions, since two mappings cannot share a page.
+    fn plan_segments(&self, headers: bool) -> Vec<SegmentPlan> {
+        let mut plans: Vec<SegmentPlan> = vec![];
+        let mut prev: Option<&OutputSection> = None;
+        for (i, section) in self.sections.iter().enumerate() {
+            if !section.is_alloc() {
+                continue;
+            }
+
+            let flags = section.segment_flags();
+            let split = match prev {
+                None => true,
+                Some(prev) => {
+                    let prev_end = prev.addr + prev.size;
+                    let same_page =
+                        section.addr / PAGE_SIZE <= prev_end.saturating_sub(1) / PAGE_SIZE;
+                    (flags != plans.last().unwrap().flags && !same_page)
+                        || prev.lma.wrapping_sub(prev.addr)
+                            != section.lma.wrapping_sub(section.addr)
+                        || section.addr < prev_end
+                        || section.addr - prev_end >= PAGE_SIZE
+                        || (prev.is_nobits() && !section.is_nobits())
+                }
+            };
+            if split {
+                plans.push(SegmentPlan {
+                    ty: SegmentType::Load,
+                    flags,
+                    sections: vec![],
+                    headers: headers && plans.is_empty() && section.addr % PAGE_SIZE != 0,
+                    paddr: None,
+                });
+            }
+            let plan = plans.last_mut().unwrap();
+            plan.flags |= flags;
+            plan.sections.push(i);
+            prev = Some(section);
+        }
+
+        let notes = self
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_alloc() && s.ty == SectionType::Note);
+        let mut note_plans: Vec<SegmentPlan> = vec![];
+        for (i, _) in notes {
+            match note_plans.last_mut() {
+                Some(plan) if *plan.sections.last().unwrap() == i - 1 => plan.sections.push(i),
+                _ => note_plans.push(SegmentPlan {
+                    ty: SegmentType::Note,
+                    flags: SegmentFlag::R,
+                    sections: vec![i],
+                    headers: false,
+                    paddr: None,
+                }),
+            }
+        }
+        plans.extend(note_plans);
+
+        // The loader needs the program headers and `.interp` before any loadable segment.
+        if let Some(i) = self
+            .sections
+            .iter()
+            .position(|s| s.is_alloc() && s.name == ".interp")
+        {
+            for (n, (ty, sections)) in [(SegmentType::Phdr, vec![]), (SegmentType::Interp, vec![i])]
+                .into_iter()
+                .enumerate()
+            {
+                plans.insert(
+                    n,
+                    SegmentPlan {
+                        ty,
+                        flags: SegmentFlag::R,
+                        sections,
+                        headers: false,
+                        paddr: None,
+                    },
+                );
+            }
+        }
+        if let Some(i) = self
+            .sections
+            .iter()
+            .position(|s| s.is_alloc() && s.name == ".dynamic")
+        {
+            plans.push(SegmentPlan {
+                ty: SegmentType::Dynamic,
+                flags: SegmentFlag::R | SegmentFlag::W,
+                sections: vec![i],
+                headers: false,
+                paddr: None,
+            });
+        }
+
+        let mut stack_flags = SegmentFlag::R | SegmentFlag::W;
+        if self.exec_stack {
+            stack_flags |= SegmentFlag::X;
+        }
+        plans.push(SegmentPlan {
+            ty: SegmentType::GnuStack,
+            flags: stack_flags,
+            sections: vec![],
+            headers: false,
+            paddr: None,
+        });
+
+        // The RELRO region is the run of RELRO sections at the start of the writable data.
+        if self.relro {
+            let relro: Vec<usize> = self
+                .sections
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.is_alloc())
+                .skip_while(|(_, s)| !s.is_relro(self.bind_now))
+                .take_while(|(_, s)| s.is_relro(self.bind_now))
+                .map(|(i, _)| i)
+                .collect();
+            if !relro.is_empty() {
+                plans.push(SegmentPlan {
+                    ty: SegmentType::GnuRelro,
+                    flags: SegmentFlag::R,
+                    sections: relro,
+                    headers: false,
+                    paddr: None,
+                });
+            }
+        }
+
+        if let Some(i) = self
+            .sections
+            .iter()
+            .position(|s| s.is_alloc() && s.name == ".eh_frame_hdr")
+        {
+            plans.push(SegmentPlan {
+                ty: SegmentType::GnuEhFrame,
+                flags: SegmentFlag::R,
+                sections: vec![i],
+                headers: false,
+                paddr: None,
+            });
+        }
+
+        if let Some(i) = self
+            .sections
+            .iter()
+            .position(|s| s.is_alloc() && s.name == ".note.gnu.property")
+        {
+            plans.push(SegmentPlan {
+                ty: SegmentType::GnuProperty,
+                flags: SegmentFlag::R,
+                sections: vec![i],
+                headers: false,
+                paddr: None,
+            });
+        }
+
+        plans
+    }
+
+    /// Assigns file offsets to the output sections and builds the program headers.
+    fn finish(&mut self, plans: Vec<SegmentPlan>, headers_size: u64) -> Result<(), String> {
+        self.headers_size = headers_size;
+
+        let first_load = plans.iter().position(|p| p.ty == SegmentType::Load);
+        let load_of = |i: usize| {
+            plans
+                .iter()
+                .position(|p| p.ty == SegmentType::Load && p.sections.contains(&i))
+        };
+
+        let mut offset = headers_size;
+        let loads: Vec<_> = (0..self.sections.len()).map(load_of).collect();
+        for (i, &load) in loads.iter().enumerate() {
+            let Some(load) = load else {
+                continue;
+            };
+            let section = &self.sections[i];
+
+            let plan = &plans[load];
+            let first = plan.sections[0];
+            let section_offset = if first == i {
+                if Some(load) == first_load && plan.headers {
+                    let page_offset = section.addr % PAGE_SIZE;
+                    if page_offset < headers_size {
+                        return Err(format!(
+                            "not enough room for the program headers before section `{}`",
+                            section.name
+                        ));
+                    }
+                    page_offset
+                } else {
+                    // The smallest offset not below `offset` congruent to the address modulo the
+                    // page size, so that the segment can be mapped.
+                    offset + (section.addr.wrapping_sub(offset) % PAGE_SIZE)
+                }
+            } else {
+                let first = &self.sections[first];
+                first.offset + (section.addr - first.addr)
+            };
+
+            let section = &mut self.sections[i];
+            section.offset = section_offset;
+            if !section.is_nobits() {
+                offset = offset.max(section_offset + section.size);
+            }
+        }
+
+        for (i, section) in self.sections.iter_mut().enumerate() {
+            if loads[i].is_some() {
+                continue;
+            }
+            section.offset = align_up(offset, section.align);
+            if !section.is_nobits() {
+                offset = section.offset + section.size;
+            }
+        }
+        self.file_size = offset;
+
+        self.segments = plans
+            .into_iter()
+            .map(|plan| self.build_segment(plan, headers_size))
+            .collect::<Result<_, _>>()?;
+
+        self.compute_placements();
+        Ok(())
+    }
+
+    fn build_segment(&self, plan: SegmentPlan, headers_size: u64) -> Result<Segment, String> {
+        let mut header = Elf64ProgramHeader {
+            ty: plan.ty,
+            flags: plan.flags,
+            offset: 0,
+            vaddr: 0,
+            paddr: 0,
+            filesz: 0,
+            memsz: 0,
+            align: match plan.ty {
+                SegmentType::Load => PAGE_SIZE,
+                SegmentType::GnuStack => 16,
+                _ => 1,
+            },
+        };
+
+        if plan.ty == SegmentType::Phdr {
+            let load = self
+                .headers_segment_base()
+                .ok_or("PT_PHDR requires loaded headers")?;
+            header.offset = ELF_HEADER_SIZE;
+            header.vaddr = load.0 + ELF_HEADER_SIZE;
+            header.paddr = load.1 + ELF_HEADER_SIZE;
+            header.filesz = headers_size - ELF_HEADER_SIZE;
+            header.memsz = header.filesz;
+            header.align = 8;
+            return Ok(Segment {
+                header,
+                sections: plan.sections,
+            });
+        }
+
+        if let (Some(&first), Some(&last)) = (plan.sections.first(), plan.sections.last()) {
+            let first = &self.sections[first];
+            let last = &self.sections[last];
+            let base = if plan.headers { first.offset } else { 0 };
+
+            header.offset = first.offset - base;
+            header.vaddr = first.addr - base;
+            header.paddr = plan.paddr.unwrap_or(first.lma - base);
+
+            let file_end = plan
+                .sections
+                .iter()
+                .map(|&i| &self.sections[i])
+                .filter(|s| !s.is_nobits())
+                .map(|s| s.offset + s.size)
+                .max()
+                .unwrap_or(header.offset);
+            header.filesz = file_end.max(header.offset) - header.offset;
+            header.memsz = (last.addr + last.size) - header.vaddr;
+
+            // The loader rounds the end of the RELRO region down to a page, so the region extends
+            // to the end of its last page unless other data shares it.
+            if plan.ty == SegmentType::GnuRelro {
+                let end = header.vaddr + header.memsz;
+                let page_end = align_up(end, PAGE_SIZE);
+                let shared = self
+                    .sections
+                    .iter()
+                    .any(|s| s.is_alloc() && s.size > 0 && s.addr >= end && s.addr < page_end);
+                if !shared {
+                    header.memsz = page_end - header.vaddr;
+                    header.filesz = header.memsz;
+                }
+            }
+
+            if plan.ty != SegmentType::Load {
+                header.align = plan
+                    .sections
+                    .iter()
+                    .map(|&i| self.sections[i].align)
+                    .max()
+                    .unwrap_or(1);
+            }
+        } else if let Some(paddr) = plan.paddr {
+            header.paddr = paddr;
+        }
+
+        Ok(Segment {
+            header,
+            sections: plan.sections,
+        })
+    }
+
+    /// The virtual and physical addresses the headers are loaded at, if they are loaded.
+    pub fn headers_segment_base(&self) -> Option<(u64, u64)> {
+        let first = self
+            .sections
+            .iter()
+            .find(|s| s.is_alloc() && s.offset < PAGE_SIZE && s.addr % PAGE_SIZE == s.offset)?;
+        Some((first.addr - first.offset, first.lma - first.offset))
+    }
+
+    fn compute_placements(&mut self) {
+        for file in self.placements.iter_mut() {
+            file.fill(None);
+        }
+        for (out, section) in self.sections.iter().enumerate() {
+            for piece in &section.pieces {
+                match piece.content {
+                    Content::Input(id) => {
+                        self.placements[id.file][id.index] = Some((out, piece.offset));
+                    }
+                    Content::Merged(group) => {
+                        for id in &self.merged.sections[group].members {
+                            self.placements[id.file][id.index] = Some((out, piece.offset));
+                        }
+                    }
+                    Content::EhFrame => {
+                        for id in &self.eh_frame.members {
+                            self.placements[id.file][id.index] = Some((out, piece.offset));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for &(from, to) in &self.folds {
+            self.placements[from.file][from.index] = self.placements[to.file][to.index];
+        }
+    }
+
+    pub fn placement(&self, id: SectionId) -> Option<(usize, u64)> {
+        self.placements[id.file][id.index]
+    }
+
+    /// Returns the address of an input section, or `None` if it is not part of the output.
+    pub fn section_address(&self, id: SectionId) -> Option<u64> {
+        let (out, offset) = self.placement(id)?;
+        Some(self.sections[out].addr + offset)
+    }
+
+    /// Returns the address of an offset in an input section, which for members of merged sections
+    /// is the address of the piece at that offset.
+    pub fn input_address(&self, id: SectionId, offset: u64) -> Option<u64> {
+        let offset = self.merged.offset(id, offset).unwrap_or(offset);
+        Some(self.section_address(id)?.wrapping_add(offset))
+    }
+
+    /// Returns the address of a symbol, or `None` if it is defined in a section which is not part
+    /// of the output. Undefined symbols are at address 0, and indirect functions with an IPLT
+    /// entry at the address of the entry.
+    pub fn symbol_address(
+        &self,
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        symbol: SymbolRef,
+    ) -> Option<u64> {
+        match self.iplt_index.get(&symbol) {
+            Some(&index) => self.iplt_entry_address(index),
+            None => self.definition_address(inputs, symbols, symbol),
+        }
+    }
+
+    /// Returns the address a symbol is defined at, which for an indirect function is that of its
+    /// resolver.
+    pub fn definition_address(
+        &self,
+        inputs: &[InputFile],
+        symbols: &SymbolTable,
+        symbol: SymbolRef,
+    ) -> Option<u64> {
+        match symbol {
+            SymbolRef::Global(id) => match symbols.symbols[id].definition {
+                Definition::Undefined => Some(0),
+                Definition::Section {
+                    file,
+                    section,
+                    value,
+                } => self.input_address(
+                    SectionId {
+                        file,
+                        index: section,
+                    },
+                    value,
+                ),
+                Definition::Absolute(value) | Definition::Linker(value) => Some(value),
+                Definition::Common { .. } => self.common_address(id),
+                Definition::Shared { .. } => Some(self.shared_address(id).unwrap_or(0)),
+            },
+            SymbolRef::Local { file, index } => {
+                let sym = &inputs[file].symbols[index];
+                match sym.shndx {
+                    SECTION_HEADER_NUMBER_UNDEF => Some(0),
+                    SECTION_HEADER_NUMBER_ABS => Some(sym.value),
+                    shndx => self.input_address(
+                        SectionId {
+                            file,
+                            index: shndx as usize,
+                        },
+                        sym.value,
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Returns the address a symbol of a shared library has in an executable: that of its copy or
+    /// its canonical PLT entry, if it has either.
+    fn shared_address(&self, id: usize) -> Option<u64> {
+        let dynamic = self.dynamic.as_ref()?;
+        match dynamic.copy(id) {
+            Some(copy) => self.copy_address(copy),
+            None if dynamic.canonical_plt.contains(&id) => self.plt_entry_address(id),
+            None => None,
+        }
+    }
+
+    /// Returns the address of the space a symbol of a shared library is copied to.
+    pub fn copy_address(&self, copy: &CopyReloc) -> Option<u64> {
+        let base = if copy.relro {
+            self.content_address(|c| matches!(c, Content::CopiesRelRo))?
+        } else {
+            self.content_address(|c| matches!(c, Content::Copies))?
+        };
+        Some(base + copy.offset)
+    }
+
+    /// Returns the address of the space allocated to a common symbol.
+    fn common_address(&self, id: usize) -> Option<u64> {
+        let base = self.content_address(|c| matches!(c, Content::Commons))?;
+        Some(base + self.commons.offsets.get(&id)?)
+    }
+
+    /// Returns the address of the GOT entry of `symbol`.
+    pub fn got_entry_address(&self, symbol: SymbolRef) -> Option<u64> {
+        let index = *self.got_index.get(&symbol)?;
+        Some(self.got_address()? + 8 * index as u64)
+    }
+
+    /// Returns the address of the PLT entry of a global symbol, if it has one.
+    pub fn plt_entry_address(&self, id: usize) -> Option<u64> {
+        let index = self.dynamic.as_ref()?.plt_index(id)?;
+        let plt = self.content_address(|c| matches!(c, Content::Plt))?;
+        Some(plt + PLT_ENTRY_SIZE * (index as u64 + 1))
+    }
+
+    /// Returns the address of the `.got.plt` slot of a PLT entry.
+    pub fn got_plt_slot_address(&self, index: usize) -> Option<u64> {
+        let got_plt = self.got_plt_address()?;
+        Some(got_plt + 8 * (GOT_PLT_RESERVED + index as u64))
+    }
+
+    /// Returns the address of an IPLT entry.
+    pub fn iplt_entry_address(&self, index: usize) -> Option<u64> {
+        let iplt = self.content_address(|c| matches!(c, Content::Iplt))?;
+        Some(iplt + PLT_ENTRY_SIZE * index as u64)
+    }
+
+    /// Returns the address of the slot an IPLT entry jumps through.
+    pub fn iplt_slot_address(&self, index: usize) -> Option<u64> {
+        let slots = self.content_address(|c| matches!(c, Content::IgotPlt))?;
+        Some(slots + 8 * index as u64)
+    }
+
+    /// Whether the output is a shared library.
+    pub fn is_shared(&self) -> bool {
+        self.dynamic.as_ref().is_some_and(|d| d.shared)
+    }
+
+    /// Returns the address of the global offset table.
+    pub fn got_address(&self) -> Option<u64> {
+        self.content_address(|c| matches!(c, Content::Got))
+    }
+
+    /// Returns the address of `.got.plt`, which `_GLOBAL_OFFSET_TABLE_` refers to.
+    pub fn got_plt_address(&self) -> Option<u64> {
+        self.content_address(|c| matches!(c, Content::GotPlt))
+    }
+
+    /// Returns the address of the output `.eh_frame`.
+    pub fn eh_frame_address(&self) -> Option<u64> {
+        self.content_address(|c| matches!(c, Content::EhFrame))
+    }
+
+    /// Returns the section header index and the value of a global symbol in the symbol tables of
+    /// the output, by id, or `None` if it is defined in a section which is not part of the output.
+    /// Symbols the linker defines belong to the section holding their address, if any. Copied
+    /// symbols belong to the section of the copy, and functions with a canonical PLT entry are
+    /// undefined with the address of the entry.
+    pub fn symbol_entry(&self, symbols: &SymbolTable, id: usize) -> Option<(u16, u64)> {
+        let symbol = &symbols.symbols[id];
+        match symbol.definition {
+            Definition::Undefined => Some((SECTION_HEADER_NUMBER_UNDEF, 0)),
+            Definition::Shared { .. } => {
+                let value = self.shared_address(id).unwrap_or(0);
+                let shndx = match self.dynamic.as_ref().and_then(|d| d.copy(id)) {
+                    Some(copy) if copy.relro => {
+                        self.content_section(|c| matches!(c, Content::CopiesRelRo))
+                    }
+                    Some(_) => self.content_section(|c| matches!(c, Content::Copies)),
+                    None => None,
+                };
+                Some((
+                    shndx.map_or(SECTION_HEADER_NUMBER_UNDEF, |i| i as u16 + 1),
+                    value,
+                ))
+            }
+            Definition::Section {
+                file,
+                section,
+                value,
+            } => {
+                let id = SectionId {
+                    file,
+                    index: section,
+                };
+                let (out, _) = self.placement(id)?;
+                Some((out as u16 + 1, self.input_address(id, value)?))
+            }
+            Definition::Absolute(value) => Some((SECTION_HEADER_NUMBER_ABS, value)),
+            Definition::Common { .. } => {
+                let out = self.content_section(|c| matches!(c, Content::Commons))?;
+                Some((out as u16 + 1, self.common_address(id)?))
+            }
+            Definition::Linker(value) => {
+                let shndx = self
+                    .sections
+                    .iter()
+                    .position(|s| s.is_alloc() && s.addr <= value && value <= s.addr + s.size)
+                    .map_or(SECTION_HEADER_NUMBER_ABS, |i| i as u16 + 1);
+                Some((shndx, value))
+            }
+        }
+    }
+
+    /// Returns the index of the output section holding the piece matched by `f`.
+    pub fn content_section(&self, f: impl Fn(&Content) -> bool) -> Option<usize> {
+        self.sections
+            .iter()
+            .position(|s| s.pieces.iter().any(|p| f(&p.content)))
+    }
+
+    /// Returns the address of the piece matched by `f`.
+    pub fn content_address(&self, f: impl Fn(&Content) -> bool) -> Option<u64> {
+        self.sections.iter().find_map(|s| {
+            s.pieces
+                .iter()
+                .find(|p| f(&p.content))
+                .map(|p| s.addr + p.offset)
+        })
+    }
+}
+
+/// Returns the size and alignment of a section the linker creates for dynamic linking.
+fn dynamic_size(dynamic: &Dynamic, content: &Content) -> (u64, u64) {
+    let symbols = dynamic.symbols.len() as u64 + 1;
+    let plt = dynamic.plt.len() as u64;
+    match content {
+        Content::Interp => (dynamic.interp.as_ref().map_or(0, |i| i.len() as u64 + 1), 1),
+        Content::DynSym => (SYMBOL_SIZE * symbols, 8),
+        Content::DynStr => (dynamic.strtab.data.len() as u64, 1),
+        Content::VerSym => (2 * symbols, 2),
+        Content::VerDef => (dynamic.verdef_data.len() as u64, 4),
+        Content::Hash => (dynamic.hash_data.len() as u64, 4),
+        Content::GnuHash => (dynamic.gnu_hash_data.len() as u64, 8),
+        Content::VerNeed => (dynamic.verneed_data.len() as u64, 4),
+        Content::RelaDyn => (RELA_SIZE * dynamic.relocs.len() as u64, 8),
+        Content::RelaPlt => (RELA_SIZE * plt, 8),
+        Content::Plt => (PLT_ENTRY_SIZE * (plt + 1), 16),
+        Content::Dynamic => (DYNAMIC_ENTRY_SIZE * dynamic.tags().len() as u64, 8),
+        Content::Copies => dynamic.copies_size(false),
+        Content::CopiesRelRo => dynamic.copies_size(true),
+        _ => (0, 1),
+    }
+}
+
+/// Lists the input sections which may become part of the output, in input order.
+pub fn candidates(inputs: &[InputFile]) -> impl Iterator<Item = SectionId> + '_ {
+    inputs.iter().enumerate().flat_map(|(file, input)| {
+        (0..input.sections.len())
+            .filter(move |&index| is_candidate(input, index))
+            .map(move |index| SectionId { file, index })
+    })
+}
+
+fn is_candidate(input: &InputFile, index: usize) -> bool {
+    is_content(input, index) && input.live[index] && input.section_names[index] != ".note.GNU-stack"
+}
+
+/// Whether a section holds contents for the output rather than information for the linker.
+pub fn is_content(input: &InputFile, index: usize) -> bool {
+    !matches!(
+        input.sections[index].ty,
+        SectionType::Null
+            | SectionType::Symtab
+            | SectionType::Strtab
+            | SectionType::Rela
+            | SectionType::Rel
+            | SectionType::Group
+            | SectionType::SymtabShndx
+    )
+}
+
+/// Returns the name of the output section an input section goes to in the built-in layout.
+pub fn default_output_name(name: &str) -> &str {
+    const PREFIXES: &[&str] = &[
+        ".text",
+        ".rodata",
+        ".data.rel.ro",
+        ".data",
+        ".bss",
+        ".tdata",
+        ".tbss",
+        ".init_array",
+        ".fini_array",
+        ".preinit_array",
+        ".gcc_except_table",
+    ];
+    for prefix in PREFIXES {
+        if name == *prefix || name.starts_with(prefix) && name[prefix.len()..].starts_with('.') {
+            return prefix;
+        }
+    }
+    name
+}
+
+/// Orders output sections in the built-in layout: read-only data first, then code, then writable
+/// data with zero-initialized data last, and finally non-allocated sections.
+fn rank(section: &OutputSection, now: bool) -> u8 {
+    if !section.is_alloc() {
+        6
+    } else if section.flags.contains(SectionFlag64::EXECINSTR) {
+        2
+    } else if !section.flags.contains(SectionFlag64::WRITE) {
+        if section.ty == SectionType::Note {
+            0
+        } else {
+            1
+        }
+    } else if section.is_relro(now) {
+        3
+    } else if section.is_nobits() {
+        5
+    } else {
+        4
+    }
+}
+
+/// The addresses, load addresses and sizes of the output sections, and the values of the script
+/// symbols, after a pass over a linker script.
+type Snapshot = (Vec<(u64, u64, u64)>, Vec<(String, u64)>);
+
+/// The state of laying out the output under a linker script.
+struct ScriptLayout<'a> {
+    inputs: &'a [InputFile],
+    symbols: &'a SymbolTable,
+    script: &'a Script,
+    layout: Layout,
+
+    /// The output section of each output section description in script order, or `None` for
+    /// `/DISCARD/`.
+    desc_sections: Vec<Option<usize>>,
+
+    /// The input sections matched by each input section description in script order.
+    matches: Vec<Vec<SectionId>>,
+
+    /// Input sections which no description matched but which go to an output section of the same
+    /// name, indexed by output section.
+    extras: Vec<Vec<SectionId>>,
+
+    /// Whether each output section is part of the output even if it is empty.
+    keep: Vec<bool>,
+
+    /// Output sections created for orphan sections and the description they follow. Orphans with
+    /// no such description go to the end.
+    orphans: Vec<(Option<usize>, usize)>,
+
+    /// The output sections holding the sections the linker creates, with their contents.
+    synthetic: Vec<(usize, Content)>,
+
+    /// The input section description matching `COMMON`, by index in `matches`, which places the
+    /// common symbols instead of the synthetic `.bss`.
+    commons_input: Option<usize>,
+
+    /// Output sections in the order the current pass laid them out.
+    order: Vec<usize>,
+
+    /// The value of each memory region's origin and length, and its current location.
+    regions: Vec<(u64, u64, u64)>,
+
+    headers_size: u64,
+    dot: u64,
+
+    /// Whether the evaluation is inside `SECTIONS`, where `.` may be used.
+    in_sections: bool,
+
+    /// The start of the output section being laid out.
+    section_start: Option<u64>,
+
+    /// Values assigned to symbols by the script. Kept across passes so that forward references
+    /// see the values of the previous pass.
+    values: HashMap<String, u64>,
+    hidden: HashSet<String>,
+
+    /// Symbols assigned so far in the current pass, for `DEFINED`.
+    assigned: HashSet<String>,
+
+    /// Whether unknown names are errors. Earlier passes evaluate them to 0.
+    strict: bool,
+}
+
+impl<'a> ScriptLayout<'a> {
+    fn new(
+        inputs: &'a [InputFile],
+        symbols: &'a SymbolTable,
+        script: &'a Script,
+        layout: Layout,
+    ) -> Result<Self, String> {
+        let mut state = Self {
+            inputs,
+            symbols,
+            script,
+            layout,
+            desc_sections: vec![],
+            matches: vec![],
+            extras: vec![],
+            keep: vec![],
+            orphans: vec![],
+            synthetic: vec![],
+            commons_input: None,
+            order: vec![],
+            regions: vec![],
+            headers_size: ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE,
+            dot: 0,
+            in_sections: false,
+            section_start: None,
+            values: HashMap::new(),
+            hidden: HashSet::new(),
+            assigned: HashSet::new(),
+            strict: true,
+        };
+
+        for region in &script.memory {
+            let origin = state.eval(&region.origin)?;
+            let length = state.eval(&region.length)?;
+            state.regions.push((origin, length, origin));
+        }
+        Ok(state)
+    }
+
+    fn error(&self, location: Location, msg: impl std::fmt::Display) -> String {
+        format!("{}:{}: {}", self.script.path, location, msg)
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<u64, String> {
+        expr.eval(self)
+            .map_err(|e| format!("{}:{}", self.script.path, e))
+    }
+
+    fn output_descs(&self) -> impl Iterator<Item = &'a OutputSectionDesc> {
+        self.script
+            .commands
+            .iter()
+            .filter_map(|c| match c {
+                Command::Sections(commands) => Some(commands),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|c| match c {
+                SectionsCommand::OutputSection(desc) => Some(desc.as_ref()),
+                _ => None,
+            })
+    }
+
+    /// Assigns input sections to output sections by the input section descriptions, and creates
+    /// output sections for the orphans.
+    fn assign_sections(&mut self) -> Result<(), String> {
+        let mut taken = HashSet::new();
+
+        for desc in self.output_descs() {
+            let out = if desc.is_discard() {
+                None
+            } else {
+                self.layout.sections.push(OutputSection::new(&desc.name));
+                self.extras.push(vec![]);
+                let has_commands = desc
+                    .commands
+                    .iter()
+                    .any(|c| !matches!(c, OutputSectionCommand::Input(_)));
+                self.keep.push(has_commands);
+                Some(self.layout.sections.len() - 1)
+            };
+            self.desc_sections.push(out);
+
+            for command in &desc.commands {
+                match command {
+                    OutputSectionCommand::Input(input) => {
+                        let matched = self.match_inputs(input, &taken);
+                        taken.extend(matched.iter().copied());
+                        if let Some(out) = out {
+                            if self.claims_commons(input) {
+                                let (_, align) = self.layout.synthetic_size(&Content::Commons);
+                                self.layout.sections[out].add_attributes(
+                                    SectionType::Nobits,
+                                    SectionFlag64::ALLOC | SectionFlag64::WRITE,
+                                    align,
+                                    0,
+                                );
+                                self.keep[out] = true;
+                                self.commons_input = Some(self.matches.len());
+                            }
+                            for &id in &matched {
+                                let header = &self.inputs[id.file].sections[id.index];
+                                self.layout.sections[out].add_attributes(
+                                    header.ty,
+                                    header.flags,
+                                    header.addralign.max(1),
+                                    header.entsize,
+                                );
+                            }
+                            self.keep[out] |= !matched.is_empty();
+                        }
+                        self.matches.push(matched);
+                    }
+                    OutputSectionCommand::Data { .. } => {
+                        if let Some(out) = out {
+                            self.layout.sections[out].add_attributes(
+                                SectionType::Progbits,
+                                SectionFlag64::ALLOC | SectionFlag64::WRITE,
+                                1,
+                                0,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(out) = out {
+                let section = &mut self.layout.sections[out];
+                if section.ty == SectionType::Null {
+                    section.add_attributes(SectionType::Progbits, SectionFlag64::ALLOC, 1, 0);
+                }
+                if desc.noload {
+                    section.ty = SectionType::Nobits;
+                }
+            }
+        }
+
+        let orphans: Vec<_> = candidates(self.inputs)
+            .filter(|id| !taken.contains(id))
+            .collect();
+        for id in orphans {
+            let name = &self.inputs[id.file].section_names[id.index];
+            let header = &self.inputs[id.file].sections[id.index];
+
+            let out = match self.layout.sections.iter().position(|s| s.name == *name) {
+                Some(out) => out,
+                None => {
+                    let mut section = OutputSection::new(name);
+                    section.add_attributes(header.ty, header.flags, 1, header.entsize);
+                    let anchor = self.orphan_anchor(&section);
+                    self.layout.sections.push(section);
+                    self.extras.push(vec![]);
+                    self.keep.push(true);
+                    let out = self.layout.sections.len() - 1;
+                    self.orphans.push((anchor, out));
+                    out
+                }
+            };
+            self.layout.sections[out].add_attributes(
+                header.ty,
+                header.flags,
+                header.addralign.max(1),
+                header.entsize,
+            );
+            self.extras[out].push(id);
+            self.keep[out] = true;
+        }
+
+        for (name, ty, flags, entsize, content) in self.layout.synthetic_sections() {
+            if matches!(content, Content::Commons) && self.commons_input.is_some() {
+                continue;
+            }
+            let (_, align) = self.layout.synthetic_size(&content);
+            let out = match self.layout.sections.iter().position(|s| s.name == name) {
+                Some(out) => out,
+                None => {
+                    let mut section = OutputSection::new(name);
+                    section.add_attributes(ty, flags, align, entsize);
+                    let anchor = self.orphan_anchor(&section);
+                    self.layout.sections.push(section);
+                    self.extras.push(vec![]);
+                    self.keep.push(true);
+                    let out = self.layout.sections.len() - 1;
+                    self.orphans.push((anchor, out));
+                    out
+                }
+            };
+            self.layout.sections[out].add_attributes(ty, flags, align, entsize);
+            self.keep[out] = true;
+            self.synthetic.push((out, content));
+        }
+
+        Ok(())
+    }
+
+    /// Whether an input section description is the first to match `COMMON`, the pseudo section of
+    /// common symbols, while the output has any.
+    fn claims_commons(&self, desc: &InputSectionDesc) -> bool {
+        self.commons_input.is_none()
+            && !self.layout.commons.offsets.is_empty()
+            && self
+                .inputs
+                .iter()
+                .any(|i| desc.matches(&i.path, "COMMON").is_some())
+    }
+
+    fn match_inputs(&self, desc: &InputSectionDesc, taken: &HashSet<SectionId>) -> Vec<SectionId> {
+        let mut matched: Vec<(usize, SectionId)> = vec![];
+        for id in candidates(self.inputs) {
+            if taken.contains(&id) {
+                continue;
+            }
+            let input = &self.inputs[id.file];
+            if let Some(pattern) = desc.matches(&input.path, &input.section_names[id.index]) {
+                matched.push((pattern, id));
+            }
+        }
+
+        if desc.sections.iter().all(|p| p.sort == SortKind::None) {
+            return matched.into_iter().map(|(_, id)| id).collect();
+        }
+
+        // Sorted patterns are laid out one after another, each sorted by its own key.
+        let mut result = vec![];
+        for (index, pattern) in desc.sections.iter().enumerate() {
+            let mut group: Vec<SectionId> = matched
+                .iter()
+                .filter(|(p, _)| *p == index)
+                .map(|(_, id)| *id)
+                .collect();
+            match pattern.sort {
+                SortKind::None => {}
+                SortKind::Name => {
+                    group.sort_by_key(|id| &self.inputs[id.file].section_names[id.index]);
+                }
+                SortKind::Alignment => {
+                    group.sort_by_key(|id| {
+                        std::cmp::Reverse(self.inputs[id.file].sections[id.index].addralign)
+                    });
+                }
+                SortKind::InitPriority => {
+                    group.sort_by_key(|id| {
+                        init_priority(&self.inputs[id.file].section_names[id.index])
+                    });
+                }
+            }
+            result.extend(group);
+        }
+        result
+    }
+
+    /// Chooses the output section description an orphan section follows: the last one whose
+    /// output section has the same attributes.
+    fn orphan_anchor(&self, orphan: &OutputSection) -> Option<usize> {
+        let class = |s: &OutputSection| (s.is_alloc(), s.segment_flags(), s.is_nobits());
+        self.desc_sections
+            .iter()
+            .enumerate()
+            .filter_map(|(desc, out)| out.map(|out| (desc, out)))
+            .rev()
+            .find(|(_, out)| class(&self.layout.sections[*out]) == class(orphan))
+            .map(|(desc, _)| desc)
+    }
+
+    /// Evaluates the script until the addresses settle, then lays out the file.
+    fn run(&mut self) -> Result<(), String> {
+        self.strict = false;
+        self.pass()?;
+        self.reorder();
+
+        let mut previous = None;
+        let mut pass = 0;
+        loop {
+            self.strict = false;
+            self.pass()?;
+            let plans = self.plan_segments()?;
+            let headers_size = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * plans.len() as u64;
+
+            let state = self.snapshot();
+            pass += 1;
+            if previous.as_ref() == Some(&state) && headers_size == self.headers_size {
+                break;
+            }
+            if pass >= MAX_PASSES {
+                return Err(format!(
+                    "{}: addresses do not settle; the script may refer to itself circularly",
+                    self.script.path
+                ));
+            }
+            self.headers_size = headers_size;
+            previous = Some(state);
+        }
+
+        self.strict = true;
+        self.pass()?;
+        let plans = self.plan_segments()?;
+
+        self.layout.script_symbols = self
+            .values
+            .iter()
+            .map(|(name, &value)| (name.clone(), value, self.hidden.contains(name)))
+            .collect();
+        self.layout.script_symbols.sort();
+
+        self.remove_empty_sections(plans)
+    }
+
+    /// Reorders the output sections the way the passes lay them out, which moves orphans after
+    /// the sections they follow.
+    fn reorder(&mut self) {
+        let count = self.layout.sections.len();
+        let mut order = self.order.clone();
+        order.extend((0..count).filter(|i| !self.order.contains(i)));
+        let mut map = vec![0; count];
+        for (new, &old) in order.iter().enumerate() {
+            map[old] = new;
+        }
+
+        let mut sections: Vec<_> = std::mem::take(&mut self.layout.sections)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.layout.sections = order.iter().map(|&i| sections[i].take().unwrap()).collect();
+        self.extras = order
+            .iter()
+            .map(|&i| std::mem::take(&mut self.extras[i]))
+            .collect();
+        self.keep = order.iter().map(|&i| self.keep[i]).collect();
+        for out in self.desc_sections.iter_mut().flatten() {
+            *out = map[*out];
+        }
+        for (_, out) in self.orphans.iter_mut() {
+            *out = map[*out];
+        }
+        for (out, _) in self.synthetic.iter_mut() {
+            *out = map[*out];
+        }
+        self.layout.compute_placements();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let sections = self
+            .layout
+            .sections
+            .iter()
+            .map(|s| (s.addr, s.lma, s.size))
+            .collect();
+        let mut values: Vec<_> = self.values.iter().map(|(k, &v)| (k.clone(), v)).collect();
+        values.sort();
+        (sections, values)
+    }
+
+    fn pass(&mut self) -> Result<(), String> {
+        self.dot = 0;
+        self.in_sections = false;
+        self.assigned.clear();
+        self.order.clear();
+        for (i, region) in self.script.memory.iter().enumerate() {
+            let origin = self.eval(&region.origin)?;
+            let length = self.eval(&region.length)?;
+            self.regions[i] = (origin, length, origin);
+        }
+
+        let script = self.script;
+        let mut desc_index = 0;
+        let mut input_index = 0;
+        let mut last_region = None;
+        for command in &script.commands {
+            match command {
+                Command::Assignment(assignment) => self.assign(assignment)?,
+                Command::Assert(assert) => self.check_assert(&assert.expr, &assert.message)?,
+                Command::Sections(commands) => {
+                    self.in_sections = true;
+                    for command in commands {
+                        match command {
+                            SectionsCommand::Assignment(assignment) => self.assign(assignment)?,
+                            SectionsCommand::Assert(assert) => {
+                                self.check_assert(&assert.expr, &assert.message)?
+                            }
+                            SectionsCommand::OutputSection(desc) => {
+                                last_region = self.place_desc(
+                                    desc,
+                                    desc_index,
+                                    &mut input_index,
+                                    last_region,
+                                )?;
+                                for (_, out) in self
+                                    .orphans
+                                    .clone()
+                                    .into_iter()
+                                    .filter(|(anchor, _)| *anchor == Some(desc_index))
+                                {
+                                    self.place_orphan(out, last_region)?;
+                                }
+                                desc_index += 1;
+                            }
+                        }
+                    }
+                    self.in_sections = false;
+                }
+            }
+        }
+
+        for (_, out) in self
+            .orphans
+            .clone()
+            .into_iter()
+            .filter(|(a, _)| a.is_none())
+        {
+            self.place_orphan(out, last_region)?;
+        }
+
+        self.layout.compute_placements();
+        Ok(())
+    }
+
+    fn check_assert(&self, expr: &Expr, message: &str) -> Result<(), String> {
+        if self.strict && self.eval(expr)? == 0 {
+            return Err(self.error(expr.location, message));
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, assignment: &Assignment) -> Result<(), String> {
+        // Returns `None` when dividing by zero.
+        let combine = |current: u64, value: u64| match assignment.op {
+            AssignOp::Set => Some(value),
+            AssignOp::Add => Some(current.wrapping_add(value)),
+            AssignOp::Sub => Some(current.wrapping_sub(value)),
+            AssignOp::Mul => Some(current.wrapping_mul(value)),
+            AssignOp::Div => current.checked_div(value),
+            AssignOp::Shl => Some(current.wrapping_shl(value as u32)),
+            AssignOp::Shr => Some(current.wrapping_shr(value as u32)),
+            AssignOp::And => Some(current & value),
+            AssignOp::Or => Some(current | value),
+        };
+
+        if assignment.symbol == "." {
+            if !self.in_sections {
+                return Err(self.error(assignment.location, "`.` may only be assigned in SECTIONS"));
+            }
+            let mut value = self.eval(&assignment.expr)?;
+            if let Some(start) = self.section_start {
+                // Inside an output section, constants are offsets from its start.
+                if assignment.op == AssignOp::Set && assignment.expr.is_constant() {
+                    value += start;
+                }
+                let value = combine(self.dot, value)
+                    .ok_or_else(|| self.error(assignment.location, "division by zero"))?;
+                if value >= self.dot {
+                    self.dot = value;
+                } else if self.strict {
+                    return Err(self.error(
+                        assignment.location,
+                        "cannot move the location counter backwards",
+                    ));
+                }
+            } else {
+                self.dot = combine(self.dot, value)
+                    .ok_or_else(|| self.error(assignment.location, "division by zero"))?;
+            }
+            return Ok(());
+        }
+
+        if assignment.provide {
+            let wanted = self
+                .symbols
+                .symbol(&assignment.symbol)
+                .is_some_and(|s| s.referenced && !s.is_defined());
+            if !wanted {
+                return Ok(());
+            }
+        }
+
+        let value = self.eval(&assignment.expr)?;
+        let current = if assignment.op == AssignOp::Set {
+            0
+        } else {
+            self.symbol(&assignment.symbol).ok_or_else(|| {
+                self.error(
+                    assignment.location,
+                    format!("undefined symbol `{}`", assignment.symbol),
+                )
+            })?
+        };
+        let value = combine(current, value)
+            .ok_or_else(|| self.error(assignment.location, "division by zero"))?;
+        self.values.insert(assignment.symbol.clone(), value);
+        self.assigned.insert(assignment.symbol.clone());
+        if assignment.hidden {
+            self.hidden.insert(assignment.symbol.clone());
+        }
+        Ok(())
+    }
+
+    /// Lays out the output section of an output section description. Returns the memory region
+    /// the section went to.
+    fn place_desc(
+        &mut self,
+        desc: &OutputSectionDesc,
+        desc_index: usize,
+        input_index: &mut usize,
+        last_region: Option<usize>,
+    ) -> Result<Option<usize>, String> {
+        let input_count = desc
+            .commands
+            .iter()
+            .filter(|c| matches!(c, OutputSectionCommand::Input(_)))
+            .count();
+        let Some(out) = self.desc_sections[desc_index] else {
+            *input_index += input_count;
+            return Ok(last_region);
+        };
+        if !self.keep[out] {
+            *input_index += input_count;
+            return Ok(last_region);
+        }
+
+        let section = &self.layout.sections[out];
+        let alloc = section.is_alloc();
+        let mut align = section.align;
+        if let Some(expr) = &desc.align {
+            align = align.max(self.eval(expr)?);
+        }
+        let subalign = desc.subalign.as_ref().map(|e| self.eval(e)).transpose()?;
+
+        let region = match &desc.region {
+            Some(name) => Some(self.region_index(name, desc.location)?),
+            None if desc.address.is_none() && alloc => self.default_region(section),
+            None => None,
+        };
+
+        let addr = if !alloc {
+            0
+        } else if let Some(expr) = &desc.address {
+            align_up(self.eval(expr)?, desc.align.as_ref().map_or(1, |_| align))
+        } else if let Some(region) = region {
+            align_up(self.regions[region].2, align)
+        } else {
+            align_up(self.dot, align)
+        };
+
+        let saved_dot = self.dot;
+        self.dot = addr;
+        self.section_start = Some(addr);
+        self.layout.sections[out].pieces.clear();
+        self.layout.sections[out].addr = addr;
+
+        self.place_synthetic(out);
+        for command in &desc.commands {
+            match command {
+                OutputSectionCommand::Assignment(assignment) => self.assign(assignment)?,
+                OutputSectionCommand::Assert(assert) => {
+                    self.check_assert(&assert.expr, &assert.message)?
+                }
+                OutputSectionCommand::Input(_) => {
+                    for id in self.matches[*input_index].clone() {
+                        self.place_input(out, id, subalign);
+                    }
+                    if self.commons_input == Some(*input_index) {
+                        self.place_content(out, Content::Commons);
+                    }
+                    *input_index += 1;
+                }
+                OutputSectionCommand::Data { size, expr } => {
+                    let value = self.eval(expr)?;
+                    let offset = self.dot - addr;
+                    self.layout.sections[out].pieces.push(Piece {
+                        offset,
+                        size: *size as u64,
+                        content: Content::Data(value.to_le_bytes()[..*size as usize].to_vec()),
+                    });
+                    self.dot += *size as u64;
+                }
+                OutputSectionCommand::Fill(expr) => {
+                    self.layout.sections[out].fill = Some(self.eval(expr)? as u32);
+                }
+            }
+        }
+        for id in self.extras[out].clone() {
+            self.place_input(out, id, subalign);
+        }
+        if let Some(expr) = &desc.fill {
+            self.layout.sections[out].fill = Some(self.eval(expr)? as u32);
+        }
+        self.section_start = None;
+
+        let size = self.dot - addr;
+        let lma = if let Some(expr) = &desc.lma {
+            self.eval(expr)?
+        } else if let Some(name) = &desc.lma_region {
+            let lma_region = self.region_index(name, desc.location)?;
+            align_up(self.regions[lma_region].2, align)
+        } else {
+            self.inherited_lma(out, addr, region, last_region)
+        };
+
+        let section = &mut self.layout.sections[out];
+        section.addr = addr;
+        section.lma = lma;
+        section.size = size;
+        section.align = align;
+        self.order.push(out);
+
+        if !alloc {
+            self.dot = saved_dot;
+            return Ok(last_region);
+        }
+        self.dot = addr + size;
+
+        if let Some(region) = region {
+            self.advance_region(region, addr + size, &desc.name, desc.location)?;
+        }
+        if let Some(name) = &desc.lma_region {
+            let lma_region = self.region_index(name, desc.location)?;
+            if lma_region != region.unwrap_or(usize::MAX) || lma != addr {
+                let end = if self.layout.sections[out].is_nobits() {
+                    lma
+                } else {
+                    lma + size
+                };
+                self.advance_region(lma_region, end, &desc.name, desc.location)?;
+            }
+        }
+        Ok(region)
+    }
+
+    /// Lays out an orphan output section after the current location.
+    fn place_orphan(&mut self, out: usize, region: Option<usize>) -> Result<(), String> {
+        let section = &self.layout.sections[out];
+        let alloc = section.is_alloc();
+        let region = region.filter(|_| alloc);
+        let addr = match (alloc, region) {
+            (false, _) => 0,
+            (true, Some(region)) => align_up(self.regions[region].2, section.align),
+            (true, None) => align_up(self.dot, section.align),
+        };
+
+        // Non-allocated sections are laid out from 0 and leave the location counter alone.
+        let dot = self.dot;
+        self.dot = addr;
+        self.layout.sections[out].pieces.clear();
+        self.place_synthetic(out);
+        for id in self.extras[out].clone() {
+            self.place_input(out, id, None);
+        }
+        let size = self.dot - addr;
+
+        let lma = self.inherited_lma(out, addr, region, region);
+        let section = &mut self.layout.sections[out];
+        section.addr = addr;
+        section.lma = lma;
+        section.size = size;
+        self.order.push(out);
+
+        if alloc {
+            self.dot = addr + size;
+            if let Some(region) = region {
+                let name = self.layout.sections[out].name.clone();
+                self.advance_region(region, addr + size, &name, Location::default())?;
+            }
+        } else {
+            self.dot = dot;
+        }
+        Ok(())
+    }
+
+    fn place_input(&mut self, out: usize, id: SectionId, subalign: Option<u64>) {
+        let Some((content, size, align)) = self.layout.input_piece(self.inputs, id) else {
+            return;
+        };
+        let start = self.layout.sections[out].addr;
+        self.dot = align_up(self.dot, subalign.unwrap_or(align));
+        let members = match content {
+            Content::Merged(group) => self.layout.merged.sections[group].members.clone(),
+            Content::EhFrame => self.layout.eh_frame.members.clone(),
+            _ => vec![id],
+        };
+        for member in members {
+            self.layout.placements[member.file][member.index] = Some((out, self.dot - start));
+        }
+        self.layout.sections[out].pieces.push(Piece {
+            offset: self.dot - start,
+            size,
+            content,
+        });
+        self.dot += size;
+    }
+
+    /// Places the sections the linker creates which go to `out`.
+    fn place_synthetic(&mut self, out: usize) {
+        let contents: Vec<Content> = self
+            .synthetic
+            .iter()
+            .filter(|(o, _)| *o == out)
+            .map(|(_, content)| content.clone())
+            .collect();
+        for content in contents {
+            self.place_content(out, content);
+        }
+    }
+
+    /// Places a section the linker creates at the location counter in `out`.
+    fn place_content(&mut self, out: usize, content: Content) {
+        let start = self.layout.sections[out].addr;
+        let (size, align) = self.layout.synthetic_size(&content);
+        self.dot = align_up(self.dot, align);
+        self.layout.sections[out].pieces.push(Piece {
+            offset: self.dot - start,
+            size,
+            content,
+        });
+        self.dot += size;
+    }
+
+    /// Sections with no explicit load address keep the distance between their virtual and load
+    /// addresses of the previous section if they are in the same region.
+    fn inherited_lma(
+        &self,
+        out: usize,
+        addr: u64,
+        region: Option<usize>,
+        last_region: Option<usize>,
+    ) -> u64 {
+        let prev = self
+            .order
+            .iter()
+            .rev()
+            .map(|&i| &self.layout.sections[i])
+            .find(|s| s.is_alloc());
+        match prev {
+            Some(prev) if region == last_region && self.layout.sections[out].is_alloc() => {
+                addr.wrapping_add(prev.lma.wrapping_sub(prev.addr))
+            }
+            _ => addr,
+        }
+    }
+
+    fn region_index(&self, name: &str, location: Location) -> Result<usize, String> {
+        self.script
+            .memory
+            .iter()
+            .position(|r| r.name == name)
+            .ok_or_else(|| self.error(location, format!("undefined memory region `{}`", name)))
+    }
+
+    /// Finds the first memory region whose attributes accept the section.
+    fn default_region(&self, section: &OutputSection) -> Option<usize> {
+        self.script.memory.iter().position(|region| {
+            let mut negated = false;
+            let mut accepted = false;
+            let mut rejected = false;
+            for c in region.attributes.chars() {
+                let matches = match c.to_ascii_lowercase() {
+                    '!' => {
+                        negated = true;
+                        continue;
+                    }
+                    'r' | 'a' | 'l' | 'i' => true,
+                    'w' => section.flags.contains(SectionFlag64::WRITE),
+                    'x' => section.flags.contains(SectionFlag64::EXECINSTR),
+                    _ => false,
+                };
+                if negated {
+                    rejected |= matches;
+                } else {
+                    accepted |= matches;
+                }
+            }
+            accepted && !rejected
+        })
+    }
+
+    fn advance_region(
+        &mut self,
+        region: usize,
+        end: u64,
+        section: &str,
+        location: Location,
+    ) -> Result<(), String> {
+        let (origin, length, _) = self.regions[region];
+        self.regions[region].2 = end;
+        if self.strict && end > origin + length {
+            return Err(self.error(
+                location,
+                format!(
+                    "section `{}` will not fit in region `{}`: overflowed by {} bytes",
+                    section,
+                    self.script.memory[region].name,
+                    end - (origin + length)
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Plans segments by `PHDRS` if the script has it, and automatically otherwise.
+    fn plan_segments(&self) -> Result<Vec<SegmentPlan>, String> {
+        let live = |i: usize| self.keep[i] && self.layout.sections[i].is_alloc();
+        if self.script.phdrs.is_empty() {
+            let mut layout = Layout {
+                sections: self.layout.sections.clone(),
+                relro: self.layout.relro,
+                bind_now: self.layout.bind_now,
+                exec_stack: self.layout.exec_stack,
+                ..Default::default()
+            };
+            for (i, section) in layout.sections.iter_mut().enumerate() {
+                if !self.keep[i] {
+                    section.flags.remove(SectionFlag64::ALLOC);
+                }
+            }
+            let mut plans = layout.plan_segments(true);
+            if let Some(plan) = plans.first_mut() {
+                let first = &self.layout.sections[plan.sections[0]];
+                plan.headers = first.addr % PAGE_SIZE >= self.headers_size;
+            }
+            return Ok(plans);
+        }
+
+        let mut plans: Vec<SegmentPlan> = vec![];
+        for phdr in &self.script.phdrs {
+            plans.push(SegmentPlan {
+                ty: phdr.ty,
+                flags: phdr.flags.unwrap_or(SegmentFlag::empty()),
+                sections: vec![],
+                headers: phdr.filehdr || phdr.phdrs,
+                paddr: phdr.at.as_ref().map(|e| self.eval(e)).transpose()?,
+            });
+        }
+
+        let mut current: Vec<usize> = vec![];
+        let descs: Vec<_> = self.output_descs().collect();
+        for (out, section) in self.layout.sections.iter().enumerate() {
+            if !live(out) {
+                continue;
+            }
+            let desc = self.desc_sections.iter().position(|&o| o == Some(out));
+            if let Some(desc) = desc {
+                let desc = descs[desc];
+                if !desc.phdrs.is_empty() {
+                    current = desc
+                        .phdrs
+                        .iter()
+                        .map(|name| {
+                            self.script
+                                .phdrs
+                                .iter()
+                                .position(|p| p.name == *name)
+                                .ok_or_else(|| {
+                                    self.error(
+                                        desc.location,
+                                        format!("undefined segment `{}`", name),
+                                    )
+                                })
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+            }
+            if current.is_empty() {
+                return Err(format!(
+                    "{}: section `{}` is not assigned to any segment",
+                    self.script.path, section.name
+                ));
+            }
+            for &plan in &current {
+                plans[plan].sections.push(out);
+                if self.script.phdrs[plan].flags.is_none() {
+                    plans[plan].flags |= section.segment_flags();
+                }
+            }
+        }
+
+        for plan in plans.iter_mut() {
+            if plan.flags.is_empty() {
+                plan.flags = SegmentFlag::R;
+            }
+        }
+        Ok(plans)
+    }
+
+    /// Drops the output sections which are neither kept nor allocated to anything, then lays out
+    /// the file.
+    fn remove_empty_sections(&mut self, plans: Vec<SegmentPlan>) -> Result<(), String> {
+        let mut map = vec![None; self.layout.sections.len()];
+        let mut sections = vec![];
+        for (i, section) in std::mem::take(&mut self.layout.sections)
+            .into_iter()
+            .enumerate()
+        {
+            if self.keep[i] {
+                map[i] = Some(sections.len());
+                sections.push(section);
+            }
+        }
+        self.layout.sections = sections;
+
+        let plans = plans
+            .into_iter()
+            .map(|mut plan| {
+                plan.sections = plan.sections.iter().filter_map(|&i| map[i]).collect();
+                plan
+            })
+            .collect();
+        self.layout.finish(plans, self.headers_size)
+    }
+}
+
+impl EvalContext for ScriptLayout<'_> {
+    fn dot(&self) -> Option<u64> {
+        self.in_sections.then_some(self.dot)
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        if let Some(&value) = self.values.get(name) {
+            return Some(value);
+        }
+        let value = self.symbols.get(name).and_then(|id| {
+            if !self.symbols.symbols[id].is_defined() {
+                return None;
+            }
+            self.layout
+                .symbol_address(self.inputs, self.symbols, SymbolRef::Global(id))
+        });
+        match value {
+            Some(value) => Some(value),
+            None if !self.strict => Some(0),
+            None => None,
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.assigned.contains(name) || self.symbols.symbol(name).is_some_and(|s| s.is_defined())
+    }
+
+    fn section(&self, name: &str) -> Option<SectionInfo> {
+        let section = self.layout.sections.iter().find(|s| s.name == name)?;
+        Some(SectionInfo {
+            addr: section.addr,
+            lma: section.lma,
+            size: section.size,
+            align: section.align,
+        })
+    }
+
+    fn region(&self, name: &str) -> Option<(u64, u64)> {
+        let index = self.script.memory.iter().position(|r| r.name == name)?;
+        self.regions
+            .get(index)
+            .map(|&(origin, length, _)| (origin, length))
+    }
+
+    fn headers_size(&self) -> u64 {
+        self.headers_size
+    }
+
+    fn max_page_size(&self) -> u64 {
+        PAGE_SIZE
+    }
+
+    fn common_page_size(&self) -> u64 {
+        PAGE_SIZE
+    }
+}
+
+/// Evaluates expressions once the output is laid out, for `--defsym` without a linker script.
+pub struct LayoutContext<'a> {
+    pub inputs: &'a [InputFile],
+    pub symbols: &'a SymbolTable,
+    pub layout: &'a Layout,
+}
+
+impl EvalContext for LayoutContext<'_> {
+    fn dot(&self) -> Option<u64> {
+        None
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        let id = self.symbols.get(name)?;
+        if !self.symbols.symbols[id].is_defined() {
+            return None;
+        }
+        self.layout
+            .symbol_address(self.inputs, self.symbols, SymbolRef::Global(id))
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.symbols.symbol(name).is_some_and(|s| s.is_defined())
+    }
+
+    fn section(&self, name: &str) -> Option<SectionInfo> {
+        let section = self.layout.sections.iter().find(|s| s.name == name)?;
+        Some(SectionInfo {
+            addr: section.addr,
+            lma: section.lma,
+            size: section.size,
+            align: section.align,
+        })
+    }
+
+    fn region(&self, _name: &str) -> Option<(u64, u64)> {
+        None
+    }
+
+    fn headers_size(&self) -> u64 {
+        self.layout.headers_size
+    }
+
+    fn max_page_size(&self) -> u64 {
+        PAGE_SIZE
+    }
+
+    fn common_page_size(&self) -> u64 {
+        PAGE_SIZE
+    }
+}
+
+/// Returns the priority encoded in `.init_array.N` or `.ctors.N`. Sections without one run last.
+fn init_priority(name: &str) -> u64 {
+    let suffix = name.rsplit('.').next().and_then(|s| s.parse::<u64>().ok());
+    match suffix {
+        Some(n) if name.starts_with(".ctors.") || name.starts_with(".dtors.") => {
+            65535u64.saturating_sub(n)
+        }
+        Some(n) => n,
+        None => 65536,
+    }
+}
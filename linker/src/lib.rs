@@ -1,14 +1,69 @@
 use std::{
     fs::OpenOptions,
+    io::Write,
     os::unix::fs::{OpenOptionsExt, PermissionsExt},
 };
 
+use crate::{
+    dynamic::Dynamic,
+    eh_frame::EhFrame,
+    elf::GNU_PROPERTY_X86_FEATURE_1_IBT,
+    inputs::SectionId,
+    layout::{Commons, Layout, LayoutContext, Synthetic},
+    merge::MergedSections,
+    options::{Options, Strip},
+    script::Script,
+    symbols::SymbolTable,
+};
+
+pub mod archive;
+pub mod build_id;
+pub mod comdat;
+pub mod demangle;
+pub mod dynamic;
+pub mod eh_frame;
 pub mod elf;
+pub mod gc;
+pub mod hash;
+pub mod icf;
 pub mod inputs;
+pub mod layout;
+pub mod library;
+pub mod map;
+pub mod merge;
+pub mod note;
+pub mod options;
+pub mod output;
+pub mod property;
+pub mod provide;
+pub mod relocation;
+pub mod reproduce;
+pub mod script;
+pub mod symbols;
 pub mod util;
+pub mod version;
+pub mod xref;
+
+#[cfg(test)]
+mod tests;
+
+pub fn main(args: Vec<String>) -> i32 {
+    let options = match Options::parse(args.get(1..).unwrap_or_default()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let image = match link(&options) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
 
-pub fn main(_args: Vec<String>) -> i32 {
-    let filename = "a.out";
+    let filename = &options.output;
     let file = match OpenOptions::new()
         .create(true)
         .truncate(true)
@@ -22,6 +77,10 @@ pub fn main(_args: Vec<String>) -> i32 {
             return 1;
         }
     };
+    if let Err(e) = (&file).write_all(&image) {
+        eprintln!("cannot write {}: {}", filename, e);
+        return 1;
+    }
     let mut perm = match file.metadata() {
         Ok(meta) => meta.permissions(),
         Err(e) => {
@@ -37,3 +96,220 @@ pub fn main(_args: Vec<String>) -> i32 {
 
     0
 }
+
+/// Links the inputs given by `options` and returns the bytes of the output.
+pub fn link(options: &Options) -> Result<Vec<u8>, String> {
+    let mut script = options.script.as_deref().map(Script::read).transpose()?;
+    let defsyms = options
+        .defsyms
+        .iter()
+        .map(|d| script::parse_defsym(d))
+        .collect::<Result<Vec<_>, _>>()?;
+    // A linker script evaluates `--defsym` assignments after its own; otherwise they are
+    // evaluated once the output is laid out.
+    if let Some(script) = &mut script {
+        script
+            .commands
+            .extend(defsyms.iter().cloned().map(script::Command::Assignment));
+    }
+    let entry_name = options
+        .entry
+        .as_deref()
+        .or_else(|| script.as_ref().and_then(|s| s.entry.as_deref()))
+        .unwrap_or("_start");
+    // Symbols the options name are needed as if an input referred to them.
+    let mut roots: Vec<(String, &str)> = options
+        .undefined
+        .iter()
+        .map(|name| (name.clone(), "--undefined"))
+        .collect();
+    for assignment in &defsyms {
+        let names = assignment.expr.symbols().into_iter();
+        roots.extend(names.map(|name| (name.into(), "--defsym")));
+    }
+    if !options.shared {
+        roots.push((entry_name.into(), "--entry"));
+    }
+    let mut versions = script.as_ref().map_or(vec![], |s| s.versions.clone());
+    for path in &options.version_scripts {
+        versions.extend(script::read_version_script(path)?);
+    }
+    let mut dynamic_list = vec![];
+    for path in &options.dynamic_lists {
+        dynamic_list.extend(script::read_dynamic_list(path)?);
+    }
+
+    let paths = library::resolve(options, script.as_ref())?;
+    if paths.is_empty() {
+        return Err("no input files".into());
+    }
+    if let Some(path) = &options.reproduce {
+        reproduce::write(path, options, script.as_ref(), &paths)?;
+    }
+    let mut inputs = archive::load(&paths, &roots, &options.wrap, options.threads)?;
+    if !options.trace_symbols.is_empty() {
+        xref::trace_symbols(&options.trace_symbols, &inputs);
+    }
+    if let Some(path) = &options.why_extract {
+        xref::why_extract(path, &inputs)?;
+    }
+
+    if options.exec_stack.is_none() {
+        for input in &inputs {
+            if let Some(reason) = input.exec_stack_reason() {
+                eprintln!("warning: {}: {}", input.path, reason);
+            }
+        }
+    }
+
+    if options.strip != Strip::None {
+        for input in &mut inputs {
+            for index in 0..input.sections.len() {
+                let name = &input.section_names[index];
+                if name.starts_with(".debug") || name.starts_with(".zdebug") {
+                    input.live[index] = false;
+                }
+            }
+        }
+    }
+
+    comdat::select(&mut inputs)?;
+    let mut x86_features = property::merge(&mut inputs, options)?;
+    let mut symbols = SymbolTable::new(&inputs, &options.wrap)?;
+    for assignment in &defsyms {
+        symbols.define_linker(&assignment.symbol, 0, false);
+    }
+    if !versions.is_empty() {
+        version::apply_version_script(&mut symbols, &versions);
+    }
+    if !options.dynamic_lists.is_empty() {
+        dynamic::apply_dynamic_list(&mut symbols, &dynamic_list, options.shared);
+    }
+    if !options.shared {
+        library::check_shared_undefined(&inputs, &symbols, options)?;
+    }
+    if options.gc_sections {
+        // Symbols other modules may bind to are used even if nothing here refers to them.
+        let exported: Vec<&str> = dynamic::dynamic_symbols(&inputs, &symbols, options.shared)
+            .into_iter()
+            .map(|id| symbols.symbols[id].name.as_str())
+            .collect();
+        let roots: Vec<&str> = [entry_name]
+            .into_iter()
+            .chain(roots.iter().map(|(name, _)| name.as_str()))
+            .chain(exported)
+            .collect();
+        let removed = gc::collect(&mut inputs, &symbols, script.as_ref(), &roots)?;
+        if options.print_gc_sections {
+            for id in removed {
+                let input = &inputs[id.file];
+                if input.sections[id.index].size == 0 {
+                    continue;
+                }
+                eprintln!(
+                    "removing unused section '{}' in file '{}'",
+                    input.section_names[id.index], input.path
+                );
+            }
+        }
+    }
+
+    let folds = icf::fold(&mut inputs, &symbols, options.icf)?;
+    if options.print_icf_sections {
+        let describe = |id: SectionId| {
+            let input = &inputs[id.file];
+            format!("{}:({})", input.path, input.section_names[id.index])
+        };
+        for (kept, folded) in folds {
+            eprintln!("selected section {}", describe(kept));
+            for id in folded {
+                eprintln!("  removing identical section {}", describe(id));
+            }
+        }
+    }
+
+    let dynamic = options.shared || inputs.iter().any(|i| i.shared.is_some());
+    let provided = provide::define(&inputs, &mut symbols, script.as_ref(), dynamic);
+    let scan = relocation::scan(&inputs, &symbols, options.shared)?;
+    // PLT and IPLT entries do not start with `endbr64`, so they cannot be the target of indirect
+    // branches.
+    if !scan.plt.is_empty() || !scan.iplt.is_empty() {
+        x86_features &= !GNU_PROPERTY_X86_FEATURE_1_IBT;
+    }
+    let synthetic = Synthetic {
+        got: scan.got,
+        iplt: scan.iplt,
+        got_plt: scan.got_plt,
+        merged: MergedSections::new(&inputs, options.optimize >= 2)?,
+        eh_frame: EhFrame::new(&inputs, &symbols)?,
+        x86_features,
+        dynamic: Dynamic::new(
+            &inputs,
+            &symbols,
+            options,
+            &versions,
+            scan.plt,
+            scan.relocs,
+            &scan.addressed,
+        ),
+        commons: Commons::new(&symbols),
+    };
+    let layout = match &script {
+        Some(script) => Layout::with_script(&inputs, &symbols, synthetic, options, script)?,
+        None => Layout::new(&inputs, synthetic, options)?,
+    };
+    for (name, value, hidden) in &layout.script_symbols {
+        symbols.define_linker(name, *value, *hidden);
+    }
+    provide::assign(&mut symbols, &layout, &provided);
+    if script.is_none() {
+        for assignment in &defsyms {
+            let ctx = LayoutContext {
+                inputs: &inputs,
+                symbols: &symbols,
+                layout: &layout,
+            };
+            let value = assignment
+                .expr
+                .eval(&ctx)
+                .map_err(|e| format!("--defsym: {}", e))?;
+            symbols.define_linker(&assignment.symbol, value, false);
+        }
+    }
+
+    let entry = match symbols.get(entry_name) {
+        Some(id) if symbols.symbols[id].is_defined() => layout
+            .symbol_address(&inputs, &symbols, symbols::SymbolRef::Global(id))
+            .unwrap_or(0),
+        // Shared libraries need no entry point.
+        _ if options.shared => 0,
+        _ => {
+            let fallback = layout
+                .sections
+                .iter()
+                .find(|s| s.flags.contains(elf::SectionFlag64::EXECINSTR))
+                .map_or(0, |s| s.addr);
+            eprintln!(
+                "warning: cannot find entry symbol {}; defaulting to 0x{:x}",
+                entry_name, fallback
+            );
+            fallback
+        }
+    };
+
+    let image = output::write(&inputs, &symbols, &layout, entry)?;
+    let cref = options.cref.then(|| xref::cref(&inputs, &symbols));
+    if let Some(path) = &options.map {
+        map::write(
+            path,
+            options.map_format,
+            &inputs,
+            &symbols,
+            &layout,
+            cref.as_deref(),
+        )?;
+    } else if let Some(cref) = &cref {
+        print!("{}", xref::cref_text(&inputs, cref));
+    }
+    Ok(image)
+}
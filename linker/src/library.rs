@@ -0,0 +1,395 @@
+//! The search for libraries and the expansion of linker scripts given as inputs.
+//!
+//! `-lfoo` is looked up as `libfoo.so` and then `libfoo.a` in each directory in turn: those given
+//! by `-L`, then those given by `SEARCH_DIR`, then the default ones. `-Bstatic` restricts the
+//! search to archives, and `-l:file` looks up `file` as is. Default directories, and `-L`
+//! directories starting with `=` or `$SYSROOT`, are relative to `--sysroot`.
+//!
+//! An input which is neither an object file nor an archive is a linker script, whose `INPUT` and
+//! `GROUP` commands name more inputs, as in the `libc.so` of glibc. Since archives are searched
+//! together regardless of their position, a group is the same as its members.
+//!
+//! Executables must not leave the symbols their shared libraries refer to undefined. Those may be
+//! defined by the libraries the shared libraries need in turn, which are found the way the loader
+//! finds them: in the directories given by `-rpath-link` and `-rpath`, then `LD_LIBRARY_PATH`, then
+//! the `DT_RUNPATH` or `DT_RPATH` of the library needing them, then the default directories.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    io::Read as _,
+    path::Path,
+};
+
+use crate::{
+    archive::Archive,
+    dynamic::DynamicEntry,
+    elf::SymbolBinding,
+    inputs::{InputFile, ObjectFile},
+    options::{Input, Options},
+    script::Script,
+    symbols::SymbolTable,
+};
+
+/// The directories searched after those given by `-L` and `SEARCH_DIR`.
+const DEFAULT_DIRS: &[&str] = &[
+    "/usr/local/lib/x86_64-linux-gnu",
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/usr/local/lib64",
+    "/lib64",
+    "/usr/lib64",
+    "/usr/local/lib",
+    "/lib",
+    "/usr/lib",
+];
+
+/// How deeply linker scripts may name other linker scripts, which stops scripts naming each other
+/// from recursing forever.
+const MAX_SCRIPT_DEPTH: usize = 16;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// A file to load, found for an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPath {
+    pub path: String,
+
+    /// Whether a shared library is only needed if it defines a symbol an object file refers to.
+    pub as_needed: bool,
+}
+
+/// Finds the files of the inputs given by `options` and by the `INPUT` and `GROUP` commands of
+/// `script`, expanding the linker scripts among them.
+pub fn resolve(options: &Options, script: Option<&Script>) -> Result<Vec<InputPath>, String> {
+    let dirs = library_dirs(options, script);
+    let mut paths = vec![];
+    for input in &options.inputs {
+        paths.extend(resolve_input(options, &dirs, input)?);
+    }
+    if let Some(script) = script {
+        for input in &script.inputs {
+            let path = find_script_input(options, &dirs, script, input)?;
+            add(options, &dirs, input, path, 0, &mut paths)?;
+        }
+    }
+    Ok(paths)
+}
+
+/// Finds the files of an input given on the command line, searching `dirs` for libraries.
+pub fn resolve_input(
+    options: &Options,
+    dirs: &[String],
+    input: &Input,
+) -> Result<Vec<InputPath>, String> {
+    let path = if input.library {
+        search(dirs, &input.name, input.static_only)?
+    } else {
+        input.name.clone()
+    };
+    let mut paths = vec![];
+    add(options, dirs, input, path, 0, &mut paths)?;
+    Ok(paths)
+}
+
+/// The directories searched for libraries, in order.
+pub fn library_dirs(options: &Options, script: Option<&Script>) -> Vec<String> {
+    let mut dirs: Vec<String> = options
+        .library_paths
+        .iter()
+        .map(|dir| in_sysroot(options, dir))
+        .collect();
+    if let Some(script) = script {
+        dirs.extend(
+            script
+                .search_dirs
+                .iter()
+                .map(|dir| in_sysroot(options, dir)),
+        );
+    }
+    let sysroot = options.sysroot.as_deref().unwrap_or("");
+    dirs.extend(DEFAULT_DIRS.iter().map(|dir| format!("{}{}", sysroot, dir)));
+    dirs
+}
+
+/// Looks the library `name` of `-lname` up in `dirs`.
+pub fn search(dirs: &[String], name: &str, static_only: bool) -> Result<String, String> {
+    let candidates = match name.strip_prefix(':') {
+        Some(file) => vec![file.to_string()],
+        None if static_only => vec![format!("lib{}.a", name)],
+        None => vec![format!("lib{}.so", name), format!("lib{}.a", name)],
+    };
+    for dir in dirs {
+        for file in &candidates {
+            let path = Path::new(dir).join(file);
+            if path.is_file() {
+                return Ok(path.to_string_lossy().into());
+            }
+        }
+    }
+    Err(format!("cannot find -l{}", name))
+}
+
+/// A directory given by `-L` or `SEARCH_DIR`, with a leading `=` or `$SYSROOT` replaced by the
+/// sysroot.
+fn in_sysroot(options: &Options, dir: &str) -> String {
+    let sysroot = options.sysroot.as_deref().unwrap_or("");
+    match dir
+        .strip_prefix('=')
+        .or_else(|| dir.strip_prefix("$SYSROOT"))
+    {
+        Some(rest) => format!("{}{}", sysroot, rest),
+        None => dir.into(),
+    }
+}
+
+/// Adds the file at `path` found for `input`, or the inputs it names if it is a linker script.
+fn add(
+    options: &Options,
+    dirs: &[String],
+    input: &Input,
+    path: String,
+    depth: usize,
+    paths: &mut Vec<InputPath>,
+) -> Result<(), String> {
+    if !is_script(&path)? {
+        paths.push(InputPath {
+            path,
+            as_needed: input.as_needed,
+        });
+        return Ok(());
+    }
+    if depth == MAX_SCRIPT_DEPTH {
+        return Err(format!("{}: linker scripts nest too deeply", path));
+    }
+
+    let script = Script::read(&path)?;
+    // The directories of the script's `SEARCH_DIR` commands are searched first for its inputs.
+    let dirs: Vec<String> = script
+        .search_dirs
+        .iter()
+        .map(|dir| in_sysroot(options, dir))
+        .chain(dirs.iter().cloned())
+        .collect();
+    for inner in &script.inputs {
+        // The inputs of a script keep the `-Bstatic` and `--as-needed` the script was given with.
+        let inner = Input {
+            as_needed: input.as_needed || inner.as_needed,
+            static_only: input.static_only,
+            ..inner.clone()
+        };
+        let found = find_script_input(options, &dirs, &script, &inner)?;
+        add(options, &dirs, &inner, found, depth + 1, paths)?;
+    }
+    Ok(())
+}
+
+/// Finds a file named by `INPUT` or `GROUP`. Absolute paths in a script inside the sysroot are
+/// relative to the sysroot, and other paths not found from the current directory are searched
+/// for like libraries.
+fn find_script_input(
+    options: &Options,
+    dirs: &[String],
+    script: &Script,
+    input: &Input,
+) -> Result<String, String> {
+    if input.library {
+        return search(dirs, &input.name, input.static_only);
+    }
+    let name = &input.name;
+    if name.starts_with('/') {
+        if let Some(sysroot) = &options.sysroot {
+            let inside = fs::canonicalize(&script.path)
+                .ok()
+                .zip(fs::canonicalize(sysroot).ok())
+                .is_some_and(|(script, sysroot)| script.starts_with(sysroot));
+            let path = format!("{}{}", sysroot, name);
+            if inside && Path::new(&path).is_file() {
+                return Ok(path);
+            }
+        }
+        return Ok(name.clone());
+    }
+    if Path::new(name).is_file() {
+        return Ok(name.clone());
+    }
+    search(dirs, &format!(":{}", name), false).map_err(|_| {
+        format!(
+            "{}: cannot find {} named by the linker script",
+            script.path, name
+        )
+    })
+}
+
+/// Whether the file at `path` is a linker script, i.e. neither an ELF file nor an archive.
+fn is_script(path: &str) -> Result<bool, String> {
+    let mut magic = [0; 8];
+    let mut file = fs::File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    let len = file
+        .read(&mut magic)
+        .map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let magic = &magic[..len];
+    Ok(!magic.starts_with(ELF_MAGIC) && !Archive::is_archive(magic))
+}
+
+/// Checks that the symbols the shared libraries among `inputs` refer to are defined by the
+/// executable, by those libraries, or by the libraries they need. Libraries which cannot be found
+/// are warned about, and the check is then skipped since it would report false errors.
+pub fn check_shared_undefined(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    options: &Options,
+) -> Result<(), String> {
+    let direct: Vec<&InputFile> = inputs.iter().filter(|i| i.shared.is_some()).collect();
+    let mut loaded: HashSet<String> = direct
+        .iter()
+        .map(|i| i.shared.as_ref().unwrap().soname.clone())
+        .collect();
+    let mut indirect: Vec<InputFile> = vec![];
+    let mut complete = true;
+
+    // The libraries to look the needed libraries of up, as indexes into `direct` and then into
+    // `indirect` past its length.
+    let mut queue: VecDeque<usize> = (0..direct.len()).collect();
+    while let Some(index) = queue.pop_front() {
+        let library = match index.checked_sub(direct.len()) {
+            Some(i) => &indirect[i],
+            None => direct[index],
+        };
+        let mut needed = vec![];
+        let mut runpath = None;
+        let mut rpath = None;
+        for entry in library.object.dynamic_entries()? {
+            match entry? {
+                DynamicEntry::Needed(name) => needed.push(name),
+                DynamicEntry::Runpath(path) => runpath = Some(path),
+                DynamicEntry::Rpath(path) => rpath = Some(path),
+                _ => {}
+            }
+        }
+        // `DT_RPATH` is ignored if there is a `DT_RUNPATH`.
+        let own_dirs: Vec<String> = runpath
+            .or(rpath)
+            .iter()
+            .flat_map(|path| path.split(':'))
+            .map(|dir| match expand_origin(dir, &library.path) {
+                Some(dir) => dir,
+                None => format!("{}{}", options.sysroot.as_deref().unwrap_or(""), dir),
+            })
+            .collect();
+
+        let path = library.path.clone();
+        for name in needed {
+            if !loaded.insert(name.clone()) {
+                continue;
+            }
+            let Some(found) = find_needed(options, &own_dirs, &name) else {
+                eprintln!(
+                    "warning: {}, needed by {}, not found (try using -rpath or -rpath-link)",
+                    name, path
+                );
+                complete = false;
+                continue;
+            };
+            let data = fs::read(&found).map_err(|e| format!("cannot open {}: {}", found, e))?;
+            let object = ObjectFile::from_reader(data.as_slice())
+                .map_err(|e| format!("{}: {}", found, e))?;
+            let input = InputFile::new_shared(found.clone(), object)
+                .map_err(|e| format!("{}: {}", found, e))?;
+            indirect.push(input);
+            queue.push_back(direct.len() + indirect.len() - 1);
+        }
+    }
+    if !complete {
+        return Ok(());
+    }
+
+    let mut defined = HashSet::new();
+    for input in &indirect {
+        for (sym, name) in input.symbols.iter().zip(&input.symbol_names) {
+            if !sym.is_undefined() && sym.binding != SymbolBinding::Local {
+                defined.insert(name.split('@').next().unwrap());
+            }
+        }
+    }
+    let mut errors = vec![];
+    for input in direct.into_iter().chain(&indirect) {
+        for (sym, name) in input.symbols.iter().zip(&input.symbol_names).skip(1) {
+            if !sym.is_undefined() || sym.binding != SymbolBinding::Global {
+                continue;
+            }
+            let found = symbols.symbol(name).is_some_and(|s| s.is_defined())
+                || defined.contains(name.as_str());
+            if !found {
+                errors.push(format!(
+                    "undefined symbol: {}\n>>> referenced by {}",
+                    name, input.path
+                ));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Finds a library a shared library needs, `own_dirs` being the directories of its `DT_RUNPATH`
+/// or `DT_RPATH`.
+fn find_needed(options: &Options, own_dirs: &[String], name: &str) -> Option<String> {
+    if name.contains('/') {
+        return Path::new(name).is_file().then(|| name.into());
+    }
+    let env_dirs = |var: &str| -> Vec<String> {
+        std::env::var(var)
+            .map(|v| {
+                v.split(':')
+                    .filter(|d| !d.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let split = |paths: &[String]| -> Vec<String> {
+        paths
+            .iter()
+            .flat_map(|p| p.split(':'))
+            .map(String::from)
+            .collect()
+    };
+    let mut dirs = split(&options.rpath_link);
+    // `$ORIGIN` in `-rpath` is the directory of the output, as it is for the loader.
+    dirs.extend(
+        split(&options.rpath)
+            .iter()
+            .map(|dir| expand_origin(dir, &options.output).unwrap_or_else(|| dir.clone())),
+    );
+    if options.rpath.is_empty() {
+        dirs.extend(env_dirs("LD_RUN_PATH"));
+    }
+    dirs.extend(env_dirs("LD_LIBRARY_PATH"));
+    dirs.extend_from_slice(own_dirs);
+    let sysroot = options.sysroot.as_deref().unwrap_or("");
+    dirs.extend(DEFAULT_DIRS.iter().map(|dir| format!("{}{}", sysroot, dir)));
+    dirs.iter()
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into())
+}
+
+/// Replaces `$ORIGIN` and `${ORIGIN}` in a directory of a run path by the directory of the file
+/// at `path`, or returns `None` if there are none.
+fn expand_origin(dir: &str, path: &str) -> Option<String> {
+    if !dir.contains("$ORIGIN") && !dir.contains("${ORIGIN}") {
+        return None;
+    }
+    let origin = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().into_owned(),
+        _ => ".".into(),
+    };
+    Some(
+        dir.replace("${ORIGIN}", &origin)
+            .replace("$ORIGIN", &origin),
+    )
+}
@@ -0,0 +1,367 @@
+//! Map files, requested by `-Map`, describing where everything in the output came from.
+//!
+//! The map lists the archive members extracted and why, the input sections discarded, and each
+//! output section with the input sections placed in it and the global symbols they define, then
+//! the cross-reference table if `--cref` is given. It is written as text for people, or as JSON
+//! for tools with `--map-format=json`.
+
+use std::{collections::HashMap, fmt::Write as _, fs};
+
+use crate::{
+    inputs::{InputFile, SectionId},
+    layout::{is_content, Content, Layout},
+    options::MapFormat,
+    symbols::{Definition, SymbolTable},
+    xref,
+};
+
+/// An input section or a section the linker created, as placed in an output section.
+struct MapInput {
+    /// The file, or `<internal>` for what the linker created.
+    file: String,
+    section: String,
+    addr: u64,
+    lma: u64,
+    size: u64,
+    align: u64,
+
+    /// The global symbols defined in the section, by address.
+    symbols: Vec<(u64, String)>,
+}
+
+struct MapSection {
+    name: String,
+    addr: u64,
+    lma: u64,
+    size: u64,
+    align: u64,
+    inputs: Vec<MapInput>,
+}
+
+/// Writes the map file `path`, with the cross-reference table `cref` if given.
+pub fn write(
+    path: &str,
+    format: MapFormat,
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    layout: &Layout,
+    cref: Option<&[(String, Vec<usize>)]>,
+) -> Result<(), String> {
+    let sections = collect_sections(inputs, symbols, layout);
+    let text = match format {
+        MapFormat::Text => {
+            let mut text = text(inputs, layout, &sections);
+            if let Some(cref) = cref {
+                text.push('\n');
+                text.push_str(&xref::cref_text(inputs, cref));
+            }
+            text
+        }
+        MapFormat::Json => json(inputs, layout, &sections, cref.unwrap_or_default()),
+    };
+    fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path, e))
+}
+
+fn collect_sections(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    layout: &Layout,
+) -> Vec<MapSection> {
+    let mut defined: HashMap<SectionId, Vec<(u64, String)>> = HashMap::new();
+    for symbol in &symbols.symbols {
+        if let Definition::Section {
+            file,
+            section,
+            value,
+        } = symbol.definition
+        {
+            let id = SectionId {
+                file,
+                index: section,
+            };
+            if let Some(addr) = layout.input_address(id, value) {
+                defined
+                    .entry(id)
+                    .or_default()
+                    .push((addr, symbol.name.clone()));
+            }
+        }
+    }
+    // Symbols defined in sections folded by identical code folding are listed with the section
+    // kept.
+    let mut folded: HashMap<SectionId, Vec<SectionId>> = HashMap::new();
+    for (file, input) in inputs.iter().enumerate() {
+        for (index, to) in input.folded_into.iter().enumerate() {
+            if let Some(to) = to {
+                folded
+                    .entry(*to)
+                    .or_default()
+                    .push(SectionId { file, index });
+            }
+        }
+    }
+    let mut symbols_in = |ids: &[SectionId]| {
+        let mut list: Vec<_> = ids
+            .iter()
+            .flat_map(|id| defined.remove(id).unwrap_or_default())
+            .collect();
+        list.sort();
+        list
+    };
+    let describe = |id: SectionId| {
+        let input = &inputs[id.file];
+        (
+            input.path.clone(),
+            input.section_names[id.index].clone(),
+            input.sections[id.index].addralign,
+        )
+    };
+
+    let mut sections = vec![];
+    for section in &layout.sections {
+        let mut map_inputs = vec![];
+        for piece in &section.pieces {
+            let (file, name, align, ids) = match &piece.content {
+                Content::Input(id) => {
+                    let (file, name, align) = describe(*id);
+                    let mut ids = vec![*id];
+                    ids.extend(folded.get(id).into_iter().flatten());
+                    (file, name, align, ids)
+                }
+                Content::Merged(group) => {
+                    let merged = &layout.merged.sections[*group];
+                    let (_, name, _) = describe(merged.members[0]);
+                    (
+                        "<merged>".into(),
+                        name,
+                        merged.align,
+                        merged.members.clone(),
+                    )
+                }
+                Content::EhFrame => (
+                    "<internal>".into(),
+                    ".eh_frame".into(),
+                    layout.eh_frame.align,
+                    vec![],
+                ),
+                Content::Data(_) => continue,
+                content => {
+                    let (_, align) = layout.synthetic_size(content);
+                    ("<internal>".into(), section.name.clone(), align, vec![])
+                }
+            };
+            map_inputs.push(MapInput {
+                file,
+                section: name,
+                addr: section.addr + piece.offset,
+                lma: section.lma + piece.offset,
+                size: piece.size,
+                align,
+                symbols: symbols_in(&ids),
+            });
+        }
+        sections.push(MapSection {
+            name: section.name.clone(),
+            addr: section.addr,
+            lma: section.lma,
+            size: section.size,
+            align: section.align,
+            inputs: map_inputs,
+        });
+    }
+    sections
+}
+
+/// The input sections with contents which are not part of the output.
+fn discarded<'a>(
+    inputs: &'a [InputFile],
+    layout: &'a Layout,
+) -> impl Iterator<Item = SectionId> + 'a {
+    // The sections of shared libraries are never part of the output.
+    let objects = inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| i.shared.is_none());
+    objects.flat_map(move |(file, input)| {
+        (0..input.sections.len())
+            .filter(move |&index| {
+                is_content(input, index)
+                    && input.section_names[index] != ".note.GNU-stack"
+                    && layout.placement(SectionId { file, index }).is_none()
+            })
+            .map(move |index| SectionId { file, index })
+    })
+}
+
+fn text(inputs: &[InputFile], layout: &Layout, sections: &[MapSection]) -> String {
+    let mut out = String::new();
+
+    let extracted: Vec<_> = inputs
+        .iter()
+        .filter_map(|i| i.extraction.as_ref())
+        .collect();
+    if !extracted.is_empty() {
+        out.push_str("Archive members extracted to satisfy a reference by file (symbol)\n\n");
+        for extraction in extracted {
+            let _ = writeln!(out, "{}({})", extraction.archive, extraction.member);
+            let _ = writeln!(
+                out,
+                "{:16}{} ({})",
+                "", extraction.referenced_by, extraction.symbol
+            );
+        }
+        out.push('\n');
+    }
+
+    out.push_str("Discarded input sections\n\n");
+    for id in discarded(inputs, layout) {
+        let input = &inputs[id.file];
+        let _ = writeln!(
+            out,
+            "{:16}{}:({}) size 0x{:x}",
+            "", input.path, input.section_names[id.index], input.sections[id.index].size
+        );
+    }
+
+    out.push_str("\nMemory map\n\n");
+    let _ = writeln!(
+        out,
+        "{:>16} {:>16} {:>8} {:>5} Out     In      Symbol",
+        "VMA", "LMA", "Size", "Align"
+    );
+    for section in sections {
+        let _ = writeln!(
+            out,
+            "{:16x} {:16x} {:8x} {:5} {}",
+            section.addr, section.lma, section.size, section.align, section.name
+        );
+        for input in &section.inputs {
+            let _ = writeln!(
+                out,
+                "{:16x} {:16x} {:8x} {:5}         {}:({})",
+                input.addr, input.lma, input.size, input.align, input.file, input.section
+            );
+            for (addr, name) in &input.symbols {
+                let _ = writeln!(
+                    out,
+                    "{:16x} {:16} {:8} {:5}                 {}",
+                    addr, "", "", "", name
+                );
+            }
+        }
+    }
+    out
+}
+
+fn json(
+    inputs: &[InputFile],
+    layout: &Layout,
+    sections: &[MapSection],
+    cref: &[(String, Vec<usize>)],
+) -> String {
+    let mut out = String::from("{\n  \"sections\": [");
+    for (i, section) in sections.iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}\n    {{\"name\": {}, \"address\": {}, \"load_address\": {}, \"size\": {}, \
+             \"align\": {}, \"inputs\": [",
+            if i == 0 { "" } else { "," },
+            string(&section.name),
+            section.addr,
+            section.lma,
+            section.size,
+            section.align
+        );
+        for (j, input) in section.inputs.iter().enumerate() {
+            let symbols: Vec<String> = input
+                .symbols
+                .iter()
+                .map(|(addr, name)| {
+                    format!("{{\"name\": {}, \"address\": {}}}", string(name), addr)
+                })
+                .collect();
+            let _ = write!(
+                out,
+                "{}\n      {{\"file\": {}, \"section\": {}, \"address\": {}, \"offset\": {}, \
+                 \"size\": {}, \"align\": {}, \"symbols\": [{}]}}",
+                if j == 0 { "" } else { "," },
+                string(&input.file),
+                string(&input.section),
+                input.addr,
+                input.addr - section.addr,
+                input.size,
+                input.align,
+                symbols.join(", ")
+            );
+        }
+        out.push_str(if section.inputs.is_empty() {
+            "]}"
+        } else {
+            "\n    ]}"
+        });
+    }
+    out.push_str("\n  ],\n  \"discarded\": [");
+
+    let discarded: Vec<String> = discarded(inputs, layout)
+        .map(|id| {
+            let input = &inputs[id.file];
+            format!(
+                "\n    {{\"file\": {}, \"section\": {}, \"size\": {}}}",
+                string(&input.path),
+                string(&input.section_names[id.index]),
+                input.sections[id.index].size
+            )
+        })
+        .collect();
+    out.push_str(&discarded.join(","));
+    out.push_str("\n  ],\n  \"archive_members\": [");
+
+    let members: Vec<String> = inputs
+        .iter()
+        .filter_map(|i| i.extraction.as_ref())
+        .map(|e| {
+            format!(
+                "\n    {{\"archive\": {}, \"member\": {}, \"symbol\": {}, \"referenced_by\": {}}}",
+                string(&e.archive),
+                string(&e.member),
+                string(&e.symbol),
+                string(&e.referenced_by)
+            )
+        })
+        .collect();
+    out.push_str(&members.join(","));
+    out.push_str("\n  ],\n  \"cross_references\": [");
+
+    let references: Vec<String> = cref
+        .iter()
+        .map(|(name, files)| {
+            let files: Vec<String> = files.iter().map(|&f| string(&inputs[f].path)).collect();
+            format!(
+                "\n    {{\"symbol\": {}, \"files\": [{}]}}",
+                string(name),
+                files.join(", ")
+            )
+        })
+        .collect();
+    out.push_str(&references.join(","));
+    out.push_str("\n  ]\n}\n");
+    out
+}
+
+/// Quotes a JSON string.
+fn string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
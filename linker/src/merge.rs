@@ -0,0 +1,189 @@
+//! Merging of `SHF_MERGE` sections.
+//!
+//! Mergeable sections consist of pieces which may be shared: strings terminated by NUL for
+//! `SHF_STRINGS` sections, and constants of `entsize` bytes otherwise. Input sections with the same
+//! name, flags, entry size and alignment are combined into one merged section holding every
+//! distinct piece once. The merged section is placed where the first of its members would be, and
+//! offsets in the members are translated to offsets in it piece by piece.
+
+use std::collections::HashMap;
+
+use crate::{
+    elf::SectionFlag64,
+    inputs::{InputFile, SectionId},
+    layout::candidates,
+    util::align_up,
+};
+
+#[derive(Debug)]
+pub struct MergedSection {
+    pub data: Vec<u8>,
+    pub align: u64,
+
+    /// The input sections combined into this one, in input order.
+    pub members: Vec<SectionId>,
+}
+
+#[derive(Debug, Default)]
+pub struct MergedSections {
+    pub sections: Vec<MergedSection>,
+
+    /// The merged section each member belongs to, and the offsets of its pieces in the member and
+    /// in the merged section, sorted by the former.
+    pieces: HashMap<SectionId, (usize, Vec<(u64, u64)>)>,
+}
+
+/// What an input section must share with the others to be merged with them.
+type Key<'a> = (&'a str, u64, u64, u64);
+
+impl MergedSections {
+    /// Merges the mergeable sections of the inputs. With `tail_merge`, strings which are the end
+    /// of another string share its bytes.
+    pub fn new(inputs: &[InputFile], tail_merge: bool) -> Result<Self, String> {
+        let mut merged = Self::default();
+        let mut groups: HashMap<Key, usize> = HashMap::new();
+        for id in candidates(inputs) {
+            let input = &inputs[id.file];
+            let header = &input.sections[id.index];
+            if !header.flags.contains(SectionFlag64::MERGE) || header.entsize == 0 {
+                continue;
+            }
+            let key = (
+                input.section_names[id.index].as_str(),
+                header.flags.bits(),
+                header.entsize,
+                header.addralign.max(1),
+            );
+            let len = groups.len();
+            let group = *groups.entry(key).or_insert(len);
+            if group == merged.sections.len() {
+                merged.sections.push(MergedSection {
+                    data: vec![],
+                    align: key.3,
+                    members: vec![],
+                });
+            }
+            merged.sections[group].members.push(id);
+        }
+
+        for group in 0..merged.sections.len() {
+            merged.merge(inputs, group, tail_merge)?;
+        }
+        Ok(merged)
+    }
+
+    fn merge(
+        &mut self,
+        inputs: &[InputFile],
+        group: usize,
+        tail_merge: bool,
+    ) -> Result<(), String> {
+        let first = self.sections[group].members[0];
+        let header = &inputs[first.file].sections[first.index];
+        let strings = header.flags.contains(SectionFlag64::STRINGS);
+        let entsize = header.entsize;
+        // Each string keeps the alignment of its section; constants are aligned by their size.
+        let piece_align = if strings {
+            header.addralign.max(1)
+        } else {
+            entsize
+        };
+
+        let mut split = vec![];
+        for &id in &self.sections[group].members {
+            let input = &inputs[id.file];
+            let data = input.object.section_data(&input.sections[id.index])?;
+            let pieces = if strings {
+                split_strings(data, entsize as usize).ok_or_else(|| {
+                    format!(
+                        "{}:({}): string is not null-terminated",
+                        input.path, input.section_names[id.index]
+                    )
+                })?
+            } else {
+                if !(data.len() as u64).is_multiple_of(entsize) {
+                    return Err(format!(
+                        "{}:({}): section size is not a multiple of sh_entsize",
+                        input.path, input.section_names[id.index]
+                    ));
+                }
+                data.chunks(entsize as usize).collect()
+            };
+            split.push((id, pieces));
+        }
+
+        let mut offsets: HashMap<&[u8], u64> = HashMap::new();
+        let mut data = vec![];
+        if tail_merge && strings && entsize == 1 && piece_align == 1 {
+            // Sorted by their reversed bytes, strings come right after the strings they end.
+            let mut unique: Vec<&[u8]> =
+                split.iter().flat_map(|(_, p)| p.iter().copied()).collect();
+            unique.sort_by(|a, b| b.iter().rev().cmp(a.iter().rev()));
+            unique.dedup();
+            let mut prev: Option<(&[u8], u64)> = None;
+            for s in unique {
+                let offset = match prev {
+                    Some((p, offset)) if p.ends_with(s) => offset + (p.len() - s.len()) as u64,
+                    _ => {
+                        let offset = data.len() as u64;
+                        data.extend_from_slice(s);
+                        prev = Some((s, offset));
+                        offset
+                    }
+                };
+                offsets.insert(s, offset);
+            }
+        } else {
+            for piece in split.iter().flat_map(|(_, p)| p.iter().copied()) {
+                offsets.entry(piece).or_insert_with(|| {
+                    let offset = align_up(data.len() as u64, piece_align);
+                    data.resize(offset as usize, 0);
+                    data.extend_from_slice(piece);
+                    offset
+                });
+            }
+        }
+
+        for (id, pieces) in &split {
+            let mut input_offset = 0;
+            let mut map = Vec::with_capacity(pieces.len());
+            for piece in pieces {
+                map.push((input_offset, offsets[piece]));
+                input_offset += piece.len() as u64;
+            }
+            self.pieces.insert(*id, (group, map));
+        }
+        self.sections[group].data = data;
+        Ok(())
+    }
+
+    /// Returns the merged section an input section is a member of.
+    pub fn group(&self, id: SectionId) -> Option<usize> {
+        self.pieces.get(&id).map(|(group, _)| *group)
+    }
+
+    /// Translates an offset in a member to an offset in its merged section.
+    pub fn offset(&self, id: SectionId, offset: u64) -> Option<u64> {
+        let (_, map) = self.pieces.get(&id)?;
+        let i = map.partition_point(|&(start, _)| start <= offset);
+        // Offsets past the end of the last piece stay relative to it.
+        let (start, merged) = map.get(i.saturating_sub(1)).copied().unwrap_or((0, 0));
+        Some(merged.wrapping_add(offset.wrapping_sub(start)))
+    }
+}
+
+/// Splits string data into strings including their terminators of `entsize` zero bytes. Returns
+/// `None` if the last string is not terminated.
+fn split_strings(data: &[u8], entsize: usize) -> Option<Vec<&[u8]>> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let end = data[start..]
+            .chunks(entsize)
+            .position(|c| c.len() == entsize && c.iter().all(|&b| b == 0))?;
+        let end = start + (end + 1) * entsize;
+        pieces.push(&data[start..end]);
+        start = end;
+    }
+    Some(pieces)
+}
@@ -0,0 +1,218 @@
+//! ELF notes, the records of `SHT_NOTE` sections and `PT_NOTE` segments.
+//!
+//! A note is a header of three words giving the sizes of its name and descriptor and its type,
+//! followed by the name and the descriptor, each padded to the alignment of the section or
+//! segment. The meaning of the type depends on the name: `GNU` notes are decoded here, as is the
+//! package metadata of `.note.package`.
+
+use std::{iter::Peekable, str::Chars};
+
+use crate::{
+    elf::{
+        NOTE_FDO_PACKAGING_METADATA, NOTE_GNU_ABI_TAG, NOTE_GNU_BUILD_ID, NOTE_GNU_PROPERTY_TYPE_0,
+    },
+    util::align_up,
+};
+
+/// A note with its name and descriptor undecoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note<'a> {
+    /// The owner of the note without its null terminator, such as `GNU`.
+    pub name: &'a [u8],
+    pub ty: u32,
+    pub desc: &'a [u8],
+}
+
+/// The notes of a section or segment, in order.
+pub struct NoteIter<'a> {
+    head: &'a [u8],
+    align: usize,
+    pos: usize,
+}
+
+impl<'a> NoteIter<'a> {
+    /// Iterates over the notes in `data`, padded to `align`. Notes are aligned to 4 bytes unless
+    /// they are aligned to 8.
+    pub fn new(data: &'a [u8], align: u64) -> Self {
+        Self {
+            head: data,
+            align: if align == 8 { 8 } else { 4 },
+            pos: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for NoteIter<'a> {
+    type Item = Result<Note<'a>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Sections may be padded after the last note.
+        if self.head.len() - self.pos < 12 {
+            return None;
+        }
+
+        let word =
+            |offset: usize| u32::from_le_bytes(self.head[offset..offset + 4].try_into().unwrap());
+        let name_size = word(self.pos) as usize;
+        let desc_size = word(self.pos + 4) as usize;
+        let ty = word(self.pos + 8);
+
+        let name_start = self.pos + 12;
+        let desc_start = align_up((name_start + name_size) as u64, self.align as u64) as usize;
+        let desc_end = desc_start + desc_size;
+        if desc_end > self.head.len() {
+            let error = format!("truncated note at 0x{:x}", self.pos);
+            self.pos = self.head.len();
+            return Some(Err(error));
+        }
+        let name = &self.head[name_start..name_start + name_size];
+        let name = name.strip_suffix(&[0]).unwrap_or(name);
+
+        self.pos = (align_up(desc_end as u64, self.align as u64) as usize).min(self.head.len());
+        Some(Ok(Note {
+            name,
+            ty,
+            desc: &self.head[desc_start..desc_end],
+        }))
+    }
+}
+
+/// The contents of a note whose name and type are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteContent<'a> {
+    /// NT_GNU_BUILD_ID: an identifier of the build, usually a hash of the output.
+    BuildId(&'a [u8]),
+
+    /// NT_GNU_ABI_TAG: the oldest kernel the executable runs on.
+    AbiTag {
+        os: u32,
+        major: u32,
+        minor: u32,
+        patch: u32,
+    },
+
+    /// NT_GNU_PROPERTY_TYPE_0: the properties of `.note.gnu.property`.
+    Properties(Vec<Property<'a>>),
+
+    /// NT_FDO_PACKAGING_METADATA: the package the file belongs to, as the keys and values of the
+    /// JSON object in `.note.package`.
+    Package(Vec<(String, String)>),
+
+    Other,
+}
+
+/// A property of `.note.gnu.property`. Properties are padded to 8 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property<'a> {
+    pub ty: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> Note<'a> {
+    /// Decodes the descriptor by the name and type of the note.
+    pub fn content(&self) -> Result<NoteContent<'a>, String> {
+        let word = |i: usize| u32::from_le_bytes(self.desc[4 * i..4 * i + 4].try_into().unwrap());
+        match (self.name, self.ty) {
+            (b"GNU", NOTE_GNU_BUILD_ID) => Ok(NoteContent::BuildId(self.desc)),
+            (b"GNU", NOTE_GNU_ABI_TAG) => {
+                if self.desc.len() < 16 {
+                    return Err("NT_GNU_ABI_TAG note is truncated".into());
+                }
+                Ok(NoteContent::AbiTag {
+                    os: word(0),
+                    major: word(1),
+                    minor: word(2),
+                    patch: word(3),
+                })
+            }
+            (b"GNU", NOTE_GNU_PROPERTY_TYPE_0) => {
+                let mut properties = vec![];
+                let mut pos = 0;
+                while pos < self.desc.len() {
+                    let header = self
+                        .desc
+                        .get(pos..pos + 8)
+                        .ok_or("NT_GNU_PROPERTY_TYPE_0 note is truncated")?;
+                    let ty = u32::from_le_bytes(header[..4].try_into().unwrap());
+                    let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+                    let data = self
+                        .desc
+                        .get(pos + 8..pos + 8 + size)
+                        .ok_or("NT_GNU_PROPERTY_TYPE_0 note is truncated")?;
+                    properties.push(Property { ty, data });
+                    pos = align_up((pos + 8 + size) as u64, 8) as usize;
+                }
+                Ok(NoteContent::Properties(properties))
+            }
+            (b"FDO", NOTE_FDO_PACKAGING_METADATA) => {
+                let text = self.desc.split(|&b| b == 0).next().unwrap_or_default();
+                let text = std::str::from_utf8(text).map_err(|e| e.to_string())?;
+                let package = parse_package(text)
+                    .ok_or("NT_FDO_PACKAGING_METADATA note is not a flat JSON object")?;
+                Ok(NoteContent::Package(package))
+            }
+            _ => Ok(NoteContent::Other),
+        }
+    }
+}
+
+/// Parses the JSON object of `.note.package`, which maps names to strings.
+fn parse_package(text: &str) -> Option<Vec<(String, String)>> {
+    fn skip_whitespace(chars: &mut Peekable<Chars>) {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn string(chars: &mut Peekable<Chars>) -> Option<String> {
+        if chars.next()? != '"' {
+            return None;
+        }
+        let mut s = String::new();
+        loop {
+            match chars.next()? {
+                '"' => return Some(s),
+                '\\' => match chars.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        s.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                    }
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    let mut chars = text.chars().peekable();
+    let mut fields = vec![];
+    skip_whitespace(&mut chars);
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            if chars.next()? != ':' {
+                return None;
+            }
+            skip_whitespace(&mut chars);
+            let value = string(&mut chars)?;
+            fields.push((key, value));
+            skip_whitespace(&mut chars);
+            match chars.next()? {
+                ',' => continue,
+                '}' => break,
+                _ => return None,
+            }
+        }
+    }
+    skip_whitespace(&mut chars);
+    chars.next().is_none().then_some(fields)
+}
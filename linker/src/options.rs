@@ -0,0 +1,586 @@
+//! Command line options.
+
+use std::{fs, thread};
+
+/// Which sections identical code folding may fold, selected by `--icf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcfMode {
+    None,
+
+    /// Only sections whose address is not taken.
+    Safe,
+    All,
+}
+
+/// How to report inputs not compatible with x86 CET, selected by `-z cet-report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CetReport {
+    None,
+    Warning,
+    Error,
+}
+
+/// How to compute the build ID, selected by `--build-id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildId {
+    /// A 64-bit non-cryptographic hash of the output.
+    Fast,
+    Md5,
+    Sha1,
+
+    /// A random UUID.
+    Uuid,
+
+    /// The bytes given in hexadecimal.
+    Hex(Vec<u8>),
+}
+
+/// What to strip from the output, selected by `--strip-debug` and `--strip-all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strip {
+    None,
+
+    /// The `.debug_*` sections.
+    Debug,
+
+    /// The debug sections and the symbol table.
+    All,
+}
+
+/// Which hash tables of dynamic symbols to create, selected by `--hash-style`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashStyle {
+    /// `.hash`.
+    Sysv,
+
+    /// `.gnu.hash`.
+    Gnu,
+    Both,
+}
+
+impl HashStyle {
+    pub fn sysv(self) -> bool {
+        self != Self::Gnu
+    }
+
+    pub fn gnu(self) -> bool {
+        self != Self::Sysv
+    }
+}
+
+/// Which local symbols to leave out of the symbol table, selected by `--discard-locals` and
+/// `--discard-all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Discard {
+    #[default]
+    None,
+
+    /// Temporary symbols, whose names start with `.L`.
+    Locals,
+    All,
+}
+
+/// The format of the map file, selected by `--map-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    Text,
+    Json,
+}
+
+/// An input given on the command line: a file, or a library given by `-l`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    /// The path of the file, or the name of the library, such as `c` for `-lc` and `:libc.so.6`
+    /// for `-l:libc.so.6`.
+    pub name: String,
+    pub library: bool,
+
+    /// Whether `--as-needed` is in effect: the output then only needs a shared library if it
+    /// defines a symbol an object file refers to.
+    pub as_needed: bool,
+
+    /// Whether `-Bstatic` is in effect, so that `-l` only finds archives.
+    pub static_only: bool,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    /// The file to write the output to, given by `-o`.
+    pub output: String,
+
+    /// The linker script given by `-T`.
+    pub script: Option<String>,
+
+    /// Inputs in the order they are given.
+    pub inputs: Vec<Input>,
+
+    /// The directories to search for libraries, given by `-L`.
+    pub library_paths: Vec<String>,
+
+    /// The directory prefixed to the default library directories and to `-L` directories starting
+    /// with `=`, given by `--sysroot`.
+    pub sysroot: Option<String>,
+
+    /// Whether to remove unused sections, set by `--gc-sections`.
+    pub gc_sections: bool,
+
+    /// Whether to list the sections removed by garbage collection, set by `--print-gc-sections`.
+    pub print_gc_sections: bool,
+
+    pub icf: IcfMode,
+
+    /// Whether to list the sections folded by identical code folding, set by
+    /// `--print-icf-sections`.
+    pub print_icf_sections: bool,
+
+    /// The optimization level given by `-O`. Level 2 enables tail merging of strings.
+    pub optimize: u32,
+
+    /// Whether to make data only written while loading read-only afterwards, set by `-z relro`.
+    pub relro: bool,
+
+    /// Whether to resolve all symbols at load time rather than lazily, set by `-z now`. With
+    /// `-z relro`, this lets the lazily bound part of the GOT become read-only too.
+    pub now: bool,
+
+    /// Whether the stack is executable, set by `-z execstack` and `-z noexecstack`. By default,
+    /// it is if an input requires it.
+    pub exec_stack: Option<bool>,
+
+    pub cet_report: CetReport,
+
+    /// Whether to mark the output compatible with indirect branch tracking even if some inputs
+    /// are not, set by `-z force-ibt`.
+    pub force_ibt: bool,
+
+    /// How to compute the ID written to `.note.gnu.build-id`, or `None` for no build ID.
+    pub build_id: Option<BuildId>,
+
+    /// The number of threads to use, set by `--threads`. The output does not depend on it.
+    pub threads: usize,
+
+    /// The tar file to package the inputs and the command line into, given by `--reproduce`.
+    pub reproduce: Option<String>,
+
+    /// The file to write a map of the output to, given by `-Map`.
+    pub map: Option<String>,
+
+    pub map_format: MapFormat,
+
+    /// Whether to print a cross-reference table, set by `--cref`. It goes to the map file if
+    /// there is one.
+    pub cref: bool,
+
+    /// The file to list the archive members extracted in, given by `--why-extract`.
+    pub why_extract: Option<String>,
+
+    /// The symbols whose definitions and references to report, given by `--trace-symbol`.
+    pub trace_symbols: Vec<String>,
+
+    /// The symbol the output starts executing at, given by `--entry`. It takes precedence over
+    /// `ENTRY` in the linker script.
+    pub entry: Option<String>,
+
+    /// Symbols to extract archive members for and keep, as if an input referred to them, given
+    /// by `--undefined`.
+    pub undefined: Vec<String>,
+
+    /// Symbols to define as `sym=expr`, given by `--defsym`. The expressions are those of linker
+    /// scripts.
+    pub defsyms: Vec<String>,
+
+    /// Symbols whose references are redirected by `--wrap`: undefined references to `sym` bind
+    /// to `__wrap_sym`, and those to `__real_sym` to `sym`.
+    pub wrap: Vec<String>,
+
+    pub strip: Strip,
+
+    /// Which local symbols to leave out, set by `--discard-locals` and `--discard-all`. Ignored
+    /// with `--strip-all`, which leaves out the whole symbol table.
+    pub discard: Discard,
+
+    /// Whether to produce a shared library rather than an executable, set by `-shared`.
+    pub shared: bool,
+
+    /// The name recorded in `DT_SONAME`, given by `-soname`.
+    pub soname: Option<String>,
+
+    /// The loader named in `PT_INTERP`, given by `--dynamic-linker`.
+    pub dynamic_linker: Option<String>,
+
+    /// The directories the loader searches for libraries, given by `-rpath` and recorded in
+    /// `DT_RUNPATH`, or `DT_RPATH` with `--disable-new-dtags`.
+    pub rpath: Vec<String>,
+    pub new_dtags: bool,
+
+    /// The directories searched for the libraries shared libraries need, given by
+    /// `-rpath-link`. These are only used to check the link, and are not recorded in the output.
+    pub rpath_link: Vec<String>,
+
+    /// The version scripts given by `--version-script`, which decide the versions of the symbols
+    /// the output exports and which symbols it keeps local.
+    pub version_scripts: Vec<String>,
+
+    /// The dynamic lists given by `--dynamic-list`, naming the symbols to export.
+    pub dynamic_lists: Vec<String>,
+
+    pub hash_style: HashStyle,
+
+    /// The arguments the options were parsed from, with response files expanded.
+    pub args: Vec<String>,
+
+    /// Whether `--as-needed` and `-Bstatic` are in effect at the current argument, while parsing.
+    as_needed: bool,
+    static_only: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            output: "a.out".into(),
+            script: None,
+            inputs: vec![],
+            library_paths: vec![],
+            sysroot: None,
+            gc_sections: false,
+            print_gc_sections: false,
+            icf: IcfMode::None,
+            print_icf_sections: false,
+            optimize: 1,
+            relro: true,
+            now: false,
+            exec_stack: None,
+            cet_report: CetReport::None,
+            force_ibt: false,
+            build_id: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            reproduce: None,
+            map: None,
+            map_format: MapFormat::Text,
+            cref: false,
+            why_extract: None,
+            trace_symbols: vec![],
+            entry: None,
+            undefined: vec![],
+            defsyms: vec![],
+            wrap: vec![],
+            strip: Strip::None,
+            discard: Discard::None,
+            shared: false,
+            soname: None,
+            dynamic_linker: None,
+            rpath: vec![],
+            new_dtags: true,
+            rpath_link: vec![],
+            version_scripts: vec![],
+            dynamic_lists: vec![],
+            hash_style: HashStyle::Both,
+            args: vec![],
+            as_needed: false,
+            static_only: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments following the program name.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            args: expand_response_files(args)?,
+            ..Self::default()
+        };
+        let all_args = options.args.clone();
+        let mut args = all_args.iter();
+        while let Some(arg) = args.next() {
+            // Long options may also be spelled with a single dash, so they are matched with two.
+            let long = match arg.strip_prefix('-') {
+                Some(name) if name.len() > 1 && !name.starts_with('-') => format!("-{}", arg),
+                _ => arg.clone(),
+            };
+            match long.as_str() {
+                "--gc-sections" => options.gc_sections = true,
+                "--no-gc-sections" => options.gc_sections = false,
+                "--print-gc-sections" => options.print_gc_sections = true,
+                "--no-print-gc-sections" => options.print_gc_sections = false,
+                "--print-icf-sections" => options.print_icf_sections = true,
+                "--no-print-icf-sections" => options.print_icf_sections = false,
+                "--build-id" => options.build_id = Some(BuildId::Sha1),
+                "--no-threads" => options.threads = 1,
+                "--cref" => options.cref = true,
+                "-s" | "--strip-all" => options.strip = Strip::All,
+                "-S" | "--strip-debug" => options.strip = options.strip.max(Strip::Debug),
+                "-x" | "--discard-all" => options.discard = Discard::All,
+                "-X" | "--discard-locals" => options.discard = Discard::Locals,
+                "--discard-none" => options.discard = Discard::None,
+                "--shared" | "--Bshareable" => options.shared = true,
+                "--enable-new-dtags" => options.new_dtags = true,
+                "--disable-new-dtags" => options.new_dtags = false,
+                "--as-needed" => options.as_needed = true,
+                "--no-as-needed" => options.as_needed = false,
+                "--Bstatic" | "--dn" | "--non_shared" | "--static" => options.static_only = true,
+                "--Bdynamic" | "--dy" | "--call_shared" => options.static_only = false,
+                "-z" => {
+                    let keyword = args.next().ok_or("missing argument to -z")?;
+                    options.parse_z(keyword)?;
+                }
+                _ => options.parse_arg(arg, &mut args)?,
+            }
+        }
+        Ok(options)
+    }
+
+    /// Parses an option, or an input file. Long options, also spelled with a single dash, are tried
+    /// before short options with a joined value, so that `-hash-style=gnu` is not
+    /// `-h ash-style=gnu`.
+    fn parse_arg<'a>(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = &'a String>,
+    ) -> Result<(), String> {
+        if self.parse_value(arg, args, false)? {
+            return Ok(());
+        }
+        if let Some(style) = arg.strip_prefix("--build-id=") {
+            self.build_id = parse_build_id(style)?;
+        } else if is_unsupported_long_option(arg) {
+            return Err(format!("unknown option: {}", arg));
+        } else if self.parse_value(arg, args, true)? {
+            return Ok(());
+        } else if let Some(keyword) = arg.strip_prefix("-z") {
+            self.parse_z(keyword)?;
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            self.optimize = level
+                .parse()
+                .map_err(|_| format!("invalid optimization level: {}", arg))?;
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(format!("unknown option: {}", arg));
+        } else {
+            self.push_input(arg.into(), false);
+        }
+        Ok(())
+    }
+
+    /// Parses an option taking a value, returning whether `arg` is one. Short options with a
+    /// joined value such as `-lc` are only recognized if `joined_short` is set.
+    fn parse_value<'a>(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = &'a String>,
+        joined_short: bool,
+    ) -> Result<bool, String> {
+        let mut value = |short, long| value(arg, args, short, long, joined_short);
+        if let Some(v) = value("-o", "--output")? {
+            self.output = v;
+        } else if let Some(v) = value("-T", "--script")? {
+            self.script = Some(v);
+        } else if let Some(v) = value("", "--icf")? {
+            self.icf = match v.as_str() {
+                "none" => IcfMode::None,
+                "safe" => IcfMode::Safe,
+                "all" => IcfMode::All,
+                _ => return Err(format!("unknown --icf mode: {}", v)),
+            };
+        } else if let Some(v) = value("", "--threads")? {
+            self.threads = v
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("invalid number of threads: {}", v))?;
+        } else if let Some(v) = value("", "--reproduce")? {
+            self.reproduce = Some(v);
+        } else if let Some(v) = value("", "--why-extract")? {
+            self.why_extract = Some(v);
+        } else if let Some(v) = value("-y", "--trace-symbol")? {
+            self.trace_symbols.push(v);
+        } else if let Some(v) = value("-e", "--entry")? {
+            self.entry = Some(v);
+        } else if let Some(v) = value("-u", "--undefined")? {
+            self.undefined.push(v);
+        } else if let Some(v) = value("", "--defsym")? {
+            self.defsyms.push(v);
+        } else if let Some(v) = value("", "--wrap")? {
+            self.wrap.push(v);
+        } else if let Some(v) = value("", "--Map")? {
+            self.map = Some(v);
+        } else if let Some(v) = value("", "--map-format")? {
+            self.map_format = match v.as_str() {
+                "text" => MapFormat::Text,
+                "json" => MapFormat::Json,
+                _ => return Err(format!("unknown --map-format: {}", v)),
+            };
+        } else if let Some(v) = value("-h", "--soname")? {
+            self.soname = Some(v);
+        } else if let Some(v) = value("-I", "--dynamic-linker")? {
+            self.dynamic_linker = Some(v);
+        } else if let Some(v) = value("", "--version-script")? {
+            self.version_scripts.push(v);
+        } else if let Some(v) = value("", "--dynamic-list")? {
+            self.dynamic_lists.push(v);
+        } else if let Some(v) = value("-L", "--library-path")? {
+            self.library_paths.push(v);
+        } else if let Some(v) = value("-l", "--library")? {
+            self.push_input(v, true);
+        } else if let Some(v) = value("", "--rpath-link")? {
+            self.rpath_link.push(v);
+        } else if let Some(v) = value("-R", "--rpath")? {
+            self.rpath.push(v);
+        } else if let Some(v) = value("", "--sysroot")? {
+            self.sysroot = Some(v);
+        } else if let Some(v) = value("", "--hash-style")? {
+            self.hash_style = match v.as_str() {
+                "sysv" => HashStyle::Sysv,
+                "gnu" => HashStyle::Gnu,
+                "both" => HashStyle::Both,
+                _ => return Err(format!("unknown --hash-style: {}", v)),
+            };
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn push_input(&mut self, name: String, library: bool) {
+        self.inputs.push(Input {
+            name,
+            library,
+            as_needed: self.as_needed,
+            static_only: self.static_only,
+        });
+    }
+
+    /// Parses a keyword given by `-z`.
+    fn parse_z(&mut self, keyword: &str) -> Result<(), String> {
+        match keyword {
+            "relro" => self.relro = true,
+            "norelro" => self.relro = false,
+            "now" => self.now = true,
+            "lazy" => self.now = false,
+            "execstack" => self.exec_stack = Some(true),
+            "noexecstack" => self.exec_stack = Some(false),
+            "force-ibt" => self.force_ibt = true,
+            "cet-report=none" => self.cet_report = CetReport::None,
+            "cet-report=warning" => self.cet_report = CetReport::Warning,
+            "cet-report=error" => self.cet_report = CetReport::Error,
+            _ => return Err(format!("unknown -z value: {}", keyword)),
+        }
+        Ok(())
+    }
+}
+
+/// Replaces each argument `@file` by the arguments in `file`, separated by whitespace. Arguments
+/// may be quoted with `"` or `'`, and `\` escapes the next character.
+fn expand_response_files(args: &[String]) -> Result<Vec<String>, String> {
+    let mut expanded = vec![];
+    for arg in args {
+        let Some(path) = arg.strip_prefix('@') else {
+            expanded.push(arg.clone());
+            continue;
+        };
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+
+        let mut current: Option<String> = None;
+        let mut quote = None;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let escaped = chars.next().unwrap_or('\\');
+                    current.get_or_insert_with(String::new).push(escaped);
+                }
+                c if Some(c) == quote => quote = None,
+                '"' | '\'' if quote.is_none() => {
+                    quote = Some(c);
+                    current.get_or_insert_with(String::new);
+                }
+                c if c.is_whitespace() && quote.is_none() => expanded.extend(current.take()),
+                c => current.get_or_insert_with(String::new).push(c),
+            }
+        }
+        expanded.extend(current);
+    }
+    Ok(expanded)
+}
+
+/// Parses the style given by `--build-id=`.
+fn parse_build_id(style: &str) -> Result<Option<BuildId>, String> {
+    let id = match style {
+        "none" => return Ok(None),
+        "fast" => BuildId::Fast,
+        "md5" => BuildId::Md5,
+        "sha1" | "tree" => BuildId::Sha1,
+        "uuid" => BuildId::Uuid,
+        _ => {
+            let hex = style
+                .strip_prefix("0x")
+                .or_else(|| style.strip_prefix("0X"))
+                .filter(|hex| !hex.is_empty() && hex.len() % 2 == 0 && hex.is_ascii())
+                .ok_or_else(|| format!("unknown --build-id style: {}", style))?;
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("invalid --build-id value: {}", style))?;
+            BuildId::Hex(bytes)
+        }
+    };
+    Ok(Some(id))
+}
+
+/// Returns the value of the option `arg` if it is `short` or `long`. The value is either joined to
+/// the option (`-ofile` if `joined_short` is set, `--output=file`, `-output=file`) or the next
+/// argument.
+fn value<'a>(
+    arg: &str,
+    args: &mut impl Iterator<Item = &'a String>,
+    short: &str,
+    long: &str,
+    joined_short: bool,
+) -> Result<Option<String>, String> {
+    if (!short.is_empty() && arg == short) || arg == long || arg == &long[1..] {
+        return args
+            .next()
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| format!("missing argument to {}", arg));
+    }
+    let joined = arg
+        .strip_prefix(long)
+        .or_else(|| arg.strip_prefix(&long[1..]))
+        .and_then(|v| v.strip_prefix('='));
+    if let Some(v) = joined {
+        return Ok(Some(v.into()));
+    }
+    Ok(arg
+        .strip_prefix(short)
+        .filter(|v| joined_short && !short.is_empty() && !v.is_empty() && !arg.starts_with("--"))
+        .map(String::from))
+}
+
+/// Long options of GNU ld which are not supported, but which would read as a short option with a
+/// joined value when spelled with a single dash, such as `-export-dynamic` for `-e xport-dynamic`.
+const UNSUPPORTED_LONG_OPTIONS: &[&str] = &[
+    "eh-frame-hdr",
+    "emit-relocs",
+    "enable-non-contiguous-regions",
+    "error-limit",
+    "error-unresolved-symbols",
+    "exclude-libs",
+    "export-dynamic",
+    "export-dynamic-symbol",
+    "export-dynamic-symbol-list",
+    "hash-size",
+    "help",
+    "oformat",
+    "orphan-handling",
+    "undefined-version",
+    "unique",
+    "unresolved-symbols",
+];
+
+/// Whether `arg` names a long option in `UNSUPPORTED_LONG_OPTIONS`, with one or two dashes and
+/// possibly a value joined by `=`.
+fn is_unsupported_long_option(arg: &str) -> bool {
+    let name = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-'));
+    let name = name.map(|n| n.split_once('=').map_or(n, |(name, _)| name));
+    name.is_some_and(|n| UNSUPPORTED_LONG_OPTIONS.contains(&n))
+}
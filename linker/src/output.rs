@@ -0,0 +1,405 @@
+//! Writes the linked executable.
+
+use crate::{
+    build_id,
+    dynamic::{self, Dynamic, SYMBOL_SIZE},
+    elf::{
+        Elf64Header, Elf64SectionHeader, Elf64Symbol, ElfClass, ElfIdent, Encoding, Machine,
+        ObjectFileType, SectionFlag64, SectionType, SymbolBinding, SymbolType, SymbolVisibility,
+        SECTION_HEADER_NUMBER_ABS, SECTION_HEADER_NUMBER_UNDEF,
+    },
+    inputs::{InputFile, SectionId},
+    layout::{Content, Layout, ELF_HEADER_SIZE, PROGRAM_HEADER_SIZE},
+    options::{BuildId, Discard},
+    property, relocation,
+    symbols::{Definition, SymbolTable},
+    util::{align_up, StringTable},
+};
+
+const SECTION_HEADER_SIZE: u64 = 64;
+
+/// Produces the bytes of the output file.
+pub fn write(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    layout: &Layout,
+    entry: u64,
+) -> Result<Vec<u8>, String> {
+    let mut image = vec![0; layout.file_size as usize];
+    write_sections(&mut image, inputs, symbols, layout)?;
+    relocation::apply(&mut image, inputs, symbols, layout)?;
+
+    let mut strtab = StringTable::new();
+    let (symtab, local_count) = build_symtab(inputs, symbols, layout, &mut strtab);
+    let symtab = (!layout.strip_symbols).then_some((symtab, strtab));
+
+    let mut shstrtab = StringTable::new();
+    let mut headers = vec![Elf64SectionHeader {
+        name: 0,
+        ty: SectionType::Null,
+        flags: SectionFlag64::empty(),
+        addr: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        addralign: 0,
+        entsize: 0,
+    }];
+    for section in &layout.sections {
+        headers.push(Elf64SectionHeader {
+            name: shstrtab.add(&section.name),
+            ty: section.ty,
+            flags: section.flags,
+            addr: section.addr,
+            offset: section.offset,
+            size: section.size,
+            link: 0,
+            info: 0,
+            addralign: section.align,
+            entsize: section.entsize,
+        });
+    }
+
+    if let Some(dynamic) = &layout.dynamic {
+        link_dynamic_sections(&mut headers, layout, dynamic);
+    }
+
+    let symtab_index = headers.len() as u32;
+    let mut append = |image: &mut Vec<u8>, name: &str, ty, data: &[u8], align: u64| {
+        let offset = align_up(image.len() as u64, align);
+        image.resize(offset as usize, 0);
+        image.extend_from_slice(data);
+        headers.push(Elf64SectionHeader {
+            name: shstrtab.add(name),
+            ty,
+            flags: SectionFlag64::empty(),
+            addr: 0,
+            offset,
+            size: data.len() as u64,
+            link: 0,
+            info: 0,
+            addralign: align,
+            entsize: 0,
+        });
+    };
+    if let Some((symtab, strtab)) = &symtab {
+        append(&mut image, ".symtab", SectionType::Symtab, symtab, 8);
+        append(&mut image, ".strtab", SectionType::Strtab, &strtab.data, 1);
+    }
+    let shstrtab_name = shstrtab.add(".shstrtab");
+    let shstrtab_offset = image.len() as u64;
+    image.extend_from_slice(&shstrtab.data);
+    headers.push(Elf64SectionHeader {
+        name: shstrtab_name,
+        ty: SectionType::Strtab,
+        flags: SectionFlag64::empty(),
+        addr: 0,
+        offset: shstrtab_offset,
+        size: shstrtab.data.len() as u64,
+        link: 0,
+        info: 0,
+        addralign: 1,
+        entsize: 0,
+    });
+
+    if symtab.is_some() {
+        let symtab = &mut headers[symtab_index as usize];
+        symtab.link = symtab_index + 1;
+        symtab.info = local_count;
+        symtab.entsize = SYMBOL_SIZE;
+    }
+
+    let shoff = align_up(image.len() as u64, 8);
+    image.resize(shoff as usize, 0);
+    for header in &headers {
+        header.write(&mut image);
+    }
+
+    let header = Elf64Header {
+        ident: ElfIdent {
+            class: ElfClass::Class64,
+            data: Encoding::LSB2,
+            ..ElfIdent::new()
+        },
+        ty: if layout.is_shared() {
+            ObjectFileType::Dyn
+        } else {
+            ObjectFileType::Exec
+        },
+        machine: Machine::X86_64,
+        version: 1,
+        entry,
+        phoff: ELF_HEADER_SIZE,
+        shoff,
+        flags: 0,
+        ehsize: ELF_HEADER_SIZE as u16,
+        phentsize: PROGRAM_HEADER_SIZE as u16,
+        phnum: layout.segments.len() as u16,
+        shentsize: SECTION_HEADER_SIZE as u16,
+        shnum: headers.len() as u16,
+        shstrndx: headers.len() as u16 - 1,
+    };
+    let mut buf = vec![];
+    header.write(&mut buf);
+    for segment in &layout.segments {
+        segment.header.write(&mut buf);
+    }
+    image[..buf.len()].copy_from_slice(&buf);
+
+    if let Some(kind) = &layout.build_id {
+        write_build_id(&mut image, layout, kind)?;
+    }
+    Ok(image)
+}
+
+fn write_sections(
+    image: &mut [u8],
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    layout: &Layout,
+) -> Result<(), String> {
+    for section in layout.sections.iter().filter(|s| !s.is_nobits()) {
+        let start = section.offset as usize;
+        let data = &mut image[start..start + section.size as usize];
+        if let Some(fill) = section.fill {
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = fill.to_be_bytes()[i % 4];
+            }
+        }
+
+        for piece in &section.pieces {
+            let dest = &mut data[piece.offset as usize..(piece.offset + piece.size) as usize];
+            match &piece.content {
+                Content::Input(SectionId { file, index }) => {
+                    let input = &inputs[*file];
+                    let header = &input.sections[*index];
+                    if header.ty != SectionType::Nobits {
+                        dest.copy_from_slice(input.object.section_data(header)?);
+                    } else {
+                        dest.fill(0);
+                    }
+                }
+                Content::Data(bytes) => dest.copy_from_slice(bytes),
+                Content::Merged(group) => {
+                    dest.copy_from_slice(&layout.merged.sections[*group].data)
+                }
+                Content::EhFrame => dest.copy_from_slice(&layout.eh_frame.data),
+                Content::EhFrameHdr => {
+                    let addr = section.addr + piece.offset;
+                    dest.copy_from_slice(&layout.eh_frame.hdr(inputs, symbols, layout, addr));
+                }
+                Content::BuildId => {
+                    if let Some(kind) = &layout.build_id {
+                        dest.copy_from_slice(&build_id::note(kind));
+                    }
+                }
+                Content::GnuProperty => dest.copy_from_slice(&property::note(layout.x86_features)),
+                Content::Got => {
+                    for (entry, &symbol) in dest.chunks_mut(8).zip(&layout.got) {
+                        let addr = layout.symbol_address(inputs, symbols, symbol).unwrap_or(0);
+                        entry.copy_from_slice(&addr.to_le_bytes());
+                    }
+                }
+                Content::Iplt => dest.copy_from_slice(&dynamic::iplt_data(layout)),
+                Content::IgotPlt | Content::Commons => dest.fill(0),
+                // Static executables have no `.dynamic` for the first word to point to.
+                Content::GotPlt if layout.dynamic.is_none() => dest.fill(0),
+                Content::RelaIplt => {
+                    dest.copy_from_slice(&dynamic::rela_iplt(inputs, symbols, layout))
+                }
+                content => {
+                    if let Some(dynamic) = &layout.dynamic {
+                        let data = match content {
+                            Content::Interp => {
+                                let mut interp = dynamic.interp.clone().unwrap().into_bytes();
+                                interp.push(0);
+                                interp
+                            }
+                            Content::DynSym => dynamic.symtab(symbols, layout),
+                            Content::DynStr => dynamic.strtab.data.clone(),
+                            Content::VerSym => dynamic.versym_data(),
+                            Content::VerDef => dynamic.verdef_data.clone(),
+                            Content::Hash => dynamic.hash_data.clone(),
+                            Content::GnuHash => dynamic.gnu_hash_data.clone(),
+                            Content::VerNeed => dynamic.verneed_data.clone(),
+                            Content::RelaDyn => dynamic.rela_dyn(inputs, symbols, layout),
+                            Content::RelaPlt => dynamic.rela_plt(layout),
+                            Content::Plt => dynamic.plt_data(layout),
+                            Content::GotPlt => dynamic.got_plt_data(layout),
+                            Content::Copies | Content::CopiesRelRo => vec![0; dest.len()],
+                            _ => dynamic.section(inputs, symbols, layout),
+                        };
+                        dest.copy_from_slice(&data);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets the links of the sections the linker creates for dynamic linking: the string table of
+/// the dynamic symbols and of the sections naming things, and the symbol table of the version,
+/// hash and relocation sections.
+fn link_dynamic_sections(headers: &mut [Elf64SectionHeader], layout: &Layout, dynamic: &Dynamic) {
+    let index = |f: fn(&Content) -> bool| layout.content_section(f).map(|i| i as u32 + 1);
+    let dynstr = index(|c| matches!(c, Content::DynStr)).unwrap_or(0);
+    let dynsym = index(|c| matches!(c, Content::DynSym)).unwrap_or(0);
+    let links = [
+        (index(|c| matches!(c, Content::DynSym)), dynstr, 1),
+        (index(|c| matches!(c, Content::Dynamic)), dynstr, 0),
+        (index(|c| matches!(c, Content::VerSym)), dynsym, 0),
+        (index(|c| matches!(c, Content::Hash)), dynsym, 0),
+        (index(|c| matches!(c, Content::GnuHash)), dynsym, 0),
+        (
+            index(|c| matches!(c, Content::VerDef)),
+            dynstr,
+            dynamic.verdefs.len() as u32,
+        ),
+        (
+            index(|c| matches!(c, Content::VerNeed)),
+            dynstr,
+            dynamic.verneeds.len() as u32,
+        ),
+        (index(|c| matches!(c, Content::RelaDyn)), dynsym, 0),
+        (
+            index(|c| matches!(c, Content::RelaPlt)),
+            dynsym,
+            index(|c| matches!(c, Content::GotPlt)).unwrap_or(0),
+        ),
+    ];
+    for (section, link, info) in links {
+        if let Some(section) = section {
+            let header = &mut headers[section as usize];
+            header.link = link;
+            header.info = info;
+            // The info of `.rela.plt` is the section it applies to.
+            if header.ty == SectionType::Rela && info != 0 {
+                header.flags |= SectionFlag64::INFO_LINK;
+            }
+        }
+    }
+}
+
+/// Fills in the descriptor of `.note.gnu.build-id`, which is zero while the ID is computed.
+fn write_build_id(image: &mut [u8], layout: &Layout, kind: &BuildId) -> Result<(), String> {
+    let offset = layout.sections.iter().find_map(|section| {
+        section
+            .pieces
+            .iter()
+            .find(|p| matches!(p.content, Content::BuildId))
+            .map(|p| section.offset + p.offset)
+    });
+    let Some(offset) = offset else {
+        return Ok(());
+    };
+    let id = build_id::compute(kind, image)?;
+    let start = offset as usize + 16;
+    image[start..start + id.len()].copy_from_slice(&id);
+    Ok(())
+}
+
+/// Builds the symbol table of the output. Returns its bytes and the number of local symbols.
+fn build_symtab(
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    layout: &Layout,
+    strtab: &mut StringTable,
+) -> (Vec<u8>, u32) {
+    let shndx_of = |id: SectionId| layout.placement(id).map(|(out, _)| out as u16 + 1);
+
+    let mut locals = vec![Elf64Symbol {
+        name: 0,
+        binding: SymbolBinding::Local,
+        ty: SymbolType::NoType,
+        visibility: SymbolVisibility::Default,
+        shndx: SECTION_HEADER_NUMBER_UNDEF,
+        value: 0,
+        size: 0,
+    }];
+    for (file, input) in inputs.iter().enumerate() {
+        for (index, sym) in input.symbols.iter().enumerate().skip(1) {
+            let name = &input.symbol_names[index];
+            if sym.binding != SymbolBinding::Local
+                || sym.ty == SymbolType::Section
+                || name.is_empty()
+            {
+                continue;
+            }
+            match layout.discard {
+                Discard::All => continue,
+                Discard::Locals if name.starts_with(".L") => continue,
+                _ => {}
+            }
+
+            let (shndx, value) = match sym.shndx {
+                SECTION_HEADER_NUMBER_UNDEF => continue,
+                SECTION_HEADER_NUMBER_ABS => (SECTION_HEADER_NUMBER_ABS, sym.value),
+                shndx => {
+                    let id = SectionId {
+                        file,
+                        index: shndx as usize,
+                    };
+                    match (shndx_of(id), layout.input_address(id, sym.value)) {
+                        (Some(shndx), Some(addr)) => (shndx, addr),
+                        _ => continue,
+                    }
+                }
+            };
+            locals.push(Elf64Symbol {
+                name: strtab.add(name),
+                shndx,
+                value,
+                ..sym.clone()
+            });
+        }
+    }
+
+    let mut globals = vec![];
+    for (id, symbol) in symbols.symbols.iter().enumerate() {
+        let imported = matches!(
+            symbol.definition,
+            Definition::Undefined | Definition::Shared { .. }
+        );
+        if imported && !symbol.referenced {
+            continue;
+        }
+        let Some((shndx, value)) = layout.symbol_entry(symbols, id) else {
+            continue;
+        };
+
+        // Hidden symbols are not visible outside the executable, so they become local, as do
+        // those a version script makes local.
+        let hidden = (matches!(
+            symbol.visibility,
+            SymbolVisibility::Hidden | SymbolVisibility::Internal
+        ) || symbol.binding == SymbolBinding::Local)
+            && shndx != SECTION_HEADER_NUMBER_UNDEF;
+        let entry = Elf64Symbol {
+            name: strtab.add(&symbol.name),
+            binding: if hidden {
+                SymbolBinding::Local
+            } else {
+                symbol.binding
+            },
+            ty: symbol.ty,
+            visibility: symbol.visibility,
+            shndx,
+            value,
+            size: symbol.size,
+        };
+        if hidden {
+            locals.push(entry);
+        } else {
+            globals.push(entry);
+        }
+    }
+
+    let local_count = locals.len() as u32;
+    let mut bytes = vec![];
+    for sym in locals.iter().chain(&globals) {
+        sym.write(&mut bytes);
+    }
+    (bytes, local_count)
+}
@@ -0,0 +1,110 @@
+//! GNU property notes, which record what the code of an object is compatible with.
+//!
+//! The only property merged is `GNU_PROPERTY_X86_FEATURE_1_AND`: the output is compatible with an
+//! x86 feature such as IBT or SHSTK only if every input is. Inputs without the property are
+//! compatible with none. Shared libraries are checked by the loader instead. The
+//! `.note.gnu.property` sections of the inputs are replaced by one the linker creates.
+
+use crate::{
+    elf::{
+        GNU_PROPERTY_X86_FEATURE_1_AND, GNU_PROPERTY_X86_FEATURE_1_IBT,
+        GNU_PROPERTY_X86_FEATURE_1_SHSTK, NOTE_GNU_PROPERTY_TYPE_0,
+    },
+    inputs::InputFile,
+    note::NoteContent,
+    options::{CetReport, Options},
+};
+
+const SECTION_NAME: &str = ".note.gnu.property";
+
+/// Returns the x86 features of the inputs and the options, and drops the property notes of the
+/// inputs.
+pub fn merge(inputs: &mut [InputFile], options: &Options) -> Result<u32, String> {
+    let mut features = u32::MAX;
+    let mut errors = vec![];
+    let objects = inputs.iter().filter(|i| i.shared.is_none());
+    for input in objects.clone() {
+        let input_features = x86_features(input).map_err(|e| format!("{}: {}", input.path, e))?;
+        features &= input_features;
+
+        if options.force_ibt && input_features & GNU_PROPERTY_X86_FEATURE_1_IBT == 0 {
+            eprintln!(
+                "warning: {}: -z force-ibt: file does not have GNU_PROPERTY_X86_FEATURE_1_IBT \
+                 property",
+                input.path
+            );
+        }
+        let missing = [
+            (GNU_PROPERTY_X86_FEATURE_1_IBT, "IBT"),
+            (GNU_PROPERTY_X86_FEATURE_1_SHSTK, "SHSTK"),
+        ]
+        .into_iter()
+        .filter(|&(bit, _)| input_features & bit == 0);
+        for (_, name) in missing {
+            let message = format!("{}: missing {} property", input.path, name);
+            match options.cet_report {
+                CetReport::None => {}
+                CetReport::Warning => eprintln!("warning: {}", message),
+                CetReport::Error => errors.push(message),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    if objects.count() == 0 {
+        features = 0;
+    }
+    if options.force_ibt {
+        features |= GNU_PROPERTY_X86_FEATURE_1_IBT;
+    }
+
+    for input in inputs.iter_mut() {
+        for index in 0..input.sections.len() {
+            if input.section_names[index] == SECTION_NAME {
+                input.live[index] = false;
+            }
+        }
+    }
+    Ok(features)
+}
+
+/// Returns the value of `GNU_PROPERTY_X86_FEATURE_1_AND` in the property notes of an input, or 0
+/// if it has none.
+fn x86_features(input: &InputFile) -> Result<u32, String> {
+    let mut features = 0;
+    for (index, header) in input.sections.iter().enumerate() {
+        if input.section_names[index] != SECTION_NAME {
+            continue;
+        }
+        for note in input.object.notes(header)? {
+            let NoteContent::Properties(properties) = note?.content()? else {
+                continue;
+            };
+            for property in properties {
+                if property.ty != GNU_PROPERTY_X86_FEATURE_1_AND {
+                    continue;
+                }
+                let bytes: [u8; 4] = property.data.try_into().map_err(|_| {
+                    "GNU_PROPERTY_X86_FEATURE_1_AND has an invalid size".to_string()
+                })?;
+                features |= u32::from_le_bytes(bytes);
+            }
+        }
+    }
+    Ok(features)
+}
+
+/// Builds the `.note.gnu.property` of the output, holding `GNU_PROPERTY_X86_FEATURE_1_AND`.
+pub fn note(features: u32) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&4u32.to_le_bytes());
+    data.extend_from_slice(&16u32.to_le_bytes());
+    data.extend_from_slice(&NOTE_GNU_PROPERTY_TYPE_0.to_le_bytes());
+    data.extend_from_slice(b"GNU\0");
+    data.extend_from_slice(&GNU_PROPERTY_X86_FEATURE_1_AND.to_le_bytes());
+    data.extend_from_slice(&4u32.to_le_bytes());
+    data.extend_from_slice(&features.to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data
+}
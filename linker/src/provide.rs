@@ -0,0 +1,154 @@
+//! Symbols the linker defines for startup code, such as `_end`, `__init_array_start` and
+//! `__start_SEC`. Like symbols a linker script assigns with `PROVIDE`, they are defined only if an
+//! object file refers to them and nothing else defines them.
+//!
+//! Which symbols are defined is decided before relocations are scanned, so that references to
+//! them do not import them from other modules; their values are set once the output is laid out.
+
+use crate::{
+    elf::SectionFlag64,
+    inputs::InputFile,
+    layout::{candidates, Content, Layout},
+    script::Script,
+    symbols::{Definition, SymbolTable},
+    util::is_c_identifier,
+};
+
+/// The symbol at the start of `.got.plt`, which code addresses data relative to.
+pub const GOT_SYMBOL: &str = "_GLOBAL_OFFSET_TABLE_";
+
+/// Symbols at the bounds of the address space of the output or of its sections, and whether they
+/// are hidden.
+const NAMES: &[(&str, bool)] = &[
+    ("__ehdr_start", true),
+    ("__executable_start", true),
+    ("_etext", false),
+    ("etext", false),
+    ("_edata", false),
+    ("edata", false),
+    ("__bss_start", false),
+    ("_end", false),
+    ("end", false),
+    (GOT_SYMBOL, true),
+    ("__preinit_array_start", true),
+    ("__preinit_array_end", true),
+    ("__init_array_start", true),
+    ("__init_array_end", true),
+    ("__fini_array_start", true),
+    ("__fini_array_end", true),
+];
+
+/// Defines the symbols the linker provides which object files refer to and neither they nor the
+/// linker script define, returning their ids. `dynamic` is whether the output is dynamic, which
+/// gives it `_DYNAMIC` but leaves the IPLT relocations of static executables, between
+/// `__rela_iplt_start` and `__rela_iplt_end`, to the loader.
+pub fn define(
+    inputs: &[InputFile],
+    symbols: &mut SymbolTable,
+    script: Option<&Script>,
+    dynamic: bool,
+) -> Vec<usize> {
+    let mut sections: Vec<&str> = candidates(inputs)
+        .map(|id| inputs[id.file].section_names[id.index].as_str())
+        .filter(|name| is_c_identifier(name))
+        .collect();
+    sections.sort_unstable();
+    sections.dedup();
+
+    let mut defined = vec![];
+    for id in 0..symbols.symbols.len() {
+        let symbol = &symbols.symbols[id];
+        let wanted = symbol.referenced
+            && matches!(
+                symbol.definition,
+                Definition::Undefined | Definition::Shared { .. }
+            )
+            && !script.is_some_and(|s| s.assigns(&symbol.name));
+        if !wanted {
+            continue;
+        }
+        let name = symbol.name.as_str();
+        let hidden = match name {
+            "_DYNAMIC" if dynamic => true,
+            "__rela_iplt_start" | "__rela_iplt_end" if !dynamic => true,
+            _ => match NAMES.iter().find(|(n, _)| *n == name) {
+                Some(&(_, hidden)) => hidden,
+                None => match name
+                    .strip_prefix("__start_")
+                    .or_else(|| name.strip_prefix("__stop_"))
+                {
+                    Some(section) if sections.binary_search(&section).is_ok() => false,
+                    _ => continue,
+                },
+            },
+        };
+        let name = name.to_string();
+        symbols.define_linker(&name, 0, hidden);
+        defined.push(id);
+    }
+    defined
+}
+
+/// Sets the values of the symbols `define` defined from the layout of the output.
+pub fn assign(symbols: &mut SymbolTable, layout: &Layout, defined: &[usize]) {
+    for &id in defined {
+        let value = value(layout, &symbols.symbols[id].name);
+        symbols.symbols[id].definition = Definition::Linker(value);
+    }
+}
+
+/// Returns the value of a symbol the linker provides. Bounds of sections the output lacks are at
+/// the start of the ELF header, so that they delimit an empty range.
+fn value(layout: &Layout, name: &str) -> u64 {
+    let alloc = || layout.sections.iter().filter(|s| s.is_alloc());
+    let base = layout
+        .headers_segment_base()
+        .map(|(addr, _)| addr)
+        .or_else(|| alloc().map(|s| s.addr).min())
+        .unwrap_or(0);
+    let end_of = |f: &dyn Fn(&&_) -> bool| {
+        alloc()
+            .filter(f)
+            .map(|s| s.addr + s.size)
+            .max()
+            .unwrap_or(base)
+    };
+    let bounds = |section: &str| {
+        layout
+            .sections
+            .iter()
+            .find(|s| s.name == section)
+            .map_or((base, base), |s| (s.addr, s.addr + s.size))
+    };
+    let content = |f: fn(&Content) -> bool| layout.content_address(f);
+
+    match name {
+        "__ehdr_start" | "__executable_start" => base,
+        "_etext" | "etext" => end_of(&|s| s.flags.contains(SectionFlag64::EXECINSTR)),
+        "_edata" | "edata" => end_of(&|s| !s.is_nobits()),
+        "__bss_start" => alloc()
+            .filter(|s| s.is_nobits() && !s.flags.contains(SectionFlag64::TLS))
+            .map(|s| s.addr)
+            .min()
+            .unwrap_or_else(|| end_of(&|s| !s.is_nobits())),
+        "_end" | "end" => end_of(&|_| true),
+        GOT_SYMBOL => layout.got_plt_address().unwrap_or(base),
+        "_DYNAMIC" => content(|c| matches!(c, Content::Dynamic)).unwrap_or(base),
+        "__rela_iplt_start" => content(|c| matches!(c, Content::RelaIplt)).unwrap_or(base),
+        "__rela_iplt_end" => content(|c| matches!(c, Content::RelaIplt)).map_or(base, |start| {
+            start + layout.synthetic_size(&Content::RelaIplt).0
+        }),
+        "__preinit_array_start" => bounds(".preinit_array").0,
+        "__preinit_array_end" => bounds(".preinit_array").1,
+        "__init_array_start" => bounds(".init_array").0,
+        "__init_array_end" => bounds(".init_array").1,
+        "__fini_array_start" => bounds(".fini_array").0,
+        "__fini_array_end" => bounds(".fini_array").1,
+        _ => match name.strip_prefix("__start_") {
+            Some(section) => bounds(section).0,
+            None => name
+                .strip_prefix("__stop_")
+                .map_or(base, |section| bounds(section).1),
+        },
+    }
+}
@@ -0,0 +1,507 @@
+//! Applies x86-64 relocations to the output image.
+
+use std::collections::HashSet;
+
+use crate::{
+    dynamic::{is_preemptible, DynamicPlace, DynamicReloc},
+    elf::{
+        Elf64Rela, RelocationType, SectionFlag64, SymbolBinding, SymbolType,
+        SECTION_HEADER_NUMBER_ABS, SECTION_HEADER_NUMBER_UNDEF,
+    },
+    inputs::{InputFile, SectionId},
+    layout::{candidates, Layout},
+    provide::GOT_SYMBOL,
+    symbols::{Definition, SymbolRef, SymbolTable},
+};
+
+/// What the relocations of the inputs need the linker to create.
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Symbols which need an entry in the global offset table, in the order they are first
+    /// referenced.
+    pub got: Vec<SymbolRef>,
+
+    /// Global symbols called through the procedure linkage table, in the order they are first
+    /// called.
+    pub plt: Vec<usize>,
+
+    /// Relocations the loader must apply, to input sections and to the GOT.
+    pub relocs: Vec<DynamicReloc>,
+
+    /// Symbols of shared libraries whose addresses an executable uses directly, in the order they
+    /// are first referenced. Data is copied into the executable, and functions get a canonical
+    /// PLT entry, so that the addresses are the same in every module.
+    pub addressed: Vec<usize>,
+
+    /// Indirect functions which cannot be preempted, in the order they are first referenced.
+    /// References to them go through an IPLT entry, which jumps through a slot the loader, or the
+    /// C library of a static executable, fills with the address the resolver returns.
+    pub iplt: Vec<SymbolRef>,
+
+    /// Whether anything refers to `_GLOBAL_OFFSET_TABLE_`, the start of `.got.plt`, or to
+    /// addresses relative to it, which needs `.got.plt` even without PLT entries.
+    pub got_plt: bool,
+}
+
+/// Scans the relocations of the inputs for what they need. `shared` is whether the output is a
+/// shared library, which may be loaded anywhere and whose symbols may be preempted.
+pub fn scan(inputs: &[InputFile], symbols: &SymbolTable, shared: bool) -> Result<Scan, String> {
+    let mut scan = Scan::default();
+    let mut seen_got = HashSet::new();
+    let mut seen_plt = HashSet::new();
+    let mut seen_addressed = HashSet::new();
+    let mut seen_iplt = HashSet::new();
+    for id in candidates(inputs) {
+        let input = &inputs[id.file];
+        let flags = input.sections[id.index].flags;
+        // Non-allocated sections are not loaded.
+        let loaded = flags.contains(SectionFlag64::ALLOC);
+        for rela in input.relocations(id.index)? {
+            let ty = relocation_type(input, &rela)?;
+            let symbol = symbols.resolve(id.file, rela.sym as usize);
+            if needs_got_entry(ty) && seen_got.insert(symbol) {
+                scan.got.push(symbol);
+            }
+            scan.got_plt |= matches!(ty, RelocationType::GotPc32 | RelocationType::GotOff64)
+                || matches!(symbol, SymbolRef::Global(g) if symbols.symbols[g].name == GOT_SYMBOL);
+            if !loaded {
+                continue;
+            }
+
+            let global = match symbol {
+                SymbolRef::Global(id) => Some(id),
+                SymbolRef::Local { .. } => None,
+            };
+            let preemptible = global.is_some_and(|g| is_preemptible(&symbols.symbols[g], shared));
+            if !preemptible && is_ifunc(inputs, symbols, symbol) && seen_iplt.insert(symbol) {
+                scan.iplt.push(symbol);
+            }
+            // Executables are not position-independent, so they refer to the symbols of shared
+            // libraries by fixed addresses.
+            let in_library = !shared
+                && global.is_some_and(|g| {
+                    matches!(symbols.symbols[g].definition, Definition::Shared { .. })
+                });
+            let absolute = is_absolute(inputs, symbols, symbol);
+            let error = || {
+                let name = match symbol {
+                    SymbolRef::Global(id) => symbols.symbols[id].name.clone(),
+                    SymbolRef::Local { file, index } => inputs[file].symbol_names[index].clone(),
+                };
+                format!(
+                    "{}:({}+0x{:x}): relocation {:?} cannot be used against symbol `{}`; \
+                     recompile with -fPIC",
+                    input.path, input.section_names[id.index], rela.offset, ty, name
+                )
+            };
+
+            match ty {
+                RelocationType::Plt32 if preemptible => {
+                    let global = global.unwrap();
+                    if seen_plt.insert(global) {
+                        scan.plt.push(global);
+                    }
+                }
+                RelocationType::Abs64
+                | RelocationType::Abs32
+                | RelocationType::Abs32S
+                | RelocationType::Abs16
+                | RelocationType::Abs8
+                | RelocationType::Pc64
+                | RelocationType::Pc32
+                | RelocationType::Pc16
+                | RelocationType::Pc8
+                    if in_library
+                        && !(ty == RelocationType::Abs64
+                            && flags.contains(SectionFlag64::WRITE)) =>
+                {
+                    let global = global.unwrap();
+                    if symbols.symbols[global].ty == SymbolType::Tls {
+                        return Err(error());
+                    }
+                    if seen_addressed.insert(global) {
+                        scan.addressed.push(global);
+                    }
+                }
+                RelocationType::Abs64 if preemptible || (shared && !absolute) => {
+                    // The loader cannot write to read-only sections.
+                    if !flags.contains(SectionFlag64::WRITE) {
+                        return Err(error());
+                    }
+                    scan.relocs.push(DynamicReloc {
+                        ty: if preemptible {
+                            RelocationType::Abs64
+                        } else {
+                            RelocationType::Relative
+                        },
+                        place: DynamicPlace::Input {
+                            id,
+                            offset: rela.offset,
+                        },
+                        symbol,
+                        addend: rela.addend,
+                    });
+                }
+                RelocationType::Abs32
+                | RelocationType::Abs32S
+                | RelocationType::Abs16
+                | RelocationType::Abs8
+                    if preemptible || (shared && !absolute) =>
+                {
+                    return Err(error())
+                }
+                RelocationType::Pc64
+                | RelocationType::Pc32
+                | RelocationType::Pc16
+                | RelocationType::Pc8
+                    if preemptible =>
+                {
+                    return Err(error())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // GOT entries of preemptible symbols are filled by the loader, as are those holding addresses
+    // in a shared library, which may be loaded anywhere.
+    for (index, &symbol) in scan.got.iter().enumerate() {
+        let preemptible = match symbol {
+            SymbolRef::Global(id) => is_preemptible(&symbols.symbols[id], shared),
+            SymbolRef::Local { .. } => false,
+        };
+        let ty = if preemptible {
+            RelocationType::GlobDat
+        } else if shared && !is_absolute(inputs, symbols, symbol) {
+            RelocationType::Relative
+        } else {
+            continue;
+        };
+        scan.relocs.push(DynamicReloc {
+            ty,
+            place: DynamicPlace::Got(index),
+            symbol,
+            addend: 0,
+        });
+    }
+    for (index, &symbol) in scan.iplt.iter().enumerate() {
+        scan.relocs.push(DynamicReloc {
+            ty: RelocationType::IRelative,
+            place: DynamicPlace::IpltSlot(index),
+            symbol,
+            addend: 0,
+        });
+    }
+    Ok(scan)
+}
+
+/// Whether a symbol is an indirect function defined in an object file, whose address is the
+/// result of calling its resolver.
+fn is_ifunc(inputs: &[InputFile], symbols: &SymbolTable, symbol: SymbolRef) -> bool {
+    match symbol {
+        SymbolRef::Global(id) => {
+            let symbol = &symbols.symbols[id];
+            symbol.ty == SymbolType::GnuIfunc
+                && matches!(symbol.definition, Definition::Section { .. })
+        }
+        SymbolRef::Local { file, index } => {
+            let sym = &inputs[file].symbols[index];
+            sym.ty == SymbolType::GnuIfunc
+                && sym.shndx != SECTION_HEADER_NUMBER_UNDEF
+                && sym.shndx != SECTION_HEADER_NUMBER_ABS
+        }
+    }
+}
+
+/// Whether the address of a symbol does not depend on where the output is loaded.
+fn is_absolute(inputs: &[InputFile], symbols: &SymbolTable, symbol: SymbolRef) -> bool {
+    match symbol {
+        SymbolRef::Global(id) => matches!(
+            symbols.symbols[id].definition,
+            Definition::Absolute(_) | Definition::Undefined
+        ),
+        SymbolRef::Local { file, index } => {
+            inputs[file].symbols[index].shndx == SECTION_HEADER_NUMBER_ABS
+        }
+    }
+}
+
+fn needs_got_entry(ty: RelocationType) -> bool {
+    matches!(
+        ty,
+        RelocationType::GotPcRel | RelocationType::GotPcRelX | RelocationType::RexGotPcRelX
+    )
+}
+
+fn relocation_type(input: &InputFile, rela: &Elf64Rela) -> Result<RelocationType, String> {
+    RelocationType::try_from(rela.ty).map_err(|e| format!("{}: {}: {}", input.path, e, rela.ty))
+}
+
+/// Applies the relocations of every input section that is part of the output to `image`, the
+/// output file laid out by `layout`.
+pub fn apply(
+    image: &mut [u8],
+    inputs: &[InputFile],
+    symbols: &SymbolTable,
+    layout: &Layout,
+) -> Result<(), String> {
+    for id in candidates(inputs) {
+        let Some((out, offset)) = layout.placement(id) else {
+            continue;
+        };
+        let section = &layout.sections[out];
+        // Merged sections hold copies of the pieces of their members rather than the members.
+        if section.is_nobits() || layout.merged.group(id).is_some() {
+            continue;
+        }
+
+        // Debug information and unwind tables may refer to discarded code, such as the copies of
+        // inline functions in discarded COMDAT groups; such references resolve to a tombstone
+        // value. Address ranges in `.debug_ranges` and `.debug_loc` use 1, since 0 would end the
+        // list.
+        let input = &inputs[id.file];
+        let name = &input.section_names[id.index];
+        let tombstone = if name == ".debug_ranges" || name == ".debug_loc" {
+            Some(1)
+        } else if !section.is_alloc() || name == ".eh_frame" {
+            Some(0)
+        } else {
+            None
+        };
+        for rela in input.relocations(id.index)? {
+            // Records of `.eh_frame` move independently, and dropped records are not relocated.
+            let rela_offset = if layout.eh_frame.contains(id) {
+                match layout.eh_frame.offset(id, rela.offset) {
+                    Some(offset) => offset,
+                    None => continue,
+                }
+            } else {
+                rela.offset
+            };
+            let place = Place {
+                inputs,
+                id,
+                rela: &rela,
+                addr: section.addr + offset + rela_offset,
+            };
+            let start = (section.offset + offset + rela_offset) as usize;
+            apply_one(image, start, &place, symbols, layout, tombstone)?;
+        }
+    }
+    Ok(())
+}
+
+/// The location a relocation applies to.
+struct Place<'a> {
+    inputs: &'a [InputFile],
+    id: SectionId,
+    rela: &'a Elf64Rela,
+    addr: u64,
+}
+
+impl Place<'_> {
+    /// Describes the location for error messages, as `file:(section+0xoffset)`.
+    fn describe(&self) -> String {
+        let input = &self.inputs[self.id.file];
+        format!(
+            "{}:({}+0x{:x})",
+            input.path, input.section_names[self.id.index], self.rela.offset
+        )
+    }
+}
+
+fn apply_one(
+    image: &mut [u8],
+    start: usize,
+    place: &Place,
+    symbols: &SymbolTable,
+    layout: &Layout,
+    tombstone: Option<u64>,
+) -> Result<(), String> {
+    let inputs = place.inputs;
+    let rela = place.rela;
+    let ty = relocation_type(&inputs[place.id.file], rela)?;
+    if ty == RelocationType::None {
+        return Ok(());
+    }
+
+    let symbol = symbols.resolve(place.id.file, rela.sym as usize);
+    let name = || match symbol {
+        SymbolRef::Global(id) => symbols.symbols[id].name.clone(),
+        SymbolRef::Local { file, index } => {
+            let name = &inputs[file].symbol_names[index];
+            if name.is_empty() {
+                let shndx = inputs[file].symbols[index].shndx as usize;
+                inputs[file]
+                    .section_names
+                    .get(shndx)
+                    .cloned()
+                    .unwrap_or_default()
+            } else {
+                name.clone()
+            }
+        }
+    };
+
+    // Shared libraries may refer to symbols other modules define.
+    if let SymbolRef::Global(id) = symbol {
+        let sym = &symbols.symbols[id];
+        if !sym.is_defined() && sym.binding != SymbolBinding::Weak && !layout.is_shared() {
+            return Err(format!(
+                "undefined symbol: {}\n>>> referenced by {}",
+                sym.name,
+                place.describe()
+            ));
+        }
+    }
+
+    let mut a = rela.addend as u64;
+    // A section symbol of a merged section refers to the piece at the addend rather than to the
+    // start of the section, since the pieces move independently.
+    let address = match symbol {
+        SymbolRef::Local { file, index }
+            if inputs[file].symbols[index].ty == SymbolType::Section =>
+        {
+            let id = SectionId {
+                file,
+                index: inputs[file].symbols[index].shndx as usize,
+            };
+            if layout.merged.group(id).is_some() {
+                let offset = inputs[file].symbols[index].value.wrapping_add(a);
+                a = 0;
+                layout.input_address(id, offset)
+            } else {
+                layout.symbol_address(inputs, symbols, symbol)
+            }
+        }
+        // Calls to functions defined elsewhere go through their PLT entries.
+        SymbolRef::Global(id) if ty == RelocationType::Plt32 => layout
+            .plt_entry_address(id)
+            .or_else(|| layout.symbol_address(inputs, symbols, symbol)),
+        _ => layout.symbol_address(inputs, symbols, symbol),
+    };
+    let s = match (address, tombstone) {
+        (Some(s), _) => s,
+        (None, Some(value)) => {
+            a = 0;
+            value
+        }
+        (None, None) => {
+            return Err(format!(
+                "{}: relocation refers to `{}`, which is in a discarded section",
+                place.describe(),
+                name()
+            ))
+        }
+    };
+    let p = place.addr;
+    let got = || {
+        layout
+            .got_plt_address()
+            .ok_or_else(|| format!("{}: no global offset table", place.describe()))
+    };
+    let size = || match symbol {
+        SymbolRef::Global(id) => symbols.symbols[id].size,
+        SymbolRef::Local { file, index } => inputs[file].symbols[index].size,
+    };
+
+    let loc = &mut image[start..];
+    let out_of_range = |value: i128, min: i128, max: i128| {
+        format!(
+            "{}: relocation {:?} out of range: {} is not in [{}, {}]; references `{}`",
+            place.describe(),
+            ty,
+            value,
+            min,
+            max,
+            name()
+        )
+    };
+    let write_i32 = |loc: &mut [u8], value: u64| {
+        let value = value as i64;
+        if value < i32::MIN as i64 || value > i32::MAX as i64 {
+            return Err(out_of_range(
+                value as i128,
+                i32::MIN as i128,
+                i32::MAX as i128,
+            ));
+        }
+        loc[..4].copy_from_slice(&(value as i32).to_le_bytes());
+        Ok(())
+    };
+
+    match ty {
+        RelocationType::Abs64 => loc[..8].copy_from_slice(&s.wrapping_add(a).to_le_bytes()),
+        RelocationType::Pc64 => {
+            loc[..8].copy_from_slice(&s.wrapping_add(a).wrapping_sub(p).to_le_bytes())
+        }
+        RelocationType::Pc32 | RelocationType::Plt32 => {
+            write_i32(loc, s.wrapping_add(a).wrapping_sub(p))?
+        }
+        RelocationType::Abs32 => {
+            let value = s.wrapping_add(a);
+            if value > u32::MAX as u64 {
+                return Err(out_of_range(value as i128, 0, u32::MAX as i128));
+            }
+            loc[..4].copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        RelocationType::Abs32S => write_i32(loc, s.wrapping_add(a))?,
+        RelocationType::Abs16 | RelocationType::Pc16 => {
+            let mut value = s.wrapping_add(a);
+            if ty == RelocationType::Pc16 {
+                value = value.wrapping_sub(p);
+            }
+            let signed = value as i64;
+            if signed < i16::MIN as i64 || signed > u16::MAX as i64 {
+                return Err(out_of_range(
+                    signed as i128,
+                    i16::MIN as i128,
+                    u16::MAX as i128,
+                ));
+            }
+            loc[..2].copy_from_slice(&(value as u16).to_le_bytes());
+        }
+        RelocationType::Abs8 | RelocationType::Pc8 => {
+            let mut value = s.wrapping_add(a);
+            if ty == RelocationType::Pc8 {
+                value = value.wrapping_sub(p);
+            }
+            let signed = value as i64;
+            if signed < i8::MIN as i64 || signed > u8::MAX as i64 {
+                return Err(out_of_range(
+                    signed as i128,
+                    i8::MIN as i128,
+                    u8::MAX as i128,
+                ));
+            }
+            loc[0] = value as u8;
+        }
+        RelocationType::GotPcRel | RelocationType::GotPcRelX | RelocationType::RexGotPcRelX => {
+            let entry = layout
+                .got_entry_address(symbol)
+                .ok_or_else(|| format!("{}: `{}` has no GOT entry", place.describe(), name()))?;
+            write_i32(loc, entry.wrapping_add(a).wrapping_sub(p))?
+        }
+        RelocationType::GotPc32 => write_i32(loc, got()?.wrapping_add(a).wrapping_sub(p))?,
+        RelocationType::GotOff64 => {
+            loc[..8].copy_from_slice(&s.wrapping_add(a).wrapping_sub(got()?).to_le_bytes())
+        }
+        RelocationType::Size32 => {
+            let value = size().wrapping_add(a);
+            if value > u32::MAX as u64 {
+                return Err(out_of_range(value as i128, 0, u32::MAX as i128));
+            }
+            loc[..4].copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        RelocationType::Size64 => loc[..8].copy_from_slice(&size().wrapping_add(a).to_le_bytes()),
+        _ => {
+            return Err(format!(
+                "{}: unsupported relocation {:?} against `{}`",
+                place.describe(),
+                ty,
+                name()
+            ))
+        }
+    }
+    Ok(())
+}
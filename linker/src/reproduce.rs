@@ -0,0 +1,235 @@
+//! Packaging of a link into a tar file for bug reports, requested by `--reproduce`.
+//!
+//! The input files, the linker script, version scripts and dynamic lists are stored under a
+//! directory named after the tar file, each at its absolute path. `response.txt` holds the command
+//! line with the paths rewritten to those in the tar file, so that the link is repeated by
+//! extracting the tar file and running `krc @response.txt` in the directory. Libraries given by
+//! `-l`, and linker scripts given as inputs, are replaced by the files they were found to be.
+
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::{
+    library::{self, InputPath},
+    options::{Input, Options},
+    script::Script,
+};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Writes the tar file `path` for the link described by `options`, the linker script it gives and
+/// the files found for its inputs.
+pub fn write(
+    path: &str,
+    options: &Options,
+    script: Option<&Script>,
+    inputs: &[InputPath],
+) -> Result<(), String> {
+    let root = Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("repro");
+    let mut tar = vec![];
+
+    let mut files: Vec<&String> = inputs.iter().map(|input| &input.path).collect();
+    files.extend(&options.script);
+    files.extend(&options.version_scripts);
+    files.extend(&options.dynamic_lists);
+    let mut seen = HashSet::new();
+    for file in files {
+        if !seen.insert(file) {
+            continue;
+        }
+        let data = fs::read(file).map_err(|e| format!("cannot open {}: {}", file, e))?;
+        append(&mut tar, &format!("{}/{}", root, relative(file)?), &data);
+    }
+
+    let response: Vec<String> = response(options, script)?
+        .iter()
+        .map(|a| quote(a))
+        .collect();
+    append(
+        &mut tar,
+        &format!("{}/response.txt", root),
+        format!("{}\n", response.join(" ")).as_bytes(),
+    );
+    append(
+        &mut tar,
+        &format!("{}/version.txt", root),
+        format!("krc {}\n", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+
+    fs::write(path, tar).map_err(|e| format!("cannot write {}: {}", path, e))
+}
+
+/// The path of a file relative to the root of the tar file: its absolute path without the leading
+/// `/`.
+fn relative(path: &str) -> Result<String, String> {
+    let absolute = std::path::absolute(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(absolute.to_string_lossy().trim_start_matches('/').into())
+}
+
+/// The command line with the paths of the files rewritten, the output written to the current
+/// directory and `--reproduce` removed.
+fn response(options: &Options, script: Option<&Script>) -> Result<Vec<String>, String> {
+    let is_file = |arg: &str| {
+        options.script.as_deref() == Some(arg)
+            || options.version_scripts.iter().any(|p| p == arg)
+            || options.dynamic_lists.iter().any(|p| p == arg)
+    };
+    let dirs = library::library_dirs(options, script);
+    // Inputs are in the order of the arguments giving them.
+    let mut inputs = options.inputs.iter().peekable();
+    // Files a linker script names in `AS_NEEDED` are given with `--as-needed`.
+    let found = |input: &Input| -> Result<Vec<String>, String> {
+        let mut args = vec![];
+        for path in library::resolve_input(options, &dirs, input)? {
+            if path.as_needed && !input.as_needed {
+                args.extend(["--as-needed".into(), relative(&path.path)?]);
+                args.push("--no-as-needed".into());
+            } else {
+                args.push(relative(&path.path)?);
+            }
+        }
+        Ok(args)
+    };
+
+    let mut response = vec![];
+    let mut args = options.args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(input) = inputs.next_if(|input| !input.library && input.name == *arg) {
+            response.extend(found(input)?);
+            continue;
+        }
+        let library = match library_arg(arg) {
+            Some(Some(name)) => Some(name.to_string()),
+            Some(None) => args.clone().next().cloned(),
+            None => None,
+        };
+        if let Some(input) =
+            inputs.next_if(|input| input.library && Some(&input.name) == library.as_ref())
+        {
+            if library_arg(arg) == Some(None) {
+                args.next();
+            }
+            response.extend(found(input)?);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--reproduce" | "-reproduce" => {
+                args.next();
+            }
+            "-o" | "--output" | "-output" => {
+                response.push(arg.clone());
+                if let Some(output) = args.next() {
+                    response.push(file_name(output));
+                }
+            }
+            _ if arg.starts_with("--reproduce=") || arg.starts_with("-reproduce=") => {}
+            _ if is_file(arg) => response.push(relative(arg)?),
+            _ => {
+                let joined = [
+                    "-T",
+                    "--script=",
+                    "-script=",
+                    "--version-script=",
+                    "-version-script=",
+                    "--dynamic-list=",
+                    "-dynamic-list=",
+                ]
+                .iter()
+                .find_map(|prefix| Some((prefix, arg.strip_prefix(prefix)?)))
+                .filter(|(_, path)| is_file(path));
+                let output = ["--output=", "-output=", "-o"]
+                    .iter()
+                    .find_map(|prefix| Some((prefix, arg.strip_prefix(prefix)?)));
+                if let Some((prefix, path)) = joined {
+                    response.push(format!("{}{}", prefix, relative(path)?));
+                } else if let Some((prefix, output)) = output {
+                    response.push(format!("{}{}", prefix, file_name(output)));
+                } else {
+                    response.push(arg.clone());
+                }
+            }
+        }
+    }
+    Ok(response)
+}
+
+/// If `arg` gives a library, the name of the library, or `None` if it is the next argument.
+fn library_arg(arg: &str) -> Option<Option<&str>> {
+    match arg {
+        "-l" | "--library" | "-library" => Some(None),
+        // `-library-path` is `-L`.
+        _ if arg.starts_with("-library-path") => None,
+        _ => ["--library=", "-library=", "-l"]
+            .iter()
+            .find_map(|prefix| arg.strip_prefix(prefix))
+            .filter(|name| !name.is_empty())
+            .map(Some),
+    }
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map_or_else(|| path.into(), |name| name.to_string_lossy().into())
+}
+
+/// Quotes an argument for a response file if it has whitespace or quotes.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return arg.into();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Appends a file to a tar file. The path is given by a pax extended header, which has no length
+/// limit, and timestamps and owners are zero so that the tar file is reproducible too.
+fn append(tar: &mut Vec<u8>, path: &str, data: &[u8]) {
+    // The length of a pax record counts its own digits.
+    let body = format!(" path={}\n", path);
+    let mut len = body.len() + 1;
+    while format!("{}{}", len, body).len() != len {
+        len += 1;
+    }
+    let record = format!("{}{}", len, body);
+
+    append_entry(tar, b"pax", b'x', record.as_bytes());
+    let name = &path.as_bytes()[..path.len().min(100)];
+    append_entry(tar, name, b'0', data);
+}
+
+fn append_entry(tar: &mut Vec<u8>, name: &[u8], ty: u8, data: &[u8]) {
+    let mut header = [0u8; BLOCK_SIZE];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name);
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", data.len()).as_bytes());
+    field(136, b"00000000000\0");
+    field(156, &[ty]);
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    // The checksum is computed with its own field filled with spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(data);
+    tar.resize(tar.len().next_multiple_of(BLOCK_SIZE), 0);
+}
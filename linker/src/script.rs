@@ -0,0 +1,1706 @@
+//! Parser and expression evaluator for GNU linker scripts.
+//!
+//! See [Linker Scripts] in the GNU ld manual.
+//!
+//! [Linker Scripts]: https://sourceware.org/binutils/docs/ld/Scripts.html
+
+use std::fmt::Display;
+
+use crate::{
+    elf::{SegmentFlag, SegmentType},
+    options::Input,
+    util::{align_up, glob_match},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A position in a linker script, counted from 1.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Script {
+    /// The file the script was read from, used in error messages.
+    pub path: String,
+
+    /// The symbol given by `ENTRY`.
+    pub entry: Option<String>,
+
+    /// Top-level commands in the order they appear.
+    pub commands: Vec<Command>,
+
+    /// Regions declared by `MEMORY`.
+    pub memory: Vec<MemoryRegion>,
+
+    /// Segments declared by `PHDRS`. Empty if the script has no `PHDRS` command.
+    pub phdrs: Vec<PhdrDesc>,
+
+    /// Directories given by `SEARCH_DIR`.
+    pub search_dirs: Vec<String>,
+
+    /// Files and libraries given by `INPUT` and `GROUP`, with `as_needed` set for those in
+    /// `AS_NEEDED`.
+    pub inputs: Vec<Input>,
+
+    /// Version nodes given by `VERSION`.
+    pub versions: Vec<VersionNode>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Assignment(Assignment),
+    Assert(Assert),
+    Sections(Vec<SectionsCommand>),
+}
+
+#[derive(Debug)]
+pub enum SectionsCommand {
+    Assignment(Assignment),
+    Assert(Assert),
+    OutputSection(Box<OutputSectionDesc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignOp {
+    Set,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shl,
+    Shr,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    /// The assigned symbol, or `.` for the location counter.
+    pub symbol: String,
+    pub op: AssignOp,
+    pub expr: Expr,
+
+    /// Whether the assignment is wrapped in `PROVIDE` or `PROVIDE_HIDDEN`, which defines the symbol
+    /// only if it is referenced but not defined.
+    pub provide: bool,
+
+    /// Whether the assignment is wrapped in `HIDDEN` or `PROVIDE_HIDDEN`.
+    pub hidden: bool,
+
+    pub location: Location,
+}
+
+#[derive(Debug)]
+pub struct Assert {
+    pub expr: Expr,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct OutputSectionDesc {
+    pub name: String,
+    pub address: Option<Expr>,
+
+    /// Whether the section is `(NOLOAD)`, which makes it occupy no file space.
+    pub noload: bool,
+
+    /// The load address given by `AT(...)`.
+    pub lma: Option<Expr>,
+    pub align: Option<Expr>,
+    pub subalign: Option<Expr>,
+    pub commands: Vec<OutputSectionCommand>,
+
+    /// The region given by `> REGION`.
+    pub region: Option<String>,
+
+    /// The region given by `AT> REGION`.
+    pub lma_region: Option<String>,
+
+    /// The segments given by `:PHDR`.
+    pub phdrs: Vec<String>,
+
+    /// The fill pattern given by `=FILL`.
+    pub fill: Option<Expr>,
+
+    pub location: Location,
+}
+
+impl OutputSectionDesc {
+    pub fn is_discard(&self) -> bool {
+        self.name == "/DISCARD/"
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputSectionCommand {
+    Assignment(Assignment),
+    Assert(Assert),
+    Input(InputSectionDesc),
+
+    /// `BYTE`, `SHORT`, `LONG`, `QUAD` or `SQUAD`, storing a value of `size` bytes.
+    Data {
+        size: u8,
+        expr: Expr,
+    },
+
+    /// `FILL(expr)`.
+    Fill(Expr),
+}
+
+#[derive(Debug)]
+pub struct InputSectionDesc {
+    /// Whether the matched sections are wrapped in `KEEP`, which protects them from garbage
+    /// collection.
+    pub keep: bool,
+
+    /// The wildcard pattern input file names are matched against.
+    pub file: String,
+
+    /// Files excluded by `EXCLUDE_FILE` preceding the file pattern.
+    pub exclude_files: Vec<String>,
+
+    pub sections: Vec<SectionPattern>,
+    pub location: Location,
+}
+
+impl InputSectionDesc {
+    /// Returns the index of the first pattern matching the section `name` of the file at `path`,
+    /// or `None` if the description does not match it.
+    pub fn matches(&self, path: &str, name: &str) -> Option<usize> {
+        let excluded = |patterns: &[String]| patterns.iter().any(|p| glob_match(p, path));
+        if !glob_match(&self.file, path) || excluded(&self.exclude_files) {
+            return None;
+        }
+        self.sections
+            .iter()
+            .position(|p| glob_match(&p.pattern, name) && !excluded(&p.exclude_files))
+    }
+}
+
+#[derive(Debug)]
+pub struct SectionPattern {
+    pub pattern: String,
+
+    /// Files excluded by `EXCLUDE_FILE` preceding the pattern.
+    pub exclude_files: Vec<String>,
+    pub sort: SortKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKind {
+    None,
+    Name,
+    Alignment,
+    InitPriority,
+}
+
+#[derive(Debug)]
+pub struct MemoryRegion {
+    pub name: String,
+
+    /// Attributes such as `rx` or `!w`, used to place sections with no explicit region.
+    pub attributes: String,
+    pub origin: Expr,
+    pub length: Expr,
+    pub location: Location,
+}
+
+#[derive(Debug)]
+pub struct PhdrDesc {
+    pub name: String,
+    pub ty: SegmentType,
+
+    /// Whether the segment includes the ELF file header (`FILEHDR`).
+    pub filehdr: bool,
+
+    /// Whether the segment includes the program header table (`PHDRS`).
+    pub phdrs: bool,
+    pub at: Option<Expr>,
+    pub flags: Option<SegmentFlag>,
+    pub location: Location,
+}
+
+/// A node of a version script, such as `VER_2 { global: foo; local: *; } VER_1;`.
+///
+/// See [VERSION] in the GNU ld manual.
+///
+/// [VERSION]: https://sourceware.org/binutils/docs/ld/VERSION.html
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VersionNode {
+    /// The name of the version, or `None` for the only node of a script which just lists the
+    /// symbols to export.
+    pub name: Option<String>,
+
+    /// The versions this version inherits from.
+    pub parents: Vec<String>,
+    pub globals: Vec<SymbolPattern>,
+    pub locals: Vec<SymbolPattern>,
+}
+
+/// A pattern of a version script or a dynamic list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolPattern {
+    pub pattern: String,
+
+    /// Whether the pattern is in an `extern "C++"` block, so matched against demangled names.
+    pub cxx: bool,
+
+    /// Whether the pattern is quoted or has no wildcards, so matched literally.
+    pub exact: bool,
+}
+
+impl SymbolPattern {
+    /// Whether the pattern matches a symbol, given its name and its demangled name if it has one.
+    pub fn matches(&self, name: &str, demangled: Option<&str>) -> bool {
+        let name = match (self.cxx, demangled) {
+            (false, _) => name,
+            (true, Some(demangled)) => demangled,
+            (true, None) => return false,
+        };
+        if self.exact {
+            self.pattern == name
+        } else {
+            glob_match(&self.pattern, name)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Number(u64),
+    Symbol(String),
+
+    /// The location counter `.`.
+    Dot,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+
+    /// `ALIGN(align)` or `ALIGN(expr, align)`.
+    Align(Option<Box<Expr>>, Box<Expr>),
+    Addr(String),
+    LoadAddr(String),
+    SizeOf(String),
+    AlignOf(String),
+    Defined(String),
+    Origin(String),
+    Length(String),
+    Max(Box<Expr>, Box<Expr>),
+    Min(Box<Expr>, Box<Expr>),
+    SizeOfHeaders,
+    MaxPageSize,
+    CommonPageSize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// Address information of an output section, as seen by expressions.
+#[derive(Debug, Clone, Copy)]
+pub struct SectionInfo {
+    pub addr: u64,
+    pub lma: u64,
+    pub size: u64,
+    pub align: u64,
+}
+
+/// Supplies the values expressions refer to. Lookups return `None` for unknown names.
+pub trait EvalContext {
+    /// The current location counter, or `None` where `.` may not be used.
+    fn dot(&self) -> Option<u64>;
+    fn symbol(&self, name: &str) -> Option<u64>;
+    fn is_defined(&self, name: &str) -> bool;
+    fn section(&self, name: &str) -> Option<SectionInfo>;
+
+    /// The origin and the length of a memory region.
+    fn region(&self, name: &str) -> Option<(u64, u64)>;
+    fn headers_size(&self) -> u64;
+    fn max_page_size(&self) -> u64;
+    fn common_page_size(&self) -> u64;
+}
+
+impl Expr {
+    pub fn eval(&self, ctx: &dyn EvalContext) -> Result<u64, String> {
+        let error = |msg: String| format!("{}: {}", self.location, msg);
+        let section = |name: &str| {
+            ctx.section(name).ok_or_else(|| {
+                error(format!(
+                    "undefined section `{}` referenced in expression",
+                    name
+                ))
+            })
+        };
+        let region = |name: &str| {
+            ctx.region(name)
+                .ok_or_else(|| error(format!("undefined memory region `{}`", name)))
+        };
+
+        Ok(match &self.kind {
+            ExprKind::Number(n) => *n,
+            ExprKind::Symbol(name) => ctx.symbol(name).ok_or_else(|| {
+                error(format!(
+                    "undefined symbol `{}` referenced in expression",
+                    name
+                ))
+            })?,
+            ExprKind::Dot => ctx
+                .dot()
+                .ok_or_else(|| error("`.` cannot be used here".into()))?,
+            ExprKind::Unary(op, e) => {
+                let v = e.eval(ctx)?;
+                match op {
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Not => (v == 0) as u64,
+                    UnaryOp::BitNot => !v,
+                }
+            }
+            ExprKind::Binary(op, l, r) => {
+                let l = l.eval(ctx)?;
+                let r = r.eval(ctx)?;
+                match op {
+                    BinaryOp::Mul => l.wrapping_mul(r),
+                    BinaryOp::Div | BinaryOp::Rem if r == 0 => {
+                        return Err(error("division by zero".into()))
+                    }
+                    BinaryOp::Div => l / r,
+                    BinaryOp::Rem => l % r,
+                    BinaryOp::Add => l.wrapping_add(r),
+                    BinaryOp::Sub => l.wrapping_sub(r),
+                    BinaryOp::Shl => l.wrapping_shl(r as u32),
+                    BinaryOp::Shr => l.wrapping_shr(r as u32),
+                    BinaryOp::Lt => (l < r) as u64,
+                    BinaryOp::Le => (l <= r) as u64,
+                    BinaryOp::Gt => (l > r) as u64,
+                    BinaryOp::Ge => (l >= r) as u64,
+                    BinaryOp::Eq => (l == r) as u64,
+                    BinaryOp::Ne => (l != r) as u64,
+                    BinaryOp::BitAnd => l & r,
+                    BinaryOp::BitXor => l ^ r,
+                    BinaryOp::BitOr => l | r,
+                    BinaryOp::And => (l != 0 && r != 0) as u64,
+                    BinaryOp::Or => (l != 0 || r != 0) as u64,
+                }
+            }
+            ExprKind::Conditional(c, t, f) => {
+                if c.eval(ctx)? != 0 {
+                    t.eval(ctx)?
+                } else {
+                    f.eval(ctx)?
+                }
+            }
+            ExprKind::Align(e, align) => {
+                let value = match e {
+                    Some(e) => e.eval(ctx)?,
+                    None => ctx
+                        .dot()
+                        .ok_or_else(|| error("`.` cannot be used here".into()))?,
+                };
+                let align = align.eval(ctx)?;
+                if align != 0 && !align.is_power_of_two() {
+                    return Err(error(format!(
+                        "alignment 0x{:x} is not a power of 2",
+                        align
+                    )));
+                }
+                align_up(value, align)
+            }
+            ExprKind::Addr(name) => section(name)?.addr,
+            ExprKind::LoadAddr(name) => section(name)?.lma,
+            ExprKind::SizeOf(name) => section(name)?.size,
+            ExprKind::AlignOf(name) => section(name)?.align,
+            ExprKind::Defined(name) => ctx.is_defined(name) as u64,
+            ExprKind::Origin(name) => region(name)?.0,
+            ExprKind::Length(name) => region(name)?.1,
+            ExprKind::Max(l, r) => l.eval(ctx)?.max(r.eval(ctx)?),
+            ExprKind::Min(l, r) => l.eval(ctx)?.min(r.eval(ctx)?),
+            ExprKind::SizeOfHeaders => ctx.headers_size(),
+            ExprKind::MaxPageSize => ctx.max_page_size(),
+            ExprKind::CommonPageSize => ctx.common_page_size(),
+        })
+    }
+
+    /// Returns the symbols whose values the expression uses, in order.
+    pub fn symbols(&self) -> Vec<&str> {
+        match &self.kind {
+            ExprKind::Symbol(name) => vec![name],
+            ExprKind::Unary(_, e) | ExprKind::Align(None, e) => e.symbols(),
+            ExprKind::Binary(_, l, r)
+            | ExprKind::Max(l, r)
+            | ExprKind::Min(l, r)
+            | ExprKind::Align(Some(l), r) => [l.symbols(), r.symbols()].concat(),
+            ExprKind::Conditional(c, t, f) => [c.symbols(), t.symbols(), f.symbols()].concat(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether the expression is a constant, i.e. refers to neither symbols nor the location
+    /// counter. Constants assigned to `.` inside an output section are offsets from its start.
+    pub fn is_constant(&self) -> bool {
+        match &self.kind {
+            ExprKind::Number(_)
+            | ExprKind::SizeOfHeaders
+            | ExprKind::MaxPageSize
+            | ExprKind::CommonPageSize => true,
+            ExprKind::Unary(_, e) => e.is_constant(),
+            ExprKind::Binary(_, l, r) | ExprKind::Max(l, r) | ExprKind::Min(l, r) => {
+                l.is_constant() && r.is_constant()
+            }
+            ExprKind::Conditional(c, t, f) => c.is_constant() && t.is_constant() && f.is_constant(),
+            _ => false,
+        }
+    }
+}
+
+impl Script {
+    /// Parses a linker script. Errors are prefixed with the line and the column they occur at.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(text);
+        let mut script = Script::default();
+        parser.parse_script(&mut script)?;
+        Ok(script)
+    }
+
+    /// Reads and parses the linker script at `path`.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let mut script = read_file(path, Self::parse)?;
+        script.path = path.into();
+        Ok(script)
+    }
+
+    /// Whether the script assigns a value to the symbol `name`, anywhere.
+    pub fn assigns(&self, name: &str) -> bool {
+        self.commands.iter().any(|command| match command {
+            Command::Assignment(a) => a.symbol == name,
+            Command::Assert(_) => false,
+            Command::Sections(commands) => commands.iter().any(|command| match command {
+                SectionsCommand::Assignment(a) => a.symbol == name,
+                SectionsCommand::Assert(_) => false,
+                SectionsCommand::OutputSection(desc) => {
+                    desc.commands.iter().any(|command| match command {
+                        OutputSectionCommand::Assignment(a) => a.symbol == name,
+                        _ => false,
+                    })
+                }
+            }),
+        })
+    }
+}
+
+/// Parses the argument of `--defsym`, `sym=expr`, as an assignment of a linker script.
+pub fn parse_defsym(text: &str) -> Result<Assignment, String> {
+    let expected = || format!("--defsym: expected `sym=expr`: {}", text);
+    if !text.contains('=') {
+        return Err(expected());
+    }
+    let script = Script::parse(&format!("{};", text)).map_err(|e| format!("--defsym: {}", e))?;
+    match script.commands.as_slice() {
+        [Command::Assignment(assignment)]
+            if assignment.op == AssignOp::Set
+                && !assignment.provide
+                && !assignment.hidden
+                && assignment.symbol != "." =>
+        {
+            Ok(assignment.clone())
+        }
+        _ => Err(expected()),
+    }
+}
+
+/// Parses a version script, as given by `--version-script`.
+pub fn parse_version_script(text: &str) -> Result<Vec<VersionNode>, String> {
+    let mut parser = Parser::new(text);
+    let nodes = parser.parse_version_nodes()?;
+    match parser.peek(Mode::Symbol)? {
+        Token::Eof => Ok(nodes),
+        token => Err(parser.error(format!("unexpected {}", token))),
+    }
+}
+
+/// Reads and parses the version script at `path`.
+pub fn read_version_script(path: &str) -> Result<Vec<VersionNode>, String> {
+    read_file(path, parse_version_script)
+}
+
+/// Parses a dynamic list, as given by `--dynamic-list`: patterns in braces, such as
+/// `{ foo; extern "C++" { ns::*; }; };`.
+pub fn parse_dynamic_list(text: &str) -> Result<Vec<SymbolPattern>, String> {
+    let mut parser = Parser::new(text);
+    let mut node = VersionNode::default();
+    parser.parse_version_block(false, false, &mut node)?;
+    if !node.locals.is_empty() {
+        return Err("a dynamic list cannot have local symbols".into());
+    }
+    parser.consume(";")?;
+    match parser.peek(Mode::Symbol)? {
+        Token::Eof => Ok(node.globals),
+        token => Err(parser.error(format!("unexpected {}", token))),
+    }
+}
+
+/// Reads and parses the dynamic list at `path`.
+pub fn read_dynamic_list(path: &str) -> Result<Vec<SymbolPattern>, String> {
+    read_file(path, parse_dynamic_list)
+}
+
+/// Reads the file at `path` and parses it with `parse`, prefixing errors with the path.
+fn read_file<T>(path: &str, parse: fn(&str) -> Result<T, String>) -> Result<T, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}:{}", path, e))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(u64),
+    Str(String),
+    Punct(&'static str),
+    Eof,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "`{}`", w),
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Punct(p) => write!(f, "`{}`", p),
+            Token::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+/// Linker scripts tokenize differently depending on the context: `a-b` is a subtraction in an
+/// expression but `.note.GNU-stack` is a single section name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Expr,
+
+    /// Output section names.
+    Name,
+
+    /// File and input section patterns.
+    Pattern,
+
+    /// Symbol patterns of version scripts, which may have `::` but end at a single `:`.
+    Symbol,
+}
+
+const PUNCTS: &[&str] = &[
+    "<<=", ">>=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "&=",
+    "|=", "(", ")", "{", "}", ";", ",", ":", "=", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!",
+    "<", ">", "?",
+];
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            text,
+            pos: 0,
+            line_starts,
+        }
+    }
+
+    fn location_at(&self, pos: usize) -> Location {
+        let line = self.line_starts.partition_point(|&start| start <= pos);
+        let column = self.text[self.line_starts[line - 1]..pos].chars().count() + 1;
+        Location { line, column }
+    }
+
+    fn error(&self, msg: impl Display) -> String {
+        format!("{}: {}", self.location_at(self.pos), msg)
+    }
+
+    fn skip_space(&mut self) -> Result<(), String> {
+        loop {
+            let rest = &self.text[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if let Some(comment) = trimmed.strip_prefix("/*") {
+                match comment.find("*/") {
+                    Some(end) => self.pos += end + 4,
+                    None => return Err(self.error("unterminated comment")),
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The current location, after skipping spaces.
+    fn location(&mut self) -> Result<Location, String> {
+        self.skip_space()?;
+        Ok(self.location_at(self.pos))
+    }
+
+    /// Lexes the token at the current position without consuming it, returning its end.
+    fn lex(&mut self, mode: Mode) -> Result<(Token, usize), String> {
+        self.skip_space()?;
+        let rest = &self.text[self.pos..];
+        let Some(c) = rest.chars().next() else {
+            return Ok((Token::Eof, self.pos));
+        };
+
+        if c == '"' {
+            return match rest[1..].find('"') {
+                Some(end) => Ok((Token::Str(rest[1..end + 1].into()), self.pos + end + 2)),
+                None => Err(self.error("unterminated string")),
+            };
+        }
+
+        let word_len =
+            |is_word: &dyn Fn(char) -> bool| rest.find(|c: char| !is_word(c)).unwrap_or(rest.len());
+
+        let len = match mode {
+            Mode::Expr if c.is_ascii_digit() => {
+                let len = word_len(&|c| c.is_ascii_alphanumeric());
+                let number = parse_number(&rest[..len])
+                    .ok_or_else(|| self.error(format!("invalid number `{}`", &rest[..len])))?;
+                return Ok((Token::Number(number), self.pos + len));
+            }
+            Mode::Expr if c.is_ascii_alphabetic() || "_.$".contains(c) => {
+                word_len(&|c| c.is_ascii_alphanumeric() || "_.$".contains(c))
+            }
+            Mode::Expr => 0,
+            Mode::Name => word_len(&|c| !c.is_whitespace() && !"(){}:;,=+\"".contains(c)),
+            Mode::Pattern => word_len(&|c| !c.is_whitespace() && !"(){};,=\"".contains(c)),
+            Mode::Symbol => {
+                let mut len = 0;
+                while let Some(c) = rest[len..].chars().next() {
+                    if rest[len..].starts_with("::") {
+                        len += 2;
+                    } else if c.is_whitespace() || "{};:\"".contains(c) {
+                        break;
+                    } else {
+                        len += c.len_utf8();
+                    }
+                }
+                len
+            }
+        };
+
+        if len > 0 {
+            return Ok((Token::Word(rest[..len].into()), self.pos + len));
+        }
+
+        match PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+            Some(p) => Ok((Token::Punct(p), self.pos + p.len())),
+            None => Err(self.error(format!("unexpected character `{}`", c))),
+        }
+    }
+
+    fn peek(&mut self, mode: Mode) -> Result<Token, String> {
+        Ok(self.lex(mode)?.0)
+    }
+
+    fn next(&mut self, mode: Mode) -> Result<Token, String> {
+        let (token, end) = self.lex(mode)?;
+        self.pos = end;
+        Ok(token)
+    }
+
+    fn consume(&mut self, punct: &str) -> Result<bool, String> {
+        let (token, end) = self.lex(Mode::Expr)?;
+        if token == Token::Punct(PUNCTS.iter().find(|p| **p == punct).unwrap()) {
+            self.pos = end;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.consume(punct)? {
+            Ok(())
+        } else {
+            let token = self.peek(Mode::Expr)?;
+            Err(self.error(format!("expected `{}`, found {}", punct, token)))
+        }
+    }
+
+    fn consume_keyword(&mut self, keyword: &str) -> Result<bool, String> {
+        let (token, end) = self.lex(Mode::Expr)?;
+        if matches!(&token, Token::Word(w) if w == keyword) {
+            self.pos = end;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn peek_keyword(&mut self) -> Result<Option<String>, String> {
+        match self.peek(Mode::Expr)? {
+            Token::Word(w) => Ok(Some(w)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads a name in the given mode. Quoted strings are accepted as names.
+    fn name(&mut self, mode: Mode, what: &str) -> Result<String, String> {
+        match self.lex(mode)? {
+            (Token::Word(w) | Token::Str(w), end) => {
+                self.pos = end;
+                Ok(w)
+            }
+            (token, _) => Err(self.error(format!("expected {}, found {}", what, token))),
+        }
+    }
+
+    /// Whether an assignment such as `sym = expr` or `. += 4` starts at the current position.
+    fn at_assignment(&mut self) -> Result<bool, String> {
+        let start = self.pos;
+        let result = match self.next(Mode::Expr)? {
+            Token::Word(_) | Token::Str(_) => {
+                matches!(self.peek(Mode::Expr)?, Token::Punct(p) if assign_op(p).is_some())
+            }
+            _ => false,
+        };
+        self.pos = start;
+        Ok(result)
+    }
+
+    fn parse_script(&mut self, script: &mut Script) -> Result<(), String> {
+        loop {
+            if self.consume(";")? {
+                continue;
+            }
+
+            let location = self.location()?;
+            let Some(keyword) = self.peek_keyword()? else {
+                match self.peek(Mode::Expr)? {
+                    Token::Eof => return Ok(()),
+                    token => return Err(self.error(format!("unexpected {}", token))),
+                }
+            };
+
+            match keyword.as_str() {
+                "SECTIONS" => {
+                    self.next(Mode::Expr)?;
+                    let commands = self.parse_sections(script)?;
+                    script.commands.push(Command::Sections(commands));
+                }
+                "MEMORY" => {
+                    self.next(Mode::Expr)?;
+                    self.parse_memory(script)?;
+                }
+                "PHDRS" => {
+                    self.next(Mode::Expr)?;
+                    self.parse_phdrs(script)?;
+                }
+                "ENTRY" => {
+                    self.next(Mode::Expr)?;
+                    script.entry = Some(self.parse_entry()?);
+                }
+                "OUTPUT_FORMAT" | "OUTPUT_ARCH" | "TARGET" => {
+                    self.next(Mode::Expr)?;
+                    self.expect("(")?;
+                    while !self.consume(")")? {
+                        self.consume(",")?;
+                        self.name(Mode::Pattern, "an argument")?;
+                    }
+                }
+                "VERSION" => {
+                    self.next(Mode::Expr)?;
+                    self.expect("{")?;
+                    script.versions.extend(self.parse_version_nodes()?);
+                    self.expect("}")?;
+                }
+                "SEARCH_DIR" => {
+                    self.next(Mode::Expr)?;
+                    self.expect("(")?;
+                    script
+                        .search_dirs
+                        .push(self.name(Mode::Pattern, "a directory")?);
+                    self.expect(")")?;
+                }
+                "INPUT" | "GROUP" => {
+                    self.next(Mode::Expr)?;
+                    self.parse_inputs(script, false)?;
+                }
+                "ASSERT" => {
+                    let assert = self.parse_assert()?;
+                    script.commands.push(Command::Assert(assert));
+                }
+                "PROVIDE" | "PROVIDE_HIDDEN" | "HIDDEN" => {
+                    let assignment = self.parse_assignment()?;
+                    script.commands.push(Command::Assignment(assignment));
+                }
+                _ if self.at_assignment()? => {
+                    let assignment = self.parse_assignment()?;
+                    script.commands.push(Command::Assignment(assignment));
+                }
+                _ => {
+                    return Err(format!("{}: unsupported command `{}`", location, keyword));
+                }
+            }
+        }
+    }
+
+    /// Parses the parenthesized list of `INPUT` or `GROUP`. Names may be separated by commas, and
+    /// `-lname` names a library.
+    fn parse_inputs(&mut self, script: &mut Script, as_needed: bool) -> Result<(), String> {
+        self.expect("(")?;
+        while !self.consume(")")? {
+            if self.consume(",")? {
+                continue;
+            }
+            if !as_needed && self.consume_keyword("AS_NEEDED")? {
+                self.parse_inputs(script, true)?;
+                continue;
+            }
+            let name = self.name(Mode::Pattern, "a file")?;
+            let (name, library) = match name.strip_prefix("-l") {
+                Some(library) => (library.into(), true),
+                None => (name, false),
+            };
+            script.inputs.push(Input {
+                name,
+                library,
+                as_needed,
+                static_only: false,
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_entry(&mut self) -> Result<String, String> {
+        self.expect("(")?;
+        let name = self.name(Mode::Expr, "a symbol")?;
+        self.expect(")")?;
+        Ok(name)
+    }
+
+    fn parse_assert(&mut self) -> Result<Assert, String> {
+        self.next(Mode::Expr)?;
+        self.expect("(")?;
+        let expr = self.parse_expr()?;
+        self.expect(",")?;
+        let message = match self.next(Mode::Expr)? {
+            Token::Str(s) | Token::Word(s) => s,
+            token => return Err(self.error(format!("expected a message, found {}", token))),
+        };
+        self.expect(")")?;
+        Ok(Assert { expr, message })
+    }
+
+    /// Parses `sym op expr;`, optionally wrapped in `PROVIDE`, `PROVIDE_HIDDEN` or `HIDDEN`.
+    fn parse_assignment(&mut self) -> Result<Assignment, String> {
+        let location = self.location()?;
+        let (provide, hidden) = match self.peek_keyword()?.as_deref() {
+            Some("PROVIDE") => (true, false),
+            Some("PROVIDE_HIDDEN") => (true, true),
+            Some("HIDDEN") => (false, true),
+            _ => (false, false),
+        };
+        let wrapped = provide || hidden;
+        if wrapped {
+            self.next(Mode::Expr)?;
+            self.expect("(")?;
+        }
+
+        let symbol = self.name(Mode::Expr, "a symbol")?;
+        let op = match self.next(Mode::Expr)? {
+            Token::Punct(p) if assign_op(p).is_some() => assign_op(p).unwrap(),
+            token => return Err(self.error(format!("expected an assignment, found {}", token))),
+        };
+        if wrapped && op != AssignOp::Set {
+            return Err(format!("{}: only `=` is allowed here", location));
+        }
+        if provide && symbol == "." {
+            return Err(format!("{}: `.` cannot be provided", location));
+        }
+        let expr = self.parse_expr()?;
+
+        if wrapped {
+            self.expect(")")?;
+            self.consume(";")?;
+        } else if !self.consume(";")? && self.peek(Mode::Expr)? != Token::Punct("}") {
+            let token = self.peek(Mode::Expr)?;
+            return Err(self.error(format!("expected `;`, found {}", token)));
+        }
+
+        Ok(Assignment {
+            symbol,
+            op,
+            expr,
+            provide,
+            hidden,
+            location,
+        })
+    }
+
+    fn parse_sections(&mut self, script: &mut Script) -> Result<Vec<SectionsCommand>, String> {
+        self.expect("{")?;
+        let mut commands = vec![];
+        loop {
+            if self.consume(";")? {
+                continue;
+            }
+            if self.consume("}")? {
+                return Ok(commands);
+            }
+
+            match self.peek_keyword()?.as_deref() {
+                Some("ENTRY") => {
+                    self.next(Mode::Expr)?;
+                    script.entry = Some(self.parse_entry()?);
+                    continue;
+                }
+                Some("ASSERT") => {
+                    commands.push(SectionsCommand::Assert(self.parse_assert()?));
+                    continue;
+                }
+                Some("PROVIDE" | "PROVIDE_HIDDEN" | "HIDDEN") => {
+                    commands.push(SectionsCommand::Assignment(self.parse_assignment()?));
+                    continue;
+                }
+                Some(keyword @ ("OVERLAY" | "INCLUDE" | "INSERT")) => {
+                    return Err(self.error(format!("unsupported command `{}`", keyword)));
+                }
+                _ => {}
+            }
+
+            if self.at_assignment()? {
+                commands.push(SectionsCommand::Assignment(self.parse_assignment()?));
+            } else {
+                commands.push(SectionsCommand::OutputSection(Box::new(
+                    self.parse_output_section()?,
+                )));
+            }
+        }
+    }
+
+    fn parse_output_section(&mut self) -> Result<OutputSectionDesc, String> {
+        let location = self.location()?;
+        let name = self.name(Mode::Name, "an output section name")?;
+
+        let mut desc = OutputSectionDesc {
+            name,
+            address: None,
+            noload: false,
+            lma: None,
+            align: None,
+            subalign: None,
+            commands: vec![],
+            region: None,
+            lma_region: None,
+            phdrs: vec![],
+            fill: None,
+            location,
+        };
+
+        if !self.at_section_type()? && self.peek(Mode::Expr)? != Token::Punct(":") {
+            desc.address = Some(self.parse_expr()?);
+        }
+        if self.at_section_type()? {
+            self.expect("(")?;
+            match self.name(Mode::Expr, "a section type")?.as_str() {
+                "NOLOAD" => desc.noload = true,
+                "COPY" | "INFO" | "DSECT" | "OVERLAY" | "READONLY" => {}
+                ty => return Err(self.error(format!("unknown section type `{}`", ty))),
+            }
+            self.expect(")")?;
+        }
+        self.expect(":")?;
+
+        loop {
+            match self.peek_keyword()?.as_deref() {
+                Some("AT") => {
+                    self.next(Mode::Expr)?;
+                    self.expect("(")?;
+                    desc.lma = Some(self.parse_expr()?);
+                    self.expect(")")?;
+                }
+                Some("ALIGN") => {
+                    self.next(Mode::Expr)?;
+                    self.expect("(")?;
+                    desc.align = Some(self.parse_expr()?);
+                    self.expect(")")?;
+                }
+                Some("SUBALIGN") => {
+                    self.next(Mode::Expr)?;
+                    self.expect("(")?;
+                    desc.subalign = Some(self.parse_expr()?);
+                    self.expect(")")?;
+                }
+                Some(keyword @ ("ONLY_IF_RO" | "ONLY_IF_RW" | "ALIGN_WITH_INPUT")) => {
+                    return Err(self.error(format!("unsupported keyword `{}`", keyword)));
+                }
+                _ => break,
+            }
+        }
+
+        self.expect("{")?;
+        loop {
+            if self.consume(";")? {
+                continue;
+            }
+            if self.consume("}")? {
+                break;
+            }
+            desc.commands.push(self.parse_output_section_command()?);
+        }
+
+        loop {
+            if self.consume(">")? {
+                desc.region = Some(self.name(Mode::Expr, "a memory region")?);
+            } else if self.consume_keyword("AT")? {
+                self.expect(">")?;
+                desc.lma_region = Some(self.name(Mode::Expr, "a memory region")?);
+            } else if self.consume(":")? {
+                desc.phdrs.push(self.name(Mode::Expr, "a segment")?);
+            } else if self.consume("=")? {
+                desc.fill = Some(self.parse_expr()?);
+            } else {
+                break;
+            }
+        }
+        self.consume(",")?;
+
+        Ok(desc)
+    }
+
+    /// Whether a section type such as `(NOLOAD)` starts at the current position.
+    fn at_section_type(&mut self) -> Result<bool, String> {
+        let start = self.pos;
+        let result = self.consume("(")?
+            && matches!(
+                self.next(Mode::Expr)?,
+                Token::Word(w) if ["NOLOAD", "COPY", "INFO", "DSECT", "OVERLAY", "READONLY"]
+                    .contains(&w.as_str())
+            )
+            && self.consume(")")?;
+        self.pos = start;
+        Ok(result)
+    }
+
+    fn parse_output_section_command(&mut self) -> Result<OutputSectionCommand, String> {
+        let data_size = |keyword: &str| match keyword {
+            "BYTE" => Some(1),
+            "SHORT" => Some(2),
+            "LONG" => Some(4),
+            "QUAD" | "SQUAD" => Some(8),
+            _ => None,
+        };
+
+        let keyword = self.peek_keyword()?;
+        match keyword.as_deref() {
+            Some(k) if data_size(k).is_some() && self.at_call()? => {
+                self.next(Mode::Expr)?;
+                self.expect("(")?;
+                let expr = self.parse_expr()?;
+                self.expect(")")?;
+                Ok(OutputSectionCommand::Data {
+                    size: data_size(k).unwrap(),
+                    expr,
+                })
+            }
+            Some("FILL") if self.at_call()? => {
+                self.next(Mode::Expr)?;
+                self.expect("(")?;
+                let expr = self.parse_expr()?;
+                self.expect(")")?;
+                Ok(OutputSectionCommand::Fill(expr))
+            }
+            Some("ASSERT") => Ok(OutputSectionCommand::Assert(self.parse_assert()?)),
+            Some("PROVIDE" | "PROVIDE_HIDDEN" | "HIDDEN") => {
+                Ok(OutputSectionCommand::Assignment(self.parse_assignment()?))
+            }
+            Some("KEEP") if self.at_call()? => {
+                self.next(Mode::Expr)?;
+                self.expect("(")?;
+                let mut desc = self.parse_input_section_desc()?;
+                desc.keep = true;
+                self.expect(")")?;
+                Ok(OutputSectionCommand::Input(desc))
+            }
+            Some(keyword @ ("INCLUDE" | "CONSTRUCTORS" | "CREATE_OBJECT_SYMBOLS")) => {
+                Err(self.error(format!("unsupported command `{}`", keyword)))
+            }
+            _ if self.at_assignment()? => {
+                Ok(OutputSectionCommand::Assignment(self.parse_assignment()?))
+            }
+            _ => Ok(OutputSectionCommand::Input(
+                self.parse_input_section_desc()?,
+            )),
+        }
+    }
+
+    /// Whether a word followed by `(` starts at the current position.
+    fn at_call(&mut self) -> Result<bool, String> {
+        let start = self.pos;
+        self.next(Mode::Expr)?;
+        let result = self.peek(Mode::Expr)? == Token::Punct("(");
+        self.pos = start;
+        Ok(result)
+    }
+
+    fn parse_exclude_file(&mut self) -> Result<Vec<String>, String> {
+        let mut files = vec![];
+        if self.peek(Mode::Pattern)? == Token::Word("EXCLUDE_FILE".into()) {
+            self.next(Mode::Pattern)?;
+            self.expect("(")?;
+            while !self.consume(")")? {
+                self.consume(",")?;
+                files.push(self.name(Mode::Pattern, "a file pattern")?);
+            }
+        }
+        Ok(files)
+    }
+
+    fn parse_input_section_desc(&mut self) -> Result<InputSectionDesc, String> {
+        let location = self.location()?;
+        let exclude_files = self.parse_exclude_file()?;
+        let file = self.name(Mode::Pattern, "an input section description")?;
+
+        let mut desc = InputSectionDesc {
+            keep: false,
+            file,
+            exclude_files,
+            sections: vec![],
+            location,
+        };
+
+        if !self.consume("(")? {
+            desc.sections.push(SectionPattern {
+                pattern: "*".into(),
+                exclude_files: vec![],
+                sort: SortKind::None,
+            });
+            return Ok(desc);
+        }
+
+        while !self.consume(")")? {
+            self.consume(",")?;
+            let exclude_files = self.parse_exclude_file()?;
+            for (pattern, sort) in self.parse_section_patterns()? {
+                desc.sections.push(SectionPattern {
+                    pattern,
+                    exclude_files: exclude_files.clone(),
+                    sort,
+                });
+            }
+        }
+
+        Ok(desc)
+    }
+
+    /// Parses a section pattern, or a list of them wrapped in a `SORT` variant.
+    fn parse_section_patterns(&mut self) -> Result<Vec<(String, SortKind)>, String> {
+        let word = self.name(Mode::Pattern, "a section pattern")?;
+        let sort = match word.as_str() {
+            "SORT" | "SORT_BY_NAME" => SortKind::Name,
+            "SORT_BY_ALIGNMENT" => SortKind::Alignment,
+            "SORT_BY_INIT_PRIORITY" => SortKind::InitPriority,
+            "SORT_NONE" => SortKind::None,
+            _ => return Ok(vec![(word, SortKind::None)]),
+        };
+
+        self.expect("(")?;
+        let mut patterns = vec![];
+        while !self.consume(")")? {
+            self.consume(",")?;
+            for (pattern, inner) in self.parse_section_patterns()? {
+                // The outermost sort wins, e.g. `SORT_BY_NAME(SORT_BY_ALIGNMENT(...))`.
+                let sort = if word == "SORT_NONE" { inner } else { sort };
+                patterns.push((pattern, sort));
+            }
+        }
+        Ok(patterns)
+    }
+
+    fn parse_memory(&mut self, script: &mut Script) -> Result<(), String> {
+        self.expect("{")?;
+        while !self.consume("}")? {
+            let location = self.location()?;
+            let name = self.name(Mode::Expr, "a memory region name")?;
+
+            let mut attributes = String::new();
+            if self.consume("(")? {
+                attributes = self.name(Mode::Pattern, "memory region attributes")?;
+                self.expect(")")?;
+            }
+            self.expect(":")?;
+
+            let origin = match self.name(Mode::Expr, "`ORIGIN`")?.as_str() {
+                "ORIGIN" | "org" | "o" => {
+                    self.expect("=")?;
+                    self.parse_expr()?
+                }
+                keyword => {
+                    return Err(self.error(format!("expected `ORIGIN`, found `{}`", keyword)))
+                }
+            };
+            self.expect(",")?;
+            let length = match self.name(Mode::Expr, "`LENGTH`")?.as_str() {
+                "LENGTH" | "len" | "l" => {
+                    self.expect("=")?;
+                    self.parse_expr()?
+                }
+                keyword => {
+                    return Err(self.error(format!("expected `LENGTH`, found `{}`", keyword)))
+                }
+            };
+
+            if script.memory.iter().any(|r| r.name == name) {
+                return Err(format!(
+                    "{}: memory region `{}` is already defined",
+                    location, name
+                ));
+            }
+            script.memory.push(MemoryRegion {
+                name,
+                attributes,
+                origin,
+                length,
+                location,
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_phdrs(&mut self, script: &mut Script) -> Result<(), String> {
+        self.expect("{")?;
+        while !self.consume("}")? {
+            let location = self.location()?;
+            let name = self.name(Mode::Expr, "a segment name")?;
+
+            let ty = match self.peek(Mode::Expr)? {
+                Token::Word(w) => {
+                    self.next(Mode::Expr)?;
+                    segment_type(&w)
+                        .ok_or_else(|| self.error(format!("unknown segment type `{}`", w)))?
+                }
+                _ => {
+                    let expr = self.parse_expr()?;
+                    let value = expr.eval(&ConstantContext)?;
+                    SegmentType::try_from(value as u32).map_err(|e| self.error(e))?
+                }
+            };
+
+            let mut phdr = PhdrDesc {
+                name,
+                ty,
+                filehdr: false,
+                phdrs: false,
+                at: None,
+                flags: None,
+                location,
+            };
+            loop {
+                if self.consume_keyword("FILEHDR")? {
+                    phdr.filehdr = true;
+                } else if self.consume_keyword("PHDRS")? {
+                    phdr.phdrs = true;
+                } else if self.consume_keyword("AT")? {
+                    self.expect("(")?;
+                    phdr.at = Some(self.parse_expr()?);
+                    self.expect(")")?;
+                } else if self.consume_keyword("FLAGS")? {
+                    self.expect("(")?;
+                    let flags = self.parse_expr()?.eval(&ConstantContext)?;
+                    phdr.flags = Some(SegmentFlag::from_bits_retain(flags as u32));
+                    self.expect(")")?;
+                } else {
+                    break;
+                }
+            }
+            self.expect(";")?;
+
+            script.phdrs.push(phdr);
+        }
+        Ok(())
+    }
+
+    /// Parses version nodes up to the end of the text or a closing brace.
+    fn parse_version_nodes(&mut self) -> Result<Vec<VersionNode>, String> {
+        let mut nodes: Vec<VersionNode> = vec![];
+        loop {
+            let location = self.location()?;
+            let name = match self.peek(Mode::Symbol)? {
+                Token::Eof | Token::Punct("}") => return Ok(nodes),
+                Token::Punct("{") => None,
+                Token::Word(name) | Token::Str(name) => {
+                    self.next(Mode::Symbol)?;
+                    Some(name)
+                }
+                token => return Err(self.error(format!("expected a version, found {}", token))),
+            };
+            if nodes.iter().any(|n| n.name.is_none() || name.is_none()) {
+                return Err(format!(
+                    "{}: an anonymous version must be the only version",
+                    location
+                ));
+            }
+            if nodes.iter().any(|n| n.name == name) {
+                return Err(format!(
+                    "{}: version `{}` is already defined",
+                    location,
+                    name.unwrap_or_default()
+                ));
+            }
+
+            let mut node = VersionNode {
+                name,
+                ..Default::default()
+            };
+            self.parse_version_block(false, false, &mut node)?;
+            while let Token::Word(parent) = self.peek(Mode::Symbol)? {
+                self.next(Mode::Symbol)?;
+                node.parents.push(parent);
+            }
+            self.expect(";")?;
+            nodes.push(node);
+        }
+    }
+
+    /// Parses the patterns in braces of a version node or an `extern` block into `node`. `cxx` is
+    /// whether they are C++ patterns, and `local` whether they are local until a `global:` or
+    /// `local:` label.
+    fn parse_version_block(
+        &mut self,
+        cxx: bool,
+        mut local: bool,
+        node: &mut VersionNode,
+    ) -> Result<(), String> {
+        self.expect("{")?;
+        loop {
+            let (pattern, exact) = match self.next(Mode::Symbol)? {
+                Token::Punct("}") => return Ok(()),
+                Token::Punct(";") => continue,
+                Token::Word(w) if (w == "global" || w == "local") && self.consume(":")? => {
+                    local = w == "local";
+                    continue;
+                }
+                Token::Word(w) if w == "extern" => {
+                    let cxx = match self.name(Mode::Symbol, "a language")?.as_str() {
+                        "C++" => true,
+                        "C" => false,
+                        language => {
+                            return Err(self.error(format!("unsupported language `{}`", language)))
+                        }
+                    };
+                    self.parse_version_block(cxx, local, node)?;
+                    continue;
+                }
+                Token::Word(w) => {
+                    let exact = !w.contains(['*', '?', '[']);
+                    (w, exact)
+                }
+                Token::Str(s) => (s, true),
+                token => return Err(self.error(format!("expected a symbol, found {}", token))),
+            };
+            let pattern = SymbolPattern {
+                pattern,
+                cxx,
+                exact,
+            };
+            if local {
+                node.locals.push(pattern);
+            } else {
+                node.globals.push(pattern);
+            }
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        let cond = self.parse_binary(0)?;
+        if self.consume("?")? {
+            let t = self.parse_expr()?;
+            self.expect(":")?;
+            let f = self.parse_expr()?;
+            let location = cond.location;
+            return Ok(Expr {
+                kind: ExprKind::Conditional(Box::new(cond), Box::new(t), Box::new(f)),
+                location,
+            });
+        }
+        Ok(cond)
+    }
+
+    /// Parses binary operators whose precedence is at least `min`, by precedence climbing.
+    fn parse_binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let (token, end) = self.lex(Mode::Expr)?;
+            let Token::Punct(p) = token else {
+                return Ok(lhs);
+            };
+            let Some((op, prec)) = binary_op(p) else {
+                return Ok(lhs);
+            };
+            if prec < min {
+                return Ok(lhs);
+            }
+            self.pos = end;
+
+            let rhs = self.parse_binary(prec + 1)?;
+            let location = lhs.location;
+            lhs = Expr {
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                location,
+            };
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let location = self.location()?;
+        let op = if self.consume("-")? {
+            UnaryOp::Neg
+        } else if self.consume("!")? {
+            UnaryOp::Not
+        } else if self.consume("~")? {
+            UnaryOp::BitNot
+        } else {
+            return self.parse_primary();
+        };
+        let e = self.parse_unary()?;
+        Ok(Expr {
+            kind: ExprKind::Unary(op, Box::new(e)),
+            location,
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let location = self.location()?;
+        let kind = match self.next(Mode::Expr)? {
+            Token::Number(n) => ExprKind::Number(n),
+            Token::Punct("(") => {
+                let e = self.parse_expr()?;
+                self.expect(")")?;
+                return Ok(e);
+            }
+            Token::Punct("+") => return self.parse_unary(),
+            Token::Str(s) => ExprKind::Symbol(s),
+            Token::Word(w) if w == "." => ExprKind::Dot,
+            Token::Word(w) if w == "SIZEOF_HEADERS" => ExprKind::SizeOfHeaders,
+            Token::Word(w) => {
+                if !self.consume("(")? {
+                    return Ok(Expr {
+                        kind: ExprKind::Symbol(w),
+                        location,
+                    });
+                }
+                let kind = self.parse_call(&w, location)?;
+                self.expect(")")?;
+                kind
+            }
+            token => {
+                return Err(format!(
+                    "{}: expected an expression, found {}",
+                    location, token
+                ))
+            }
+        };
+        Ok(Expr { kind, location })
+    }
+
+    /// Parses the arguments of the builtin function `name`, whose `(` has been consumed.
+    fn parse_call(&mut self, name: &str, location: Location) -> Result<ExprKind, String> {
+        Ok(match name {
+            "ALIGN" | "BLOCK" | "NEXT" => {
+                let first = self.parse_expr()?;
+                if self.consume(",")? {
+                    let align = self.parse_expr()?;
+                    ExprKind::Align(Some(Box::new(first)), Box::new(align))
+                } else {
+                    ExprKind::Align(None, Box::new(first))
+                }
+            }
+            "ADDR" => ExprKind::Addr(self.name(Mode::Name, "a section name")?),
+            "LOADADDR" => ExprKind::LoadAddr(self.name(Mode::Name, "a section name")?),
+            "SIZEOF" => ExprKind::SizeOf(self.name(Mode::Name, "a section name")?),
+            "ALIGNOF" => ExprKind::AlignOf(self.name(Mode::Name, "a section name")?),
+            "DEFINED" => ExprKind::Defined(self.name(Mode::Expr, "a symbol")?),
+            "ORIGIN" => ExprKind::Origin(self.name(Mode::Expr, "a memory region")?),
+            "LENGTH" => ExprKind::Length(self.name(Mode::Expr, "a memory region")?),
+            "ABSOLUTE" => return Ok(self.parse_expr()?.kind),
+            "MAX" | "MIN" => {
+                let l = Box::new(self.parse_expr()?);
+                self.expect(",")?;
+                let r = Box::new(self.parse_expr()?);
+                if name == "MAX" {
+                    ExprKind::Max(l, r)
+                } else {
+                    ExprKind::Min(l, r)
+                }
+            }
+            "CONSTANT" => match self.name(Mode::Expr, "a constant name")?.as_str() {
+                "MAXPAGESIZE" => ExprKind::MaxPageSize,
+                "COMMONPAGESIZE" => ExprKind::CommonPageSize,
+                constant => {
+                    return Err(format!("{}: unknown constant `{}`", location, constant));
+                }
+            },
+            _ => return Err(format!("{}: unknown function `{}`", location, name)),
+        })
+    }
+}
+
+/// Evaluates expressions which may not refer to anything but constants.
+struct ConstantContext;
+
+impl EvalContext for ConstantContext {
+    fn dot(&self) -> Option<u64> {
+        None
+    }
+
+    fn symbol(&self, _name: &str) -> Option<u64> {
+        None
+    }
+
+    fn is_defined(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn section(&self, _name: &str) -> Option<SectionInfo> {
+        None
+    }
+
+    fn region(&self, _name: &str) -> Option<(u64, u64)> {
+        None
+    }
+
+    fn headers_size(&self) -> u64 {
+        0
+    }
+
+    fn max_page_size(&self) -> u64 {
+        0x1000
+    }
+
+    fn common_page_size(&self) -> u64 {
+        0x1000
+    }
+}
+
+fn assign_op(punct: &str) -> Option<AssignOp> {
+    Some(match punct {
+        "=" => AssignOp::Set,
+        "+=" => AssignOp::Add,
+        "-=" => AssignOp::Sub,
+        "*=" => AssignOp::Mul,
+        "/=" => AssignOp::Div,
+        "<<=" => AssignOp::Shl,
+        ">>=" => AssignOp::Shr,
+        "&=" => AssignOp::And,
+        "|=" => AssignOp::Or,
+        _ => return None,
+    })
+}
+
+/// Returns the binary operator and its precedence, higher binding tighter.
+fn binary_op(punct: &str) -> Option<(BinaryOp, u8)> {
+    Some(match punct {
+        "*" => (BinaryOp::Mul, 9),
+        "/" => (BinaryOp::Div, 9),
+        "%" => (BinaryOp::Rem, 9),
+        "+" => (BinaryOp::Add, 8),
+        "-" => (BinaryOp::Sub, 8),
+        "<<" => (BinaryOp::Shl, 7),
+        ">>" => (BinaryOp::Shr, 7),
+        "<" => (BinaryOp::Lt, 6),
+        "<=" => (BinaryOp::Le, 6),
+        ">" => (BinaryOp::Gt, 6),
+        ">=" => (BinaryOp::Ge, 6),
+        "==" => (BinaryOp::Eq, 5),
+        "!=" => (BinaryOp::Ne, 5),
+        "&" => (BinaryOp::BitAnd, 4),
+        "^" => (BinaryOp::BitXor, 3),
+        "|" => (BinaryOp::BitOr, 2),
+        "&&" => (BinaryOp::And, 1),
+        "||" => (BinaryOp::Or, 0),
+        _ => return None,
+    })
+}
+
+/// Parses integers as GNU ld does: `0x` prefixes hexadecimal, a leading `0` octal, and `K` or `M`
+/// suffixes multiply by 1024 or 1024 * 1024.
+fn parse_number(word: &str) -> Option<u64> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16).ok();
+    }
+
+    let (digits, multiplier) = match word.as_bytes().last()? {
+        b'K' | b'k' => (&word[..word.len() - 1], 1024),
+        b'M' | b'm' => (&word[..word.len() - 1], 1024 * 1024),
+        _ => (word, 1),
+    };
+    let value = if let Some(hex) = digits.strip_suffix(['h', 'H']) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    value.checked_mul(multiplier)
+}
+
+fn segment_type(name: &str) -> Option<SegmentType> {
+    Some(match name {
+        "PT_NULL" => SegmentType::Null,
+        "PT_LOAD" => SegmentType::Load,
+        "PT_DYNAMIC" => SegmentType::Dynamic,
+        "PT_INTERP" => SegmentType::Interp,
+        "PT_NOTE" => SegmentType::Note,
+        "PT_SHLIB" => SegmentType::Shlib,
+        "PT_PHDR" => SegmentType::Phdr,
+        "PT_TLS" => SegmentType::Tls,
+        "PT_GNU_EH_FRAME" => SegmentType::GnuEhFrame,
+        "PT_GNU_STACK" => SegmentType::GnuStack,
+        "PT_GNU_RELRO" => SegmentType::GnuRelro,
+        "PT_GNU_PROPERTY" => SegmentType::GnuProperty,
+        _ => return None,
+    })
+}
@@ -0,0 +1,212 @@
+use super::{
+    parse_dynamic_list, parse_version_script, Command, EvalContext, OutputSectionCommand, Script,
+    SectionInfo, SectionsCommand, SortKind,
+};
+
+struct TestContext;
+
+impl EvalContext for TestContext {
+    fn dot(&self) -> Option<u64> {
+        Some(0x1234)
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        (name == "foo").then_some(0x10)
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        name == "foo"
+    }
+
+    fn section(&self, name: &str) -> Option<SectionInfo> {
+        (name == ".text").then_some(SectionInfo {
+            addr: 0x1000,
+            lma: 0x8000,
+            size: 0x20,
+            align: 16,
+        })
+    }
+
+    fn region(&self, name: &str) -> Option<(u64, u64)> {
+        (name == "rom").then_some((0x800_0000, 0x1_0000))
+    }
+
+    fn headers_size(&self) -> u64 {
+        0xb0
+    }
+
+    fn max_page_size(&self) -> u64 {
+        0x1000
+    }
+
+    fn common_page_size(&self) -> u64 {
+        0x1000
+    }
+}
+
+fn eval_assignment(text: &str) -> Result<u64, String> {
+    let script = Script::parse(text)?;
+    match &script.commands[..] {
+        [Command::Assignment(a)] => a.expr.eval(&TestContext),
+        _ => panic!("expected a single assignment"),
+    }
+}
+
+#[test]
+fn sections_test() {
+    let script = Script::parse(
+        r#"
+        ENTRY(_start)
+        MEMORY { rom (rx) : ORIGIN = 0x8000000, LENGTH = 64K }
+        SECTIONS {
+            . = 0x400000 + SIZEOF_HEADERS;
+            .text ALIGN(16) : {
+                KEEP(*(.init))
+                *(EXCLUDE_FILE(crt*.o) .text .text.*)
+                *(SORT(.text.sorted.*))
+            } > rom
+            PROVIDE_HIDDEN(__etext = .);
+            /DISCARD/ : { *(.comment) }
+        }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(script.entry.as_deref(), Some("_start"));
+    assert_eq!(script.memory.len(), 1);
+    assert_eq!(script.memory[0].name, "rom");
+
+    let [Command::Sections(commands)] = &script.commands[..] else {
+        panic!("expected SECTIONS");
+    };
+    assert_eq!(commands.len(), 4);
+
+    let SectionsCommand::OutputSection(text) = &commands[1] else {
+        panic!("expected an output section");
+    };
+    assert_eq!(text.name, ".text");
+    assert_eq!(text.region.as_deref(), Some("rom"));
+    let inputs: Vec<_> = text
+        .commands
+        .iter()
+        .map(|c| match c {
+            OutputSectionCommand::Input(input) => input,
+            _ => panic!("expected an input section description"),
+        })
+        .collect();
+    assert!(inputs[0].keep);
+    assert_eq!(inputs[1].sections.len(), 2);
+    assert_eq!(inputs[1].sections[0].exclude_files, ["crt*.o"]);
+    assert_eq!(inputs[2].sections[0].sort, SortKind::Name);
+
+    let SectionsCommand::Assignment(etext) = &commands[2] else {
+        panic!("expected an assignment");
+    };
+    assert!(etext.provide && etext.hidden);
+
+    let SectionsCommand::OutputSection(discard) = &commands[3] else {
+        panic!("expected an output section");
+    };
+    assert!(discard.is_discard());
+}
+
+#[test]
+fn expression_test() {
+    assert_eq!(eval_assignment("x = 1 + 2 * 3 << 1;").unwrap(), 14);
+    assert_eq!(eval_assignment("x = 4K + 1M;").unwrap(), 0x10_1000);
+    assert_eq!(eval_assignment("x = 010 | 0x10 | 10h;").unwrap(), 0x18);
+    assert_eq!(eval_assignment("x = ALIGN(.,0x100);").unwrap(), 0x1300);
+    assert_eq!(eval_assignment("x = ALIGN(0x100);").unwrap(), 0x1300);
+    assert_eq!(
+        eval_assignment("x = DEFINED(foo) ? foo : 7;").unwrap(),
+        0x10
+    );
+    assert_eq!(eval_assignment("x = DEFINED(bar) ? bar : 7;").unwrap(), 7);
+    assert_eq!(
+        eval_assignment("x = LOADADDR(.text) + SIZEOF(.text);").unwrap(),
+        0x8020
+    );
+    assert_eq!(
+        eval_assignment("x = ORIGIN(rom) + LENGTH(rom);").unwrap(),
+        0x801_0000
+    );
+    assert_eq!(
+        eval_assignment("x = MAX(SIZEOF_HEADERS, 1);").unwrap(),
+        0xb0
+    );
+}
+
+#[test]
+fn error_location_test() {
+    let err = Script::parse("SECTIONS {\n  .text : {\n    *(.text\n  }\n}\n").unwrap_err();
+    assert!(err.starts_with("4:3:"), "{}", err);
+
+    let err = eval_assignment("\n  x = 1 / (foo - 16);").unwrap_err();
+    assert!(err.starts_with("2:"), "{}", err);
+    assert!(err.contains("division by zero"), "{}", err);
+
+    let err = eval_assignment("x = undefined_symbol;").unwrap_err();
+    assert!(err.starts_with("1:5:"), "{}", err);
+}
+
+#[test]
+fn version_script_test() {
+    let nodes = parse_version_script(
+        "V1 { global: foo; bar_*; local: *; };\n\
+         V2 {\n\
+           extern \"C++\" { ns::*; \"ns::f(int)\"; };\n\
+         } V1;",
+    )
+    .unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(nodes[0].name.as_deref(), Some("V1"));
+    let patterns: Vec<_> = nodes[0]
+        .globals
+        .iter()
+        .map(|p| p.pattern.as_str())
+        .collect();
+    assert_eq!(patterns, ["foo", "bar_*"]);
+    assert!(nodes[0].globals[0].exact && !nodes[0].globals[1].exact);
+    assert_eq!(nodes[0].locals[0].pattern, "*");
+    assert_eq!(nodes[1].parents, ["V1"]);
+
+    let cxx = &nodes[1].globals;
+    assert!(cxx.iter().all(|p| p.cxx));
+    assert!(cxx[0].matches("_ZN2ns1gEv", Some("ns::g()")));
+    assert!(cxx[1].matches("_ZN2ns1fEi", Some("ns::f(int)")));
+    assert!(!cxx[1].matches("ns::f(int)", None));
+
+    let script = Script::parse("VERSION { { global: foo; local: *; }; }").unwrap();
+    assert_eq!(script.versions[0].name, None);
+    assert!(parse_version_script("{ foo; };\nV1 { bar; };").is_err());
+
+    let list = parse_dynamic_list("{ foo; extern \"C++\" { \"ns::f()\"; }; };").unwrap();
+    assert_eq!(list.len(), 2);
+    assert!(list[1].cxx);
+    assert!(parse_dynamic_list("{ local: foo; };").is_err());
+}
+
+#[test]
+fn input_test() {
+    let script = Script::parse(
+        "OUTPUT_FORMAT(elf64-x86-64)\n\
+         GROUP ( /lib/libc.so.6, libc_nonshared.a AS_NEEDED ( -lm ) )\n\
+         INPUT(crt1.o)",
+    )
+    .unwrap();
+    let inputs: Vec<_> = script
+        .inputs
+        .iter()
+        .map(|i| (i.name.as_str(), i.library, i.as_needed))
+        .collect();
+    assert_eq!(
+        inputs,
+        [
+            ("/lib/libc.so.6", false, false),
+            ("libc_nonshared.a", false, false),
+            ("m", true, true),
+            ("crt1.o", false, false),
+        ]
+    );
+    assert!(Script::parse("GROUP ( AS_NEEDED ( AS_NEEDED ( a ) ) )").is_err());
+}
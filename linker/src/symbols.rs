@@ -0,0 +1,316 @@
+//! Resolves global symbols across input files.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::{
+    elf::{
+        SymbolBinding, SymbolType, SymbolVisibility, SECTION_HEADER_NUMBER_ABS,
+        SECTION_HEADER_NUMBER_COMMON, SECTION_HEADER_NUMBER_LORESERVE,
+    },
+    inputs::InputFile,
+    version,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Definition {
+    Undefined,
+
+    /// Defined at `value` from the start of a section of an input file.
+    Section {
+        file: usize,
+        section: usize,
+        value: u64,
+    },
+
+    /// Defined with an absolute value by an input file.
+    Absolute(u64),
+
+    /// A common symbol, allocated in `.bss` with the largest size and alignment any input gives it
+    /// unless an input defines it otherwise.
+    Common {
+        align: u64,
+    },
+
+    /// Defined by a linker script or the linker itself, at an address known after layout.
+    Linker(u64),
+
+    /// Defined by the symbol at `index` of a shared library, at an address known when the output
+    /// is loaded.
+    Shared {
+        file: usize,
+        index: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub definition: Definition,
+
+    /// The binding of the definition, or of the references while the symbol is undefined. An
+    /// undefined symbol is weak only if all its references are weak.
+    pub binding: SymbolBinding,
+    pub ty: SymbolType,
+    pub visibility: SymbolVisibility,
+    pub size: u64,
+
+    /// Whether an object file refers to the symbol without defining it.
+    pub referenced: bool,
+
+    /// The version the symbol is defined with, given by a shared library or by the name in an
+    /// object file.
+    pub version: Option<String>,
+
+    /// Whether the output exports the symbol even if it is an executable, set by `--dynamic-list`.
+    pub export: bool,
+
+    /// Whether references in the output bind to its own definition of the symbol even if it is a
+    /// shared library, set for the symbols `--dynamic-list` leaves out.
+    pub bind_local: bool,
+}
+
+impl Symbol {
+    pub fn is_defined(&self) -> bool {
+        self.definition != Definition::Undefined
+    }
+}
+
+/// A symbol as seen from a relocation: either an entry of the global table or a local symbol of
+/// an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SymbolRef {
+    Global(usize),
+    Local { file: usize, index: usize },
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    names: HashMap<String, usize>,
+
+    /// Global symbols in the order they are first seen.
+    pub symbols: Vec<Symbol>,
+
+    /// For each input file, the global symbol each of its symbol table entries resolves to. Local
+    /// symbols map to `None`.
+    pub file_symbols: Vec<Vec<Option<usize>>>,
+}
+
+impl SymbolTable {
+    /// Resolves the symbols of the inputs. `wrap` are the symbols given by `--wrap`.
+    pub fn new(inputs: &[InputFile], wrap: &[String]) -> Result<Self, String> {
+        let mut table = Self::default();
+        for file in 0..inputs.len() {
+            table.add_file(inputs, file, wrap)?;
+        }
+        Ok(table)
+    }
+
+    fn add_file(
+        &mut self,
+        inputs: &[InputFile],
+        file: usize,
+        wrap: &[String],
+    ) -> Result<(), String> {
+        let input = &inputs[file];
+        let mut resolved = vec![None; input.symbols.len()];
+        for (index, sym) in input.symbols.iter().enumerate().skip(1) {
+            if sym.binding == SymbolBinding::Local {
+                continue;
+            }
+
+            // Definitions in object files take precedence over those in shared libraries, of
+            // which the first wins. References from shared libraries do not count.
+            if let Some(shared) = &input.shared {
+                let id = self.intern(&input.symbol_names[index]);
+                resolved[index] = Some(id);
+                let symbol = &mut self.symbols[id];
+                if !sym.is_undefined() && !symbol.is_defined() {
+                    symbol.definition = Definition::Shared { file, index };
+                    symbol.binding = sym.binding;
+                    symbol.ty = sym.ty;
+                    symbol.size = sym.size;
+                    symbol.version = shared.versions[index].clone();
+                }
+                continue;
+            }
+
+            // `foo@@VER` defines the default version of `foo`, which references to `foo` bind to.
+            let mut name = input.symbol_names[index].as_str();
+            let mut version = None;
+            let wrapped;
+            if sym.is_undefined() {
+                wrapped = wrapped_name(name, wrap);
+                name = &wrapped;
+            } else {
+                if let (base, Some((v, default))) = version::split_name(name) {
+                    if default {
+                        name = base;
+                    }
+                    version = Some(v.to_string());
+                }
+            }
+            let id = self.intern(name);
+            resolved[index] = Some(id);
+
+            let symbol = &mut self.symbols[id];
+            symbol.visibility = merge_visibility(symbol.visibility, sym.visibility);
+
+            if sym.is_undefined() {
+                symbol.referenced = true;
+                if !symbol.is_defined() && sym.binding != SymbolBinding::Weak {
+                    symbol.binding = SymbolBinding::Global;
+                }
+                continue;
+            }
+
+            let definition = match sym.shndx {
+                SECTION_HEADER_NUMBER_ABS => Definition::Absolute(sym.value),
+                SECTION_HEADER_NUMBER_COMMON if sym.ty == SymbolType::Tls => {
+                    return Err(format!(
+                        "{}: thread-local common symbol `{}` is not supported",
+                        input.path, name
+                    ));
+                }
+                // The value of a common symbol is its alignment.
+                SECTION_HEADER_NUMBER_COMMON => Definition::Common {
+                    align: sym.value.max(1),
+                },
+                shndx if shndx >= SECTION_HEADER_NUMBER_LORESERVE => {
+                    return Err(format!(
+                        "{}: symbol `{}` has an unsupported section index 0x{:x}",
+                        input.path, name, shndx
+                    ));
+                }
+                shndx => Definition::Section {
+                    file,
+                    section: shndx as usize,
+                    value: sym.value,
+                },
+            };
+
+            // Definitions in discarded COMDAT groups yield to those of the group kept.
+            if let Definition::Section { section, .. } = definition {
+                if !input.live[section] {
+                    continue;
+                }
+            }
+
+            let replace = match (symbol.definition, symbol.binding) {
+                (Definition::Undefined | Definition::Shared { .. }, _) => true,
+                // Common symbols of the same name are one. Common symbols override weak
+                // definitions and yield to the others.
+                (Definition::Common { align }, _) => match definition {
+                    Definition::Common { align: other } => {
+                        symbol.definition = Definition::Common {
+                            align: align.max(other),
+                        };
+                        symbol.size = symbol.size.max(sym.size);
+                        false
+                    }
+                    _ => sym.binding != SymbolBinding::Weak,
+                },
+                (_, binding) if matches!(definition, Definition::Common { .. }) => {
+                    binding == SymbolBinding::Weak
+                }
+                (_, SymbolBinding::Weak) => sym.binding != SymbolBinding::Weak,
+                _ if sym.binding == SymbolBinding::Weak => false,
+                (other, _) => {
+                    let other = match other {
+                        Definition::Section { file, .. } => inputs[file].path.as_str(),
+                        _ => "<absolute>",
+                    };
+                    return Err(format!(
+                        "duplicate symbol: {}\n>>> defined in {}\n>>> defined in {}",
+                        name, other, input.path
+                    ));
+                }
+            };
+
+            if replace {
+                symbol.definition = definition;
+                symbol.binding = sym.binding;
+                symbol.ty = sym.ty;
+                symbol.size = sym.size;
+                symbol.version = version;
+            }
+        }
+
+        self.file_symbols.push(resolved);
+        Ok(())
+    }
+
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.names.get(name) {
+            return id;
+        }
+        let id = self.symbols.len();
+        self.names.insert(name.into(), id);
+        self.symbols.push(Symbol {
+            name: name.into(),
+            definition: Definition::Undefined,
+            binding: SymbolBinding::Weak,
+            ty: SymbolType::NoType,
+            visibility: SymbolVisibility::Default,
+            size: 0,
+            referenced: false,
+            version: None,
+            export: false,
+            bind_local: false,
+        });
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.get(name).map(|id| &self.symbols[id])
+    }
+
+    /// Defines `name` with a value the linker computed, overriding any definition by input files.
+    pub fn define_linker(&mut self, name: &str, value: u64, hidden: bool) {
+        let id = self.intern(name);
+        let symbol = &mut self.symbols[id];
+        symbol.definition = Definition::Linker(value);
+        symbol.binding = SymbolBinding::Global;
+        if hidden {
+            symbol.visibility = SymbolVisibility::Hidden;
+        }
+    }
+
+    /// Returns what the symbol at `index` of the symbol table of `file` refers to.
+    pub fn resolve(&self, file: usize, index: usize) -> SymbolRef {
+        match self.file_symbols[file].get(index).copied().flatten() {
+            Some(id) => SymbolRef::Global(id),
+            None => SymbolRef::Local { file, index },
+        }
+    }
+}
+
+/// Returns the name an undefined reference to `name` in an object file binds to, given the
+/// symbols `wrap` named by `--wrap`.
+pub fn wrapped_name<'a>(name: &'a str, wrap: &[String]) -> Cow<'a, str> {
+    if wrap.iter().any(|w| w == name) {
+        return Cow::Owned(format!("__wrap_{}", name));
+    }
+    match name.strip_prefix("__real_") {
+        Some(real) if wrap.iter().any(|w| w == real) => Cow::Borrowed(real),
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// Returns the more constraining of two visibilities.
+fn merge_visibility(a: SymbolVisibility, b: SymbolVisibility) -> SymbolVisibility {
+    let rank = |v| match v {
+        SymbolVisibility::Default => 0,
+        SymbolVisibility::Protected => 1,
+        SymbolVisibility::Hidden => 2,
+        SymbolVisibility::Internal => 3,
+    };
+    if rank(b) > rank(a) {
+        b
+    } else {
+        a
+    }
+}
@@ -0,0 +1,1531 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    dynamic::DynamicEntry,
+    elf::{ObjectFileType, RelocationType, SectionType, SegmentFlag, SegmentType, SymbolBinding},
+    inputs::InputFile,
+    note::NoteContent,
+    version::VER_FLG_BASE,
+};
+
+/// A scratch directory for a test, removed when dropped.
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("krc-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().into()
+    }
+
+    /// Compiles C source into an object file, returning its path.
+    fn compile(&self, name: &str, source: &str, flags: &[&str]) -> String {
+        let src = self.path(&format!("{}.c", name));
+        let obj = self.path(&format!("{}.o", name));
+        fs::write(&src, source).unwrap();
+        let status = Command::new("cc")
+            .args([
+                "-c",
+                "-O1",
+                "-fno-pie",
+                "-fno-stack-protector",
+                "-o",
+                &obj,
+                &src,
+            ])
+            .args(flags)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        obj
+    }
+
+    /// Compiles C++ source into an object file, returning its path.
+    fn compile_cxx(&self, name: &str, source: &str, flags: &[&str]) -> String {
+        let src = self.path(&format!("{}.cc", name));
+        let obj = self.path(&format!("{}.o", name));
+        fs::write(&src, source).unwrap();
+        let status = Command::new("c++")
+            .args(["-c", "-O1", "-o", &obj, &src])
+            .args(flags)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        obj
+    }
+
+    /// Links C++ objects into an executable with the startup files and libraries of the toolchain,
+    /// placing `options` first.
+    fn link_cxx(&self, options: &[&str], objects: &[&str]) -> InputFile {
+        let (gcc_dir, lib_dir) = (toolchain_dir("libgcc.a"), toolchain_dir("libc.so"));
+        let [crt1, crti, crtbegin, crtend, crtn] =
+            ["crt1.o", "crti.o", "crtbegin.o", "crtend.o", "crtn.o"].map(toolchain_file);
+        let mut args = options.to_vec();
+        args.extend([&*crt1, &crti, &crtbegin]);
+        args.extend(objects);
+        args.extend(["-L", &gcc_dir, "-L", &lib_dir]);
+        args.extend([
+            "-lstdc++", "-lm", "-lgcc_s", "-lgcc", "-lc", "-lgcc_s", "-lgcc",
+        ]);
+        args.extend([&*crtend, &crtn]);
+        self.link(&args)
+    }
+
+    /// Links with the given arguments and returns the output.
+    fn link(&self, args: &[&str]) -> InputFile {
+        let output = self.path("a.out");
+        let mut args: Vec<String> = args.iter().map(|&a| a.into()).collect();
+        args.insert(0, "krc".into());
+        args.extend(["-o".into(), output.clone()]);
+        assert_eq!(crate::main(args), 0);
+        InputFile::open(&output).unwrap()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Exits with the value of `main`.
+const START: &str = r#"
+int main(void);
+void _start(void) {
+    long code = main();
+    __asm__ volatile("syscall" :: "a"(60L), "D"(code));
+    __builtin_unreachable();
+}
+"#;
+
+fn run(path: &Path) -> i32 {
+    Command::new(path).status().unwrap().code().unwrap()
+}
+
+/// Returns the path of a file of the C toolchain, such as a startup file or a library.
+fn toolchain_file(name: &str) -> String {
+    let output = Command::new("c++")
+        .arg(format!("-print-file-name={}", name))
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Returns the directory holding a file of the C toolchain.
+fn toolchain_dir(name: &str) -> String {
+    let path = toolchain_file(name);
+    let dir = Path::new(&path).parent().unwrap();
+    dir.to_str().unwrap().to_string()
+}
+
+fn has_symbol(output: &InputFile, name: &str) -> bool {
+    output.symbol_names.iter().any(|n| n == name)
+}
+
+fn symbol_value(output: &InputFile, name: &str) -> u64 {
+    let index = output.symbol_names.iter().position(|n| n == name).unwrap();
+    output.symbols[index].value
+}
+
+#[test]
+fn common_symbol_test() {
+    let dir = TestDir::new("common");
+    let start = dir.compile("start", START, &[]);
+    let a = dir.compile(
+        "a",
+        "int buffer[2]; int answer;\n\
+         int *small(void) { return buffer; }",
+        &["-fcommon"],
+    );
+    let b = dir.compile(
+        "b",
+        "int buffer[8] __attribute__((aligned(64)));\n\
+         int *large(void) { return buffer; }",
+        &["-fcommon"],
+    );
+    let main = dir.compile(
+        "main",
+        "extern int answer, buffer[8];\n\
+         int *small(void), *large(void);\n\
+         int answer = 40;\n\
+         int main(void) {\n\
+             buffer[7] = 2;\n\
+             return (small() == large()) * (answer + small()[7]);\n\
+         }",
+        &[],
+    );
+
+    // Common symbols of the same name are one with the largest size and alignment, and a
+    // definition overrides them.
+    let output = dir.link(&[&start, &a, &b, &main]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    let index = output.symbol_names.iter().position(|n| n == "buffer");
+    let buffer = &output.symbols[index.unwrap()];
+    assert_eq!(buffer.size, 32);
+    assert_eq!(buffer.value % 64, 0);
+    let bss = output.section_names.iter().position(|n| n == ".bss");
+    assert_eq!(buffer.shndx as usize, bss.unwrap());
+
+    // In a linker script, `COMMON` places the common symbols. The orphan `.eh_frame` gets a page
+    // of its own, since it is read-only.
+    let script = dir.path("script.ld");
+    fs::write(
+        &script,
+        "SECTIONS {\n\
+             . = 0x400000 + SIZEOF_HEADERS;\n\
+             .text : { *(.text*) }\n\
+             .data : { *(.data*) }\n\
+             .bss (NOLOAD) : { _sbss = .; *(.bss*) *(COMMON) _ebss = .; }\n\
+             . = ALIGN(0x1000);\n\
+         }",
+    )
+    .unwrap();
+    let output = dir.link(&["-T", &script, &start, &a, &b, &main]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    let buffer = symbol_value(&output, "buffer");
+    assert!(symbol_value(&output, "_sbss") <= buffer);
+    assert!(buffer + 32 <= symbol_value(&output, "_ebss"));
+}
+
+#[test]
+fn gc_sections_test() {
+    let dir = TestDir::new("gc-sections");
+    let flags = ["-ffunction-sections", "-fdata-sections"];
+    let start = dir.compile("start", START, &flags);
+    let main = dir.compile(
+        "main",
+        r#"
+        int used = 40, unused = 1;
+        int unused_fn(void) { return unused; }
+        __attribute__((used, retain)) int retained_fn(void) { return 1; }
+        int main(void) { return used + 2; }
+        "#,
+        &flags,
+    );
+
+    let output = dir.link(&["--gc-sections", &start, &main]);
+    assert!(has_symbol(&output, "retained_fn"));
+    assert!(!has_symbol(&output, "unused_fn"));
+    assert!(!has_symbol(&output, "unused"));
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+
+    let output = dir.link(&[&start, &main]);
+    assert!(has_symbol(&output, "unused_fn"));
+}
+
+#[test]
+fn icf_test() {
+    let dir = TestDir::new("icf");
+    let flags = [
+        "-ffunction-sections",
+        "-fno-inline",
+        "-fno-optimize-sibling-calls",
+    ];
+    let start = dir.compile("start", START, &flags);
+    let main = dir.compile(
+        "main",
+        r#"
+        int odd1(int), odd2(int);
+        int even1(int x) { return x ? odd1(x - 1) : 1; }
+        int odd1(int x) { return x ? even1(x - 1) : 0; }
+        int even2(int x) { return x ? odd2(x - 1) : 1; }
+        int odd2(int x) { return x ? even2(x - 1) : 0; }
+        int (*volatile taken)(int) = even2;
+        int main(void) { return even1(4) + even2(4) + (taken == even1) * 40; }
+        const int small[4] = {1, 2, 3, 4};
+        __attribute__((aligned(64))) const int large[4] = {1, 2, 3, 4};
+        "#,
+        &[&flags[..], &["-fdata-sections"]].concat(),
+    );
+
+    let exe = dir.path("a.out");
+    let output = dir.link(&["--icf=all", &start, &main]);
+    assert_eq!(
+        symbol_value(&output, "even1"),
+        symbol_value(&output, "even2")
+    );
+    assert_eq!(run(Path::new(&exe)), 42);
+    // Sections with different alignments are not folded.
+    let large = symbol_value(&output, "large");
+    assert_ne!(symbol_value(&output, "small"), large);
+    assert_eq!(large % 64, 0);
+
+    // `even2` has its address taken, so safe folding keeps it apart from `even1`.
+    let output = dir.link(&["--icf=safe", &start, &main]);
+    assert_ne!(
+        symbol_value(&output, "even1"),
+        symbol_value(&output, "even2")
+    );
+    assert_eq!(run(Path::new(&exe)), 2);
+}
+
+#[test]
+fn merge_test() {
+    let dir = TestDir::new("merge");
+    let start = dir.compile("start", START, &[]);
+    let a = dir.compile(
+        "a",
+        r#"
+        const char *greeting(void) { return "hello, world"; }
+        const char *world(void) { return "world"; }
+        "#,
+        &[],
+    );
+    let main = dir.compile(
+        "main",
+        r#"
+        const char *greeting(void), *world(void);
+        int main(void) {
+            const char *s = "hello, world";
+            return (s == greeting()) * 40 + (s + 7 == world()) * 2 + (s[12] != 0) * 100;
+        }
+        "#,
+        &[],
+    );
+
+    let exe = dir.path("a.out");
+    dir.link(&[&start, &a, &main]);
+    assert_eq!(run(Path::new(&exe)), 40);
+
+    // At `-O2`, "world" shares the end of "hello, world".
+    dir.link(&["-O2", &start, &a, &main]);
+    assert_eq!(run(Path::new(&exe)), 42);
+}
+
+/// Defines `answer` returning `value` in the COMDAT group `answer`.
+fn comdat_source(value: i32) -> String {
+    format!(
+        r#"
+        __asm__(".section .text.answer,\"axG\",@progbits,answer,comdat\n"
+                ".globl answer\n"
+                ".type answer, @function\n"
+                "answer: mov ${}, %eax\n"
+                "ret\n"
+                ".text");
+        "#,
+        value
+    )
+}
+
+#[test]
+fn comdat_test() {
+    let dir = TestDir::new("comdat");
+    let start = dir.compile("start", START, &[]);
+    let a = dir.compile("a", &comdat_source(40), &[]);
+    let b = dir.compile("b", &comdat_source(1), &[]);
+    let main = dir.compile(
+        "main",
+        "int answer(void); int main(void) { return answer() + 2; }",
+        &[],
+    );
+
+    // Both groups define `answer` globally, but only the first is linked.
+    dir.link(&[&start, &a, &b, &main]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+}
+
+/// Whether the output has a section named `name`.
+fn has_section(output: &InputFile, name: &str) -> bool {
+    output.section_names.iter().any(|n| n == name)
+}
+
+/// Returns the contents of the section `name` of the output.
+fn section_data<'a>(output: &'a InputFile, name: &str) -> &'a [u8] {
+    let index = output.section_names.iter().position(|n| n == name).unwrap();
+    output.object.section_data(&output.sections[index]).unwrap()
+}
+
+#[test]
+fn eh_frame_test() {
+    let dir = TestDir::new("eh-frame");
+    let flags = ["-ffunction-sections"];
+    let start = dir.compile("start", START, &flags);
+    let main = dir.compile(
+        "main",
+        r#"
+        int unused(int x) { return x + 1; }
+        int main(void) { return 42; }
+        "#,
+        &flags,
+    );
+    let fde_count = |output: &InputFile| {
+        let hdr = section_data(output, ".eh_frame_hdr");
+        u32::from_le_bytes(hdr[8..12].try_into().unwrap())
+    };
+
+    let output = dir.link(&[&start, &main]);
+    assert_eq!(fde_count(&output), 3);
+    assert!(section_data(&output, ".eh_frame").ends_with(&[0; 4]));
+
+    // The FDE of `unused` goes with its section.
+    let output = dir.link(&["--gc-sections", &start, &main]);
+    assert_eq!(fde_count(&output), 2);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+}
+
+#[test]
+fn cxx_exception_test() {
+    let dir = TestDir::new("cxx-exception");
+    let source = r#"
+        #include <cstdio>
+        #include <stdexcept>
+        static void thrower(int x) { if (x) throw std::runtime_error("caught"); }
+        int main(int argc, char **) {
+            try { thrower(argc); } catch (const std::exception &e) { std::puts(e.what()); return 42; }
+            return 1;
+        }
+        "#;
+
+    // The personality routine is in libstdc++.so, which non-PIE code refers to by a fixed address
+    // in its CIE, and PIE code through a `DW.ref` cell only the CIE refers to.
+    for pic in ["-fno-pie", "-fpie"] {
+        let obj = dir.compile_cxx(
+            "main",
+            source,
+            &["-ffunction-sections", "-fdata-sections", pic],
+        );
+        for gc in [None, Some("--gc-sections")] {
+            let options: Vec<&str> = gc.into_iter().collect();
+            dir.link_cxx(&options, &[&obj]);
+            let output = Command::new(dir.path("a.out")).output().unwrap();
+            assert_eq!(output.status.code(), Some(42), "{} {:?}", pic, gc);
+            assert_eq!(output.stdout, b"caught\n");
+        }
+    }
+}
+
+#[test]
+fn gc_exception_test() {
+    let dir = TestDir::new("gc-exception");
+    let obj = dir.compile_cxx(
+        "main",
+        r#"
+        struct Err {};
+        void unused() { try { throw Err(); } catch (const Err &) {} }
+        int main() { try { throw 42; } catch (int x) { return x; } }
+        "#,
+        &["-ffunction-sections", "-fdata-sections", "-fno-pie"],
+    );
+
+    // The LSDA of `unused` and the type information it refers to go with the function.
+    let output = dir.link_cxx(&["--gc-sections"], &[&obj]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    for name in ["_Z6unusedv", "_ZTI3Err", "_ZTS3Err"] {
+        assert!(!has_symbol(&output, name), "{}", name);
+    }
+    let output = dir.link_cxx(&[], &[&obj]);
+    assert!(has_symbol(&output, "_ZTI3Err"));
+}
+
+#[test]
+fn icf_exception_test() {
+    let dir = TestDir::new("icf-exception");
+    // `f1` and `f2` have the same code, but their LSDAs catch different types.
+    let obj = dir.compile_cxx(
+        "main",
+        r#"
+        __attribute__((noinline)) void thrower() { throw 5L; }
+        __attribute__((noinline)) int f1() { try { thrower(); } catch (int) { return 1; } return 0; }
+        __attribute__((noinline)) int f2() { try { thrower(); } catch (long) { return 1; } return 0; }
+        int main() {
+            try { f1(); return 1; } catch (...) {}
+            try { return f2() ? 42 : 2; } catch (...) { return 3; }
+        }
+        "#,
+        &["-ffunction-sections", "-fno-pie"],
+    );
+
+    for icf in ["--icf=none", "--icf=all", "--icf=safe"] {
+        let output = dir.link_cxx(&[icf], &[&obj]);
+        assert_ne!(
+            symbol_value(&output, "_Z2f1v"),
+            symbol_value(&output, "_Z2f2v"),
+            "{}",
+            icf
+        );
+        assert_eq!(run(Path::new(&dir.path("a.out"))), 42, "{}", icf);
+    }
+}
+
+#[test]
+fn linker_script_test() {
+    let dir = TestDir::new("linker-script");
+    let start = dir.compile("start", START, &["-ffunction-sections"]);
+    let main = dir.compile(
+        "main",
+        "extern char answer[];\n\
+         extern int kept_start[], kept_end[];\n\
+         static int kept __attribute__((section(\".kept\"), used)) = 2;\n\
+         int discarded __attribute__((section(\".discarded\"))) = 1;\n\
+         static void init(void) {}\n\
+         static void (*high)(void) __attribute__((section(\".ctors.70000\"), used)) = init;\n\
+         static void (*low)(void) __attribute__((section(\".ctors.100\"), used)) = init;\n\
+         const char message[] = \"hello\";\n\
+         const char *volatile pointer = message;\n\
+         int main(void) {\n\
+             int sum = (long)answer;\n\
+             for (int *p = kept_start; p < kept_end; p++) sum -= *p;\n\
+             return sum + (pointer[0] == 'h') * 2;\n\
+         }",
+        &["-ffunction-sections", "-fdata-sections"],
+    );
+    let script = dir.path("script.ld");
+    fs::write(
+        &script,
+        "ENTRY(_start)\n\
+         SECTIONS {\n\
+             . = 0x600000 + SIZEOF_HEADERS;\n\
+             .text : { *(.text .text.*) }\n\
+             . = ALIGN(0x1000);\n\
+             .rodata : { *(.rodata .rodata.*) }\n\
+             . = 0x800000;\n\
+             data_start = .;\n\
+             .data : { *(.data .data.*) }\n\
+             .ctors : { KEEP(*(SORT_BY_INIT_PRIORITY(.ctors.*))) }\n\
+             .kept : { kept_start = .; KEEP(*(.kept)) kept_end = .; }\n\
+             .bss : { *(.bss .bss.*) }\n\
+             PROVIDE(answer = 42);\n\
+             PROVIDE(unused = 1);\n\
+             /DISCARD/ : { *(.discarded) }\n\
+         }",
+    )
+    .unwrap();
+    let output = dir.link(&["--gc-sections", "-T", &script, &start, &main]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+
+    let section = |name: &str| {
+        let index = output.section_names.iter().position(|n| n == name);
+        index.map(|i| &output.sections[i])
+    };
+    assert!(symbol_value(&output, "main") > 0x600000);
+    assert_eq!(section(".rodata").unwrap().addr % 0x1000, 0);
+    assert_eq!(section(".data").unwrap().addr, 0x800000);
+    assert_eq!(symbol_value(&output, "data_start"), 0x800000);
+    assert_eq!(symbol_value(&output, "answer"), 42);
+    assert!(!has_symbol(&output, "unused"));
+    // `KEEP` retains sections nothing refers to, and `/DISCARD/` drops them.
+    assert_eq!(section(".kept").unwrap().size, 4);
+    assert_eq!(section(".ctors").unwrap().size, 16);
+    assert!(section(".discarded").is_none());
+    assert!(!has_symbol(&output, "discarded"));
+
+    // Non-allocated orphans such as `.comment` do not move the location counter, so the
+    // allocated orphans after them such as `.eh_frame` follow the sections the script lists.
+    let plain = dir.compile(
+        "plain",
+        "int value = 40;\n\
+         int main(void) { return value + 2; }",
+        &[],
+    );
+    fs::write(
+        &script,
+        "SECTIONS {\n\
+             . = 0x400000 + SIZEOF_HEADERS;\n\
+             .text : { *(.text*) }\n\
+             .data : { *(.data*) *(.bss*) }\n\
+         }",
+    )
+    .unwrap();
+    let output = dir.link(&["-T", &script, &start, &plain]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    assert!(has_section(&output, ".comment"));
+    let index = output.section_names.iter().position(|n| n == ".eh_frame");
+    assert!(output.sections[index.unwrap()].addr > 0x400000);
+
+    // Dividing by zero in an assignment is an error, as it is in an expression.
+    let symbols = "answer = 84; kept_start = 0; kept_end = 0;";
+    fs::write(&script, format!("{} answer /= 2;", symbols)).unwrap();
+    let output = dir.link(&["-T", &script, &start, &main]);
+    assert_eq!(symbol_value(&output, "answer"), 42);
+    fs::write(&script, format!("{} answer /= 0;", symbols)).unwrap();
+    let exe = dir.path("a.out");
+    let args = ["krc", "-T", &script, &start, &main, "-o", &exe];
+    assert_ne!(crate::main(args.iter().map(|&a| a.into()).collect()), 0);
+}
+
+#[test]
+fn relro_test() {
+    let dir = TestDir::new("relro");
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        r#"
+        int x = 40, y = 2;
+        __attribute__((section(".data.rel.ro"))) int *const p = &x;
+        int main(void) { return *p + y; }
+        "#,
+        &[],
+    );
+    let relro = |output: &InputFile| {
+        output
+            .object
+            .program_headers()
+            .map(|h| h.unwrap())
+            .find(|h| h.ty == SegmentType::GnuRelro)
+    };
+
+    // The region ends on a page boundary, before `.data`.
+    let output = dir.link(&[&start, &main]);
+    let segment = relro(&output).unwrap();
+    assert_eq!((segment.vaddr + segment.memsz) % 0x1000, 0);
+    assert!(segment.vaddr + segment.memsz <= symbol_value(&output, "x"));
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+
+    let output = dir.link(&["-z", "norelro", &start, &main]);
+    assert!(relro(&output).is_none());
+}
+
+#[test]
+fn exec_stack_test() {
+    let dir = TestDir::new("exec-stack");
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile("main", "int main(void) { return 42; }", &[]);
+    let exec = dir.compile("exec", "int f(void) { return 1; }", &["-Wa,--execstack"]);
+    let stack_flags = |output: &InputFile| {
+        output
+            .object
+            .program_headers()
+            .map(|h| h.unwrap())
+            .find(|h| h.ty == SegmentType::GnuStack)
+            .unwrap()
+            .flags
+    };
+
+    let output = dir.link(&[&start, &main]);
+    assert_eq!(stack_flags(&output), SegmentFlag::R | SegmentFlag::W);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+
+    let output = dir.link(&[&start, &main, &exec]);
+    assert!(stack_flags(&output).contains(SegmentFlag::X));
+
+    let output = dir.link(&["-z", "noexecstack", &start, &main, &exec]);
+    assert!(!stack_flags(&output).contains(SegmentFlag::X));
+
+    let output = dir.link(&["-zexecstack", &start, &main]);
+    assert!(stack_flags(&output).contains(SegmentFlag::X));
+}
+
+#[test]
+fn gnu_property_test() {
+    let dir = TestDir::new("gnu-property");
+    let cet = ["-fcf-protection=full"];
+    let start = dir.compile("start", START, &cet);
+    let main = dir.compile("main", "int main(void) { return 42; }", &cet);
+    let plain = dir.compile(
+        "plain",
+        "int f(void) { return 1; }",
+        &["-fcf-protection=none"],
+    );
+    let features = |output: &InputFile| {
+        let has_segment = output
+            .object
+            .program_headers()
+            .map(|h| h.unwrap())
+            .any(|h| h.ty == SegmentType::GnuProperty);
+        let data = section_data(output, ".note.gnu.property");
+        assert!(has_segment);
+        u32::from_le_bytes(data[24..28].try_into().unwrap())
+    };
+
+    let output = dir.link(&[&start, &main]);
+    assert_eq!(features(&output), 3);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+
+    let output = dir.link(&[&start, &main, &plain]);
+    assert!(!output
+        .section_names
+        .iter()
+        .any(|n| n == ".note.gnu.property"));
+
+    let output = dir.link(&["-z", "force-ibt", &start, &main, &plain]);
+    assert_eq!(features(&output), 1);
+
+    let args = ["krc", "-z", "cet-report=error", &start, &main, &plain];
+    assert_ne!(crate::main(args.iter().map(|&a| a.into()).collect()), 0);
+}
+
+#[test]
+fn build_id_test() {
+    let dir = TestDir::new("build-id");
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile("main", "int main(void) { return 42; }", &[]);
+    let build_id = |output: &InputFile| {
+        let index = output
+            .section_names
+            .iter()
+            .position(|n| n == ".note.gnu.build-id")
+            .unwrap();
+        let header = &output.sections[index];
+        let note = output
+            .object
+            .notes(header)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let NoteContent::BuildId(id) = note.content().unwrap() else {
+            panic!("not a build ID note");
+        };
+        (header.offset as usize + 16, id.to_vec())
+    };
+
+    // The ID is the hash of the output with the ID zeroed.
+    let output = dir.link(&["--build-id=sha1", &start, &main]);
+    let (offset, id) = build_id(&output);
+    assert_eq!(id.len(), 20);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    let mut image = fs::read(dir.path("a.out")).unwrap();
+    image[offset..offset + id.len()].fill(0);
+    fs::write(dir.path("zeroed"), image).unwrap();
+    let sha1sum = Command::new("sha1sum")
+        .arg(dir.path("zeroed"))
+        .output()
+        .unwrap();
+    let expected: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+    assert!(String::from_utf8(sha1sum.stdout)
+        .unwrap()
+        .starts_with(&expected));
+
+    let output = dir.link(&["--build-id=fast", &start, &main]);
+    assert_eq!(build_id(&output).1.len(), 8);
+
+    let output = dir.link(&["--build-id=0x0123abcd", &start, &main]);
+    assert_eq!(build_id(&output).1, [0x01, 0x23, 0xab, 0xcd]);
+
+    let output = dir.link(&["--build-id=none", &start, &main]);
+    assert!(!has_section(&output, ".note.gnu.build-id"));
+}
+
+#[test]
+fn reproducible_test() {
+    let dir = TestDir::new("reproducible");
+    let objects = [
+        dir.compile("start", START, &[]),
+        dir.compile("main", "int f(void); int main(void) { return f(); }", &[]),
+        dir.compile("f", "int g(void); int f(void) { return g() + 1; }", &[]),
+        dir.compile("g", "int g(void) { return 41; }", &[]),
+        dir.compile("unused", "int unused(void) { return 7; }", &[]),
+    ];
+
+    // The same inputs under different paths, with archive members of different timestamps.
+    let mut images = vec![];
+    for (prefix, threads, mtime) in [("a", "1", 1_000_000), ("deeper/prefix", "8", 2_000_000)] {
+        let sub = dir.path(prefix);
+        fs::create_dir_all(&sub).unwrap();
+        let copies: Vec<String> = objects
+            .iter()
+            .map(|object| {
+                let copy = format!(
+                    "{}/{}",
+                    sub,
+                    Path::new(object).file_name().unwrap().to_str().unwrap()
+                );
+                fs::copy(object, &copy).unwrap();
+                let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+                fs::File::options()
+                    .write(true)
+                    .open(&copy)
+                    .unwrap()
+                    .set_modified(time)
+                    .unwrap();
+                copy
+            })
+            .collect();
+        let archive = format!("{}/lib.a", sub);
+        let status = Command::new("ar")
+            .args(["rcU", &archive, &copies[2], &copies[3], &copies[4]])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = dir.link(&["--threads", threads, &archive, &copies[0], &copies[1]]);
+        assert!(has_symbol(&output, "g"));
+        assert!(!has_symbol(&output, "unused"));
+        assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+        images.push(fs::read(dir.path("a.out")).unwrap());
+    }
+    assert_eq!(images[0], images[1]);
+
+    let tar = dir.path("repro.tar");
+    dir.link(&[
+        &format!("--reproduce={}", tar),
+        &objects[0],
+        &objects[1],
+        &objects[2],
+        &objects[3],
+    ]);
+    let listing = Command::new("tar").args(["tf", &tar]).output().unwrap();
+    let listing = String::from_utf8(listing.stdout).unwrap();
+    assert!(listing.lines().any(|l| l == "repro/response.txt"));
+    assert!(listing
+        .lines()
+        .any(|l| l.starts_with("repro/") && l.ends_with("/g.o")));
+}
+
+#[test]
+fn map_test() {
+    let dir = TestDir::new("map");
+    let start = dir.compile("start", START, &["-ffunction-sections"]);
+    let main = dir.compile(
+        "main",
+        "int f(void); int unused(void) { return 1; } int main(void) { return f(); }",
+        &["-ffunction-sections"],
+    );
+    let f = dir.compile("f", "int f(void) { return 42; }", &[]);
+    let archive = dir.path("lib.a");
+    let status = Command::new("ar")
+        .args(["rc", &archive, &f])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let map = dir.path("map.txt");
+    dir.link(&[
+        "--gc-sections",
+        &format!("-Map={}", map),
+        &start,
+        &main,
+        &archive,
+    ]);
+    let text = fs::read_to_string(&map).unwrap();
+    assert!(text.contains(&format!("{}(f.o)", archive)));
+    assert!(text.contains(&format!("{} (f)", main)));
+    assert!(text.contains(&format!("{}:(.text.unused)", main)));
+    let main_line = text.lines().find(|l| l.ends_with(" main")).unwrap();
+    let addr = u64::from_str_radix(main_line.split_whitespace().next().unwrap(), 16).unwrap();
+    let output = InputFile::open(&dir.path("a.out")).unwrap();
+    assert_eq!(addr, symbol_value(&output, "main"));
+
+    dir.link(&["-Map", &map, "--map-format=json", &start, &main, &archive]);
+    let json = fs::read_to_string(&map).unwrap();
+    assert!(json.contains(r#""archive_members": ["#));
+    assert!(json.contains(r#""symbol": "f""#));
+}
+
+#[test]
+fn xref_test() {
+    let dir = TestDir::new("xref");
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile("main", "int f(void); int main(void) { return f(); }", &[]);
+    let f = dir.compile("f", "int g(void); int f(void) { return g() + 1; }", &[]);
+    let g = dir.compile("g", "int g(void) { return 41; }", &[]);
+    let archive = dir.path("lib.a");
+    let status = Command::new("ar")
+        .args(["rc", &archive, &f, &g])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let why = dir.path("why.txt");
+    let map = dir.path("map.txt");
+    let why_arg = format!("--why-extract={}", why);
+    dir.link(&[&why_arg, "--cref", "-Map", &map, &start, &main, &archive]);
+    assert_eq!(
+        fs::read_to_string(&why).unwrap(),
+        format!(
+            "reference\textracted\tsymbol\n{}\t{}(f.o)\tf\n{}(f.o)\t{}(g.o)\tg\n",
+            main, archive, archive, archive
+        )
+    );
+
+    // The file defining a symbol comes before those referring to it.
+    let text = fs::read_to_string(&map).unwrap();
+    let table = &text[text.find("Cross Reference Table").unwrap()..];
+    let lines: Vec<&str> = table.lines().collect();
+    let f_line = lines.iter().position(|l| l.starts_with("f ")).unwrap();
+    assert!(lines[f_line].ends_with(&format!("{}(f.o)", archive)));
+    assert_eq!(lines[f_line + 1].trim(), main);
+}
+
+#[test]
+fn strip_test() {
+    let dir = TestDir::new("strip");
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "__attribute__((noinline)) static int f(int x) { return x * 2; }\n\
+         int main(void) { return f(21); }",
+        &["-g", "-Wa,-L"],
+    );
+
+    let output = dir.link(&[&start, &main]);
+    assert!(has_section(&output, ".debug_info"));
+    assert!(has_symbol(&output, "f"));
+    assert!(output.symbol_names.iter().any(|n| n.starts_with(".L")));
+
+    let output = dir.link(&["-S", &start, &main]);
+    assert!(!has_section(&output, ".debug_info"));
+    assert!(has_symbol(&output, "main"));
+
+    let output = dir.link(&["-X", &start, &main]);
+    assert!(has_symbol(&output, "f"));
+    assert!(!output.symbol_names.iter().any(|n| n.starts_with(".L")));
+
+    let output = dir.link(&["-x", &start, &main]);
+    assert!(!has_symbol(&output, "f"));
+    assert!(has_symbol(&output, "main"));
+
+    let output = dir.link(&["--strip-all", &start, &main]);
+    assert!(!has_section(&output, ".symtab"));
+    assert!(!has_section(&output, ".debug_info"));
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+}
+
+#[test]
+fn shared_library_test() {
+    let dir = TestDir::new("shared-library");
+    let lib = dir.path("libadd.c");
+    let script = dir.path("add.map");
+    let so = dir.path("libadd.so");
+    fs::write(
+        &lib,
+        "int base = 40;\n\
+         int add_v1(int x) { return x; }\n\
+         int add_v2(int x) { return x + base; }\n\
+         __asm__(\".symver add_v1, add@V1\");\n\
+         __asm__(\".symver add_v2, add@@V2\");",
+    )
+    .unwrap();
+    fs::write(&script, "V1 { local: *; };\nV2 { global: base; } V1;").unwrap();
+    let status = Command::new("cc")
+        .args(["-shared", "-nostdlib", "-fPIC", "-o", &so, &lib])
+        .arg(format!("-Wl,--version-script={},-soname,libadd.so", script))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // Calls to `add` bind to its default version through the PLT.
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "int add(int); int main(void) { return add(2); }",
+        &[],
+    );
+    let output = dir.link(&[&start, &main, &so]);
+    let entries: Vec<_> = output
+        .object
+        .dynamic_entries()
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert!(entries.contains(&DynamicEntry::Needed("libadd.so".into())));
+    assert!(entries.contains(&DynamicEntry::VerNeedNum(1)));
+    assert!(entries.iter().any(|e| matches!(e, DynamicEntry::Debug(_))));
+    let header = |ty| output.sections.iter().find(|h| h.ty == ty).unwrap();
+    let needs = output
+        .object
+        .version_needs(header(SectionType::GnuVerneed), &output.sections)
+        .unwrap();
+    assert_eq!(needs.len(), 1);
+    assert_eq!(needs[0].file, "libadd.so");
+    assert_eq!(needs[0].versions, [("V2".to_string(), 2)]);
+    let status = Command::new(dir.path("a.out"))
+        .env("LD_LIBRARY_PATH", &dir.0)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(42));
+
+    // Objects name versions with `.symver`.
+    let foo = dir.compile(
+        "foo",
+        "int foo_v1(void) { return 1; }\n\
+         int foo_v2(void) { return 2; }\n\
+         __asm__(\".symver foo_v1, foo@V1\");\n\
+         __asm__(\".symver foo_v2, foo@@V2\");",
+        &["-fPIC"],
+    );
+    let output = dir.link(&["-shared", "-soname", "libfoo.so.1", &foo]);
+    assert_eq!(output.object.header.ty, ObjectFileType::Dyn);
+    assert_eq!(
+        output.object.dynamic_entries().unwrap().next(),
+        Some(Ok(DynamicEntry::Soname("libfoo.so.1".into())))
+    );
+    let header = |ty| output.sections.iter().find(|h| h.ty == ty).unwrap();
+    let definitions = output
+        .object
+        .version_definitions(header(SectionType::GnuVerdef), &output.sections)
+        .unwrap();
+    let names: Vec<_> = definitions.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["libfoo.so.1", "V1", "V2"]);
+    assert_eq!(definitions[0].flags, VER_FLG_BASE);
+
+    let dynamic = InputFile::new_shared(output.path.clone(), output.object).unwrap();
+    let version = |name: &str| {
+        let index = dynamic.symbol_names.iter().position(|n| n == name).unwrap();
+        dynamic.shared.as_ref().unwrap().versions[index].clone()
+    };
+    assert_eq!(version("foo").as_deref(), Some("V2"));
+    assert_eq!(version("foo@V1").as_deref(), Some("V1"));
+}
+
+#[test]
+fn version_script_test() {
+    let dir = TestDir::new("version-script");
+    let lib = dir.compile(
+        "lib",
+        "int api_one(void) { return 1; }\n\
+         int api_two(void) { return 2; }\n\
+         int helper(void) { return 3; }\n\
+         int _ZN2ns3getEv(void) { return 4; }",
+        &["-fPIC"],
+    );
+    let script = dir.path("lib.map");
+    fs::write(
+        &script,
+        "V1 {\n\
+           global: api_one; extern \"C++\" { \"ns::get()\"; };\n\
+           local: *;\n\
+         };\n\
+         V2 { api_t*; } V1;",
+    )
+    .unwrap();
+    let version_script = format!("--version-script={}", script);
+    let output = dir.link(&["-shared", "-soname", "libv.so", &version_script, &lib]);
+    let header = |ty| output.sections.iter().find(|h| h.ty == ty).unwrap();
+    let definitions = output
+        .object
+        .version_definitions(header(SectionType::GnuVerdef), &output.sections)
+        .unwrap();
+    let names: Vec<_> = definitions.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["libv.so", "V1", "V2"]);
+    assert_eq!(definitions[2].parents, ["V1"]);
+
+    // `helper` stays in the symbol table, but as a local symbol.
+    let index = output
+        .symbol_names
+        .iter()
+        .position(|n| n == "helper")
+        .unwrap();
+    assert_eq!(output.symbols[index].binding, SymbolBinding::Local);
+
+    let library = dir.path("libv.so");
+    fs::rename(&output.path, &library).unwrap();
+    let dynamic = InputFile::open(&library)
+        .and_then(|i| InputFile::new_shared(i.path, i.object))
+        .unwrap();
+    let version = |name: &str| {
+        let index = dynamic.symbol_names.iter().position(|n| n == name)?;
+        dynamic.shared.as_ref().unwrap().versions[index].clone()
+    };
+    assert_eq!(version("api_one").as_deref(), Some("V1"));
+    assert_eq!(version("api_two").as_deref(), Some("V2"));
+    assert_eq!(version("_ZN2ns3getEv").as_deref(), Some("V1"));
+    assert!(!dynamic.symbol_names.iter().any(|n| n == "helper"));
+
+    // Executables export the symbols a dynamic list names.
+    let list = dir.path("exports.list");
+    fs::write(&list, "{ exported; };").unwrap();
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "int api_one(void); int exported(void) { return 0; }\n\
+         int unexported(void) { return 0; }\n\
+         int main(void) { return api_one(); }",
+        &[],
+    );
+    let dynamic_list = format!("--dynamic-list={}", list);
+    let output = dir.link(&[&start, &main, &library, &dynamic_list]);
+    let dynamic = InputFile::new_shared(output.path, output.object).unwrap();
+    assert!(dynamic.symbol_names.iter().any(|n| n == "exported"));
+    assert!(!dynamic.symbol_names.iter().any(|n| n == "unexported"));
+}
+
+#[test]
+fn hash_table_test() {
+    let dir = TestDir::new("hash-table");
+    let lib = dir.compile(
+        "lib",
+        "int base = 40;\n\
+         int add(int x) { return x + base; }\n\
+         int twice(int x) { return add(x) * 2 - base; }",
+        &["-fPIC"],
+    );
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "int add(int); int twice(int);\n\
+         int main(void) { return twice(1) - add(0) + 40; }",
+        &[],
+    );
+    let library = dir.path("libh.so");
+    for (style, types) in [
+        ("sysv", &[SectionType::Hash][..]),
+        ("gnu", &[SectionType::GnuHash]),
+        ("both", &[SectionType::Hash, SectionType::GnuHash]),
+    ] {
+        let hash_style = format!("--hash-style={}", style);
+        let output = dir.link(&["-shared", "-soname", "libh.so", &hash_style, &lib]);
+        let tables: Vec<_> = output
+            .sections
+            .iter()
+            .filter(|h| matches!(h.ty, SectionType::Hash | SectionType::GnuHash))
+            .collect();
+        assert_eq!(tables.iter().map(|h| h.ty).collect::<Vec<_>>(), types);
+        for table in tables {
+            let dynstr = &output.sections[output.sections[table.link as usize].link as usize];
+            for name in ["base", "add", "twice"] {
+                let (_, symbol) = output
+                    .object
+                    .lookup_symbol(table, &output.sections, name)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(output.object.string(dynstr, symbol.name).unwrap(), name);
+            }
+            let missing = output
+                .object
+                .lookup_symbol(table, &output.sections, "main")
+                .unwrap();
+            assert!(missing.is_none());
+        }
+
+        // The loader finds the symbols of the library through the table.
+        fs::rename(&output.path, &library).unwrap();
+        dir.link(&[&start, &main, &library]);
+        let status = Command::new(dir.path("a.out"))
+            .env("LD_LIBRARY_PATH", &dir.0)
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(42), "{}", style);
+    }
+
+    // The single-dash spelling is not `-h` with a joined soname.
+    let output = dir.link(&["-shared", "-hash-style=sysv", &lib]);
+    assert!(!output.sections.iter().any(|h| h.ty == SectionType::GnuHash));
+    let options = crate::options::Options::parse(&["-hash-style=sysv".into()]).unwrap();
+    assert_eq!(options.soname, None);
+}
+
+#[test]
+fn library_search_test() {
+    let dir = TestDir::new("library-search");
+    let libs = dir.path("libs");
+    fs::create_dir_all(&libs).unwrap();
+    let shared = dir.compile("shared", "int foo(void) { return 42; }", &["-fPIC"]);
+    let archived = dir.compile("archived", "int foo(void) { return 41; }", &[]);
+    let unused = dir.compile("unused", "int unused(void) { return 0; }", &["-fPIC"]);
+    for (name, object) in [("libfoo.so", &shared), ("libunused.so", &unused)] {
+        let soname = format!("-soname={}", name);
+        dir.link(&["-shared", &soname, object]);
+        fs::rename(dir.path("a.out"), format!("{}/{}", libs, name)).unwrap();
+    }
+    let status = Command::new("ar")
+        .args(["rc", &format!("{}/libfoo.a", libs), &archived])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "int foo(void); int main(void) { return foo(); }",
+        &[],
+    );
+
+    let needed = |output: &InputFile| -> Vec<String> {
+        match output.object.dynamic_entries() {
+            Ok(entries) => entries
+                .filter_map(|e| match e.unwrap() {
+                    DynamicEntry::Needed(name) => Some(name),
+                    _ => None,
+                })
+                .collect(),
+            Err(_) => vec![],
+        }
+    };
+    let search = format!("-L{}", libs);
+
+    // `libfoo.so` is preferred to `libfoo.a`, unless `-Bstatic` is in effect.
+    let output = dir.link(&[&search, &start, &main, "-lfoo"]);
+    assert_eq!(needed(&output), ["libfoo.so"]);
+    let status = Command::new(dir.path("a.out"))
+        .env("LD_LIBRARY_PATH", &libs)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(42));
+    for args in [&["-Bstatic", "-lfoo"], &["-l", ":libfoo.a"]] {
+        let output = dir.link(&[&[&search[..], &start, &main], &args[..]].concat());
+        assert!(needed(&output).is_empty());
+        assert_eq!(run(Path::new(&dir.path("a.out"))), 41);
+    }
+
+    // Libraries given with `--as-needed` are needed only if they define a symbol referred to.
+    let output = dir.link(&[&search, &start, &main, "--as-needed", "-lunused", "-lfoo"]);
+    assert_eq!(needed(&output), ["libfoo.so"]);
+    let output = dir.link(&[&search, &start, &main, "-lunused", "-lfoo"]);
+    assert_eq!(needed(&output), ["libunused.so", "libfoo.so"]);
+
+    // Linker scripts name libraries with `GROUP`, and `-L=` is relative to the sysroot.
+    fs::write(
+        format!("{}/libgroup.so", libs),
+        "/* a script */\nOUTPUT_FORMAT(elf64-x86-64)\n\
+         GROUP ( libfoo.so AS_NEEDED ( -lunused ) )",
+    )
+    .unwrap();
+    let sysroot = format!("--sysroot={}", dir.0.to_str().unwrap());
+    let output = dir.link(&[&sysroot, "-L=/libs", &start, &main, "-lgroup"]);
+    assert_eq!(needed(&output), ["libfoo.so"]);
+
+    let options = crate::options::Options::parse(&[main.clone(), "-lmissing".into()]).unwrap();
+    assert_eq!(crate::link(&options).unwrap_err(), "cannot find -lmissing");
+}
+
+#[test]
+fn rpath_test() {
+    let dir = TestDir::new("rpath");
+    let libs = dir.path("libs");
+    for sub in ["libs/sub", "other", "empty"] {
+        fs::create_dir_all(dir.path(sub)).unwrap();
+    }
+    let bar = dir.compile("bar", "int bar(void) { return 42; }", &["-fPIC"]);
+    let foo = dir.compile(
+        "foo",
+        "int bar(void); int foo(void) { return bar(); }",
+        &["-fPIC"],
+    );
+    let empty = dir.compile("empty", "int unrelated(void) { return 0; }", &["-fPIC"]);
+    let libbar = dir.path("libs/sub/libbar.so");
+    let libfoo = dir.path("libs/libfoo.so");
+    dir.link(&["-shared", "-soname", "libbar.so", &bar]);
+    fs::rename(dir.path("a.out"), &libbar).unwrap();
+    dir.link(&["-shared", "-soname", "libbar.so", &empty]);
+    fs::rename(dir.path("a.out"), dir.path("empty/libbar.so")).unwrap();
+
+    // Old dtags record `DT_RPATH`, and new ones `DT_RUNPATH`.
+    let output = dir.link(&[
+        "-shared",
+        "--disable-new-dtags",
+        "-rpath",
+        "/x",
+        &foo,
+        &libbar,
+    ]);
+    let entries: Vec<_> = output.object.dynamic_entries().unwrap().collect();
+    assert!(entries.contains(&Ok(DynamicEntry::Rpath("/x".into()))));
+    dir.link(&[
+        "-shared",
+        "-soname",
+        "libfoo.so",
+        "-rpath=$ORIGIN/sub",
+        &foo,
+        &libbar,
+    ]);
+    fs::rename(dir.path("a.out"), &libfoo).unwrap();
+
+    // `libbar.so` is found through the `$ORIGIN` of the run path of `libfoo.so`, and the loader
+    // finds `libfoo.so` through the run path of the executable.
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "int foo(void); int main(void) { return foo(); }",
+        &[],
+    );
+    let output = dir.link(&["-rpath", &libs, "-rpath", &libs, &start, &main, &libfoo]);
+    let entries: Vec<_> = output.object.dynamic_entries().unwrap().collect();
+    assert!(entries.contains(&Ok(DynamicEntry::Runpath(libs.clone()))));
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+
+    // Symbols libraries refer to must be defined by the libraries they need, which are looked
+    // up in `-rpath-link` directories first.
+    fs::rename(&libbar, dir.path("other/libbar.so")).unwrap();
+    dir.link(&[&start, &main, &libfoo]);
+    dir.link(&["-rpath-link", &dir.path("other"), &start, &main, &libfoo]);
+    let options = crate::options::Options::parse(&[
+        "-rpath-link".into(),
+        dir.path("empty"),
+        start,
+        main,
+        libfoo.clone(),
+    ])
+    .unwrap();
+    assert_eq!(
+        crate::link(&options).unwrap_err(),
+        format!("undefined symbol: bar\n>>> referenced by {}", libfoo)
+    );
+}
+
+#[test]
+fn copy_relocation_test() {
+    let dir = TestDir::new("copy-relocation");
+    let lib = dir.path("libdata.so");
+    let source = dir.path("data.c");
+    fs::write(
+        &source,
+        "int counter = 40;\n\
+         extern int counter_alias __attribute__((alias(\"counter\")));\n\
+         const int table[4] = { 1, 2, 3, 4 };\n\
+         int get(void) { return counter; }\n\
+         int (*get_address(void))(void) { return get; }",
+    )
+    .unwrap();
+    let status = Command::new("cc")
+        .args(["-shared", "-nostdlib", "-fPIC", "-O1", "-o", &lib, &source])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The executable refers to the data by fixed addresses, and to `get` by its PLT entry,
+    // which the library sees as its address too.
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "extern int counter, counter_alias;\n\
+         extern const int table[4];\n\
+         int get(void);\n\
+         int (*get_address(void))(void);\n\
+         int main(void) {\n\
+             counter += 1;\n\
+             if (&counter != &counter_alias) return 1;\n\
+             if (get() != 41) return 2;\n\
+             if (get_address() != get) return 3;\n\
+             counter_alias += 1;\n\
+             if (get() != 42) return 4;\n\
+             return table[3] * 10 + 2;\n\
+         }",
+        &[],
+    );
+    let output = dir.link(&[&start, &main, &lib]);
+    let status = Command::new(dir.path("a.out"))
+        .env("LD_LIBRARY_PATH", &dir.0)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(42));
+
+    let section = |name: &str| {
+        let index = output.section_names.iter().position(|n| n == name).unwrap();
+        &output.sections[index]
+    };
+    let copies: Vec<_> = output
+        .object
+        .relocations(section(".rela.dyn"))
+        .unwrap()
+        .map(Result::unwrap)
+        .filter(|r| r.ty == RelocationType::Copy as u32)
+        .collect();
+    assert_eq!(copies.len(), 2);
+    let rel_ro = section(".bss.rel.ro");
+    assert!(copies
+        .iter()
+        .any(|r| (rel_ro.addr..rel_ro.addr + rel_ro.size).contains(&r.offset)));
+
+    // The alias is defined by the copy, so that the library binds to it.
+    let dynsym = InputFile::new_shared(output.path.clone(), output.object).unwrap();
+    let value = |name: &str| {
+        let index = dynsym.symbol_names.iter().position(|n| n == name).unwrap();
+        let symbol = &dynsym.symbols[index];
+        (symbol.shndx != 0, symbol.value)
+    };
+    assert_eq!(value("counter"), value("counter_alias"));
+    assert!(value("counter").0);
+    // `get` stays undefined, with the address of its PLT entry.
+    assert!(!value("get").0);
+    assert_ne!(value("get").1, 0);
+}
+
+#[test]
+fn ifunc_test() {
+    let dir = TestDir::new("ifunc");
+    let source = "typedef struct { unsigned long offset, info; long addend; } Rela;\n\
+         extern const Rela __rela_iplt_start[] __attribute__((weak));\n\
+         extern const Rela __rela_iplt_end[] __attribute__((weak));\n\
+         static int add_one(int x) { return x + 1; }\n\
+         static int twice(int x) { return 2 * x; }\n\
+         static void *resolve_increment(void) { return add_one; }\n\
+         static void *resolve_twice(void) { return twice; }\n\
+         int increment(int) __attribute__((ifunc(\"resolve_increment\")));\n\
+         static int local_twice(int) __attribute__((ifunc(\"resolve_twice\")));\n\
+         int (*pointer)(int) = increment;\n\
+         int main(void) {\n\
+             for (const Rela *r = __rela_iplt_start; r < __rela_iplt_end; r++)\n\
+                 *(unsigned long *)r->offset = ((unsigned long (*)(void))r->addend)();\n\
+             if (pointer != increment) return 1;\n\
+             return increment(local_twice(20)) + pointer(0);\n\
+         }";
+
+    // A static executable applies the relocations of its IPLT slots itself.
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile("main", source, &[]);
+    let output = dir.link(&[&start, &main]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    let count = |output: &InputFile, name: &str| {
+        output
+            .section_names
+            .iter()
+            .position(|n| n == name)
+            .map(|index| {
+                output
+                    .object
+                    .relocations(&output.sections[index])
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .filter(|r| r.ty == RelocationType::IRelative as u32)
+                    .count()
+            })
+    };
+    assert_eq!(count(&output, ".rela.iplt"), Some(2));
+
+    // Shared libraries leave the preemptible `increment` to the loader, and only the local
+    // function gets an IPLT entry, relocated in `.rela.dyn`.
+    let main = dir.compile("main", source, &["-fPIC"]);
+    let output = dir.link(&["-shared", &main]);
+    assert_eq!(count(&output, ".rela.iplt"), None);
+    assert_eq!(count(&output, ".rela.dyn"), Some(1));
+}
+
+#[test]
+fn provided_symbols_test() {
+    let dir = TestDir::new("provided-symbols");
+    let start = dir.compile("start", START, &[]);
+    let main = dir.compile(
+        "main",
+        "extern char __ehdr_start[], _etext[], _edata[], __bss_start[], _end[];\n\
+         extern void (*__init_array_start[])(void), (*__init_array_end[])(void);\n\
+         extern int __start_items[], __stop_items[];\n\
+         static int a __attribute__((section(\"items\"), used)) = 20;\n\
+         static int b __attribute__((section(\"items\"), used)) = 12;\n\
+         static int initialized, zeroed[64];\n\
+         static void init(void) { initialized += 10; }\n\
+         static void (*init_pointer)(void) __attribute__((section(\".init_array\"), used)) = init;\n\
+         int main(void) {\n\
+             if (__ehdr_start[0] != 0x7f || __ehdr_start[1] != 'E') return 1;\n\
+             if (!(_etext < _edata && _edata <= __bss_start && __bss_start <= (char *)zeroed\n\
+                   && (char *)(zeroed + 64) <= _end))\n\
+                 return 2;\n\
+             for (void (**f)(void) = __init_array_start; f < __init_array_end; f++) (*f)();\n\
+             int sum = initialized;\n\
+             for (int *p = __start_items; p < __stop_items; p++) sum += *p;\n\
+             return sum;\n\
+         }",
+        &[],
+    );
+    let output = dir.link(&[&start, &main]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    // Only the symbols something refers to are defined.
+    assert!(has_symbol(&output, "__start_items"));
+    let index = output.section_names.iter().position(|n| n == ".bss");
+    assert_eq!(
+        symbol_value(&output, "__bss_start"),
+        output.sections[index.unwrap()].addr
+    );
+    assert!(!has_symbol(&output, "end"));
+
+    // Addresses relative to `_GLOBAL_OFFSET_TABLE_` need `.got.plt` even without a GOT or a PLT.
+    let got_relative = dir.compile(
+        "got-relative",
+        "static int value = 42;\n\
+         int main(void) {\n\
+             char *got;\n\
+             long offset;\n\
+             __asm__(\"leaq _GLOBAL_OFFSET_TABLE_(%%rip), %0\" : \"=r\"(got));\n\
+             __asm__(\"movabsq $value@GOTOFF, %0\" : \"=r\"(offset) : \"X\"(&value));\n\
+             return *(int *)(got + offset);\n\
+         }",
+        &[],
+    );
+    let output = dir.link(&[&start, &got_relative]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    let index = output.section_names.iter().position(|n| n == ".got.plt");
+    assert_eq!(
+        symbol_value(&output, "_GLOBAL_OFFSET_TABLE_"),
+        output.sections[index.unwrap()].addr
+    );
+
+    // Shared libraries refer to their own `_DYNAMIC` rather than importing it.
+    let lib = dir.compile(
+        "lib",
+        "extern char _DYNAMIC[];\n\
+         void *dynamic(void) { return _DYNAMIC; }",
+        &["-fPIC"],
+    );
+    let output = dir.link(&["-shared", &lib]);
+    let index = output
+        .section_names
+        .iter()
+        .position(|n| n == ".dynamic")
+        .unwrap();
+    assert_eq!(
+        symbol_value(&output, "_DYNAMIC"),
+        output.sections[index].addr
+    );
+    let dynsym = InputFile::new_shared(output.path.clone(), output.object).unwrap();
+    assert!(!dynsym.symbol_names.iter().any(|n| n == "_DYNAMIC"));
+}
+
+#[test]
+fn symbol_options_test() {
+    let dir = TestDir::new("symbol-options");
+    let start = dir.compile("start", START, &["-D_start=begin"]);
+    let main = dir.compile(
+        "main",
+        "extern char delta[];\n\
+         int compute(int), twice(int);\n\
+         int main(void) {\n\
+             if (twice(1) != 2) return 1;\n\
+             return compute(20) + (long)delta;\n\
+         }",
+        &[],
+    );
+    let wrap = dir.compile(
+        "wrap",
+        "int __real_compute(int);\n\
+         int __wrap_compute(int x) { return __real_compute(x) + 1; }",
+        &[],
+    );
+    let compute = dir.compile("compute", "int compute(int x) { return 2 * x; }", &[]);
+    let extra = dir.compile("extra", "int extra = 5;", &[]);
+    let archive = dir.path("libextra.a");
+    let status = Command::new("ar")
+        .args(["rc", &archive, &compute, &extra])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // `compute` comes from the archive through `__real_compute` and `twice`, and `extra` only
+    // because `-u` asks for it.
+    let output = dir.link(&[
+        "-e",
+        "begin",
+        "--wrap=compute",
+        "--defsym=delta=1",
+        "--defsym",
+        "twice=compute",
+        "-u",
+        "extra",
+        &start,
+        &main,
+        &wrap,
+        &archive,
+    ]);
+    assert_eq!(run(Path::new(&dir.path("a.out"))), 42);
+    assert_eq!(output.object.header.entry, symbol_value(&output, "begin"));
+    assert_eq!(symbol_value(&output, "delta"), 1);
+    assert_eq!(
+        symbol_value(&output, "twice"),
+        symbol_value(&output, "compute")
+    );
+    assert!(has_symbol(&output, "extra"));
+
+    // Short options take joined values, but not from single-dash long options.
+    let parse = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|&a| a.into()).collect();
+        crate::options::Options::parse(&args)
+    };
+    let options = parse(&["-ebegin", "-uextra", "-entry=start"]).unwrap();
+    assert_eq!(options.entry.as_deref(), Some("start"));
+    assert_eq!(options.undefined, ["extra"]);
+    let error = parse(&["-export-dynamic", "main.o"]).unwrap_err();
+    assert_eq!(error, "unknown option: -export-dynamic");
+    assert!(parse(&["-unique"]).is_err());
+
+    // Boolean long options may also be spelled with a single dash.
+    let options = parse(&["-gc-sections", "-print-gc-sections", "-cref"]).unwrap();
+    assert!(options.gc_sections && options.print_gc_sections && options.cref);
+    let options = parse(&["--enable-new-dtags", "-disable-new-dtags", "--shared"]).unwrap();
+    assert!(!options.new_dtags && options.shared);
+}
@@ -0,0 +1,322 @@
+//! GNU symbol versioning.
+//!
+//! A shared library may define several versions of a symbol, such as `memcpy@GLIBC_2.2.5` and
+//! `memcpy@@GLIBC_2.14`, the latter being the default version, which references without a version
+//! bind to. `.gnu.version` holds the version index of each dynamic symbol, `.gnu.version_d` the
+//! versions a file defines, and `.gnu.version_r` the versions it needs from each library. Object
+//! files give versions in symbol names: `foo@@VER` defines the default version of `foo`, and
+//! `foo@VER` another version.
+//!
+//! Version scripts give versions to the symbols defined without one, and may make symbols local.
+
+use crate::{
+    demangle::demangle,
+    elf::SymbolBinding,
+    script::{SymbolPattern, VersionNode},
+    symbols::{Definition, SymbolTable},
+    util::{FromBytes as _, ToBytes as _},
+};
+
+/// The version index of local symbols.
+pub const VER_NDX_LOCAL: u16 = 0;
+
+/// The version index of global symbols without a version.
+pub const VER_NDX_GLOBAL: u16 = 1;
+
+/// The bit of a version index set for symbols which are not the default version.
+pub const VERSYM_HIDDEN: u16 = 0x8000;
+
+/// The flag of the version definition naming the file itself, which has index 1.
+pub const VER_FLG_BASE: u16 = 1;
+
+const VERDEF_SIZE: usize = 20;
+const VERDAUX_SIZE: usize = 8;
+const VERNEED_SIZE: usize = 16;
+const VERNAUX_SIZE: usize = 16;
+
+/// An entry of `.gnu.version_d`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionDefinition {
+    pub index: u16,
+    pub flags: u16,
+    pub name: String,
+
+    /// The versions this version inherits from.
+    pub parents: Vec<String>,
+}
+
+/// An entry of `.gnu.version_r`: the versions needed from a library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionNeed {
+    /// The name the library is needed by, as in `DT_NEEDED`.
+    pub file: String,
+
+    /// The names of the versions and the indexes they are given in the needing file.
+    pub versions: Vec<(String, u16)>,
+}
+
+/// Splits a symbol name of an object file into the name and the version, if any, and whether the
+/// version is the default one: `foo@@VER` gives `("foo", Some(("VER", true)))`.
+pub fn split_name(name: &str) -> (&str, Option<(&str, bool)>) {
+    match name.split_once('@') {
+        None => (name, None),
+        Some((base, version)) => match version.strip_prefix('@') {
+            Some(version) => (base, Some((version, true))),
+            None => (base, Some((version, false))),
+        },
+    }
+}
+
+/// The hash of a name used by SysV hash tables and by version sections.
+pub fn elf_hash(name: &str) -> u32 {
+    let mut h: u32 = 0;
+    for &b in name.as_bytes() {
+        h = (h << 4).wrapping_add(b as u32);
+        let g = h & 0xF000_0000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
+/// Parses the contents of `.gnu.version`.
+pub fn parse_versyms(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Parses the contents of `.gnu.version_d`, looking names up with `string`.
+pub fn parse_definitions<'a>(
+    data: &[u8],
+    string: impl Fn(u32) -> Result<&'a str, String>,
+) -> Result<Vec<VersionDefinition>, String> {
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut definitions = vec![];
+    let mut pos = 0;
+    loop {
+        let mut head = data
+            .get(pos..pos + VERDEF_SIZE)
+            .ok_or("truncated version definition")?;
+        let _version = u16::read_le_bytes(&mut head);
+        let flags = u16::read_le_bytes(&mut head);
+        let index = u16::read_le_bytes(&mut head);
+        let count = u16::read_le_bytes(&mut head);
+        let _hash = u32::read_le_bytes(&mut head);
+        let aux = u32::read_le_bytes(&mut head) as usize;
+        let next = u32::read_le_bytes(&mut head) as usize;
+
+        // The first auxiliary entry names the version, and the others its parents.
+        let mut names = vec![];
+        let mut aux_pos = pos + aux;
+        for _ in 0..count {
+            let mut head = data
+                .get(aux_pos..aux_pos + VERDAUX_SIZE)
+                .ok_or("truncated version definition")?;
+            names.push(string(u32::read_le_bytes(&mut head))?.to_string());
+            aux_pos += u32::read_le_bytes(&mut head) as usize;
+        }
+        let mut names = names.into_iter();
+        definitions.push(VersionDefinition {
+            index,
+            flags,
+            name: names.next().ok_or("a version definition has no name")?,
+            parents: names.collect(),
+        });
+
+        if next == 0 {
+            return Ok(definitions);
+        }
+        pos += next;
+    }
+}
+
+/// Parses the contents of `.gnu.version_r`, looking names up with `string`.
+pub fn parse_needs<'a>(
+    data: &[u8],
+    string: impl Fn(u32) -> Result<&'a str, String>,
+) -> Result<Vec<VersionNeed>, String> {
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut needs = vec![];
+    let mut pos = 0;
+    loop {
+        let mut head = data
+            .get(pos..pos + VERNEED_SIZE)
+            .ok_or("truncated version need")?;
+        let _version = u16::read_le_bytes(&mut head);
+        let count = u16::read_le_bytes(&mut head);
+        let file = string(u32::read_le_bytes(&mut head))?.to_string();
+        let aux = u32::read_le_bytes(&mut head) as usize;
+        let next = u32::read_le_bytes(&mut head) as usize;
+
+        let mut versions = vec![];
+        let mut aux_pos = pos + aux;
+        for _ in 0..count {
+            let mut head = data
+                .get(aux_pos..aux_pos + VERNAUX_SIZE)
+                .ok_or("truncated version need")?;
+            let _hash = u32::read_le_bytes(&mut head);
+            let _flags = u16::read_le_bytes(&mut head);
+            let index = u16::read_le_bytes(&mut head);
+            let name = string(u32::read_le_bytes(&mut head))?.to_string();
+            versions.push((name, index));
+            aux_pos += u32::read_le_bytes(&mut head) as usize;
+        }
+        needs.push(VersionNeed { file, versions });
+
+        if next == 0 {
+            return Ok(needs);
+        }
+        pos += next;
+    }
+}
+
+/// Builds the contents of `.gnu.version_d`, with `offset` giving the offset of a name in the
+/// dynamic string table.
+pub fn build_definitions(
+    definitions: &[VersionDefinition],
+    mut offset: impl FnMut(&str) -> u32,
+) -> Vec<u8> {
+    let mut data = vec![];
+    for (i, definition) in definitions.iter().enumerate() {
+        let names: Vec<&str> = std::iter::once(definition.name.as_str())
+            .chain(definition.parents.iter().map(String::as_str))
+            .collect();
+        let size = VERDEF_SIZE + VERDAUX_SIZE * names.len();
+        1u16.write_le_bytes(&mut data);
+        definition.flags.write_le_bytes(&mut data);
+        definition.index.write_le_bytes(&mut data);
+        (names.len() as u16).write_le_bytes(&mut data);
+        elf_hash(&definition.name).write_le_bytes(&mut data);
+        (VERDEF_SIZE as u32).write_le_bytes(&mut data);
+        let next = if i + 1 == definitions.len() { 0 } else { size };
+        (next as u32).write_le_bytes(&mut data);
+
+        for (j, name) in names.iter().enumerate() {
+            offset(name).write_le_bytes(&mut data);
+            let next = if j + 1 == names.len() {
+                0
+            } else {
+                VERDAUX_SIZE
+            };
+            (next as u32).write_le_bytes(&mut data);
+        }
+    }
+    data
+}
+
+/// Builds the contents of `.gnu.version_r`, with `offset` giving the offset of a name in the
+/// dynamic string table.
+pub fn build_needs(needs: &[VersionNeed], mut offset: impl FnMut(&str) -> u32) -> Vec<u8> {
+    let mut data = vec![];
+    for (i, need) in needs.iter().enumerate() {
+        let size = VERNEED_SIZE + VERNAUX_SIZE * need.versions.len();
+        1u16.write_le_bytes(&mut data);
+        (need.versions.len() as u16).write_le_bytes(&mut data);
+        offset(&need.file).write_le_bytes(&mut data);
+        (VERNEED_SIZE as u32).write_le_bytes(&mut data);
+        let next = if i + 1 == needs.len() { 0 } else { size };
+        (next as u32).write_le_bytes(&mut data);
+
+        for (j, (name, index)) in need.versions.iter().enumerate() {
+            elf_hash(name).write_le_bytes(&mut data);
+            0u16.write_le_bytes(&mut data);
+            index.write_le_bytes(&mut data);
+            offset(name).write_le_bytes(&mut data);
+            let next = if j + 1 == need.versions.len() {
+                0
+            } else {
+                VERNAUX_SIZE
+            };
+            (next as u32).write_le_bytes(&mut data);
+        }
+    }
+    data
+}
+
+/// Applies the nodes of version scripts to the global symbols object files define: a symbol a
+/// named node lists as global gets its version, and a symbol a node lists as local becomes local.
+/// Symbols whose names give their versions are left alone. Patterns without wildcards take
+/// precedence over those with, of which later nodes take precedence, and `*` comes last. Warns
+/// about patterns which match no symbol.
+pub fn apply_version_script(symbols: &mut SymbolTable, nodes: &[VersionNode]) {
+    let mut patterns: Vec<(&VersionNode, bool, &SymbolPattern)> = vec![];
+    for node in nodes {
+        let exact = |p: &&SymbolPattern| p.exact;
+        patterns.extend(node.globals.iter().filter(exact).map(|p| (node, false, p)));
+        patterns.extend(node.locals.iter().filter(exact).map(|p| (node, true, p)));
+    }
+    for all in [false, true] {
+        for node in nodes.iter().rev() {
+            let wildcard = |p: &&SymbolPattern| !p.exact && (p.pattern == "*") == all;
+            patterns.extend(
+                node.globals
+                    .iter()
+                    .filter(wildcard)
+                    .map(|p| (node, false, p)),
+            );
+            patterns.extend(node.locals.iter().filter(wildcard).map(|p| (node, true, p)));
+        }
+    }
+
+    let defined = defined_symbols(symbols, patterns.iter().any(|(_, _, p)| p.cxx));
+    for (id, demangled) in &defined {
+        let symbol = &mut symbols.symbols[*id];
+        if symbol.version.is_some() {
+            continue;
+        }
+        let Some(&(node, local, _)) = patterns
+            .iter()
+            .find(|(_, _, p)| p.matches(&symbol.name, demangled.as_deref()))
+        else {
+            continue;
+        };
+        if local {
+            symbol.binding = SymbolBinding::Local;
+        } else {
+            symbol.version = node.name.clone();
+        }
+    }
+
+    for (node, _, pattern) in patterns {
+        if pattern.pattern != "*"
+            && !defined
+                .iter()
+                .any(|(id, d)| pattern.matches(&symbols.symbols[*id].name, d.as_deref()))
+        {
+            let version = node
+                .name
+                .as_ref()
+                .map_or("the anonymous version".into(), |n| {
+                    format!("version `{}`", n)
+                });
+            eprintln!(
+                "warning: pattern `{}` in {} of the version script matches no symbol",
+                pattern.pattern, version
+            );
+        }
+    }
+}
+
+/// Returns the global symbols object files define, by id, with their demangled names if
+/// `cxx` is set and they are C++ names.
+pub fn defined_symbols(symbols: &SymbolTable, cxx: bool) -> Vec<(usize, Option<String>)> {
+    symbols
+        .symbols
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| {
+            matches!(
+                s.definition,
+                Definition::Section { .. } | Definition::Absolute(_) | Definition::Common { .. }
+            )
+        })
+        .map(|(id, s)| (id, cxx.then(|| demangle(&s.name)).flatten()))
+        .collect()
+}
@@ -0,0 +1,110 @@
+//! Reports of where symbols are defined and referenced, for debugging what a link pulls in.
+//!
+//! `--why-extract` lists the archive members extracted with the symbol and file which caused it,
+//! `--trace-symbol` reports each definition and reference of a symbol as the inputs are read, and
+//! `--cref` prints a cross-reference table of all global symbols.
+
+use std::{collections::BTreeMap, fmt::Write as _, fs};
+
+use crate::{
+    elf::SymbolBinding,
+    inputs::InputFile,
+    symbols::{Definition, SymbolTable},
+};
+
+/// Writes the archive members extracted to `path`, or to the standard output for `-`, as
+/// tab-separated lines of the file referring to a symbol, the member defining it and the symbol.
+pub fn why_extract(path: &str, inputs: &[InputFile]) -> Result<(), String> {
+    let mut text = String::from("reference\textracted\tsymbol\n");
+    for input in inputs {
+        if let Some(extraction) = &input.extraction {
+            let _ = writeln!(
+                text,
+                "{}\t{}\t{}",
+                extraction.referenced_by, input.path, extraction.symbol
+            );
+        }
+    }
+    if path == "-" {
+        print!("{}", text);
+        return Ok(());
+    }
+    fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path, e))
+}
+
+/// Reports each definition and reference of the symbols `names` in the inputs.
+pub fn trace_symbols(names: &[String], inputs: &[InputFile]) {
+    for input in inputs {
+        for (sym, name) in input.symbols.iter().zip(&input.symbol_names).skip(1) {
+            if sym.binding == SymbolBinding::Local || !names.contains(name) {
+                continue;
+            }
+            let kind = if sym.is_undefined() {
+                "reference to"
+            } else {
+                "definition of"
+            };
+            println!("{}: {} {}", input.path, kind, name);
+        }
+    }
+}
+
+/// Builds the cross-reference table: each global symbol by name, with the files defining it
+/// followed by the files referring to it. The definition the link uses comes first.
+pub fn cref(inputs: &[InputFile], symbols: &SymbolTable) -> Vec<(String, Vec<usize>)> {
+    let mut files: BTreeMap<&str, (Vec<usize>, Vec<usize>)> = BTreeMap::new();
+    for (file, input) in inputs.iter().enumerate() {
+        for (index, sym) in input.symbols.iter().enumerate().skip(1) {
+            if sym.binding == SymbolBinding::Local {
+                continue;
+            }
+            let entry = files.entry(&input.symbol_names[index]).or_default();
+            if sym.is_undefined() {
+                entry.1.push(file);
+            } else {
+                entry.0.push(file);
+            }
+        }
+    }
+
+    // Symbols only shared libraries mention are not part of the link.
+    files
+        .into_iter()
+        .filter(|(_, (definers, referrers))| {
+            definers
+                .iter()
+                .chain(referrers)
+                .any(|&f| inputs[f].shared.is_none())
+        })
+        .map(|(name, (mut definers, referrers))| {
+            if let Some(Definition::Section { file, .. } | Definition::Shared { file, .. }) =
+                symbols.symbol(name).map(|s| s.definition)
+            {
+                if let Some(i) = definers.iter().position(|&f| f == file) {
+                    definers[..=i].rotate_right(1);
+                }
+            }
+            definers.extend(referrers);
+            (name.to_string(), definers)
+        })
+        .collect()
+}
+
+/// Formats the cross-reference table as GNU ld does.
+pub fn cref_text(inputs: &[InputFile], table: &[(String, Vec<usize>)]) -> String {
+    const WIDTH: usize = 50;
+    let mut text = format!("Cross Reference Table\n\n{:<WIDTH$}File\n", "Symbol");
+    for (name, files) in table {
+        // Names too long for the column go on a line of their own.
+        let mut label = name.as_str();
+        if name.len() >= WIDTH {
+            let _ = writeln!(text, "{}", name);
+            label = "";
+        }
+        for &file in files {
+            let _ = writeln!(text, "{:<WIDTH$}{}", label, inputs[file].path);
+            label = "";
+        }
+    }
+    text
+}